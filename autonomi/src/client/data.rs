@@ -8,7 +8,7 @@ use libp2p::{
     PeerId,
 };
 use self_encryption::{decrypt_full_set, DataMap, EncryptedChunk};
-use sn_networking::{GetRecordCfg, NetworkError, PutRecordCfg};
+use sn_networking::{GetRecordCfg, NetworkError, PutRecordCfg, VerifyAfterPutCfg};
 use sn_protocol::{
     storage::{
         try_deserialize_record, try_serialize_record, Chunk, ChunkAddress, RecordHeader, RecordKind,
@@ -84,10 +84,12 @@ impl Client {
 
         let get_cfg = GetRecordCfg {
             get_quorum: Quorum::One,
+            resolution: Default::default(),
             retry_strategy: None,
             target_record: None,
             expected_holders: HashSet::new(),
             is_register: false,
+            bypass_cache: false,
         };
         let record = self.network.get_record_from_network(key, &get_cfg).await?;
         let header = RecordHeader::from_record(&record)?;
@@ -313,6 +315,7 @@ impl Client {
             retry_strategy: None,
             use_put_record_to: Some(vec![payee]),
             verification: None,
+            verify_after_put: Some(VerifyAfterPutCfg::default()),
         };
         Ok(self.network.put_record(record, &put_cfg).await?)
     }
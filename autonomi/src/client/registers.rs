@@ -119,6 +119,7 @@ impl Client {
             retry_strategy: None,
             use_put_record_to: Some(vec![payee]),
             verification: None,
+            verify_after_put: None,
         };
 
         self.network.put_record(record, &put_cfg).await?;
@@ -138,10 +139,12 @@ impl Client {
 
         let get_cfg = GetRecordCfg {
             get_quorum: Quorum::One,
+            resolution: Default::default(),
             retry_strategy: None,
             target_record: None,
             expected_holders: Default::default(),
             is_register: true,
+            bypass_cache: false,
         };
 
         let record = self.network.get_record_from_network(key, &get_cfg).await?;
@@ -200,6 +203,7 @@ impl Client {
             retry_strategy: None,
             use_put_record_to: None,
             verification: None,
+            verify_after_put: None,
         };
 
         // Store the updated register on the network
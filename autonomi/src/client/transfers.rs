@@ -313,16 +313,19 @@ async fn store_spend(network: Network, spend: SignedSpend) -> Result<(), Network
     // When there is retry on Put side, no need to have a retry on Get
     let verification_cfg = GetRecordCfg {
         get_quorum: Quorum::Majority,
+        resolution: Default::default(),
         retry_strategy: None,
         target_record: record_to_verify,
         expected_holders,
         is_register: false,
+        bypass_cache: false,
     };
     let put_cfg = PutRecordCfg {
         put_quorum: Quorum::Majority,
         retry_strategy: Some(RetryStrategy::Persistent),
         use_put_record_to: None,
         verification: Some((VerificationKind::Network, verification_cfg)),
+        verify_after_put: None,
     };
     network.put_record(record, &put_cfg).await
 }
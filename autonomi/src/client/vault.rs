@@ -68,10 +68,12 @@ impl Client {
 
         let get_cfg = GetRecordCfg {
             get_quorum: Quorum::Majority,
+            resolution: Default::default(),
             retry_strategy: None,
             target_record: None,
             expected_holders: HashSet::new(),
             is_register: false,
+            bypass_cache: false,
         };
 
         let record = self
@@ -164,12 +166,15 @@ impl Client {
                 VerificationKind::Network,
                 GetRecordCfg {
                     get_quorum: Quorum::Majority,
+                    resolution: Default::default(),
                     retry_strategy: None,
                     target_record: None,
                     expected_holders: HashSet::new(),
                     is_register: false,
+                    bypass_cache: false,
                 },
             )),
+            verify_after_put: None,
         };
 
         self.network.put_record(record, &put_cfg).await?;
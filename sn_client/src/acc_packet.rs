@@ -11,8 +11,11 @@ use bip39::Mnemonic;
 use sn_transfers::{get_faucet_data_dir, HotWallet, MainSecretKey};
 use std::path::Path;
 
+mod secrets;
 pub mod user_secret;
 
+pub use self::secrets::{InvalidMnemonic, SecretsError};
+
 const DEFAULT_WALLET_DERIVIATION_PASSPHRASE: &str = "default";
 
 /// Load a account from disk, with wallet, or create a new one using the mnemonic system
@@ -62,7 +65,10 @@ pub fn secret_key_from_mnemonic(
 ) -> Result<MainSecretKey> {
     let passphrase =
         derivation_passphrase.unwrap_or(DEFAULT_WALLET_DERIVIATION_PASSPHRASE.to_owned());
-    user_secret::account_wallet_secret_key(mnemonic, &passphrase)
+    Ok(user_secret::account_wallet_secret_key(
+        mnemonic,
+        &passphrase,
+    )?)
 }
 
 pub fn create_faucet_account_and_wallet() -> HotWallet {
@@ -0,0 +1,81 @@
+// Copyright 2024 MaidSafe.net limited.
+//
+// This SAFE Network Software is licensed to you under The General Public License (GPL), version 3.
+// Unless required by applicable law or agreed to in writing, the SAFE Network Software distributed
+// under the GPL Licence is distributed on an "AS IS" BASIS, WITHOUT WARRANTIES OR CONDITIONS OF ANY
+// KIND, either express or implied. Please review the Licences for the specific language governing
+// permissions and limitations relating to use of the SAFE Network Software.
+
+use thiserror::Error;
+
+pub(crate) type Result<T, E = SecretsError> = std::result::Result<T, E>;
+
+/// Errors of the mnemonic and of the secret keys derived from it, see `user_secret`. They can be matched on, e.g., to
+/// tell a user that they mistyped their backup rather than that the derivation failed. Being a `std::error::Error`,
+/// it converts into an `eyre::Report` or a `crate::Error` with `?`.
+#[derive(Debug, Error)]
+pub enum SecretsError {
+    /// The phrase written down by the user is not a valid mnemonic.
+    #[error("Invalid mnemonic phrase: {0}")]
+    InvalidMnemonic(#[from] InvalidMnemonic),
+
+    /// The entropy cannot be encoded by a mnemonic, it must be 16, 20, 24, 28 or 32 bytes long.
+    #[error("The entropy is {0} bytes long, it must be 16, 20, 24, 28 or 32 bytes long")]
+    InvalidEntropyLength(usize),
+
+    /// A mnemonic could not be created from the entropy.
+    #[error("Failed to create the mnemonic from its entropy: {0}")]
+    Bip39(#[source] bip39::Error),
+
+    /// The seed is too short for the master key to be derived from it, EIP-2333 requires at least 32 bytes.
+    #[error("The seed is {0} bytes long, at least 32 bytes are needed to derive keys")]
+    SeedTooShort(usize),
+
+    /// The derived bytes are not a valid secret key.
+    #[error("Failed to derive the secret key: {0}")]
+    Derivation(#[source] bls::Error),
+
+    /// The secret key is not valid hex, see `user_secret::main_sk_from_hex`.
+    #[error("The secret key is not valid hex: {0}")]
+    InvalidSecretKeyHex(#[source] hex::FromHexError),
+
+    /// The decoded secret key does not have the 32 bytes of a secret key.
+    #[error("The secret key is {0} bytes long, it must be 32 bytes long")]
+    InvalidSecretKeyLength(usize),
+
+    /// The decoded bytes are not a valid secret key, e.g., they are beyond the order of the curve.
+    #[error("SecretKey could not be created from the provided bytes: {0}")]
+    InvalidKeyBytes(#[source] bls::Error),
+
+    /// The derivation path is not a valid EIP-2333 path, see `user_secret::DerivationPath`.
+    #[error("The derivation path is invalid: {0:?}")]
+    MalformedDerivationPath(String),
+
+    /// A derivation path of a batch is not a valid EIP-2333 path.
+    #[error("The derivation path #{index} is invalid: {path:?}")]
+    InvalidDerivationPath { index: usize, path: String },
+
+    /// The key of a batch could not be derived at the derivation path #`index`.
+    #[error("Failed to derive the key #{index} of the batch: {source}")]
+    BatchDerivation {
+        index: usize,
+        #[source]
+        source: Box<SecretsError>,
+    },
+}
+
+/// Why a phrase is not a valid mnemonic.
+#[derive(Debug, Clone, PartialEq, Eq, Error)]
+pub enum InvalidMnemonic {
+    #[error("the phrase has {0} words, it must have 12, 15, 18, 21 or 24 words")]
+    BadWordCount(usize),
+
+    #[error("the word #{index} is not part of the word list: {word:?}")]
+    UnknownWord { index: usize, word: String },
+
+    #[error("the checksum is invalid")]
+    InvalidChecksum,
+
+    #[error("{0}")]
+    Other(bip39::Error),
+}
@@ -6,28 +6,170 @@
 // KIND, either express or implied. Please review the Licences for the specific language governing
 // permissions and limitations relating to use of the SAFE Network Software.
 
+use super::secrets::{InvalidMnemonic, Result, SecretsError};
 use crate::{
-    error::{Error, Result},
-    transfers::MainSecretKey,
+    error::Error,
+    transfers::{MainPubkey, MainSecretKey},
 };
 use bls::SecretKey;
 use curv::elliptic::curves::ECScalar;
 use rand::RngCore;
+use rayon::prelude::*;
 use std::path::Path;
+use subtle::ConstantTimeEq;
 use xor_name::XorName;
+use zeroize::{Zeroize, Zeroizing};
 
 const MNEMONIC_FILENAME: &str = "account_secret";
 
 const ACCOUNT_ROOT_XORNAME_DERIVATION: &str = "m/1/0";
 
-const ACCOUNT_WALLET_DERIVATION: &str = "m/2/0";
+/// The derivation path of the wallet of account `i` is `m/2/i`.
+const ACCOUNT_WALLET_DERIVATION: &str = "m/2";
 
+/// The lengths of entropy, in bytes, that can be encoded by a BIP39 mnemonic.
+const VALID_ENTROPY_LENGTHS: [usize; 5] = [16, 20, 24, 28, 32];
+
+/// The number of words of a mnemonic. More words carry more entropy, at the cost of a longer phrase to back up.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum MnemonicWordCount {
+    /// 128 bits of entropy.
+    Twelve,
+    /// 192 bits of entropy.
+    Eighteen,
+    /// 256 bits of entropy.
+    #[default]
+    TwentyFour,
+}
+
+impl MnemonicWordCount {
+    /// The number of words of the mnemonic.
+    pub fn words(&self) -> usize {
+        match self {
+            Self::Twelve => 12,
+            Self::Eighteen => 18,
+            Self::TwentyFour => 24,
+        }
+    }
+
+    /// The number of bytes of entropy encoded by the mnemonic, as defined by BIP39.
+    fn entropy_bytes(&self) -> usize {
+        match self {
+            Self::Twelve => 16,
+            Self::Eighteen => 24,
+            Self::TwentyFour => 32,
+        }
+    }
+}
+
+/// An EIP-2333 derivation path, e.g., `m/2/0`, checked to be well formed.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct DerivationPath(String);
+
+impl DerivationPath {
+    /// Parses the path, which must start with `m` followed by `/`-separated indexes.
+    pub fn new(path: &str) -> Result<Self> {
+        if eip2333::path_to_node(path).is_err() {
+            return Err(SecretsError::MalformedDerivationPath(path.to_string()));
+        }
+        Ok(Self(path.to_string()))
+    }
+
+    pub fn as_str(&self) -> &str {
+        &self.0
+    }
+}
+
+/// The path of the wallet of the account 0, from which `account_wallet_secret_key` derives its key.
+impl Default for DerivationPath {
+    fn default() -> Self {
+        Self(format!("{ACCOUNT_WALLET_DERIVATION}/0"))
+    }
+}
+
+/// A mnemonic along with the wallet keys derived from it, e.g., to onboard a user in a single step. The mnemonic is
+/// kept around so that it can be displayed for a backup.
+#[derive(Clone)]
+pub struct WalletSeed {
+    mnemonic: bip39::Mnemonic,
+}
+
+impl WalletSeed {
+    /// Generates a new 24 words mnemonic.
+    pub fn generate() -> Result<Self> {
+        Ok(Self {
+            mnemonic: random_eip2333_mnemonic()?,
+        })
+    }
+
+    /// Restores the mnemonic from a phrase written down by the user, see `mnemonic_from_phrase`.
+    pub fn from_phrase(phrase: &str) -> Result<Self> {
+        Ok(Self {
+            mnemonic: mnemonic_from_phrase(phrase)?,
+        })
+    }
+
+    /// The mnemonic, to be backed up by the user.
+    pub fn mnemonic(&self) -> &bip39::Mnemonic {
+        &self.mnemonic
+    }
+
+    /// Derives the wallet secret key for the passphrase, see `account_wallet_secret_key`.
+    pub fn main_sk(&self, passphrase: &str) -> Result<MainSecretKey> {
+        account_wallet_secret_key(self.mnemonic.clone(), passphrase)
+    }
+
+    /// The public key of the wallet for the passphrase, see `main_pubkey_from_mnemonic`.
+    pub fn main_pubkey(&self, passphrase: &str) -> Result<MainPubkey> {
+        main_pubkey_from_mnemonic(self.mnemonic.clone(), passphrase)
+    }
+}
+
+// The mnemonic is not printed, as it is enough to recover the wallet.
+impl std::fmt::Debug for WalletSeed {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("WalletSeed").finish_non_exhaustive()
+    }
+}
+
+/// Generates a 24 words mnemonic.
 pub fn random_eip2333_mnemonic() -> Result<bip39::Mnemonic> {
-    let mut entropy = [1u8; 32];
+    random_eip2333_mnemonic_with_word_count(MnemonicWordCount::default())
+}
+
+/// Generates a mnemonic of the provided number of words.
+pub fn random_eip2333_mnemonic_with_word_count(
+    word_count: MnemonicWordCount,
+) -> Result<bip39::Mnemonic> {
+    let mut entropy = Zeroizing::new(vec![1u8; word_count.entropy_bytes()]);
     let rng = &mut rand::rngs::OsRng;
     rng.fill_bytes(&mut entropy);
-    let mnemonic =
-        bip39::Mnemonic::from_entropy(&entropy).map_err(|_error| Error::FailedToParseEntropy)?;
+    mnemonic_from_entropy(&entropy)
+}
+
+fn mnemonic_from_entropy(entropy: &[u8]) -> Result<bip39::Mnemonic> {
+    bip39::Mnemonic::from_entropy(entropy).map_err(SecretsError::Bip39)
+}
+
+/// Parses and validates a mnemonic phrase written down by the user, e.g., to restore a wallet from a paper backup.
+/// The words are case insensitive and can be separated by any whitespace.
+pub fn mnemonic_from_phrase(phrase: &str) -> Result<bip39::Mnemonic> {
+    let words = Zeroizing::new(
+        phrase
+            .split_whitespace()
+            .map(str::to_lowercase)
+            .collect::<Vec<_>>(),
+    );
+    let phrase = Zeroizing::new(words.join(" "));
+    let mnemonic = bip39::Mnemonic::parse_normalized(&phrase).map_err(|err| match err {
+        bip39::Error::BadWordCount(count) => InvalidMnemonic::BadWordCount(count),
+        bip39::Error::UnknownWord(index) => InvalidMnemonic::UnknownWord {
+            index,
+            word: phrase.split(' ').nth(index).unwrap_or_default().to_string(),
+        },
+        bip39::Error::InvalidChecksum => InvalidMnemonic::InvalidChecksum,
+        other => InvalidMnemonic::Other(other),
+    })?;
     Ok(mnemonic)
 }
 
@@ -36,39 +178,633 @@ pub fn account_wallet_secret_key(
     mnemonic: bip39::Mnemonic,
     passphrase: &str,
 ) -> Result<MainSecretKey> {
-    let seed = mnemonic.to_seed(passphrase);
+    account_wallet_secret_key_indexed(mnemonic, passphrase, 0)
+}
+
+/// Derive the public key of the wallet of the account, i.e., the address to receive funds at, see
+/// `account_wallet_secret_key`. The secret key is dropped once its public counterpart has been derived.
+pub fn main_pubkey_from_mnemonic(
+    mnemonic: bip39::Mnemonic,
+    passphrase: &str,
+) -> Result<MainPubkey> {
+    Ok(account_wallet_secret_key(mnemonic, passphrase)?.main_pubkey())
+}
+
+/// Derive a wallet secret key from raw entropy, e.g., as produced by a hardware wallet, without going through a
+/// mnemonic phrase. The key is the one derived from the mnemonic encoding that entropy, see
+/// `account_wallet_secret_key`. The entropy must be 16, 20, 24, 28 or 32 bytes long.
+pub fn main_sk_from_entropy(entropy: &[u8], passphrase: &str) -> Result<MainSecretKey> {
+    if !VALID_ENTROPY_LENGTHS.contains(&entropy.len()) {
+        return Err(SecretsError::InvalidEntropyLength(entropy.len()));
+    }
+    let mnemonic = mnemonic_from_entropy(entropy)?;
+    account_wallet_secret_key(mnemonic, passphrase)
+}
+
+/// Derive a wallet secret key from the mnemonic for the account, where `None` explicitly means that no passphrase is
+/// used. As per BIP39, having no passphrase is the same as using the empty passphrase, so `None` and `Some("")` derive
+/// the same key.
+///
+/// This is not to be confused with `acc_packet::secret_key_from_mnemonic`, which falls back to a default passphrase
+/// when none is provided.
+pub fn account_wallet_secret_key_optional_passphrase(
+    mnemonic: bip39::Mnemonic,
+    passphrase: Option<&str>,
+) -> Result<MainSecretKey> {
+    account_wallet_secret_key(mnemonic, passphrase.unwrap_or_default())
+}
+
+/// Derive the wallet secret key of the `account_index`th account from the mnemonic, so that a single mnemonic can
+/// hold multiple independent wallets. The account 0 is the one returned by `account_wallet_secret_key`.
+pub fn account_wallet_secret_key_indexed(
+    mnemonic: bip39::Mnemonic,
+    passphrase: &str,
+    account_index: u32,
+) -> Result<MainSecretKey> {
+    let derivation_path = DerivationPath(format!("{ACCOUNT_WALLET_DERIVATION}/{account_index}"));
+    main_sk_from_mnemonic_with_path(mnemonic, passphrase, &derivation_path)
+}
+
+/// Derive a wallet secret key from the mnemonic at the provided derivation path, e.g., to match the derivation scheme
+/// of another wallet. `DerivationPath::default()` derives the key returned by `account_wallet_secret_key`.
+///
+/// Any other path derives a different key, unrelated to the default one: the funds held by the wallet of one path
+/// cannot be spent with the key of another.
+pub fn main_sk_from_mnemonic_with_path(
+    mnemonic: bip39::Mnemonic,
+    passphrase: &str,
+    path: &DerivationPath,
+) -> Result<MainSecretKey> {
+    let key_bytes = derive_key_bytes(mnemonic.to_seed(passphrase), path.as_str())?;
+    main_sk_from_key_bytes(&key_bytes)
+}
+
+/// Derive the wallet secret keys at each of the derivation paths, e.g., `m/2/7` for the wallet of the account 7, in
+/// parallel. The keys are returned in the order of the paths. The paths are all checked before deriving any key, the
+/// first invalid one failing the whole batch.
+pub fn batch_main_sk(
+    mnemonic: bip39::Mnemonic,
+    passphrase: &str,
+    derivations: &[String],
+) -> Result<Vec<MainSecretKey>> {
+    if let Some((index, path)) = derivations
+        .iter()
+        .enumerate()
+        .find(|(_index, path)| eip2333::path_to_node(path).is_err())
+    {
+        return Err(SecretsError::InvalidDerivationPath {
+            index,
+            path: path.clone(),
+        });
+    }
 
-    let root_sk =
-        eip2333::derive_master_sk(&seed).map_err(|_err| Error::InvalidMnemonicSeedPhrase)?;
-    let derived_key = eip2333::derive_child_sk(root_sk, ACCOUNT_WALLET_DERIVATION);
-    let key_bytes = derived_key.serialize();
-    let sk = SecretKey::from_bytes(key_bytes.into()).map_err(|_err| Error::InvalidKeyBytes)?;
+    // The seed is costly to compute, hence shared by all the derivations.
+    let seed = Zeroizing::new(mnemonic.to_seed(passphrase));
+    batch_main_sk_from_seed(&*seed, derivations)
+}
+
+/// Derive the wallet secret keys of a batch from a seed, a failure carrying the index of its derivation path.
+fn batch_main_sk_from_seed<S: AsRef<[u8]> + Zeroize + Clone + Sync>(
+    seed: &S,
+    derivations: &[String],
+) -> Result<Vec<MainSecretKey>> {
+    derivations
+        .par_iter()
+        .enumerate()
+        .map(|(index, derivation_path)| {
+            derive_key_bytes(seed.clone(), derivation_path)
+                .and_then(|key_bytes| main_sk_from_key_bytes(&key_bytes))
+                .map_err(|source| SecretsError::BatchDerivation {
+                    index,
+                    source: Box::new(source),
+                })
+        })
+        .collect()
+}
+
+/// Checks that the mnemonic and passphrase derive the `expected` wallet secret key, e.g., to confirm that a user has
+/// typed their mnemonic correctly during a recovery. The keys are compared in constant time, so the duration of the
+/// check does not reveal how much of the derived key matches. A mnemonic that fails to derive a key does not match.
+pub fn verify_mnemonic_matches(
+    mnemonic: bip39::Mnemonic,
+    passphrase: &str,
+    expected: &MainSecretKey,
+) -> bool {
+    let Ok(derived) = account_wallet_secret_key(mnemonic, passphrase) else {
+        return false;
+    };
+    let derived_bytes = Zeroizing::new(derived.to_bytes());
+    let expected_bytes = Zeroizing::new(expected.to_bytes());
+    derived_bytes.ct_eq(&expected_bytes).into()
+}
+
+/// Encode the wallet secret key as hex, e.g., to persist it once derived. The returned string is as sensitive as the key
+/// itself.
+pub fn main_sk_to_hex(main_sk: &MainSecretKey) -> String {
+    let bytes = Zeroizing::new(main_sk.to_bytes());
+    hex::encode(bytes.as_slice())
+}
+
+/// Decode a wallet secret key encoded by `main_sk_to_hex`. The hex must be well formed and encode 32 bytes.
+pub fn main_sk_from_hex(hex: &str) -> Result<MainSecretKey> {
+    let bytes = Zeroizing::new(hex::decode(hex).map_err(SecretsError::InvalidSecretKeyHex)?);
+    let mut key_bytes = Zeroizing::new([0u8; bls::SK_SIZE]);
+    if bytes.len() != key_bytes.len() {
+        return Err(SecretsError::InvalidSecretKeyLength(bytes.len()));
+    }
+    key_bytes.copy_from_slice(&bytes);
+    let sk = SecretKey::from_bytes(*key_bytes).map_err(SecretsError::InvalidKeyBytes)?;
     Ok(MainSecretKey::new(sk))
 }
 
 #[expect(dead_code)] // as yet unused, will be used soon
 /// Derive an xorname from the mnemonic for the account to store data.
 pub(crate) fn account_root_xorname(mnemonic: bip39::Mnemonic, passphrase: &str) -> Result<XorName> {
-    let seed = mnemonic.to_seed(passphrase);
+    let derived_key_bytes = derive_key_bytes(
+        mnemonic.to_seed(passphrase),
+        ACCOUNT_ROOT_XORNAME_DERIVATION,
+    )?;
+    Ok(XorName::from_content(derived_key_bytes.as_slice()))
+}
+
+/// The wallet secret key of the bytes of a derived key.
+fn main_sk_from_key_bytes(key_bytes: &[u8; 32]) -> Result<MainSecretKey> {
+    let sk = SecretKey::from_bytes(*key_bytes).map_err(SecretsError::Derivation)?;
+    Ok(MainSecretKey::new(sk))
+}
+
+/// Derive the bytes of the child key at `derivation_path` from the seed of a mnemonic. The seed, and the keys derived
+/// along the way, are zeroed once done with, whether the derivation succeeds or not.
+fn derive_key_bytes<S: AsRef<[u8]> + Zeroize>(
+    seed: S,
+    derivation_path: &str,
+) -> Result<Zeroizing<[u8; 32]>> {
+    let seed = Zeroizing::new(seed);
 
-    let root_sk =
-        eip2333::derive_master_sk(&seed).map_err(|_err| Error::InvalidMnemonicSeedPhrase)?;
-    let derived_key = eip2333::derive_child_sk(root_sk, ACCOUNT_ROOT_XORNAME_DERIVATION);
-    let derived_key_bytes = derived_key.serialize();
-    Ok(XorName::from_content(&derived_key_bytes))
+    // The eip2333 keys zero themselves on drop. The master key can only fail to be derived from a short seed.
+    let root_sk = eip2333::derive_master_sk(seed.as_ref())
+        .map_err(|_err| SecretsError::SeedTooShort(seed.as_ref().len()))?;
+    let derived_key = eip2333::derive_child_sk(root_sk, derivation_path);
+    let mut serialized = derived_key.serialize();
+    let mut key_bytes = Zeroizing::new([0u8; 32]);
+    key_bytes.copy_from_slice(&serialized);
+    serialized.as_mut_slice().zeroize();
+    Ok(key_bytes)
 }
 
-pub fn write_mnemonic_to_disk(files_dir: &Path, mnemonic: &bip39::Mnemonic) -> Result<()> {
+pub fn write_mnemonic_to_disk(files_dir: &Path, mnemonic: &bip39::Mnemonic) -> Result<(), Error> {
     let filename = files_dir.join(MNEMONIC_FILENAME);
-    let content = mnemonic.to_string();
-    std::fs::write(filename, content)?;
+    let content = Zeroizing::new(mnemonic.to_string());
+    std::fs::write(filename, content.as_bytes())?;
     Ok(())
 }
 
-pub(super) fn read_mnemonic_from_disk(files_dir: &Path) -> Result<bip39::Mnemonic> {
+pub(super) fn read_mnemonic_from_disk(files_dir: &Path) -> Result<bip39::Mnemonic, Error> {
     let filename = files_dir.join(MNEMONIC_FILENAME);
-    let content = std::fs::read_to_string(filename)?;
-    let mnemonic =
-        bip39::Mnemonic::parse_normalized(&content).map_err(|_err| Error::FailedToParseMnemonic)?;
-    Ok(mnemonic)
+    let content = Zeroizing::new(std::fs::read_to_string(filename)?);
+    Ok(mnemonic_from_phrase(&content)?)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use assert_matches::assert_matches;
+    use std::{cell::Cell, rc::Rc};
+
+    const VALID_PHRASE: &str = "abandon abandon abandon abandon abandon abandon abandon abandon abandon abandon abandon about";
+
+    #[test]
+    fn valid_mnemonic_phrase_is_imported() -> Result<()> {
+        let mnemonic = mnemonic_from_phrase(VALID_PHRASE)?;
+        assert_eq!(mnemonic.to_string(), VALID_PHRASE);
+
+        let untidy_phrase = format!("  {}\n", VALID_PHRASE.to_uppercase().replace(' ', " \t "));
+        assert_eq!(mnemonic_from_phrase(&untidy_phrase)?, mnemonic);
+        Ok(())
+    }
+
+    #[test]
+    fn invalid_mnemonic_phrases_are_rejected() {
+        let wrong_word = VALID_PHRASE.replacen("abandon", "abandonn", 1);
+        assert_matches!(
+            mnemonic_from_phrase(&wrong_word),
+            Err(SecretsError::InvalidMnemonic(InvalidMnemonic::UnknownWord { index: 0, word }))
+                if word == "abandonn"
+        );
+
+        // "about" is the only valid last word after 11 "abandon"
+        let broken_checksum = VALID_PHRASE.replace("about", "abandon");
+        assert_matches!(
+            mnemonic_from_phrase(&broken_checksum),
+            Err(SecretsError::InvalidMnemonic(
+                InvalidMnemonic::InvalidChecksum
+            ))
+        );
+
+        let missing_word = VALID_PHRASE.replacen("abandon ", "", 1);
+        assert_matches!(
+            mnemonic_from_phrase(&missing_word),
+            Err(SecretsError::InvalidMnemonic(
+                InvalidMnemonic::BadWordCount(11)
+            ))
+        );
+    }
+
+    #[test]
+    fn unsupported_entropy_is_a_bip39_error() {
+        assert_matches!(
+            mnemonic_from_entropy(&[0; 3]),
+            Err(SecretsError::Bip39(bip39::Error::BadEntropyBitCount(24)))
+        );
+    }
+
+    #[test]
+    fn bytes_out_of_the_scalar_field_are_a_derivation_error() {
+        assert_matches!(
+            main_sk_from_key_bytes(&[u8::MAX; 32]).map(|sk| sk.main_pubkey()),
+            Err(SecretsError::Derivation(_))
+        );
+    }
+
+    #[test]
+    fn secrets_errors_convert_into_client_errors_and_eyre_reports() {
+        fn parse(phrase: &str) -> eyre::Result<bip39::Mnemonic> {
+            Ok(mnemonic_from_phrase(phrase)?)
+        }
+
+        let report = parse("abandon about").expect_err("the phrase is too short");
+        assert_matches!(
+            report.downcast_ref::<SecretsError>(),
+            Some(SecretsError::InvalidMnemonic(
+                InvalidMnemonic::BadWordCount(2)
+            ))
+        );
+
+        let error = Error::from(SecretsError::SeedTooShort(16));
+        assert_matches!(error, Error::Secrets(SecretsError::SeedTooShort(16)));
+    }
+
+    #[test]
+    fn mnemonic_has_the_requested_word_count() -> Result<()> {
+        assert_eq!(random_eip2333_mnemonic()?.word_count(), 24);
+
+        for word_count in [
+            MnemonicWordCount::Twelve,
+            MnemonicWordCount::Eighteen,
+            MnemonicWordCount::TwentyFour,
+        ] {
+            let mnemonic = random_eip2333_mnemonic_with_word_count(word_count)?;
+            assert_eq!(mnemonic.word_count(), word_count.words());
+
+            // the phrase can be restored and derives the same wallet
+            let restored = mnemonic_from_phrase(&mnemonic.to_string())?;
+            assert_eq!(
+                account_wallet_secret_key(restored, "")?.main_pubkey(),
+                account_wallet_secret_key(mnemonic, "")?.main_pubkey()
+            );
+        }
+        Ok(())
+    }
+
+    #[test]
+    fn indexed_account_wallets_are_distinct_and_deterministic() -> Result<()> {
+        let mnemonic = random_eip2333_mnemonic()?;
+        let passphrase = "passphrase";
+
+        // the account 0 is the historical `m/2/0` wallet
+        let default_sk = account_wallet_secret_key(mnemonic.clone(), passphrase)?;
+        let first_sk = account_wallet_secret_key_indexed(mnemonic.clone(), passphrase, 0)?;
+        assert_eq!(default_sk.main_pubkey(), first_sk.main_pubkey());
+
+        let pubkeys = (0..3)
+            .map(|index| {
+                account_wallet_secret_key_indexed(mnemonic.clone(), passphrase, index)
+                    .map(|sk| sk.main_pubkey())
+            })
+            .collect::<Result<Vec<_>>>()?;
+        assert_ne!(pubkeys[0], pubkeys[1]);
+        assert_ne!(pubkeys[1], pubkeys[2]);
+        assert_ne!(pubkeys[0], pubkeys[2]);
+
+        let second_sk = account_wallet_secret_key_indexed(mnemonic.clone(), passphrase, 1)?;
+        assert_eq!(second_sk.main_pubkey(), pubkeys[1]);
+
+        // the passphrase is still part of the derivation
+        let other_sk = account_wallet_secret_key_indexed(mnemonic, "other passphrase", 1)?;
+        assert_ne!(other_sk.main_pubkey(), pubkeys[1]);
+        Ok(())
+    }
+
+    #[test]
+    fn wallet_seed_derives_the_same_keys_as_the_standalone_functions() -> Result<()> {
+        let passphrase = "passphrase";
+        let seed = WalletSeed::from_phrase(VALID_PHRASE)?;
+        assert_eq!(seed.mnemonic().to_string(), VALID_PHRASE);
+        assert_eq!(
+            seed.main_sk(passphrase)?.main_pubkey(),
+            account_wallet_secret_key(mnemonic_from_phrase(VALID_PHRASE)?, passphrase)?
+                .main_pubkey()
+        );
+        assert_matches!(
+            WalletSeed::from_phrase(&VALID_PHRASE.replace("about", "abandon")),
+            Err(SecretsError::InvalidMnemonic(
+                InvalidMnemonic::InvalidChecksum
+            ))
+        );
+
+        let generated = WalletSeed::generate()?;
+        assert_eq!(generated.mnemonic().word_count(), 24);
+        let main_sk = generated.main_sk(passphrase)?;
+        assert_eq!(
+            main_sk.main_pubkey(),
+            account_wallet_secret_key(generated.mnemonic().clone(), passphrase)?.main_pubkey()
+        );
+        // the key is derived again for each passphrase
+        assert_eq!(
+            generated.main_sk(passphrase)?.main_pubkey(),
+            main_sk.main_pubkey()
+        );
+        assert_ne!(generated.main_sk("")?.main_pubkey(), main_sk.main_pubkey());
+
+        // the backup can be restored
+        let restored = WalletSeed::from_phrase(&generated.mnemonic().to_string())?;
+        assert_eq!(
+            restored.main_sk(passphrase)?.main_pubkey(),
+            main_sk.main_pubkey()
+        );
+        assert!(!format!("{generated:?}").contains(&generated.mnemonic().to_string()));
+        Ok(())
+    }
+
+    #[test]
+    fn main_pubkey_is_deterministic_and_matches_the_secret_key() -> Result<()> {
+        let passphrase = "passphrase";
+        let mnemonic = mnemonic_from_phrase(VALID_PHRASE)?;
+        let pubkey = main_pubkey_from_mnemonic(mnemonic.clone(), passphrase)?;
+        assert_eq!(
+            main_pubkey_from_mnemonic(mnemonic.clone(), passphrase)?,
+            pubkey
+        );
+        assert_eq!(
+            account_wallet_secret_key(mnemonic.clone(), passphrase)?.main_pubkey(),
+            pubkey
+        );
+        assert_ne!(main_pubkey_from_mnemonic(mnemonic, "")?, pubkey);
+
+        let seed = WalletSeed::from_phrase(VALID_PHRASE)?;
+        assert_eq!(seed.main_pubkey(passphrase)?, pubkey);
+        Ok(())
+    }
+
+    #[test]
+    fn entropy_derives_the_same_key_as_its_mnemonic() -> Result<()> {
+        let passphrase = "passphrase";
+        // The valid phrase encodes 16 zero bytes.
+        assert_eq!(
+            main_sk_from_entropy(&[0; 16], passphrase)?.main_pubkey(),
+            account_wallet_secret_key(mnemonic_from_phrase(VALID_PHRASE)?, passphrase)?
+                .main_pubkey()
+        );
+
+        let rng = &mut rand::rngs::OsRng;
+        for length in VALID_ENTROPY_LENGTHS {
+            let mut entropy = vec![0u8; length];
+            rng.fill_bytes(&mut entropy);
+            let mnemonic = mnemonic_from_entropy(&entropy)?;
+            assert_eq!(
+                main_sk_from_entropy(&entropy, passphrase)?.main_pubkey(),
+                account_wallet_secret_key(mnemonic, passphrase)?.main_pubkey()
+            );
+        }
+
+        for length in [0, 15, 17, 31, 33, 64] {
+            assert_matches!(
+                main_sk_from_entropy(&vec![0; length], passphrase).map(|sk| sk.main_pubkey()),
+                Err(SecretsError::InvalidEntropyLength(bad)) if bad == length
+            );
+        }
+        Ok(())
+    }
+
+    #[test]
+    fn no_passphrase_is_the_empty_passphrase() -> Result<(), Error> {
+        let mnemonic = mnemonic_from_phrase(VALID_PHRASE)?;
+        let no_passphrase =
+            account_wallet_secret_key_optional_passphrase(mnemonic.clone(), None)?.main_pubkey();
+        let empty_passphrase =
+            account_wallet_secret_key_optional_passphrase(mnemonic.clone(), Some(""))?
+                .main_pubkey();
+        assert_eq!(no_passphrase, empty_passphrase);
+        assert_eq!(
+            no_passphrase,
+            account_wallet_secret_key(mnemonic.clone(), "")?.main_pubkey()
+        );
+
+        let passphrase =
+            account_wallet_secret_key_optional_passphrase(mnemonic.clone(), Some("passphrase"))?
+                .main_pubkey();
+        assert_eq!(
+            passphrase,
+            account_wallet_secret_key(mnemonic.clone(), "passphrase")?.main_pubkey()
+        );
+        assert_ne!(passphrase, no_passphrase);
+
+        // the account packet falls back to its default passphrase instead
+        let account_packet_default =
+            crate::acc_packet::secret_key_from_mnemonic(mnemonic, None)?.main_pubkey();
+        assert_ne!(account_packet_default, no_passphrase);
+        Ok(())
+    }
+
+    #[test]
+    fn batch_derivation_matches_the_serial_one() -> Result<()> {
+        let mnemonic = mnemonic_from_phrase(VALID_PHRASE)?;
+        let passphrase = "passphrase";
+        let derivations = (0..24)
+            .map(|index| format!("{ACCOUNT_WALLET_DERIVATION}/{index}"))
+            .collect::<Vec<_>>();
+
+        let batch = batch_main_sk(mnemonic.clone(), passphrase, &derivations)?;
+        assert_eq!(batch.len(), derivations.len());
+        for (index, sk) in batch.iter().enumerate() {
+            let serial =
+                account_wallet_secret_key_indexed(mnemonic.clone(), passphrase, index as u32)?;
+            assert_eq!(sk.main_pubkey(), serial.main_pubkey());
+        }
+        assert!(batch_main_sk(mnemonic.clone(), passphrase, &[])?.is_empty());
+
+        let mut invalid = derivations.clone();
+        invalid[3] = "m/2/three".to_string();
+        invalid[7] = "2/7".to_string();
+        assert_matches!(
+            batch_main_sk(mnemonic, passphrase, &invalid).map(|keys| keys.len()),
+            Err(SecretsError::InvalidDerivationPath { index: 3, path }) if path == "m/2/three"
+        );
+
+        // a failed derivation tells which path it was for
+        let short_seed = [0u8; 16];
+        assert_matches!(
+            batch_main_sk_from_seed(&short_seed, &derivations[..1]).map(|keys| keys.len()),
+            Err(SecretsError::BatchDerivation { index: 0, source })
+                if matches!(*source, SecretsError::SeedTooShort(16))
+        );
+        let failure = batch_main_sk_from_seed(&short_seed, &derivations)
+            .map(|keys| keys.len())
+            .expect_err("the seed is too short");
+        assert_matches!(
+            failure,
+            SecretsError::BatchDerivation { index, .. } if index < derivations.len()
+        );
+        Ok(())
+    }
+
+    #[test]
+    fn mnemonic_is_verified_against_the_wallet_key() -> Result<()> {
+        let mnemonic = mnemonic_from_phrase(VALID_PHRASE)?;
+        let passphrase = "passphrase";
+        let expected = account_wallet_secret_key(mnemonic.clone(), passphrase)?;
+        assert!(verify_mnemonic_matches(
+            mnemonic.clone(),
+            passphrase,
+            &expected
+        ));
+
+        // a different passphrase, mnemonic, or account does not match
+        assert!(!verify_mnemonic_matches(
+            mnemonic.clone(),
+            "other passphrase",
+            &expected
+        ));
+        assert!(!verify_mnemonic_matches(
+            random_eip2333_mnemonic()?,
+            passphrase,
+            &expected
+        ));
+        let other_account = account_wallet_secret_key_indexed(mnemonic.clone(), passphrase, 1)?;
+        assert!(!verify_mnemonic_matches(
+            mnemonic,
+            passphrase,
+            &other_account
+        ));
+        Ok(())
+    }
+
+    /// A seed that counts how many times it has been zeroed.
+    struct CountingSeed {
+        bytes: Vec<u8>,
+        zeroized: Rc<Cell<usize>>,
+    }
+
+    impl CountingSeed {
+        fn new(bytes: &[u8]) -> (Self, Rc<Cell<usize>>) {
+            let zeroized = Rc::new(Cell::new(0));
+            let seed = Self {
+                bytes: bytes.to_vec(),
+                zeroized: Rc::clone(&zeroized),
+            };
+            (seed, zeroized)
+        }
+    }
+
+    impl AsRef<[u8]> for CountingSeed {
+        fn as_ref(&self) -> &[u8] {
+            &self.bytes
+        }
+    }
+
+    impl Zeroize for CountingSeed {
+        fn zeroize(&mut self) {
+            self.bytes.zeroize();
+            self.zeroized.set(self.zeroized.get() + 1);
+        }
+    }
+
+    #[test]
+    fn default_derivation_path_derives_the_account_wallet_key() -> Result<()> {
+        let mnemonic = mnemonic_from_phrase(VALID_PHRASE)?;
+        let default_sk = main_sk_from_mnemonic_with_path(
+            mnemonic.clone(),
+            "passphrase",
+            &DerivationPath::default(),
+        )?;
+        assert_eq!(
+            default_sk.to_bytes(),
+            account_wallet_secret_key(mnemonic.clone(), "passphrase")?.to_bytes()
+        );
+
+        let other_sk = main_sk_from_mnemonic_with_path(
+            mnemonic,
+            "passphrase",
+            &DerivationPath::new("m/44/0")?,
+        )?;
+        assert_ne!(other_sk.to_bytes(), default_sk.to_bytes());
+        assert_matches!(
+            DerivationPath::new("m/2/zero"),
+            Err(SecretsError::MalformedDerivationPath(path)) if path == "m/2/zero"
+        );
+        Ok(())
+    }
+
+    #[test]
+    fn main_sk_round_trips_through_hex() -> Result<()> {
+        let mnemonic = mnemonic_from_phrase(VALID_PHRASE)?;
+        let main_sk = account_wallet_secret_key(mnemonic, "")?;
+        let hex = main_sk_to_hex(&main_sk);
+        assert_eq!(hex.len(), 64);
+        assert_eq!(main_sk_from_hex(&hex)?.to_bytes(), main_sk.to_bytes());
+
+        let random_sk = MainSecretKey::random();
+        let decoded = main_sk_from_hex(&main_sk_to_hex(&random_sk))?;
+        assert_eq!(decoded.main_pubkey(), random_sk.main_pubkey());
+        Ok(())
+    }
+
+    #[test]
+    fn malformed_main_sk_hex_is_rejected() {
+        let hex = main_sk_to_hex(&MainSecretKey::random());
+        assert_matches!(
+            main_sk_from_hex(&hex[1..]).map(|sk| sk.main_pubkey()),
+            Err(SecretsError::InvalidSecretKeyHex(
+                hex::FromHexError::OddLength
+            ))
+        );
+        assert_matches!(
+            main_sk_from_hex(&format!("zz{}", &hex[2..])).map(|sk| sk.main_pubkey()),
+            Err(SecretsError::InvalidSecretKeyHex(
+                hex::FromHexError::InvalidHexCharacter { c: 'z', index: 0 }
+            ))
+        );
+        assert_matches!(
+            main_sk_from_hex(&hex[2..]).map(|sk| sk.main_pubkey()),
+            Err(SecretsError::InvalidSecretKeyLength(31))
+        );
+        // A value beyond the order of the curve is not a valid key.
+        assert_matches!(
+            main_sk_from_hex(&"ff".repeat(32)).map(|sk| sk.main_pubkey()),
+            Err(SecretsError::InvalidKeyBytes(_))
+        );
+    }
+
+    #[test]
+    fn seed_is_zeroized_after_derivation() -> Result<()> {
+        let mnemonic = mnemonic_from_phrase(VALID_PHRASE)?;
+        let (seed, zeroized) = CountingSeed::new(&mnemonic.to_seed(""));
+
+        let key_bytes = derive_key_bytes(seed, &format!("{ACCOUNT_WALLET_DERIVATION}/0"))?;
+        assert_eq!(zeroized.get(), 1);
+        // the derivation is unaffected by the zeroing
+        assert_eq!(
+            main_sk_from_key_bytes(&key_bytes)?.main_pubkey(),
+            account_wallet_secret_key(mnemonic, "")?.main_pubkey()
+        );
+
+        // the seed is zeroized on the error path as well
+        let (short_seed, zeroized) = CountingSeed::new(&[0; 16]);
+        assert_matches!(
+            derive_key_bytes(short_seed, ACCOUNT_ROOT_XORNAME_DERIVATION),
+            Err(SecretsError::SeedTooShort(16))
+        );
+        assert_eq!(zeroized.get(), 1);
+        Ok(())
+    }
 }
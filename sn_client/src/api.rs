@@ -21,7 +21,8 @@ use rand::{thread_rng, Rng};
 use sn_networking::{
     get_signed_spend_from_record, multiaddr_is_global,
     target_arch::{interval, spawn, timeout, Instant},
-    GetRecordCfg, NetworkBuilder, NetworkError, NetworkEvent, PutRecordCfg, VerificationKind,
+    GetRecordCfg, MajorityRecord, NetworkBuilder, NetworkError, NetworkEvent, PutRecordCfg,
+    VerificationKind, VerifyAfterPutCfg,
 };
 use sn_protocol::{
     error::Error as ProtocolError,
@@ -446,8 +447,8 @@ impl Client {
     ) -> Result<SignedRegister> {
         let key = NetworkAddress::from_register_address(address).to_record_key();
 
-        let maybe_records = self.network.get_register_record_from_network(key).await?;
-        merge_register_records(address, &maybe_records)
+        let copies = self.network.get_register_record_from_network(key).await?;
+        resolve_register_records(address, &copies)
     }
 
     /// Retrieve a Register from the network.
@@ -614,10 +615,12 @@ impl Client {
         let verification = if verify_store {
             let verification_cfg = GetRecordCfg {
                 get_quorum: Quorum::N(QUORUM_N_IS_2),
+                resolution: Default::default(),
                 retry_strategy,
                 target_record: None, // Not used since we use ChunkProof
                 expected_holders: Default::default(),
                 is_register: false,
+                bypass_cache: false,
             };
             // The `ChunkWithPayment` is only used to send out via PutRecord.
             // The holders shall only hold the `Chunk` copies.
@@ -642,6 +645,7 @@ impl Client {
             retry_strategy,
             use_put_record_to: Some(vec![payee]),
             verification,
+            verify_after_put: verify_store.then(VerifyAfterPutCfg::default),
         };
         Ok(self.network.put_record(record, &put_cfg).await?)
     }
@@ -700,10 +704,12 @@ impl Client {
 
         let get_cfg = GetRecordCfg {
             get_quorum: Quorum::One,
+            resolution: Default::default(),
             retry_strategy: Some(retry_strategy.unwrap_or(RetryStrategy::Quick)),
             target_record: None,
             expected_holders,
             is_register: false,
+            bypass_cache: false,
         };
         let record = self.network.get_record_from_network(key, &get_cfg).await?;
         let header = RecordHeader::from_record(&record)?;
@@ -855,16 +861,19 @@ impl Client {
         // When there is retry on Put side, no need to have a retry on Get
         let verification_cfg = GetRecordCfg {
             get_quorum: Quorum::Majority,
+            resolution: Default::default(),
             retry_strategy: None,
             target_record: record_to_verify,
             expected_holders,
             is_register: false,
+            bypass_cache: false,
         };
         let put_cfg = PutRecordCfg {
             put_quorum: Quorum::Majority,
             retry_strategy: Some(RetryStrategy::Persistent),
             use_put_record_to: None,
             verification: Some((VerificationKind::Network, verification_cfg)),
+            verify_after_put: None,
         };
         Ok(self.network.put_record(record, &put_cfg).await?)
     }
@@ -903,10 +912,12 @@ impl Client {
             address,
             GetRecordCfg {
                 get_quorum: Quorum::Majority,
+                resolution: Default::default(),
                 retry_strategy: Some(RetryStrategy::Balanced),
                 target_record: None,
                 expected_holders: Default::default(),
                 is_register: false,
+                bypass_cache: false,
             },
         )
         .await
@@ -920,10 +931,12 @@ impl Client {
             address,
             GetRecordCfg {
                 get_quorum: Quorum::One,
+                resolution: Default::default(),
                 retry_strategy: None,
                 target_record: None,
                 expected_holders: Default::default(),
                 is_register: false,
+                bypass_cache: false,
             },
         )
         .await
@@ -936,10 +949,12 @@ impl Client {
             address,
             GetRecordCfg {
                 get_quorum: Quorum::Majority,
+                resolution: Default::default(),
                 retry_strategy: None,
                 target_record: None,
                 expected_holders: Default::default(),
                 is_register: false,
+                bypass_cache: false,
             },
         )
         .await
@@ -1059,6 +1074,34 @@ fn get_register_from_record(record: &Record) -> Result<SignedRegister> {
 }
 
 /// if multiple register records where found for a given key, merge them into a single register
+/// Picks the copy of the register held by the majority of its holders. Without a majority, or if the majority copy
+/// is invalid, all the copies are merged instead.
+fn resolve_register_records(
+    address: RegisterAddress,
+    copies: &HashMap<XorName, (Record, HashSet<PeerId>)>,
+) -> Result<SignedRegister> {
+    if let Some(majority) = MajorityRecord::from_copies(copies) {
+        if !majority.dissenting_holders.is_empty() {
+            warn!(
+                "Picking the majority copy of register {address}, dissenting holders: {:?}",
+                majority.dissenting_holders
+            );
+        }
+        let content_hash = XorName::from_content(&majority.record.value);
+        match merge_register_records(address, &HashMap::from([(content_hash, majority.record)])) {
+            Ok(register) => return Ok(register),
+            Err(err) => {
+                warn!("The majority copy of register {address} is invalid, merging all the copies instead: {err:?}");
+            }
+        }
+    }
+    let all_copies = copies
+        .iter()
+        .map(|(content_hash, (record, _holders))| (*content_hash, record.clone()))
+        .collect();
+    merge_register_records(address, &all_copies)
+}
+
 fn merge_register_records(
     address: RegisterAddress,
     map: &HashMap<XorName, Record>,
@@ -1109,8 +1152,14 @@ mod tests {
 
     use super::*;
 
-    #[test]
-    fn test_merge_register_records() -> eyre::Result<()> {
+    /// Two valid diverging copies of a register, along with a copy with an invalid signature.
+    #[allow(clippy::type_complexity)]
+    fn diverging_register_records() -> eyre::Result<(
+        RegisterAddress,
+        (SignedRegister, Record),
+        (SignedRegister, Record),
+        Record,
+    )> {
         let mut rng = rand::thread_rng();
         let meta = XorName::random(&mut rng);
         let owner_sk = SecretKey::random();
@@ -1147,20 +1196,33 @@ mod tests {
             publisher: None,
             expires: None,
         };
-        let xorname1 = XorName::from_content(&record1.value);
         let record2 = Record {
             key: NetworkAddress::from_register_address(address).to_record_key(),
             value: try_serialize_record(&signed_register2, RecordKind::Register)?.to_vec(),
             publisher: None,
             expires: None,
         };
-        let xorname2 = XorName::from_content(&record2.value);
         let record_bad = Record {
             key: NetworkAddress::from_register_address(address).to_record_key(),
             value: try_serialize_record(&signed_register_bad, RecordKind::Register)?.to_vec(),
             publisher: None,
             expires: None,
         };
+
+        Ok((
+            address,
+            (signed_register1, record1),
+            (signed_register2, record2),
+            record_bad,
+        ))
+    }
+
+    #[test]
+    fn test_merge_register_records() -> eyre::Result<()> {
+        let (address, (signed_register1, record1), (signed_register2, record2), record_bad) =
+            diverging_register_records()?;
+        let xorname1 = XorName::from_content(&record1.value);
+        let xorname2 = XorName::from_content(&record2.value);
         let xorname_bad = XorName::from_content(&record_bad.value);
 
         // test with 2 valid records: should return the two merged
@@ -1185,4 +1247,46 @@ mod tests {
 
         Ok(())
     }
+
+    #[test]
+    fn register_copy_held_by_the_majority_is_picked() -> eyre::Result<()> {
+        let (address, (signed_register1, record1), (signed_register2, record2), record_bad) =
+            diverging_register_records()?;
+        let holders = |count| (0..count).map(|_| PeerId::random()).collect::<HashSet<_>>();
+        let copies = |held: Vec<(&Record, HashSet<PeerId>)>| {
+            held.into_iter()
+                .map(|(record, holders)| {
+                    (
+                        XorName::from_content(&record.value),
+                        (record.clone(), holders),
+                    )
+                })
+                .collect::<HashMap<_, _>>()
+        };
+
+        // The minority copy is left out, rather than merged.
+        let reg = resolve_register_records(
+            address,
+            &copies(vec![(&record1, holders(3)), (&record2, holders(2))]),
+        )?;
+        assert_eq!(reg, signed_register1);
+
+        // Without a majority, the copies are merged.
+        let mut expected_merge = signed_register1.clone();
+        expected_merge.merge(&signed_register2)?;
+        let reg = resolve_register_records(
+            address,
+            &copies(vec![(&record1, holders(2)), (&record2, holders(2))]),
+        )?;
+        assert_eq!(reg, expected_merge);
+
+        // An invalid majority copy is skipped as well.
+        let reg = resolve_register_records(
+            address,
+            &copies(vec![(&record_bad, holders(4)), (&record2, holders(1))]),
+        )?;
+        assert_eq!(reg, signed_register2);
+
+        Ok(())
+    }
 }
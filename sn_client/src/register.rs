@@ -841,16 +841,19 @@ impl ClientRegister {
 
         let verification_cfg = GetRecordCfg {
             get_quorum: Quorum::One,
+            resolution: Default::default(),
             retry_strategy: Some(RetryStrategy::Quick),
             target_record: record_to_verify,
             expected_holders,
             is_register: true,
+            bypass_cache: false,
         };
         let put_cfg = PutRecordCfg {
             put_quorum: Quorum::All,
             retry_strategy: Some(RetryStrategy::Balanced),
             use_put_record_to: payee,
             verification: Some((VerificationKind::Network, verification_cfg)),
+            verify_after_put: None,
         };
 
         // Register edits might exist, so we cannot be sure that just because we get a record back that this should fail
@@ -0,0 +1,62 @@
+// Copyright 2024 MaidSafe.net limited.
+//
+// This SAFE Network Software is licensed to you under The General Public License (GPL), version 3.
+// Unless required by applicable law or agreed to in writing, the SAFE Network Software distributed
+// under the GPL Licence is distributed on an "AS IS" BASIS, WITHOUT WARRANTIES OR CONDITIONS OF ANY
+// KIND, either express or implied. Please review the Licences for the specific language governing
+// permissions and limitations relating to use of the SAFE Network Software.
+
+//! Compares the ways of computing the ilog2 bucket of the PeerIds generated as network discovery candidates: through
+//! a `NetworkAddress`, as the generation used to, or straight from the bytes of the PeerId, as it does now.
+
+#![allow(clippy::unwrap_used)]
+
+use criterion::{black_box, criterion_group, criterion_main, Criterion};
+use libp2p::{kad::KBucketKey, PeerId};
+use rand::RngCore;
+use sn_protocol::NetworkAddress;
+
+// The number of PeerIds generated by the initial population of the network discovery.
+const INITIAL_GENERATION_ATTEMPTS: usize = 10_000;
+
+fn via_network_address(self_key: &KBucketKey<PeerId>) -> usize {
+    (0..INITIAL_GENERATION_ATTEMPTS)
+        .filter_map(|_| {
+            let candidate = NetworkAddress::from_peer(PeerId::random());
+            candidate.as_kbucket_key().distance(self_key).ilog2()
+        })
+        .count()
+}
+
+fn via_peer_id_bytes(self_key: &KBucketKey<PeerId>) -> usize {
+    let mut rng = rand::thread_rng();
+    (0..INITIAL_GENERATION_ATTEMPTS)
+        .filter_map(|_| {
+            // The identity multihash of a random digest, as made by `PeerId::random`.
+            let mut bytes = [0; 34];
+            bytes[1] = 32;
+            rng.fill_bytes(&mut bytes[2..]);
+            KBucketKey::new(bytes).distance(self_key).ilog2()
+        })
+        .count()
+}
+
+fn bench_candidate_generation(c: &mut Criterion) {
+    let self_key = KBucketKey::from(PeerId::random());
+    let mut group = c.benchmark_group("network discovery candidate generation");
+    let _ = group.bench_function("via NetworkAddress", |b| {
+        b.iter(|| via_network_address(black_box(&self_key)))
+    });
+    let _ = group.bench_function("via PeerId bytes", |b| {
+        b.iter(|| via_peer_id_bytes(black_box(&self_key)))
+    });
+    group.finish();
+}
+
+criterion_group! {
+    name = network_discovery;
+    config = Criterion::default().sample_size(20);
+    targets = bench_candidate_generation
+}
+
+criterion_main!(network_discovery);
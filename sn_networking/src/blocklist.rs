@@ -0,0 +1,137 @@
+// Copyright 2024 MaidSafe.net limited.
+//
+// This SAFE Network Software is licensed to you under The General Public License (GPL), version 3.
+// Unless required by applicable law or agreed to in writing, the SAFE Network Software distributed
+// under the GPL Licence is distributed on an "AS IS" BASIS, WITHOUT WARRANTIES OR CONDITIONS OF ANY
+// KIND, either express or implied. Please review the Licences for the specific language governing
+// permissions and limitations relating to use of the SAFE Network Software.
+
+use libp2p::PeerId;
+use std::{
+    collections::BTreeSet,
+    fs,
+    path::{Path, PathBuf},
+    str::FromStr,
+};
+
+/// The file under the root dir of a node holding the peers blocked by its operator, one `PeerId` per line.
+pub(crate) const BLOCKED_PEERS_FILENAME: &str = "blocked_peers";
+
+/// The peers that the operator of the node asked never to talk to again. Unlike the bad nodes, which we detect
+/// ourselves and only block until a restart, these are persisted so that they stay blocked across restarts.
+#[derive(Debug, Default)]
+pub(crate) struct PeerBlocklist {
+    peers: BTreeSet<PeerId>,
+    // The file the blocked peers are persisted into, if any.
+    file: Option<PathBuf>,
+}
+
+impl PeerBlocklist {
+    /// Restores the blocked peers from the provided file, which is then kept up to date. A missing file yields an
+    /// empty blocklist, and the lines that are not a valid `PeerId` are skipped.
+    pub(crate) fn load(file: PathBuf) -> Self {
+        let peers = match fs::read_to_string(&file) {
+            Ok(content) => content
+                .lines()
+                .map(str::trim)
+                .filter(|line| !line.is_empty())
+                .filter_map(|line| match PeerId::from_str(line) {
+                    Ok(peer_id) => Some(peer_id),
+                    Err(err) => {
+                        warn!("Ignoring the invalid blocked peer {line:?} in {file:?}: {err}");
+                        None
+                    }
+                })
+                .collect(),
+            Err(_) => BTreeSet::new(),
+        };
+        if !peers.is_empty() {
+            info!("Restored {} blocked peers from {file:?}", peers.len());
+        }
+        Self {
+            peers,
+            file: Some(file),
+        }
+    }
+
+    /// Blocks the peer, returns false if it was already blocked.
+    pub(crate) fn block(&mut self, peer_id: PeerId) -> bool {
+        let newly_blocked = self.peers.insert(peer_id);
+        if newly_blocked {
+            self.persist();
+        }
+        newly_blocked
+    }
+
+    /// Unblocks the peer, returns false if it was not blocked.
+    pub(crate) fn unblock(&mut self, peer_id: &PeerId) -> bool {
+        let unblocked = self.peers.remove(peer_id);
+        if unblocked {
+            self.persist();
+        }
+        unblocked
+    }
+
+    pub(crate) fn contains(&self, peer_id: &PeerId) -> bool {
+        self.peers.contains(peer_id)
+    }
+
+    pub(crate) fn peers(&self) -> impl Iterator<Item = &PeerId> {
+        self.peers.iter()
+    }
+
+    fn persist(&self) {
+        if let Some(path) = &self.file {
+            if let Err(err) = self.write_to_disk(path) {
+                warn!("Failed to persist the blocked peers to {path:?}: {err}");
+            }
+        }
+    }
+
+    /// The file is written in full before replacing any existing one, so that a crash cannot leave a partially
+    /// written file behind.
+    fn write_to_disk(&self, path: &Path) -> std::io::Result<()> {
+        let content = self
+            .peers
+            .iter()
+            .map(|peer_id| format!("{peer_id}\n"))
+            .collect::<String>();
+        let tmp_path = path.with_extension("tmp");
+        fs::write(&tmp_path, content)?;
+        fs::rename(tmp_path, path)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn blocked_peers_survive_a_restart() -> eyre::Result<()> {
+        let dir = std::env::temp_dir().join(format!("blocklist-{}", uuid::Uuid::new_v4()));
+        fs::create_dir_all(&dir)?;
+        let path = dir.join(BLOCKED_PEERS_FILENAME);
+
+        let mut blocklist = PeerBlocklist::load(path.clone());
+        assert_eq!(blocklist.peers().count(), 0);
+        let (blocked, unblocked) = (PeerId::random(), PeerId::random());
+        assert!(blocklist.block(blocked));
+        assert!(blocklist.block(unblocked));
+        assert!(!blocklist.block(blocked));
+        assert!(blocklist.unblock(&unblocked));
+        assert!(!blocklist.unblock(&unblocked));
+
+        let restarted = PeerBlocklist::load(path.clone());
+        assert!(restarted.contains(&blocked));
+        assert!(!restarted.contains(&unblocked));
+        assert_eq!(restarted.peers().count(), 1);
+
+        // The operator may edit the file by hand, the invalid lines are skipped.
+        fs::write(&path, format!("not a peer id\n\n  {blocked}  \n"))?;
+        let edited = PeerBlocklist::load(path);
+        assert_eq!(edited.peers().collect::<Vec<_>>(), vec![&blocked]);
+
+        fs::remove_dir_all(dir)?;
+        Ok(())
+    }
+}
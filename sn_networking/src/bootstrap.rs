@@ -6,8 +6,16 @@
 // KIND, either express or implied. Please review the Licences for the specific language governing
 // permissions and limitations relating to use of the SAFE Network Software.
 
-use crate::{driver::PendingGetClosestType, SwarmDriver};
+#[cfg(not(target_arch = "wasm32"))]
+use crate::{cmd::LocalSwarmCmd, target_arch::spawn};
+use crate::{
+    driver::PendingGetClosestType,
+    network_discovery::{CandidateSelection, INITIAL_COVERAGE_TARGET},
+    SwarmDriver,
+};
 use rand::{rngs::OsRng, Rng};
+use sn_protocol::NetworkAddress;
+use std::collections::HashMap;
 use tokio::time::Duration;
 
 use crate::target_arch::{interval, Instant, Interval};
@@ -30,6 +38,28 @@ const LAST_BOOTSTRAP_TRIGGERED_TIME_LIMIT: Duration = Duration::from_secs(30);
 /// The bootstrap interval to use if we haven't added any new peers in a while.
 const NO_PEER_ADDED_SLOWDOWN_INTERVAL_MAX_S: u64 = 600;
 
+/// Once we have at least WELL_CONNECTED_PEERS_IN_RT peers in the routing table, the far buckets are well covered. So
+/// the network discovery queries are concentrated on the CLOSEST_BUCKETS_TO_DISCOVER nearest buckets.
+const WELL_CONNECTED_PEERS_IN_RT: usize = 100;
+const CLOSEST_BUCKETS_TO_DISCOVER: usize = 5;
+
+/// The network discovery candidates observed from real peers are dropped after this duration, as they might have gone
+/// offline since.
+const OBSERVED_CANDIDATE_MAX_AGE: Duration = Duration::from_secs(30 * 60);
+
+/// The rounds of network discovery triggered on demand within this duration of the previous one are dropped.
+const ON_DEMAND_DISCOVERY_DEBOUNCE: Duration = Duration::from_secs(5);
+
+/// The network discovery candidates are generated again at most once per this duration, however often the rounds of
+/// network discovery are triggered, e.g., on demand.
+const MIN_CANDIDATES_REFRESH_INTERVAL: Duration = Duration::from_secs(30);
+
+/// The interval at which a few network discovery candidates are queried, in between the rounds of network discovery.
+pub(crate) const NETWORK_DISCOVERY_QUERY_INTERVAL: Duration = Duration::from_secs(5);
+
+/// The number of network discovery candidates queried at every NETWORK_DISCOVERY_QUERY_INTERVAL.
+pub(crate) const QUERIES_PER_DISCOVERY_TICK: usize = 4;
+
 impl SwarmDriver {
     /// This functions triggers network discovery based on when the last peer was added to the RT and the number of
     /// peers in RT. The function also returns a new bootstrap interval that is proportional to the number of
@@ -50,24 +80,202 @@ impl SwarmDriver {
 
     pub(crate) fn trigger_network_discovery(&mut self) {
         let now = Instant::now();
-        // Fetches the candidates and also generates new candidates
-        for addr in self.network_discovery.candidates() {
-            // The query_id is tracked here. This is to update the candidate list of network_discovery with the newly
-            // found closest peers. It may fill up the candidate list of closer buckets which are harder to generate.
-            let query_id = self
-                .swarm
-                .behaviour_mut()
-                .kademlia
-                .get_closest_peers(addr.as_bytes());
-            let _ = self.pending_get_closest_peers.insert(
-                query_id,
-                (PendingGetClosestType::NetworkDiscovery, Default::default()),
+        // Generates new candidates and then fetches the closest ones
+        self.network_discovery
+            .prune_older_than(OBSERVED_CANDIDATE_MAX_AGE);
+        // Snapshot our RT, so that the queries are concentrated on the buckets that need more peers.
+        let bucket_fill: HashMap<u32, usize> = self
+            .swarm
+            .behaviour_mut()
+            .kademlia
+            .kbuckets()
+            .filter_map(|kbucket| {
+                let ilog2 = kbucket.range().0.ilog2()?;
+                Some((ilog2, kbucket.num_entries()))
+            })
+            .collect();
+        self.network_discovery.set_bucket_fill(bucket_fill);
+        if self.network_discovery.should_refresh() {
+            match self
+                .network_discovery
+                .try_refresh_candidates_throttled(MIN_CANDIDATES_REFRESH_INTERVAL)
+            {
+                Some(stats) if stats.attempts > 0 && stats.accepted == 0 => {
+                    debug!("Network discovery did not accept any new candidates: {stats:?}");
+                }
+                Some(stats) if !stats.changed() => {
+                    trace!("Network discovery refresh left the candidates unchanged: {stats:?}");
+                }
+                _ => {}
+            }
+        } else {
+            trace!(
+                "Skipping the network discovery refresh, the coverage ratio is {:.2}",
+                self.network_discovery.coverage_ratio()
+            );
+        }
+        let missing_buckets = self.network_discovery.missing_targeted_buckets();
+        if !missing_buckets.is_empty() && !self.network_discovery.is_paused() {
+            let uncovered = self.network_discovery.refresh_targeting(&missing_buckets);
+            if !uncovered.is_empty() {
+                debug!(
+                    "Network discovery could not generate candidates for the buckets {uncovered:?}"
+                );
+            }
+        }
+        let _ = self.network_discovery.check_coverage();
+        let delta = self.network_discovery.candidates_delta_since_last_round();
+        if !delta.is_empty() {
+            debug!(
+                "Network discovery candidates since the last round, gained: {:?}, lost: {:?}",
+                delta.gained().collect::<Vec<_>>(),
+                delta.lost().collect::<Vec<_>>()
             );
         }
+        if !self
+            .network_discovery
+            .has_reached_coverage(INITIAL_COVERAGE_TARGET)
+        {
+            debug!("Network discovery candidates have not reached the coverage of {INITIAL_COVERAGE_TARGET} buckets yet");
+        }
+        #[cfg(not(target_arch = "wasm32"))]
+        self.spawn_network_discovery_persistence();
+        // The round only schedules its targets, their queries being spread over the discovery ticks. The schedule is
+        // bounded, so that we don't flood the network with `FindNode` msgs when the candidates cover a lot of buckets.
+        let (scheduled, selection) = if self.peers_in_rt >= WELL_CONNECTED_PEERS_IN_RT {
+            (CLOSEST_BUCKETS_TO_DISCOVER, CandidateSelection::Closest)
+        } else {
+            (
+                self.network_discovery.queries_per_round(),
+                CandidateSelection::Weighted,
+            )
+        };
+        let missing = scheduled.saturating_sub(self.network_discovery_queue.len());
+        let candidates = self.network_discovery.take_candidates(missing, selection);
+        self.network_discovery_queue.extend(candidates);
 
         self.bootstrap.initiated();
         debug!("Trigger network discovery took {:?}", now.elapsed());
     }
+
+    /// Triggers a round of network discovery right away, e.g., once our RT has shrunk drastically. The rounds triggered
+    /// within `ON_DEMAND_DISCOVERY_DEBOUNCE` of the previous one are dropped, so a burst of triggers results in a single
+    /// round.
+    pub(crate) fn trigger_network_discovery_on_demand(&mut self) {
+        if !self.bootstrap.on_demand_triggered() {
+            debug!("Dropping the on demand network discovery, one has been triggered within {ON_DEMAND_DISCOVERY_DEBOUNCE:?}");
+            return;
+        }
+        info!("Triggering network discovery on demand");
+        self.trigger_network_discovery();
+        self.query_scheduled_network_discovery_targets();
+    }
+
+    /// Queries a few network discovery candidates at every `NETWORK_DISCOVERY_QUERY_INTERVAL`, see
+    /// `query_scheduled_network_discovery_targets`.
+    pub(crate) fn query_next_network_discovery_targets(&mut self) {
+        // Nobody to route the queries to yet.
+        if self.peers_in_rt == 0 {
+            return;
+        }
+        self.query_scheduled_network_discovery_targets();
+    }
+
+    // Queries up to `QUERIES_PER_DISCOVERY_TICK` candidates: the ones scheduled by the last round of network discovery
+    // first, topped up round-robin across the buckets. This spreads the discovery queries over time, the buckets
+    // queried lately being skipped for a while.
+    fn query_scheduled_network_discovery_targets(&mut self) {
+        let scheduled = self
+            .network_discovery_queue
+            .len()
+            .min(QUERIES_PER_DISCOVERY_TICK);
+        // The scheduled candidates whose flight has ended in the meantime, e.g., once timed out, are dropped.
+        let mut targets = self
+            .network_discovery_queue
+            .drain(..scheduled)
+            .filter(|addr| self.network_discovery.is_in_flight(addr))
+            .collect::<Vec<_>>();
+        targets.extend(self.network_discovery.take_candidates(
+            QUERIES_PER_DISCOVERY_TICK - targets.len(),
+            CandidateSelection::RoundRobin,
+        ));
+        for addr in targets {
+            self.query_network_discovery_candidate(addr);
+        }
+    }
+
+    /// Queries a candidate of the provided ilog2 bucket, generating one if needed. This is used to refill a bucket of
+    /// our RT that has become empty, without waiting for the next round of network discovery.
+    pub(crate) fn trigger_bucket_discovery(&mut self, ilog2: u32) {
+        if self.network_discovery.is_paused() {
+            return;
+        }
+        let Some(addr) = self.network_discovery.candidate_for_emptied_bucket(ilog2) else {
+            return;
+        };
+        debug!("Triggering network discovery for the emptied bucket {ilog2}");
+        self.query_network_discovery_candidate(addr);
+    }
+
+    fn query_network_discovery_candidate(&mut self, addr: NetworkAddress) {
+        // The query would get the peer dialed again. The candidate is handed out again once its flight times out.
+        if addr
+            .as_peer_id()
+            .is_some_and(|peer_id| self.is_dial_backed_off(&peer_id))
+        {
+            debug!(
+                "Not querying the network discovery candidate {addr:?}, its dials are backed off"
+            );
+            return;
+        }
+        // The query_id is tracked here. This is to update the candidate list of network_discovery with the newly
+        // found closest peers. It may fill up the candidate list of closer buckets which are harder to generate.
+        let query_id = self
+            .swarm
+            .behaviour_mut()
+            .kademlia
+            .get_closest_peers(addr.as_bytes());
+        let _ = self.pending_get_closest_peers.insert(
+            query_id,
+            (
+                PendingGetClosestType::NetworkDiscovery(addr),
+                Default::default(),
+            ),
+        );
+    }
+}
+
+impl SwarmDriver {
+    /// Generates the initial network discovery candidates on a blocking thread, so that the driver can start dialing
+    /// the bootstrap peers in the meantime. The candidates are handed back through a `LocalSwarmCmd`.
+    #[cfg(not(target_arch = "wasm32"))]
+    pub(crate) fn spawn_network_discovery_population(&mut self) {
+        let population_task = self.network_discovery.population_task();
+        let local_cmd_sender = self.local_cmd_sender.clone();
+
+        let _handle = spawn(async move {
+            match tokio::task::spawn_blocking(population_task).await {
+                Ok(candidates) => {
+                    if let Err(err) = local_cmd_sender
+                        .send(LocalSwarmCmd::AddNetworkDiscoveryCandidates { candidates })
+                        .await
+                    {
+                        error!("Failed to send the network discovery candidates: {err}");
+                    }
+                }
+                Err(err) => error!("Failed to generate the network discovery candidates: {err}"),
+            }
+        });
+    }
+
+    /// Writes the network discovery candidates to disk on a blocking thread, if they changed since they were last
+    /// written.
+    #[cfg(not(target_arch = "wasm32"))]
+    fn spawn_network_discovery_persistence(&mut self) {
+        if let Some(persist_task) = self.network_discovery.persist_task() {
+            let _handle = tokio::task::spawn_blocking(persist_task);
+        }
+    }
 }
 
 /// Tracks and helps with the continuous kad::bootstrapping process
@@ -75,6 +283,7 @@ pub(crate) struct ContinuousBootstrap {
     initial_bootstrap_done: bool,
     last_peer_added_instant: Instant,
     last_bootstrap_triggered: Option<Instant>,
+    last_on_demand_triggered: Option<Instant>,
 }
 
 impl ContinuousBootstrap {
@@ -83,6 +292,7 @@ impl ContinuousBootstrap {
             initial_bootstrap_done: false,
             last_peer_added_instant: Instant::now(),
             last_bootstrap_triggered: None,
+            last_on_demand_triggered: None,
         }
     }
 
@@ -91,6 +301,20 @@ impl ContinuousBootstrap {
         self.last_bootstrap_triggered = Some(Instant::now());
     }
 
+    /// A round of network discovery has been requested on demand. Returns false if the previous one was less than
+    /// `ON_DEMAND_DISCOVERY_DEBOUNCE` ago, in which case the request should be dropped.
+    pub(crate) fn on_demand_triggered(&mut self) -> bool {
+        let now = Instant::now();
+        if self
+            .last_on_demand_triggered
+            .is_some_and(|triggered| now.duration_since(triggered) < ON_DEMAND_DISCOVERY_DEBOUNCE)
+        {
+            return false;
+        }
+        self.last_on_demand_triggered = Some(now);
+        true
+    }
+
     /// Notify about a newly added peer to the RT. This will help with slowing down the bootstrap process.
     /// Returns `true` if we have to perform the initial bootstrapping.
     pub(crate) fn notify_new_peer(&mut self) -> bool {
@@ -11,13 +11,15 @@ use crate::{
     error::{NetworkError, Result},
     event::TerminateNodeReason,
     log_markers::Marker,
-    multiaddr_pop_p2p, GetRecordCfg, GetRecordError, MsgResponder, NetworkEvent, CLOSE_GROUP_SIZE,
-    REPLICATION_PEERS_COUNT,
+    multiaddr_pop_p2p,
+    network_discovery::NetworkDiscoveryStats,
+    GetRecordCfg, GetRecordError, MsgResponder, NetworkEvent, ObservedNetworkEvent,
+    CLOSE_GROUP_SIZE, REPLICATION_PEERS_COUNT,
 };
 use libp2p::{
     kad::{
         store::{Error as StoreError, RecordStore},
-        Quorum, Record, RecordKey,
+        QueryId, Quorum, Record, RecordKey, K_VALUE,
     },
     Multiaddr, PeerId,
 };
@@ -30,7 +32,6 @@ use sn_transfers::{NanoTokens, PaymentQuote, QuotingMetrics};
 use std::{
     collections::{BTreeMap, HashMap},
     fmt::Debug,
-    time::Duration,
 };
 use tokio::sync::oneshot;
 use xor_name::XorName;
@@ -39,9 +40,6 @@ use crate::target_arch::Instant;
 
 const MAX_CONTINUOUS_HDD_WRITE_ERROR: usize = 5;
 
-// Shall be synced with `sn_node::PERIODIC_REPLICATION_INTERVAL_MAX_S`
-const REPLICATION_TIMEOUT: Duration = Duration::from_secs(45);
-
 #[derive(Debug, Eq, PartialEq)]
 pub enum NodeIssue {
     /// Connection issues observed
@@ -63,6 +61,11 @@ pub enum LocalSwarmCmd {
     GetKBuckets {
         sender: oneshot::Sender<BTreeMap<u32, Vec<PeerId>>>,
     },
+    /// Returns the peers held by each k-bucket of the local Routing Table, keyed by the ilog2 distance of the bucket,
+    /// along with their connection state and the capacity of the bucket.
+    GetKBucketsInfo {
+        sender: oneshot::Sender<BTreeMap<u32, KBucketInfo>>,
+    },
     // Returns up to K_VALUE peers from all the k-buckets from the local Routing Table.
     // And our PeerId as well.
     GetClosestKLocalPeers {
@@ -114,6 +117,14 @@ pub enum LocalSwarmCmd {
     AddPeerToBlockList {
         peer_id: PeerId,
     },
+    /// Block a peer on request of the node operator, this is persisted across restarts
+    BlockPeer {
+        peer_id: PeerId,
+    },
+    /// Lift a block set by `BlockPeer`
+    UnblockPeer {
+        peer_id: PeerId,
+    },
     /// Notify whether peer is in trouble
     RecordNodeIssue {
         peer_id: PeerId,
@@ -135,6 +146,30 @@ pub enum LocalSwarmCmd {
     TriggerIntervalReplication,
     /// Triggers unrelevant record cleanup
     TriggerUnrelevantRecordCleanup,
+    /// Add the initial candidates that were generated for the NetworkDiscovery
+    AddNetworkDiscoveryCandidates {
+        candidates: BTreeMap<u32, Vec<NetworkAddress>>,
+    },
+    /// Triggers a round of network discovery right away, unless one has been triggered on demand within the last few
+    /// seconds
+    TriggerNetworkDiscovery,
+    /// Get the whole candidate pool of the NetworkDiscovery, as JSON
+    GetNetworkDiscoverySnapshot {
+        sender: oneshot::Sender<Result<String>>,
+    },
+    /// Pause or resume the NetworkDiscovery, the candidates being retained while paused
+    SetNetworkDiscoveryEnabled {
+        enabled: bool,
+    },
+    /// Set the bytes per second of the outbound replication transfers, 0 lifting the limit
+    SetReplicationBandwidthLimit {
+        bytes_per_sec: u64,
+    },
+    /// Switch the mDNS local discovery on or off, the peers already discovered being kept
+    #[cfg(feature = "local-discovery")]
+    SetLocalDiscovery {
+        enabled: bool,
+    },
 }
 
 /// Commands to send to the Swarm
@@ -146,7 +181,7 @@ pub enum NetworkSwarmCmd {
     // Get closest peers from the network
     GetClosestPeersToAddressFromNetwork {
         key: NetworkAddress,
-        sender: oneshot::Sender<Vec<PeerId>>,
+        sender: oneshot::Sender<(QueryId, Vec<PeerId>)>,
     },
 
     // Send Request to the PeerId.
@@ -244,6 +279,35 @@ impl Debug for LocalSwarmCmd {
             LocalSwarmCmd::GetKBuckets { .. } => {
                 write!(f, "LocalSwarmCmd::GetKBuckets")
             }
+            LocalSwarmCmd::GetKBucketsInfo { .. } => {
+                write!(f, "LocalSwarmCmd::GetKBucketsInfo")
+            }
+            LocalSwarmCmd::AddNetworkDiscoveryCandidates { candidates } => {
+                write!(
+                    f,
+                    "LocalSwarmCmd::AddNetworkDiscoveryCandidates {{ buckets: {} }}",
+                    candidates.len()
+                )
+            }
+            LocalSwarmCmd::TriggerNetworkDiscovery => {
+                write!(f, "LocalSwarmCmd::TriggerNetworkDiscovery")
+            }
+            LocalSwarmCmd::GetNetworkDiscoverySnapshot { .. } => {
+                write!(f, "LocalSwarmCmd::GetNetworkDiscoverySnapshot")
+            }
+            LocalSwarmCmd::SetNetworkDiscoveryEnabled { enabled } => {
+                write!(f, "LocalSwarmCmd::SetNetworkDiscoveryEnabled({enabled})")
+            }
+            LocalSwarmCmd::SetReplicationBandwidthLimit { bytes_per_sec } => {
+                write!(
+                    f,
+                    "LocalSwarmCmd::SetReplicationBandwidthLimit({bytes_per_sec})"
+                )
+            }
+            #[cfg(feature = "local-discovery")]
+            LocalSwarmCmd::SetLocalDiscovery { enabled } => {
+                write!(f, "LocalSwarmCmd::SetLocalDiscovery({enabled})")
+            }
             LocalSwarmCmd::GetSwarmLocalState { .. } => {
                 write!(f, "LocalSwarmCmd::GetSwarmLocalState")
             }
@@ -257,6 +321,12 @@ impl Debug for LocalSwarmCmd {
             LocalSwarmCmd::AddPeerToBlockList { peer_id } => {
                 write!(f, "LocalSwarmCmd::AddPeerToBlockList {peer_id:?}")
             }
+            LocalSwarmCmd::BlockPeer { peer_id } => {
+                write!(f, "LocalSwarmCmd::BlockPeer {peer_id:?}")
+            }
+            LocalSwarmCmd::UnblockPeer { peer_id } => {
+                write!(f, "LocalSwarmCmd::UnblockPeer {peer_id:?}")
+            }
             LocalSwarmCmd::RecordNodeIssue { peer_id, issue } => {
                 write!(
                     f,
@@ -341,6 +411,25 @@ pub struct SwarmLocalState {
     pub connected_peers: Vec<PeerId>,
     /// List of addresses the node is currently listening on
     pub listeners: Vec<Multiaddr>,
+    /// The state of the network discovery
+    pub network_discovery: NetworkDiscoveryStats,
+}
+
+/// The peers held by a k-bucket of the local Routing Table.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct KBucketInfo {
+    /// The peers of the bucket, along with whether we are currently connected to them and their score. The score
+    /// goes up with the successful exchanges and down with the failed ones, the lowest scoring peers being evicted.
+    pub peers: Vec<(PeerId, bool, i32)>,
+    /// The number of peers the bucket can hold.
+    pub capacity: usize,
+}
+
+impl KBucketInfo {
+    /// Whether the bucket holds as many peers as it can, new peers then only replacing the unresponsive ones.
+    pub fn is_full(&self) -> bool {
+        self.peers.len() >= self.capacity
+    }
 }
 
 impl SwarmDriver {
@@ -351,6 +440,17 @@ impl SwarmDriver {
             NetworkSwarmCmd::GetNetworkRecord { key, sender, cfg } => {
                 cmd_string = "GetNetworkRecord";
 
+                if let Some(record) = self.get_cached_record(&key, &cfg) {
+                    debug!(
+                        "GetNetworkRecord for {:?} served from the record cache",
+                        PrettyPrintRecordKey::from(&key)
+                    );
+                    sender
+                        .send(Ok(record))
+                        .map_err(|_| NetworkError::InternalMsgChannelDropped)?;
+                    return Ok(());
+                }
+
                 for (pending_query, (inflight_record_query_key, senders, _, _)) in
                     self.pending_get_record.iter_mut()
                 {
@@ -471,6 +571,7 @@ impl SwarmDriver {
                     .behaviour_mut()
                     .kademlia
                     .get_closest_peers(key.as_bytes());
+                debug!("Started the query {query_id:?} for the closest peers to {key:?}");
                 let _ = self.pending_get_closest_peers.insert(
                     query_id,
                     (
@@ -504,7 +605,9 @@ impl SwarmDriver {
                         .request_response
                         .send_request(&peer, req);
                     trace!("Sending request {request_id:?} to peer {peer:?}");
-                    let _ = self.pending_requests.insert(request_id, sender);
+                    let _ = self
+                        .pending_requests
+                        .insert(request_id, (sender, Instant::now()));
 
                     trace!("Pending Requests now: {:?}", self.pending_requests.len());
                 }
@@ -528,13 +631,7 @@ impl SwarmDriver {
                             }
                         }
                     }
-                    MsgResponder::FromPeer(channel) => {
-                        self.swarm
-                            .behaviour_mut()
-                            .request_response
-                            .send_response(channel, resp)
-                            .map_err(NetworkError::OutgoingResponseDropped)?;
-                    }
+                    MsgResponder::FromPeer(channel) => self.send_peer_response(channel, resp)?,
                 }
             }
         }
@@ -686,6 +783,9 @@ impl SwarmDriver {
                     PrettyPrintRecordKey::from(&key)
                 );
                 cmd_string = "AddLocalRecordAsStored";
+                self.publish_observed_event(ObservedNetworkEvent::RecordStored {
+                    key: PrettyPrintRecordKey::from(&key).into_owned(),
+                });
                 self.swarm
                     .behaviour_mut()
                     .kademlia
@@ -729,22 +829,58 @@ impl SwarmDriver {
             }
             LocalSwarmCmd::GetKBuckets { sender } => {
                 cmd_string = "GetKBuckets";
-                let mut ilog2_kbuckets = BTreeMap::new();
-                for kbucket in self.swarm.behaviour_mut().kademlia.kbuckets() {
-                    let range = kbucket.range();
-                    if let Some(distance) = range.0.ilog2() {
-                        let peers_in_kbucket = kbucket
-                            .iter()
-                            .map(|peer_entry| (*peer_entry.node.key).into_preimage())
-                            .collect::<Vec<PeerId>>();
-                        let _ = ilog2_kbuckets.insert(distance, peers_in_kbucket);
-                    } else {
-                        // This shall never happen.
-                        error!("bucket is ourself ???!!!");
-                    }
-                }
+                let ilog2_kbuckets = self
+                    .kbuckets_info()
+                    .into_iter()
+                    .map(|(ilog2, info)| {
+                        let peers = info
+                            .peers
+                            .into_iter()
+                            .map(|(peer_id, _, _)| peer_id)
+                            .collect();
+                        (ilog2, peers)
+                    })
+                    .collect();
                 let _ = sender.send(ilog2_kbuckets);
             }
+            LocalSwarmCmd::GetKBucketsInfo { sender } => {
+                cmd_string = "GetKBucketsInfo";
+                let _ = sender.send(self.kbuckets_info());
+            }
+            LocalSwarmCmd::AddNetworkDiscoveryCandidates { candidates } => {
+                cmd_string = "AddNetworkDiscoveryCandidates";
+                self.network_discovery.populate_with(candidates);
+            }
+            LocalSwarmCmd::TriggerNetworkDiscovery => {
+                cmd_string = "TriggerNetworkDiscovery";
+                self.trigger_network_discovery_on_demand();
+            }
+            LocalSwarmCmd::GetNetworkDiscoverySnapshot { sender } => {
+                cmd_string = "GetNetworkDiscoverySnapshot";
+                let snapshot = self
+                    .network_discovery
+                    .snapshot()
+                    .to_json()
+                    .map_err(|err| NetworkError::Io(err.into()));
+                let _ = sender.send(snapshot);
+            }
+            LocalSwarmCmd::SetNetworkDiscoveryEnabled { enabled } => {
+                cmd_string = "SetNetworkDiscoveryEnabled";
+                if enabled {
+                    self.network_discovery.resume();
+                } else {
+                    self.network_discovery.pause();
+                }
+            }
+            LocalSwarmCmd::SetReplicationBandwidthLimit { bytes_per_sec } => {
+                cmd_string = "SetReplicationBandwidthLimit";
+                self.set_replication_bandwidth_limit(bytes_per_sec);
+            }
+            #[cfg(feature = "local-discovery")]
+            LocalSwarmCmd::SetLocalDiscovery { enabled } => {
+                cmd_string = "SetLocalDiscovery";
+                self.set_local_discovery(enabled);
+            }
             LocalSwarmCmd::GetCloseGroupLocalPeers { key, sender } => {
                 cmd_string = "GetCloseGroupLocalPeers";
                 let key = key.as_kbucket_key();
@@ -772,6 +908,7 @@ impl SwarmDriver {
                 let current_state = SwarmLocalState {
                     connected_peers: self.swarm.connected_peers().cloned().collect(),
                     listeners: self.swarm.listeners().cloned().collect(),
+                    network_discovery: self.network_discovery.stats(),
                 };
 
                 sender
@@ -782,6 +919,32 @@ impl SwarmDriver {
                 cmd_string = "AddPeerToBlockList";
                 self.swarm.behaviour_mut().blocklist.block_peer(peer_id);
             }
+            LocalSwarmCmd::BlockPeer { peer_id } => {
+                cmd_string = "BlockPeer";
+                if self.blocked_peers.block(peer_id) {
+                    info!("Blocking {peer_id:?} on request");
+                }
+                // Any connection to the peer is closed, and new ones refused both ways.
+                self.swarm.behaviour_mut().blocklist.block_peer(peer_id);
+                if let Some(dead_peer) = self.swarm.behaviour_mut().kademlia.remove_peer(&peer_id) {
+                    self.update_on_peer_removal(*dead_peer.node.key.preimage());
+                }
+                let _ = self.network_discovery.remove_peer(&peer_id);
+            }
+            LocalSwarmCmd::UnblockPeer { peer_id } => {
+                cmd_string = "UnblockPeer";
+                if self.blocked_peers.unblock(&peer_id) {
+                    info!("Unblocking {peer_id:?} on request");
+                    // The peers we found to be bad stay blocked until a restart.
+                    let is_bad = self
+                        .bad_nodes
+                        .get(&peer_id)
+                        .is_some_and(|(_issues, is_bad)| *is_bad);
+                    if !is_bad {
+                        self.swarm.behaviour_mut().blocklist.unblock_peer(peer_id);
+                    }
+                }
+            }
             LocalSwarmCmd::RecordNodeIssue { peer_id, issue } => {
                 cmd_string = "RecordNodeIssues";
                 self.record_node_issue(peer_id, issue);
@@ -948,6 +1111,46 @@ impl SwarmDriver {
         let _ = self.quotes_history.insert(peer_id, quote);
     }
 
+    /// The peers held by each non-empty k-bucket of our RT, keyed by the ilog2 distance of the bucket.
+    fn kbuckets_info(&mut self) -> BTreeMap<u32, KBucketInfo> {
+        let mut ilog2_kbuckets = BTreeMap::new();
+        let mut peers_by_bucket = Vec::new();
+        for kbucket in self.swarm.behaviour_mut().kademlia.kbuckets() {
+            let range = kbucket.range();
+            if let Some(distance) = range.0.ilog2() {
+                let peers_in_kbucket = kbucket
+                    .iter()
+                    .map(|peer_entry| (*peer_entry.node.key).into_preimage())
+                    .collect::<Vec<PeerId>>();
+                peers_by_bucket.push((distance, peers_in_kbucket));
+            } else {
+                // This shall never happen.
+                error!("bucket is ourself ???!!!");
+            }
+        }
+        let now = Instant::now();
+        for (distance, peers) in peers_by_bucket {
+            let peers = peers
+                .into_iter()
+                .map(|peer_id| {
+                    (
+                        peer_id,
+                        self.swarm.is_connected(&peer_id),
+                        self.peer_scores.score(&peer_id, now),
+                    )
+                })
+                .collect();
+            let _ = ilog2_kbuckets.insert(
+                distance,
+                KBucketInfo {
+                    peers,
+                    capacity: K_VALUE.get(),
+                },
+            );
+        }
+        ilog2_kbuckets
+    }
+
     fn try_interval_replication(&mut self) -> Result<()> {
         // get closest peers from buckets, sorted by increasing distance to us
         let our_peer_id = self.self_peer_id.into();
@@ -990,6 +1193,7 @@ impl SwarmDriver {
                 "Sending a replication list of {} keys to {replicate_targets:?} ",
                 all_records.len()
             );
+            self.start_replication_round(&replicate_targets, all_records.len());
             let request = Request::Cmd(Cmd::Replicate {
                 holder: NetworkAddress::from_peer(self.self_peer_id),
                 keys: all_records,
@@ -1003,7 +1207,7 @@ impl SwarmDriver {
 
                 let _ = self
                     .replication_targets
-                    .insert(peer_id, now + REPLICATION_TIMEOUT);
+                    .insert(peer_id, now + self.replication_interval);
             }
         }
 
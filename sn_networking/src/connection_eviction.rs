@@ -0,0 +1,172 @@
+// Copyright 2024 MaidSafe.net limited.
+//
+// This SAFE Network Software is licensed to you under The General Public License (GPL), version 3.
+// Unless required by applicable law or agreed to in writing, the SAFE Network Software distributed
+// under the GPL Licence is distributed on an "AS IS" BASIS, WITHOUT WARRANTIES OR CONDITIONS OF ANY
+// KIND, either express or implied. Please review the Licences for the specific language governing
+// permissions and limitations relating to use of the SAFE Network Software.
+
+use crate::{
+    driver::SwarmDriver, observed_events::ObservedNetworkEvent, target_arch::Instant,
+    CLOSE_GROUP_SIZE,
+};
+use libp2p::{kad::KBucketKey, swarm::ConnectionId, PeerId};
+use std::collections::{BTreeMap, HashSet};
+
+/// Picks the connection to close once the established connections exceed the limit. The connections to the peers for
+/// which `keep` returns true are never picked. The other ones are picked in this order: the connections other than the
+/// `new_connection`, to the peers not in our RT, the oldest first. Returns None if every connection is to be kept.
+fn connection_to_evict(
+    live_connections: &BTreeMap<ConnectionId, (PeerId, Instant)>,
+    new_connection: ConnectionId,
+    keep: impl Fn(&PeerId) -> bool,
+    is_in_rt: impl Fn(&PeerId) -> bool,
+) -> Option<(ConnectionId, PeerId)> {
+    live_connections
+        .iter()
+        .filter(|(_connection_id, (peer_id, _timeout))| !keep(peer_id))
+        .min_by_key(|(connection_id, (peer_id, timeout))| {
+            (
+                **connection_id == new_connection,
+                is_in_rt(peer_id),
+                *timeout,
+            )
+        })
+        .map(|(connection_id, (peer_id, _timeout))| (*connection_id, *peer_id))
+}
+
+impl SwarmDriver {
+    /// Closes the least useful connection if the established connections exceed `max_established_connections`. The
+    /// connections to our close group and to the peers we await a response from are kept, the new connection only
+    /// being closed if no other one can be.
+    pub(crate) fn enforce_max_established_connections(&mut self, new_connection: ConnectionId) {
+        let Some(max_established) = self.max_established_connections else {
+            return;
+        };
+        if self.live_connected_peers.len() <= max_established {
+            return;
+        }
+
+        let close_group = self.connected_close_group();
+        // Looking up the RT borrows the swarm mutably, hence it is done ahead of the selection.
+        let connected = self
+            .live_connected_peers
+            .values()
+            .map(|(peer_id, _timeout)| *peer_id)
+            .collect::<HashSet<_>>();
+        let in_rt = connected
+            .into_iter()
+            .filter(|peer_id| {
+                self.swarm
+                    .behaviour_mut()
+                    .kademlia
+                    .kbucket(*peer_id)
+                    .is_some_and(|kbucket| {
+                        kbucket
+                            .iter()
+                            .any(|entry| entry.node.key.preimage() == peer_id)
+                    })
+            })
+            .collect::<HashSet<_>>();
+        let keep = |peer_id: &PeerId| {
+            close_group.contains(peer_id)
+                || self.relay_manager.keep_alive_peer(peer_id)
+                || self.pending_requests.keys().any(|request_id| {
+                    self.swarm
+                        .behaviour()
+                        .request_response
+                        .is_pending_outbound(peer_id, request_id)
+                })
+        };
+        let Some((connection_id, peer_id)) = connection_to_evict(
+            &self.live_connected_peers,
+            new_connection,
+            keep,
+            |peer_id| in_rt.contains(peer_id),
+        ) else {
+            warn!(
+                "{} connections are established, {max_established} at most are wanted, but all of them are in use",
+                self.live_connected_peers.len()
+            );
+            return;
+        };
+
+        let closed = self.swarm.close_connection(connection_id);
+        info!("Evicting the connection {connection_id:?} to {peer_id:?}, {max_established} connections at most are wanted: {closed}");
+        let _ = self.live_connected_peers.remove(&connection_id);
+        self.publish_observed_event(ObservedNetworkEvent::ConnectionEvicted { peer_id });
+        #[cfg(feature = "open-metrics")]
+        if let Some(metrics_recorder) = &self.metrics_recorder {
+            let _ = metrics_recorder.evicted_connections.inc();
+        }
+    }
+
+    // Returns the CLOSE_GROUP_SIZE peers closest to self among the ones of our RT and the connected ones. The newly
+    // connected peers that are not in our RT yet are accounted for, so that they can take over a farther peer's slot.
+    fn connected_close_group(&mut self) -> HashSet<PeerId> {
+        let self_key = *self.network_discovery.self_key();
+        let mut peers = self
+            .swarm
+            .behaviour_mut()
+            .kademlia
+            .get_closest_local_peers(&self_key)
+            .map(|key| key.into_preimage())
+            .take(CLOSE_GROUP_SIZE)
+            .chain(
+                self.live_connected_peers
+                    .values()
+                    .map(|(peer_id, _timeout)| *peer_id),
+            )
+            .collect::<HashSet<_>>()
+            .into_iter()
+            .collect::<Vec<_>>();
+        peers.sort_by_cached_key(|peer_id| KBucketKey::from(*peer_id).distance(&self_key));
+        peers.into_iter().take(CLOSE_GROUP_SIZE).collect()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::time::Duration;
+
+    #[test]
+    fn the_least_useful_connection_is_evicted_first() {
+        let now = Instant::now();
+        let peers = (0..4).map(|_| PeerId::random()).collect::<Vec<_>>();
+        let connections = (0..4)
+            .map(|index| {
+                (
+                    ConnectionId::new_unchecked(index),
+                    (peers[index], now + Duration::from_secs(index as u64)),
+                )
+            })
+            .collect::<BTreeMap<_, _>>();
+        let new_connection = ConnectionId::new_unchecked(3);
+        let in_rt = |peer_id: &PeerId| *peer_id == peers[0];
+
+        // The peers out of our RT go first, the oldest of them before the others.
+        let evicted = connection_to_evict(&connections, new_connection, |_| false, in_rt);
+        assert_eq!(evicted, Some((ConnectionId::new_unchecked(1), peers[1])));
+        let evicted = connection_to_evict(
+            &connections,
+            new_connection,
+            |peer_id| *peer_id == peers[1] || *peer_id == peers[2],
+            in_rt,
+        );
+        assert_eq!(evicted, Some((ConnectionId::new_unchecked(0), peers[0])));
+
+        // The new connection is only closed if all the other ones are kept.
+        let evicted = connection_to_evict(
+            &connections,
+            new_connection,
+            |peer_id| *peer_id != peers[3],
+            in_rt,
+        );
+        assert_eq!(evicted, Some((new_connection, peers[3])));
+        assert_eq!(
+            connection_to_evict(&connections, new_connection, |_| true, in_rt),
+            None
+        );
+    }
+}
@@ -0,0 +1,278 @@
+// Copyright 2024 MaidSafe.net limited.
+//
+// This SAFE Network Software is licensed to you under The General Public License (GPL), version 3.
+// Unless required by applicable law or agreed to in writing, the SAFE Network Software distributed
+// under the GPL Licence is distributed on an "AS IS" BASIS, WITHOUT WARRANTIES OR CONDITIONS OF ANY
+// KIND, either express or implied. Please review the Licences for the specific language governing
+// permissions and limitations relating to use of the SAFE Network Software.
+
+use crate::{driver::SwarmDriver, target_arch::Instant};
+use libp2p::{
+    core::{transport::PortUse, Endpoint},
+    swarm::{
+        dummy, ConnectionDenied, ConnectionId, DialError, DialFailure, FromSwarm, NetworkBehaviour,
+        THandler, THandlerInEvent, THandlerOutEvent, ToSwarm,
+    },
+    Multiaddr, PeerId,
+};
+use rand::Rng;
+use std::{
+    collections::HashMap,
+    task::{Context, Poll},
+    time::Duration,
+};
+use void::Void;
+
+/// The backoff after the first failed dial, doubled by each consecutive failure.
+const INITIAL_DIAL_BACKOFF: Duration = Duration::from_secs(2);
+/// The longest a peer is backed off for, however many times its dials failed.
+const MAX_DIAL_BACKOFF: Duration = Duration::from_secs(10 * 60);
+/// Each backoff is lengthened by up to this share of it, so that the peers failing together are not all dialed again
+/// at once.
+const DIAL_BACKOFF_JITTER: f64 = 0.25;
+
+/// The cause of the dials denied by the `DialBackoff`.
+#[derive(Debug, thiserror::Error)]
+#[error("Dials to {0:?} are backed off after its consecutive failures")]
+pub(crate) struct DialBackedOff(PeerId);
+
+/// The consecutive dial failures of a peer, along with when it can be dialed again.
+#[derive(Debug, Clone, Copy)]
+struct FailedDials {
+    failures: u32,
+    until: Instant,
+}
+
+/// Denies the dials to the peers whose previous dials failed, for a duration growing exponentially with their
+/// consecutive failures. This applies to all the dials, be they triggered by kad or by the driver. A connection
+/// established with the peer, in either direction, lifts its backoff.
+#[derive(Debug, Default)]
+pub(crate) struct DialBackoff {
+    peers: HashMap<PeerId, FailedDials>,
+}
+
+impl DialBackoff {
+    pub(crate) fn is_backed_off(&self, peer_id: &PeerId, now: Instant) -> bool {
+        self.peers
+            .get(peer_id)
+            .is_some_and(|failed| now < failed.until)
+    }
+
+    /// The number of peers that cannot be dialed currently.
+    #[cfg(any(test, feature = "open-metrics"))]
+    pub(crate) fn backed_off_count(&self, now: Instant) -> usize {
+        self.peers
+            .values()
+            .filter(|failed| now < failed.until)
+            .count()
+    }
+
+    fn record_failure(&mut self, peer_id: PeerId, now: Instant) {
+        // The peers that have not failed for long are given a fresh start.
+        self.peers
+            .retain(|_peer_id, failed| now < failed.until + MAX_DIAL_BACKOFF);
+        let failures = self
+            .peers
+            .get(&peer_id)
+            .map_or(1, |failed| failed.failures.saturating_add(1));
+        let backoff = INITIAL_DIAL_BACKOFF
+            .saturating_mul(2_u32.saturating_pow(failures - 1))
+            .mul_f64(1.0 + rand::thread_rng().gen_range(0.0..DIAL_BACKOFF_JITTER))
+            .min(MAX_DIAL_BACKOFF);
+        debug!("Backing off the dials to {peer_id:?} for {backoff:?} after {failures} consecutive failures");
+        let _ = self.peers.insert(
+            peer_id,
+            FailedDials {
+                failures,
+                until: now + backoff,
+            },
+        );
+    }
+
+    fn record_success(&mut self, peer_id: &PeerId) {
+        if self.peers.remove(peer_id).is_some() {
+            debug!("Connected to {peer_id:?}, lifting its dial backoff");
+        }
+    }
+}
+
+impl NetworkBehaviour for DialBackoff {
+    type ConnectionHandler = dummy::ConnectionHandler;
+    type ToSwarm = Void;
+
+    fn handle_established_inbound_connection(
+        &mut self,
+        _connection_id: ConnectionId,
+        _peer: PeerId,
+        _local_addr: &Multiaddr,
+        _remote_addr: &Multiaddr,
+    ) -> Result<THandler<Self>, ConnectionDenied> {
+        Ok(dummy::ConnectionHandler)
+    }
+
+    fn handle_pending_outbound_connection(
+        &mut self,
+        _connection_id: ConnectionId,
+        maybe_peer: Option<PeerId>,
+        _addresses: &[Multiaddr],
+        _effective_role: Endpoint,
+    ) -> Result<Vec<Multiaddr>, ConnectionDenied> {
+        match maybe_peer {
+            Some(peer_id) if self.is_backed_off(&peer_id, Instant::now()) => {
+                Err(ConnectionDenied::new(DialBackedOff(peer_id)))
+            }
+            _ => Ok(vec![]),
+        }
+    }
+
+    fn handle_established_outbound_connection(
+        &mut self,
+        _connection_id: ConnectionId,
+        _peer: PeerId,
+        _addr: &Multiaddr,
+        _role_override: Endpoint,
+        _port_use: PortUse,
+    ) -> Result<THandler<Self>, ConnectionDenied> {
+        Ok(dummy::ConnectionHandler)
+    }
+
+    fn on_swarm_event(&mut self, event: FromSwarm) {
+        match event {
+            FromSwarm::ConnectionEstablished(established) => {
+                self.record_success(&established.peer_id)
+            }
+            FromSwarm::DialFailure(DialFailure {
+                peer_id: Some(peer_id),
+                error,
+                ..
+            }) => match error {
+                // The peer could not be reached, or is not the one expected.
+                DialError::Transport(_)
+                | DialError::NoAddresses
+                | DialError::WrongPeerId { .. } => self.record_failure(peer_id, Instant::now()),
+                // The dials denied locally, including by this backoff, and the ones that never started are not the
+                // peer's fault.
+                DialError::Denied { .. }
+                | DialError::Aborted
+                | DialError::DialPeerConditionFalse(_)
+                | DialError::LocalPeerId { .. } => {}
+            },
+            _ => {}
+        }
+    }
+
+    fn on_connection_handler_event(
+        &mut self,
+        _peer_id: PeerId,
+        _connection_id: ConnectionId,
+        event: THandlerOutEvent<Self>,
+    ) {
+        void::unreachable(event)
+    }
+
+    fn poll(
+        &mut self,
+        _cx: &mut Context<'_>,
+    ) -> Poll<ToSwarm<Self::ToSwarm, THandlerInEvent<Self>>> {
+        Poll::Pending
+    }
+}
+
+impl SwarmDriver {
+    /// Whether the dials to the peer are currently backed off, see `DialBackoff`.
+    pub(crate) fn is_dial_backed_off(&self, peer_id: &PeerId) -> bool {
+        self.swarm
+            .behaviour()
+            .dial_backoff
+            .is_backed_off(peer_id, Instant::now())
+    }
+
+    pub(crate) fn record_dial_backoff_metric(&self) {
+        #[cfg(feature = "open-metrics")]
+        if let Some(metrics_recorder) = self.metrics_recorder.as_ref() {
+            let _ = metrics_recorder.peers_in_dial_backoff.set(
+                self.swarm
+                    .behaviour()
+                    .dial_backoff
+                    .backed_off_count(Instant::now()) as i64,
+            );
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn dials_are_suppressed_until_the_backoff_elapses() {
+        let mut backoff = DialBackoff::default();
+        let peer_id = PeerId::random();
+        let start = Instant::now();
+        let max_jitter = |backoff: Duration| backoff.mul_f64(1.0 + DIAL_BACKOFF_JITTER);
+
+        let mut failed_at = start;
+        let mut expected = INITIAL_DIAL_BACKOFF;
+        for _ in 0..4 {
+            backoff.record_failure(peer_id, failed_at);
+            assert!(
+                backoff.is_backed_off(&peer_id, failed_at + expected - Duration::from_millis(1))
+            );
+            let retried_at = failed_at + max_jitter(expected);
+            assert!(!backoff.is_backed_off(&peer_id, retried_at));
+            // Each consecutive failure doubles the backoff.
+            failed_at = retried_at;
+            expected *= 2;
+        }
+        assert_eq!(backoff.backed_off_count(failed_at), 0);
+
+        // However many failures, a peer is not backed off for longer than the cap.
+        for _ in 0..20 {
+            backoff.record_failure(peer_id, failed_at);
+        }
+        assert!(backoff.is_backed_off(
+            &peer_id,
+            failed_at + MAX_DIAL_BACKOFF - Duration::from_secs(1)
+        ));
+        assert!(!backoff.is_backed_off(&peer_id, failed_at + MAX_DIAL_BACKOFF));
+        assert_eq!(backoff.backed_off_count(failed_at), 1);
+
+        // A connection lifts the backoff, the next failure starting over.
+        backoff.record_success(&peer_id);
+        assert!(!backoff.is_backed_off(&peer_id, failed_at));
+        backoff.record_failure(peer_id, failed_at);
+        assert!(!backoff.is_backed_off(&peer_id, failed_at + max_jitter(INITIAL_DIAL_BACKOFF)));
+    }
+
+    #[test]
+    fn failed_dials_get_the_next_ones_denied() {
+        let mut backoff = DialBackoff::default();
+        let (unreachable, other) = (PeerId::random(), PeerId::random());
+        let dial = |backoff: &mut DialBackoff, peer_id| {
+            backoff.handle_pending_outbound_connection(
+                ConnectionId::new_unchecked(0),
+                Some(peer_id),
+                &[],
+                Endpoint::Dialer,
+            )
+        };
+
+        // The denials are not the peer's fault, and do not extend its backoff.
+        backoff.on_swarm_event(FromSwarm::DialFailure(DialFailure {
+            peer_id: Some(unreachable),
+            error: &DialError::Aborted,
+            connection_id: ConnectionId::new_unchecked(0),
+        }));
+        assert!(dial(&mut backoff, unreachable).is_ok());
+
+        backoff.on_swarm_event(FromSwarm::DialFailure(DialFailure {
+            peer_id: Some(unreachable),
+            error: &DialError::NoAddresses,
+            connection_id: ConnectionId::new_unchecked(0),
+        }));
+        let denied = dial(&mut backoff, unreachable).expect_err("the dial is backed off");
+        assert!(denied.downcast_ref::<DialBackedOff>().is_some());
+        assert!(dial(&mut backoff, other).is_ok());
+        assert_eq!(backoff.backed_off_count(Instant::now()), 1);
+    }
+}
@@ -6,20 +6,31 @@
 // KIND, either express or implied. Please review the Licences for the specific language governing
 // permissions and limitations relating to use of the SAFE Network Software.
 
+#[cfg(feature = "local-discovery")]
+use crate::local_discovery::LocalDiscovery;
 use crate::{
-    bootstrap::{ContinuousBootstrap, BOOTSTRAP_INTERVAL},
+    blocklist::{PeerBlocklist, BLOCKED_PEERS_FILENAME},
+    bootstrap::{ContinuousBootstrap, BOOTSTRAP_INTERVAL, NETWORK_DISCOVERY_QUERY_INTERVAL},
     circular_vec::CircularVec,
     cmd::{LocalSwarmCmd, NetworkSwarmCmd},
+    dial_backoff::DialBackoff,
     error::{NetworkError, Result},
     event::{NetworkEvent, NodeEvent},
     external_address::ExternalAddressManager,
     log_markers::Marker,
     multiaddr_pop_p2p,
-    network_discovery::NetworkDiscovery,
+    network_discovery::{
+        NetworkDiscovery, NetworkDiscoveryConfig, NetworkDiscoverySnapshot,
+        NETWORK_DISCOVERY_CANDIDATES_FILENAME,
+    },
+    observed_events::{ObservedNetworkEvent, ReplicationRounds, OBSERVED_EVENTS_CAPACITY},
+    peer_score::{PeerScores, EVICTION_CHECK_INTERVAL},
+    record_cache::{RecordCache, RecordCacheConfig},
     record_store::{ClientRecordStore, NodeRecordStore, NodeRecordStoreConfig},
     record_store_api::UnifiedRecordStore,
     relay_manager::RelayManager,
     replication_fetcher::ReplicationFetcher,
+    replication_throttle::{ReplicationThrottle, REPLICATION_THROTTLE_CHECK_INTERVAL},
     target_arch::{interval, spawn, Instant},
     GetRecordError, Network, CLOSE_GROUP_SIZE,
 };
@@ -47,6 +58,7 @@ use libp2p::{
 };
 #[cfg(feature = "open-metrics")]
 use prometheus_client::metrics::info::Info;
+use rand::Rng;
 use sn_protocol::{
     messages::{ChunkProof, Nonce, Request, Response},
     storage::{try_deserialize_record, RetryStrategy},
@@ -59,13 +71,13 @@ use sn_protocol::{
 use sn_registers::SignedRegister;
 use sn_transfers::PaymentQuote;
 use std::{
-    collections::{btree_map::Entry, BTreeMap, HashMap, HashSet},
+    collections::{btree_map::Entry, BTreeMap, HashMap, HashSet, VecDeque},
     fmt::Debug,
     net::SocketAddr,
     num::NonZeroUsize,
     path::PathBuf,
 };
-use tokio::sync::{mpsc, oneshot};
+use tokio::sync::{broadcast, mpsc, oneshot};
 use tokio::time::Duration;
 use tracing::warn;
 use xor_name::XorName;
@@ -83,11 +95,15 @@ const KAD_STREAM_PROTOCOL_ID: StreamProtocol = StreamProtocol::new("/autonomi/ka
 pub(crate) enum PendingGetClosestType {
     /// The network discovery method is present at the networking layer
     /// Thus we can just process the queries made by NetworkDiscovery without using any channels
-    NetworkDiscovery,
+    /// Contains the candidate that has been queried.
+    NetworkDiscovery(NetworkAddress),
     /// These are queries made by a function at the upper layers and contains a channel to send the result back.
-    FunctionCall(oneshot::Sender<Vec<PeerId>>),
+    FunctionCall(oneshot::Sender<(QueryId, Vec<PeerId>)>),
 }
 type PendingGetClosest = HashMap<QueryId, (PendingGetClosestType, Vec<PeerId>)>;
+/// The requests awaiting a response, along with when they were sent.
+type PendingRequests =
+    HashMap<OutboundRequestId, (Option<oneshot::Sender<Result<Response>>>, Instant)>;
 
 /// Using XorName to differentiate different record content under the same key.
 type GetRecordResultMap = HashMap<XorName, (Record, HashSet<PeerId>)>;
@@ -123,6 +139,16 @@ const NETWORKING_CHANNEL_SIZE: usize = 10_000;
 /// Time before a Kad query times out if no response is received
 const KAD_QUERY_TIMEOUT_S: Duration = Duration::from_secs(10);
 
+/// Time before a Kad query times out in client mode, the libp2p default
+const CLIENT_KAD_QUERY_TIMEOUT_S: Duration = Duration::from_secs(60);
+
+/// The minimum interval before replicating to the same peer again.
+// Shall be synced with `sn_node::PERIODIC_REPLICATION_INTERVAL_MAX_S`
+const REPLICATION_INTERVAL_DEFAULT: Duration = Duration::from_secs(45);
+
+/// The shortest query timeout or replication interval accepted by the `NetworkBuilder`.
+const MIN_CONFIGURABLE_INTERVAL: Duration = Duration::from_secs(1);
+
 // Init during compilation, instead of runtime error that should never happen
 // Option<T>::expect will be stabilised as const in the future (https://github.com/rust-lang/rust/issues/67441)
 const REPLICATION_FACTOR: NonZeroUsize = match NonZeroUsize::new(CLOSE_GROUP_SIZE) {
@@ -130,11 +156,102 @@ const REPLICATION_FACTOR: NonZeroUsize = match NonZeroUsize::new(CLOSE_GROUP_SIZ
     None => panic!("CLOSE_GROUP_SIZE should not be zero"),
 };
 
+/// How the closest peers queries are retried when they do not yield enough peers, e.g., as they timed out.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct ClosestPeersRetryPolicy {
+    /// The number of queries issued at most, the first one included.
+    pub attempts: usize,
+    /// The delay before the first retry, doubled for each retry after it. This is at least a second, as a retry
+    /// issued right away would dial the peers that have just failed to respond back-to-back.
+    pub base_delay: Duration,
+    /// The upper bound of the random delay added before each retry, to spread out the callers retrying together.
+    pub jitter: Duration,
+}
+
+impl Default for ClosestPeersRetryPolicy {
+    fn default() -> Self {
+        Self {
+            attempts: 3,
+            base_delay: Duration::from_secs(1),
+            jitter: Duration::from_millis(500),
+        }
+    }
+}
+
+impl ClosestPeersRetryPolicy {
+    /// A single query, the caller being left to retry.
+    pub fn no_retry() -> Self {
+        Self {
+            attempts: 1,
+            ..Default::default()
+        }
+    }
+
+    /// The delay before the `retry`th retry, counting from 1.
+    pub(crate) fn delay_before_retry(&self, retry: u32) -> Duration {
+        let backoff = self
+            .base_delay
+            .saturating_mul(2u32.saturating_pow(retry.saturating_sub(1)));
+        let jitter = if self.jitter.is_zero() {
+            Duration::ZERO
+        } else {
+            rand::thread_rng().gen_range(Duration::ZERO..=self.jitter)
+        };
+        backoff.saturating_add(jitter)
+    }
+}
+
+/// How a GetRecord query picks the copy to return when the holders disagree.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum GetRecordResolution {
+    /// Returns the first copy returned by the quorum of holders, any disagreement before that being a split record.
+    #[default]
+    FirstQuorum,
+    /// Waits for the quorum of holders to answer, whatever copy they hold, then returns the copy held by more than
+    /// half of them. See [`MajorityRecord`].
+    Majority,
+}
+
+/// The copy of a record held by the majority of the holders that answered.
+#[derive(Clone)]
+pub struct MajorityRecord {
+    pub record: Record,
+    /// The holders that returned a different copy.
+    pub dissenting_holders: HashSet<PeerId>,
+}
+
+impl MajorityRecord {
+    /// Groups the copies by content, returns the one held by more than half of the holders if any.
+    pub fn from_copies(result_map: &GetRecordResultMap) -> Option<Self> {
+        let total_holders = result_map
+            .values()
+            .map(|(_, holders)| holders.len())
+            .sum::<usize>();
+        let (majority_hash, (record, holders)) = result_map
+            .iter()
+            .max_by_key(|(_, (_, holders))| holders.len())?;
+        if holders.len() * 2 <= total_holders {
+            return None;
+        }
+        let dissenting_holders = result_map
+            .iter()
+            .filter(|(content_hash, _)| *content_hash != majority_hash)
+            .flat_map(|(_, (_, holders))| holders.iter().copied())
+            .collect();
+        Some(Self {
+            record: record.clone(),
+            dissenting_holders,
+        })
+    }
+}
+
 /// The various settings to apply to when fetching a record from network
 #[derive(Clone)]
 pub struct GetRecordCfg {
     /// The query will result in an error if we get records less than the provided Quorum
     pub get_quorum: Quorum,
+    /// How the copy to return is picked when the holders disagree.
+    pub resolution: GetRecordResolution,
     /// If enabled, the provided `RetryStrategy` is used to retry if a GET attempt fails.
     pub retry_strategy: Option<RetryStrategy>,
     /// Only return if we fetch the provided record.
@@ -143,9 +260,17 @@ pub struct GetRecordCfg {
     pub expected_holders: HashSet<PeerId>,
     /// For register record, only root value shall be checked, not the entire content.
     pub is_register: bool,
+    /// Fetches the record from the network even if it is held by the record cache, e.g., to verify a PUT.
+    pub bypass_cache: bool,
 }
 
 impl GetRecordCfg {
+    /// Whether the record can be served from the record cache. A quorum above one asks for several holders to confirm
+    /// the record, which the cache cannot do.
+    pub(crate) fn may_use_cache(&self) -> bool {
+        !self.bypass_cache && matches!(self.get_quorum, Quorum::One)
+    }
+
     pub fn does_target_match(&self, record: &Record) -> bool {
         if let Some(ref target_record) = self.target_record {
             if self.is_register {
@@ -183,6 +308,7 @@ impl Debug for GetRecordCfg {
     fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
         let mut f = f.debug_struct("GetRecordCfg");
         f.field("get_quorum", &self.get_quorum)
+            .field("resolution", &self.resolution)
             .field("retry_strategy", &self.retry_strategy);
 
         match &self.target_record {
@@ -195,7 +321,9 @@ impl Debug for GetRecordCfg {
             }
         };
 
-        f.field("expected_holders", &self.expected_holders).finish()
+        f.field("expected_holders", &self.expected_holders)
+            .field("bypass_cache", &self.bypass_cache)
+            .finish()
     }
 }
 
@@ -213,6 +341,32 @@ pub struct PutRecordCfg {
     pub use_put_record_to: Option<Vec<PeerId>>,
     /// Enables verification after writing. The VerificationKind is used to determine the method to use.
     pub verification: Option<(VerificationKind, GetRecordCfg)>,
+    /// If enabled, the record is fetched back after the PUT and re-PUT to the intended holders missing it, see
+    /// `VerifyAfterPutCfg`.
+    pub verify_after_put: Option<VerifyAfterPutCfg>,
+}
+
+/// A successful KAD PUT only means that the record has been sent out. This fetches the record back with a quorum of
+/// one and compares its content. If it does not match, the intended holders, i.e., the `use_put_record_to` peers or
+/// else the close group of the record, are asked for their copy, and the record is put again to the ones missing it.
+///
+/// Only the chunks are verified: the other records are merged or transformed by their holders, so the copy fetched
+/// back cannot be compared to the one put.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct VerifyAfterPutCfg {
+    /// The number of times the record is put again to the peers missing it.
+    pub retries: usize,
+    /// The time left to the holders to store the record before each verification.
+    pub delay: Duration,
+}
+
+impl Default for VerifyAfterPutCfg {
+    fn default() -> Self {
+        Self {
+            retries: 2,
+            delay: Duration::from_secs(1),
+        }
+    }
 }
 
 /// The methods in which verification on a PUT can be carried out.
@@ -235,13 +389,16 @@ pub enum VerificationKind {
 pub(super) struct NodeBehaviour {
     pub(super) blocklist:
         libp2p::allow_block_list::Behaviour<libp2p::allow_block_list::BlockedPeers>,
+    pub(super) connection_limits: libp2p::connection_limits::Behaviour,
+    pub(super) dial_backoff: DialBackoff,
     pub(super) identify: libp2p::identify::Behaviour,
     #[cfg(feature = "local-discovery")]
-    pub(super) mdns: mdns::tokio::Behaviour,
+    pub(super) mdns: LocalDiscovery,
     #[cfg(feature = "upnp")]
     pub(super) upnp: libp2p::swarm::behaviour::toggle::Toggle<libp2p::upnp::tokio::Behaviour>,
     pub(super) relay_client: libp2p::relay::client::Behaviour,
     pub(super) relay_server: libp2p::relay::Behaviour,
+    pub(super) dcutr: libp2p::dcutr::Behaviour,
     pub(super) kademlia: kad::Behaviour<UnifiedRecordStore>,
     pub(super) request_response: request_response::cbor::Behaviour<Request, Response>,
 }
@@ -254,14 +411,27 @@ pub struct NetworkBuilder {
     root_dir: PathBuf,
     listen_addr: Option<SocketAddr>,
     request_timeout: Option<Duration>,
+    query_timeout: Option<Duration>,
+    replication_interval: Option<Duration>,
+    closest_peers_retry: ClosestPeersRetryPolicy,
     concurrency_limit: Option<usize>,
     initial_peers: Vec<Multiaddr>,
+    network_discovery_config: NetworkDiscoveryConfig,
+    // The buckets to cover and the max attempts of the initial generation, see `network_discovery_coverage`.
+    network_discovery_coverage: Option<(usize, usize)>,
+    record_cache_config: Option<RecordCacheConfig>,
+    replication_bandwidth_limit: u64,
+    max_established_connections: Option<u32>,
+    max_pending_incoming_connections: Option<u32>,
+    max_connections_per_peer: Option<u32>,
     #[cfg(feature = "open-metrics")]
     metrics_registries: Option<MetricsRegistries>,
     #[cfg(feature = "open-metrics")]
     metrics_server_port: Option<u16>,
     #[cfg(feature = "upnp")]
     upnp: bool,
+    #[cfg(feature = "local-discovery")]
+    local_discovery: bool,
 }
 
 impl NetworkBuilder {
@@ -273,14 +443,26 @@ impl NetworkBuilder {
             root_dir,
             listen_addr: None,
             request_timeout: None,
+            query_timeout: None,
+            replication_interval: None,
+            closest_peers_retry: Default::default(),
             concurrency_limit: None,
             initial_peers: Default::default(),
+            network_discovery_config: Default::default(),
+            network_discovery_coverage: None,
+            record_cache_config: None,
+            replication_bandwidth_limit: 0,
+            max_established_connections: None,
+            max_pending_incoming_connections: None,
+            max_connections_per_peer: None,
             #[cfg(feature = "open-metrics")]
             metrics_registries: None,
             #[cfg(feature = "open-metrics")]
             metrics_server_port: None,
             #[cfg(feature = "upnp")]
             upnp: false,
+            #[cfg(feature = "local-discovery")]
+            local_discovery: true,
         }
     }
 
@@ -296,6 +478,22 @@ impl NetworkBuilder {
         self.request_timeout = Some(request_timeout);
     }
 
+    /// Set the time before a Kad query times out. Defaults to 10s for nodes and 60s for clients.
+    /// Raise it on high-latency links, where the closest peers queries do not complete in time.
+    pub fn query_timeout(&mut self, query_timeout: Duration) {
+        self.query_timeout = Some(query_timeout);
+    }
+
+    /// Set the minimum interval before replicating to the same peer again. Defaults to 45s.
+    pub fn replication_interval(&mut self, replication_interval: Duration) {
+        self.replication_interval = Some(replication_interval);
+    }
+
+    /// Set how the closest peers queries are retried when they do not yield enough peers.
+    pub fn closest_peers_retry(&mut self, policy: ClosestPeersRetryPolicy) {
+        self.closest_peers_retry = policy;
+    }
+
     pub fn concurrency_limit(&mut self, concurrency_limit: usize) {
         self.concurrency_limit = Some(concurrency_limit);
     }
@@ -304,6 +502,46 @@ impl NetworkBuilder {
         self.initial_peers = initial_peers;
     }
 
+    /// Set the config used to generate and retain the network discovery candidates.
+    pub fn network_discovery_config(&mut self, config: NetworkDiscoveryConfig) {
+        self.network_discovery_config = config;
+    }
+
+    /// Generate the initial network discovery candidates until they cover at least `target_buckets` buckets, or
+    /// `max_attempts` PeerIds have been generated, rather than the fixed `initial_attempts` of the config. The
+    /// candidates are then generated while building, instead of in the background once the driver runs.
+    pub fn network_discovery_coverage(&mut self, target_buckets: usize, max_attempts: usize) {
+        self.network_discovery_coverage = Some((target_buckets, max_attempts));
+    }
+
+    /// Set the cache of the records fetched from the network. Defaults to enabled for clients and disabled for nodes.
+    pub fn record_cache(&mut self, config: RecordCacheConfig) {
+        self.record_cache_config = Some(config);
+    }
+
+    /// Set the bytes per second of the records sent to the peers fetching them during replication, the excess transfers
+    /// being queued. The records fetched by the clients are never throttled. Defaults to 0, i.e., unlimited.
+    pub fn replication_bandwidth_limit(&mut self, bytes_per_sec: u64) {
+        self.replication_bandwidth_limit = bytes_per_sec;
+    }
+
+    /// Set the max number of established connections. Beyond it, the connection to a peer out of our close group with
+    /// no request in flight is closed, rather than the new connection being refused. Unlimited by default.
+    pub fn max_established_connections(&mut self, limit: u32) {
+        self.max_established_connections = Some(limit);
+    }
+
+    /// Set the max number of incoming connections being negotiated, the extra ones being refused. Unlimited by default.
+    pub fn max_pending_incoming_connections(&mut self, limit: u32) {
+        self.max_pending_incoming_connections = Some(limit);
+    }
+
+    /// Set the max number of established connections to a single peer, the extra ones being refused. Unlimited by
+    /// default.
+    pub fn max_connections_per_peer(&mut self, limit: u32) {
+        self.max_connections_per_peer = Some(limit);
+    }
+
     /// Set the registries used inside the metrics server.
     /// Configure the `metrics_server_port` to enable the metrics server.
     #[cfg(feature = "open-metrics")]
@@ -322,6 +560,13 @@ impl NetworkBuilder {
         self.upnp = upnp;
     }
 
+    /// Set whether the peers of the local network are discovered through mDNS. Enabled by default, this can be
+    /// switched at runtime through `Network::set_local_discovery`.
+    #[cfg(feature = "local-discovery")]
+    pub fn local_discovery(&mut self, enable: bool) {
+        self.local_discovery = enable;
+    }
+
     /// Creates a new `SwarmDriver` instance, along with a `Network` handle
     /// for sending commands and an `mpsc::Receiver<NetworkEvent>` for receiving
     /// network events. It initializes the swarm, sets up the transport, and
@@ -336,30 +581,9 @@ impl NetworkBuilder {
     ///
     /// Returns an error if there is a problem initializing the mDNS behaviour.
     pub fn build_node(self) -> Result<(Network, mpsc::Receiver<NetworkEvent>, SwarmDriver)> {
-        let mut kad_cfg = kad::Config::new(KAD_STREAM_PROTOCOL_ID);
-        let _ = kad_cfg
-            .set_kbucket_inserts(libp2p::kad::BucketInserts::Manual)
-            // how often a node will replicate records that it has stored, aka copying the key-value pair to other nodes
-            // this is a heavier operation than publication, so it is done less frequently
-            // Set to `None` to ensure periodic replication disabled.
-            .set_replication_interval(None)
-            // how often a node will publish a record key, aka telling the others it exists
-            // Set to `None` to ensure periodic publish disabled.
-            .set_publication_interval(None)
-            // 1mb packet size
-            .set_max_packet_size(MAX_PACKET_SIZE)
-            // How many nodes _should_ store data.
-            .set_replication_factor(REPLICATION_FACTOR)
-            .set_query_timeout(KAD_QUERY_TIMEOUT_S)
-            // Require iterative queries to use disjoint paths for increased resiliency in the presence of potentially adversarial nodes.
-            .disjoint_query_paths(true)
-            // Records never expire
-            .set_record_ttl(None)
-            // Emit PUT events for validation prior to insertion into the RecordStore.
-            // This is no longer needed as the record_storage::put now can carry out validation.
-            // .set_record_filtering(KademliaStoreInserts::FilterBoth)
-            // Disable provider records publication job
-            .set_provider_publication_interval(None);
+        self.validate_intervals()?;
+        self.validate_generation_attempts()?;
+        let kad_cfg = self.node_kad_config();
 
         let store_cfg = {
             // Configures the disk_store to store records under the provided path and increase the max record size
@@ -419,18 +643,9 @@ impl NetworkBuilder {
 
     /// Same as `build_node` API but creates the network components in client mode
     pub fn build_client(self) -> Result<(Network, mpsc::Receiver<NetworkEvent>, SwarmDriver)> {
-        // Create a Kademlia behaviour for client mode, i.e. set req/resp protocol
-        // to outbound-only mode and don't listen on any address
-        let mut kad_cfg = kad::Config::new(KAD_STREAM_PROTOCOL_ID); // default query timeout is 60 secs
-
-        // 1mb packet size
-        let _ = kad_cfg
-            .set_kbucket_inserts(libp2p::kad::BucketInserts::Manual)
-            .set_max_packet_size(MAX_PACKET_SIZE)
-            // Require iterative queries to use disjoint paths for increased resiliency in the presence of potentially adversarial nodes.
-            .disjoint_query_paths(true)
-            // How many nodes _should_ store data.
-            .set_replication_factor(REPLICATION_FACTOR);
+        self.validate_intervals()?;
+        self.validate_generation_attempts()?;
+        let kad_cfg = self.client_kad_config();
 
         let (network, net_event_recv, driver) = self.build(
             kad_cfg,
@@ -445,6 +660,110 @@ impl NetworkBuilder {
         Ok((network, net_event_recv, driver))
     }
 
+    /// Rejects the sub-second query timeout, replication interval and retry delay, as nothing could complete within
+    /// those.
+    fn validate_intervals(&self) -> Result<()> {
+        for (name, value) in [
+            ("query timeout", self.query_timeout),
+            ("replication interval", self.replication_interval),
+            (
+                "closest peers retry delay",
+                Some(self.closest_peers_retry.base_delay),
+            ),
+        ] {
+            if let Some(value) = value {
+                if value < MIN_CONFIGURABLE_INTERVAL {
+                    return Err(NetworkError::IntervalTooShort {
+                        name,
+                        value,
+                        min: MIN_CONFIGURABLE_INTERVAL,
+                    });
+                }
+            }
+        }
+        Ok(())
+    }
+
+    /// Rejects the network discovery attempts of 0, with which no candidate would ever be generated.
+    fn validate_generation_attempts(&self) -> Result<()> {
+        for (name, value) in [
+            (
+                "initial attempts",
+                self.network_discovery_config.initial_attempts,
+            ),
+            (
+                "refresh attempts",
+                self.network_discovery_config.refresh_attempts,
+            ),
+        ]
+        .into_iter()
+        .chain(
+            self.network_discovery_coverage
+                .map(|(_, max_attempts)| ("coverage max attempts", max_attempts)),
+        ) {
+            if value == 0 {
+                return Err(NetworkError::ZeroGenerationAttempts { name });
+            }
+        }
+        Ok(())
+    }
+
+    /// The Kademlia config used in node mode.
+    fn node_kad_config(&self) -> kad::Config {
+        let mut kad_cfg = kad::Config::new(KAD_STREAM_PROTOCOL_ID);
+        let _ = kad_cfg
+            .set_kbucket_inserts(libp2p::kad::BucketInserts::Manual)
+            // how often a node will replicate records that it has stored, aka copying the key-value pair to other nodes
+            // this is a heavier operation than publication, so it is done less frequently
+            // Set to `None` to ensure periodic replication disabled.
+            .set_replication_interval(None)
+            // how often a node will publish a record key, aka telling the others it exists
+            // Set to `None` to ensure periodic publish disabled.
+            .set_publication_interval(None)
+            // 1mb packet size
+            .set_max_packet_size(MAX_PACKET_SIZE)
+            // How many nodes _should_ store data.
+            .set_replication_factor(REPLICATION_FACTOR)
+            .set_query_timeout(self.effective_query_timeout(false))
+            // Require iterative queries to use disjoint paths for increased resiliency in the presence of potentially adversarial nodes.
+            .disjoint_query_paths(true)
+            // Records never expire
+            .set_record_ttl(None)
+            // Emit PUT events for validation prior to insertion into the RecordStore.
+            // This is no longer needed as the record_storage::put now can carry out validation.
+            // .set_record_filtering(KademliaStoreInserts::FilterBoth)
+            // Disable provider records publication job
+            .set_provider_publication_interval(None);
+        kad_cfg
+    }
+
+    /// The Kademlia config used in client mode.
+    fn client_kad_config(&self) -> kad::Config {
+        // Create a Kademlia behaviour for client mode, i.e. set req/resp protocol
+        // to outbound-only mode and don't listen on any address
+        let mut kad_cfg = kad::Config::new(KAD_STREAM_PROTOCOL_ID);
+
+        // 1mb packet size
+        let _ = kad_cfg
+            .set_kbucket_inserts(libp2p::kad::BucketInserts::Manual)
+            .set_max_packet_size(MAX_PACKET_SIZE)
+            // Require iterative queries to use disjoint paths for increased resiliency in the presence of potentially adversarial nodes.
+            .disjoint_query_paths(true)
+            // How many nodes _should_ store data.
+            .set_replication_factor(REPLICATION_FACTOR)
+            .set_query_timeout(self.effective_query_timeout(true));
+        kad_cfg
+    }
+
+    /// The Kademlia query timeout in effect, the configured one or the default of the mode.
+    fn effective_query_timeout(&self, is_client: bool) -> Duration {
+        self.query_timeout.unwrap_or(if is_client {
+            CLIENT_KAD_QUERY_TIMEOUT_S
+        } else {
+            KAD_QUERY_TIMEOUT_S
+        })
+    }
+
     /// Private helper to create the network components with the provided config and req/res behaviour
     fn build(
         self,
@@ -467,6 +786,14 @@ impl NetworkBuilder {
             PrettyPrintKBucketKey(NetworkAddress::from_peer(peer_id).as_kbucket_key())
         );
 
+        let replication_interval = self
+            .replication_interval
+            .unwrap_or(REPLICATION_INTERVAL_DEFAULT);
+        let query_timeout = self.effective_query_timeout(is_client);
+        info!(
+            "Kademlia query timeout: {query_timeout:?}, replication interval: {replication_interval:?}"
+        );
+
         #[cfg(feature = "open-metrics")]
         let mut metrics_registries = self.metrics_registries.unwrap_or_default();
 
@@ -547,6 +874,7 @@ impl NetworkBuilder {
         };
 
         let (network_event_sender, network_event_receiver) = mpsc::channel(NETWORKING_CHANNEL_SIZE);
+        let (observed_events, _) = broadcast::channel(OBSERVED_EVENTS_CAPACITY);
         let (network_swarm_cmd_sender, network_swarm_cmd_receiver) =
             mpsc::channel(NETWORKING_CHANNEL_SIZE);
         let (local_swarm_cmd_sender, local_swarm_cmd_receiver) =
@@ -593,7 +921,7 @@ impl NetworkBuilder {
         };
 
         #[cfg(feature = "local-discovery")]
-        let mdns = mdns::tokio::Behaviour::new(mdns_config, peer_id)?;
+        let mdns = LocalDiscovery::new(mdns_config, peer_id, self.local_discovery)?;
 
         // Identify Behaviour
         let identify_protocol_str = IDENTIFY_PROTOCOL_STR.to_string();
@@ -629,10 +957,41 @@ impl NetworkBuilder {
             libp2p::relay::Behaviour::new(peer_id, relay_server_cfg)
         };
 
+        // Nodes persist the peers blocked by their operator, so that they stay blocked across restarts.
+        let blocked_peers = if is_client {
+            PeerBlocklist::default()
+        } else {
+            PeerBlocklist::load(self.root_dir.join(BLOCKED_PEERS_FILENAME))
+        };
+        // Only the clients fetch the same records again and again, the nodes hold theirs in the record store.
+        let record_cache_config = self.record_cache_config.unwrap_or_else(|| {
+            if is_client {
+                RecordCacheConfig::default()
+            } else {
+                RecordCacheConfig::disabled()
+            }
+        });
+        let mut blocklist = libp2p::allow_block_list::Behaviour::default();
+        for peer_id in blocked_peers.peers() {
+            blocklist.block_peer(*peer_id);
+        }
+
+        // The established connections are not limited here, as the least useful ones are evicted beyond the limit
+        // instead, see `enforce_max_established_connections`.
+        let connection_limits = libp2p::connection_limits::Behaviour::new(
+            libp2p::connection_limits::ConnectionLimits::default()
+                .with_max_pending_incoming(self.max_pending_incoming_connections)
+                .with_max_established_per_peer(self.max_connections_per_peer),
+        );
+
         let behaviour = NodeBehaviour {
-            blocklist: libp2p::allow_block_list::Behaviour::default(),
+            blocklist,
+            connection_limits,
+            dial_backoff: Default::default(),
             relay_client: relay_behaviour,
             relay_server,
+            // Both ends of a relayed connection need it, hence it is not restricted to the nodes behind a NAT.
+            dcutr: libp2p::dcutr::Behaviour::new(peer_id),
             #[cfg(feature = "upnp")]
             upnp,
             request_response,
@@ -659,6 +1018,71 @@ impl NetworkBuilder {
         }
         let external_address_manager = ExternalAddressManager::new(peer_id);
 
+        // The initial candidates are generated off the driver thread once it starts running.
+        #[cfg(not(target_arch = "wasm32"))]
+        let mut network_discovery = {
+            // Nodes persist their candidates alongside the record store, so that restarts can reuse them.
+            let snapshot = (!is_client)
+                .then(|| {
+                    NetworkDiscoverySnapshot::read_from_disk(
+                        &self.root_dir.join(NETWORK_DISCOVERY_CANDIDATES_FILENAME),
+                    )
+                })
+                .flatten();
+            let mut network_discovery = match snapshot {
+                Some(snapshot) => NetworkDiscovery::from_snapshot(
+                    &peer_id,
+                    self.network_discovery_config,
+                    snapshot,
+                ),
+                None => match self.network_discovery_coverage {
+                    Some((target_buckets, max_attempts)) => {
+                        NetworkDiscovery::new_until_coverage(
+                            &peer_id,
+                            self.network_discovery_config,
+                            target_buckets,
+                            max_attempts,
+                        )
+                        .0
+                    }
+                    None => NetworkDiscovery::new_lazy(&peer_id, self.network_discovery_config),
+                },
+            };
+            if !is_client {
+                network_discovery
+                    .set_candidates_file(self.root_dir.join(NETWORK_DISCOVERY_CANDIDATES_FILENAME));
+            }
+            network_discovery
+        };
+        // There are no blocking threads to spare here, hence the generation is spread over the first refreshes.
+        #[cfg(target_arch = "wasm32")]
+        let mut network_discovery = match self.network_discovery_coverage {
+            Some((target_buckets, max_attempts)) => {
+                NetworkDiscovery::new_until_coverage(
+                    &peer_id,
+                    self.network_discovery_config,
+                    target_buckets,
+                    max_attempts,
+                )
+                .0
+            }
+            None => NetworkDiscovery::new_incremental(&peer_id, self.network_discovery_config),
+        };
+        // Our bootstrap peers are known to be reachable, hence seed the candidates with them.
+        let seed_peers = self
+            .initial_peers
+            .iter()
+            .filter_map(|addr| multiaddr_pop_p2p(&mut addr.clone()))
+            .collect::<Vec<_>>();
+        // Set before the seeding, so that the buckets populated by the bootstrap peers are notified as well.
+        let (populated_bucket_sender, populated_bucket_receiver) = mpsc::channel(256);
+        network_discovery.set_populated_bucket_sender(populated_bucket_sender);
+        network_discovery.seed(&seed_peers);
+        #[cfg(feature = "open-metrics")]
+        if let Some(metrics_recorder) = &metrics_recorder {
+            network_discovery.set_metrics(metrics_recorder.network_discovery.clone());
+        }
+
         let swarm_driver = SwarmDriver {
             swarm,
             self_peer_id: peer_id,
@@ -685,15 +1109,30 @@ impl NetworkBuilder {
             // We use 255 here which allows covering a network larger than 64k without any rotating.
             // This is based on the libp2p kad::kBuckets peers distribution.
             dialed_peers: CircularVec::new(255),
-            network_discovery: NetworkDiscovery::new(&peer_id),
+            network_discovery,
+            populated_bucket_receiver,
+            network_discovery_queue: Default::default(),
             bootstrap_peers: Default::default(),
             live_connected_peers: Default::default(),
             handling_statistics: Default::default(),
             handled_times: 0,
             hard_disk_write_error: 0,
             bad_nodes: Default::default(),
+            blocked_peers,
+            peer_scores: PeerScores::default(),
+            record_cache: RecordCache::new(record_cache_config),
+            max_established_connections: self
+                .max_established_connections
+                .map(|limit| limit as usize),
+            replication_throttle: ReplicationThrottle::new(
+                self.replication_bandwidth_limit,
+                Instant::now(),
+            ),
             quotes_history: Default::default(),
             replication_targets: Default::default(),
+            replication_interval,
+            observed_events: observed_events.clone(),
+            replication_rounds: Default::default(),
         };
 
         let network = Network::new(
@@ -702,6 +1141,8 @@ impl NetworkBuilder {
             peer_id,
             self.root_dir,
             self.keypair,
+            self.closest_peers_retry,
+            observed_events,
         );
 
         Ok((network, network_event_receiver, swarm_driver))
@@ -732,14 +1173,17 @@ pub struct SwarmDriver {
 
     /// Trackers for underlying behaviour related events
     pub(crate) pending_get_closest_peers: PendingGetClosest,
-    pub(crate) pending_requests:
-        HashMap<OutboundRequestId, Option<oneshot::Sender<Result<Response>>>>,
+    pub(crate) pending_requests: PendingRequests,
     pub(crate) pending_get_record: PendingGetRecord,
     /// A list of the most recent peers we have dialed ourselves. Old dialed peers are evicted once the vec fills up.
     pub(crate) dialed_peers: CircularVec<PeerId>,
     // A list of random `PeerId` candidates that falls into kbuckets,
     // This is to ensure a more accurate network discovery.
     pub(crate) network_discovery: NetworkDiscovery,
+    /// Receives the ilog2 of each bucket the first time the network discovery gets a candidate for it.
+    populated_bucket_receiver: mpsc::Receiver<u32>,
+    /// The targets taken by the last round of network discovery, queried a few at a time by the discovery ticks.
+    pub(crate) network_discovery_queue: VecDeque<NetworkAddress>,
     pub(crate) bootstrap_peers: BTreeMap<Option<u32>, HashSet<PeerId>>,
     // Peers that having live connection to. Any peer got contacted during kad network query
     // will have live connection established. And they may not appear in the RT.
@@ -749,8 +1193,24 @@ pub struct SwarmDriver {
     handled_times: usize,
     pub(crate) hard_disk_write_error: usize,
     pub(crate) bad_nodes: BadNodes,
+    /// The peers blocked on request of the node operator.
+    pub(crate) blocked_peers: PeerBlocklist,
+    /// The scores of the peers we exchange with, the consistently failing ones being evicted for a while.
+    pub(crate) peer_scores: PeerScores,
+    /// The records fetched lately, served again without querying the network.
+    pub(crate) record_cache: RecordCache,
+    /// The connections beyond this are evicted, see `enforce_max_established_connections`.
+    pub(crate) max_established_connections: Option<usize>,
+    /// Paces the records sent to the peers fetching them during replication.
+    pub(crate) replication_throttle:
+        ReplicationThrottle<(request_response::ResponseChannel<Response>, Response)>,
     pub(crate) quotes_history: BTreeMap<PeerId, PaymentQuote>,
     pub(crate) replication_targets: BTreeMap<PeerId, Instant>,
+    /// The minimum interval before replicating to the same peer again.
+    pub(crate) replication_interval: Duration,
+    /// Publishes the events observed through `Network::events_channel`.
+    pub(crate) observed_events: broadcast::Sender<ObservedNetworkEvent>,
+    pub(crate) replication_rounds: ReplicationRounds,
 }
 
 impl SwarmDriver {
@@ -765,6 +1225,12 @@ impl SwarmDriver {
         let mut bootstrap_interval = interval(BOOTSTRAP_INTERVAL);
         let mut set_farthest_record_interval = interval(CLOSET_RECORD_CHECK_INTERVAL);
         let mut relay_manager_reservation_interval = interval(RELAY_MANAGER_RESERVATION_INTERVAL);
+        let mut network_discovery_query_interval = interval(NETWORK_DISCOVERY_QUERY_INTERVAL);
+        let mut eviction_check_interval = interval(EVICTION_CHECK_INTERVAL);
+        let mut replication_throttle_interval = interval(REPLICATION_THROTTLE_CHECK_INTERVAL);
+
+        #[cfg(not(target_arch = "wasm32"))]
+        self.spawn_network_discovery_population();
 
         loop {
             tokio::select! {
@@ -826,6 +1292,12 @@ impl SwarmDriver {
                     }
                 }
                 _ = relay_manager_reservation_interval.tick() => self.relay_manager.try_connecting_to_relay(&mut self.swarm, &self.bad_nodes),
+                _ = network_discovery_query_interval.tick() => self.query_next_network_discovery_targets(),
+                Some(bucket) = self.populated_bucket_receiver.recv() => {
+                    self.publish_observed_event(ObservedNetworkEvent::DiscoveryBucketPopulated { bucket });
+                }
+                _ = eviction_check_interval.tick() => self.lift_expired_evictions(),
+                _ = replication_throttle_interval.tick() => self.send_ready_replication_transfers(),
             }
         }
     }
@@ -999,3 +1471,363 @@ impl SwarmDriver {
         Ok(())
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn builder() -> NetworkBuilder {
+        NetworkBuilder::new(Keypair::generate_ed25519(), true, std::env::temp_dir())
+    }
+
+    #[test]
+    fn query_timeout_defaults_to_the_mode_and_follows_the_builder() {
+        let builder = builder();
+        assert_eq!(builder.effective_query_timeout(false), KAD_QUERY_TIMEOUT_S);
+        assert_eq!(
+            builder.effective_query_timeout(true),
+            CLIENT_KAD_QUERY_TIMEOUT_S
+        );
+
+        let mut builder = self::builder();
+        builder.query_timeout(Duration::from_secs(90));
+        assert_eq!(
+            builder.effective_query_timeout(false),
+            Duration::from_secs(90)
+        );
+        assert_eq!(
+            builder.effective_query_timeout(true),
+            Duration::from_secs(90)
+        );
+    }
+
+    #[test]
+    fn sub_second_intervals_are_rejected() {
+        let mut builder = builder();
+        builder.query_timeout(Duration::from_millis(500));
+        assert!(matches!(
+            builder.build_client(),
+            Err(NetworkError::IntervalTooShort {
+                name: "query timeout",
+                ..
+            })
+        ));
+
+        let mut builder = self::builder();
+        builder.replication_interval(Duration::from_millis(999));
+        assert!(matches!(
+            builder.build_client(),
+            Err(NetworkError::IntervalTooShort {
+                name: "replication interval",
+                ..
+            })
+        ));
+
+        let mut builder = self::builder();
+        builder.closest_peers_retry(ClosestPeersRetryPolicy {
+            base_delay: Duration::from_millis(100),
+            ..Default::default()
+        });
+        assert!(matches!(
+            builder.build_client(),
+            Err(NetworkError::IntervalTooShort {
+                name: "closest peers retry delay",
+                ..
+            })
+        ));
+    }
+
+    #[test]
+    fn zero_generation_attempts_are_rejected() {
+        let mut builder = builder();
+        builder.network_discovery_config(NetworkDiscoveryConfig {
+            initial_attempts: 0,
+            ..Default::default()
+        });
+        assert!(matches!(
+            builder.build_client(),
+            Err(NetworkError::ZeroGenerationAttempts {
+                name: "initial attempts"
+            })
+        ));
+
+        let mut builder = self::builder();
+        builder.network_discovery_config(NetworkDiscoveryConfig {
+            initial_attempts: 2_000,
+            refresh_attempts: 0,
+            ..Default::default()
+        });
+        assert!(matches!(
+            builder.build_client(),
+            Err(NetworkError::ZeroGenerationAttempts {
+                name: "refresh attempts"
+            })
+        ));
+
+        let mut builder = self::builder();
+        builder.network_discovery_config(NetworkDiscoveryConfig {
+            initial_attempts: 2_000,
+            refresh_attempts: 100,
+            ..Default::default()
+        });
+        assert!(builder.build_client().is_ok());
+
+        let mut builder = self::builder();
+        builder.network_discovery_coverage(4, 0);
+        assert!(matches!(
+            builder.build_client(),
+            Err(NetworkError::ZeroGenerationAttempts {
+                name: "coverage max attempts"
+            })
+        ));
+    }
+
+    #[tokio::test]
+    async fn network_discovery_coverage_is_generated_while_building() -> Result<()> {
+        let (_, _, driver) = builder().build_client()?;
+        assert!(!driver.network_discovery.has_reached_coverage(4));
+
+        let mut builder = builder();
+        builder.network_discovery_coverage(4, 100_000);
+        let (_, _, driver) = builder.build_client()?;
+        assert!(driver.network_discovery.has_reached_coverage(4));
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn replication_interval_reaches_the_driver() {
+        let (_, _, driver) = builder().build_client().expect("client builds");
+        assert_eq!(driver.replication_interval, REPLICATION_INTERVAL_DEFAULT);
+
+        let mut builder = builder();
+        builder.replication_interval(Duration::from_secs(90));
+        let (_, _, driver) = builder.build_client().expect("client builds");
+        assert_eq!(driver.replication_interval, Duration::from_secs(90));
+    }
+
+    #[tokio::test]
+    async fn replication_bandwidth_limit_is_adjustable_at_runtime() -> Result<()> {
+        let (_, _, driver) = builder().build_client()?;
+        assert!(!driver.replication_throttle.is_limited());
+
+        let mut builder = builder();
+        builder.replication_bandwidth_limit(1_000);
+        let (_, _, mut driver) = builder.build_client()?;
+        assert!(driver.replication_throttle.is_limited());
+
+        driver
+            .handle_local_cmd(LocalSwarmCmd::SetReplicationBandwidthLimit { bytes_per_sec: 0 })?;
+        assert!(!driver.replication_throttle.is_limited());
+        Ok(())
+    }
+
+    fn copies_held_by(copies: &[(&[u8], usize)]) -> GetRecordResultMap {
+        let key = RecordKey::new(&b"key");
+        copies
+            .iter()
+            .map(|(value, holders)| {
+                let record = Record::new(key.clone(), value.to_vec());
+                let holders = (0..*holders).map(|_| PeerId::random()).collect();
+                (XorName::from_content(&record.value), (record, holders))
+            })
+            .collect()
+    }
+
+    #[test]
+    fn majority_copy_needs_more_than_half_of_the_holders() {
+        let unanimous = MajorityRecord::from_copies(&copies_held_by(&[(b"a", 3)]))
+            .expect("a single copy is the majority");
+        assert_eq!(unanimous.record.value, b"a");
+        assert!(unanimous.dissenting_holders.is_empty());
+
+        let result_map = copies_held_by(&[(b"a", 3), (b"b", 1), (b"c", 1)]);
+        let majority = MajorityRecord::from_copies(&result_map).expect("3 out of 5 is a majority");
+        assert_eq!(majority.record.value, b"a");
+        let dissenting = result_map
+            .values()
+            .filter(|(record, _)| record.value != b"a")
+            .flat_map(|(_, holders)| holders.iter().copied())
+            .collect::<HashSet<_>>();
+        assert_eq!(majority.dissenting_holders, dissenting);
+
+        // The most held copy is not enough, it must be held by a majority.
+        assert!(
+            MajorityRecord::from_copies(&copies_held_by(&[(b"a", 2), (b"b", 1), (b"c", 1)]))
+                .is_none()
+        );
+        assert!(MajorityRecord::from_copies(&copies_held_by(&[(b"a", 2), (b"b", 2)])).is_none());
+        assert!(MajorityRecord::from_copies(&Default::default()).is_none());
+    }
+
+    #[tokio::test]
+    async fn majority_resolution_waits_for_the_quorum_of_holders() -> eyre::Result<()> {
+        let (_, _, mut driver) = builder().build_client()?;
+        let key = RecordKey::new(&b"key");
+        let query_id = driver
+            .swarm
+            .behaviour_mut()
+            .kademlia
+            .get_record(key.clone());
+        let cfg = GetRecordCfg {
+            get_quorum: Quorum::N(NonZeroUsize::new(5).expect("5 is not 0")),
+            resolution: GetRecordResolution::Majority,
+            retry_strategy: None,
+            target_record: None,
+            expected_holders: Default::default(),
+            is_register: false,
+            bypass_cache: false,
+        };
+        let (sender, mut receiver) = oneshot::channel();
+        let _ = driver.pending_get_record.insert(
+            query_id,
+            (key.clone(), vec![sender], Default::default(), cfg),
+        );
+
+        // The holders answer with mixed copies, the quorum being reached with the fifth one.
+        let mut minority_holders = HashSet::new();
+        for (index, value) in [b"new", b"old", b"new", b"old", b"new"].iter().enumerate() {
+            assert!(receiver.try_recv().is_err());
+            let holder = PeerId::random();
+            if *value == b"old" {
+                let _ = minority_holders.insert(holder);
+            }
+            driver.accumulate_get_record_found(
+                query_id,
+                kad::PeerRecord {
+                    peer: Some(holder),
+                    record: Record::new(key.clone(), value.to_vec()),
+                },
+                kad::ProgressStep {
+                    count: NonZeroUsize::new(index + 1).expect("index + 1 is not 0"),
+                    last: false,
+                },
+            )?;
+        }
+
+        let result_map = match receiver.try_recv()? {
+            Err(GetRecordError::SplitRecord { result_map }) => result_map,
+            other => eyre::bail!("expected the copies to be split, got {other:?}"),
+        };
+        assert_eq!(result_map.len(), 2);
+        let majority = MajorityRecord::from_copies(&result_map).expect("3 out of 5 is a majority");
+        assert_eq!(majority.record.value, b"new");
+        assert_eq!(majority.dissenting_holders, minority_holders);
+        assert!(!driver.pending_get_record.contains_key(&query_id));
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn second_fetch_of_a_chunk_is_served_from_the_record_cache() -> eyre::Result<()> {
+        use sn_protocol::storage::{try_serialize_record, Chunk, RecordKind};
+
+        let (_, _, mut driver) = builder().build_client()?;
+        let chunk = Chunk::new(bytes::Bytes::from_static(b"chunk content"));
+        let key = chunk.network_address().to_record_key();
+        let record = Record::new(
+            key.clone(),
+            try_serialize_record(&chunk, RecordKind::Chunk)?.to_vec(),
+        );
+        let cfg = GetRecordCfg {
+            get_quorum: Quorum::One,
+            resolution: Default::default(),
+            retry_strategy: None,
+            target_record: None,
+            expected_holders: Default::default(),
+            is_register: false,
+            bypass_cache: false,
+        };
+
+        let (sender, mut receiver) = oneshot::channel();
+        driver.handle_network_cmd(NetworkSwarmCmd::GetNetworkRecord {
+            key: key.clone(),
+            sender,
+            cfg: cfg.clone(),
+        })?;
+        let query_id = *driver
+            .pending_get_record
+            .keys()
+            .next()
+            .expect("the first fetch queries the network");
+        driver.accumulate_get_record_found(
+            query_id,
+            kad::PeerRecord {
+                peer: Some(PeerId::random()),
+                record: record.clone(),
+            },
+            kad::ProgressStep {
+                count: NonZeroUsize::new(1).expect("1 is not 0"),
+                last: false,
+            },
+        )?;
+        assert!(matches!(receiver.try_recv()?, Ok(fetched) if fetched == record));
+        assert!(driver.pending_get_record.is_empty());
+
+        let (sender, mut receiver) = oneshot::channel();
+        driver.handle_network_cmd(NetworkSwarmCmd::GetNetworkRecord { key, sender, cfg })?;
+        assert!(matches!(receiver.try_recv()?, Ok(fetched) if fetched == record));
+        assert!(driver.pending_get_record.is_empty());
+        assert_eq!(
+            (driver.record_cache.hits, driver.record_cache.misses),
+            (1, 1)
+        );
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn get_above_quorum_one_or_bypassing_the_cache_reaches_the_network() -> eyre::Result<()> {
+        use sn_protocol::storage::{try_serialize_record, Chunk, RecordKind};
+
+        let (_, _, mut driver) = builder().build_client()?;
+        let chunk = Chunk::new(bytes::Bytes::from_static(b"chunk content"));
+        let key = chunk.network_address().to_record_key();
+        let record = Record::new(
+            key.clone(),
+            try_serialize_record(&chunk, RecordKind::Chunk)?.to_vec(),
+        );
+        assert!(driver.record_cache.insert(&record));
+
+        let cfg = GetRecordCfg {
+            get_quorum: Quorum::One,
+            resolution: Default::default(),
+            retry_strategy: None,
+            target_record: None,
+            expected_holders: Default::default(),
+            is_register: false,
+            bypass_cache: false,
+        };
+        for cfg in [
+            GetRecordCfg {
+                get_quorum: Quorum::Majority,
+                ..cfg.clone()
+            },
+            GetRecordCfg {
+                get_quorum: Quorum::All,
+                ..cfg.clone()
+            },
+            GetRecordCfg {
+                bypass_cache: true,
+                ..cfg.clone()
+            },
+        ] {
+            let (sender, mut receiver) = oneshot::channel();
+            driver.handle_network_cmd(NetworkSwarmCmd::GetNetworkRecord {
+                key: key.clone(),
+                sender,
+                cfg: cfg.clone(),
+            })?;
+            assert!(receiver.try_recv().is_err(), "{cfg:?} is served locally");
+            assert_eq!(driver.pending_get_record.len(), 1);
+            driver.pending_get_record.clear();
+        }
+        assert_eq!(
+            (driver.record_cache.hits, driver.record_cache.misses),
+            (0, 0)
+        );
+
+        let (sender, mut receiver) = oneshot::channel();
+        driver.handle_network_cmd(NetworkSwarmCmd::GetNetworkRecord { key, sender, cfg })?;
+        assert!(matches!(receiver.try_recv()?, Ok(fetched) if fetched == record));
+        assert!(driver.pending_get_record.is_empty());
+        Ok(())
+    }
+}
@@ -19,6 +19,7 @@ use std::{
     fmt::Debug,
     io,
     path::PathBuf,
+    time::Duration,
 };
 use thiserror::Error;
 use tokio::sync::oneshot;
@@ -156,6 +157,16 @@ pub enum NetworkError {
     #[error("Node Listen Address was not provided during construction")]
     ListenAddressNotProvided,
 
+    #[error("The {name} of {value:?} is too short, it shall be at least {min:?}")]
+    IntervalTooShort {
+        name: &'static str,
+        value: Duration,
+        min: Duration,
+    },
+
+    #[error("The network discovery {name} shall not be 0, no candidate would be generated")]
+    ZeroGenerationAttempts { name: &'static str },
+
     #[cfg(feature = "open-metrics")]
     #[error("Network Metric error")]
     NetworkMetricError,
@@ -7,17 +7,21 @@
 // permissions and limitations relating to use of the SAFE Network Software.
 
 use crate::{
-    driver::PendingGetClosestType, get_quorum_value, get_raw_signed_spends_from_record,
-    GetRecordCfg, GetRecordError, NetworkError, Result, SwarmDriver, CLOSE_GROUP_SIZE,
+    driver::{GetRecordResolution, PendingGetClosestType},
+    get_quorum_value, get_raw_signed_spends_from_record, GetRecordCfg, GetRecordError,
+    NetworkError, ObservedNetworkEvent, Result, SwarmDriver, CLOSE_GROUP_SIZE,
 };
 use itertools::Itertools;
-use libp2p::kad::{
-    self, GetClosestPeersError, InboundRequest, PeerRecord, ProgressStep, QueryId, QueryResult,
-    QueryStats, Record, K_VALUE,
+use libp2p::{
+    kad::{
+        self, GetClosestPeersError, InboundRequest, PeerRecord, ProgressStep, QueryId, QueryResult,
+        QueryStats, Record, K_VALUE,
+    },
+    PeerId,
 };
 use sn_protocol::{
     storage::{try_serialize_record, RecordKind},
-    PrettyPrintRecordKey,
+    NetworkAddress, PrettyPrintRecordKey,
 };
 use sn_transfers::SignedSpend;
 use std::{
@@ -32,6 +36,21 @@ impl SwarmDriver {
         let start = Instant::now();
         let event_string;
 
+        #[cfg(feature = "open-metrics")]
+        if let kad::Event::OutboundQueryProgressed {
+            result,
+            stats,
+            step,
+            ..
+        } = &kad_event
+        {
+            if let (true, Some(metrics_recorder)) = (step.last, &self.metrics_recorder) {
+                metrics_recorder
+                    .operation_latency
+                    .observe_kad_query(result, stats);
+            }
+        }
+
         match kad_event {
             kad::Event::OutboundQueryProgressed {
                 id,
@@ -53,16 +72,27 @@ impl SwarmDriver {
                     //       following criteria:
                     //   1, `stats.num_pending()` is 0
                     //   2, `stats.duration()` is longer than a defined period
-                    current_closest.extend(closest_peers.peers.iter().map(|i| i.peer_id));
+                    current_closest.extend(
+                        closest_peers
+                            .peers
+                            .iter()
+                            .map(|i| i.peer_id)
+                            .filter(|peer_id| !self.blocked_peers.contains(peer_id)),
+                    );
                     if current_closest.len() >= usize::from(K_VALUE) || step.last {
                         let (get_closest_type, current_closest) = entry.remove();
                         match get_closest_type {
-                            PendingGetClosestType::NetworkDiscovery => self
-                                .network_discovery
-                                .handle_get_closest_query(current_closest),
+                            PendingGetClosestType::NetworkDiscovery(candidate) => {
+                                let success = !current_closest.is_empty();
+                                self.handle_network_discovery_result(
+                                    &candidate,
+                                    current_closest,
+                                    success,
+                                );
+                            }
                             PendingGetClosestType::FunctionCall(sender) => {
                                 sender
-                                    .send(current_closest)
+                                    .send((id, current_closest))
                                     .map_err(|_| NetworkError::InternalMsgChannelDropped)?;
                             }
                         }
@@ -101,17 +131,22 @@ impl SwarmDriver {
                 // Trust them and leave for the caller to check whether they are enough.
                 match err {
                     GetClosestPeersError::Timeout { ref peers, .. } => {
-                        current_closest.extend(peers.iter().map(|i| i.peer_id));
+                        current_closest.extend(
+                            peers
+                                .iter()
+                                .map(|i| i.peer_id)
+                                .filter(|peer_id| !self.blocked_peers.contains(peer_id)),
+                        );
                     }
                 }
 
                 match get_closest_type {
-                    PendingGetClosestType::NetworkDiscovery => self
-                        .network_discovery
-                        .handle_get_closest_query(current_closest),
+                    PendingGetClosestType::NetworkDiscovery(candidate) => {
+                        self.handle_network_discovery_result(&candidate, current_closest, false);
+                    }
                     PendingGetClosestType::FunctionCall(sender) => {
                         sender
-                            .send(current_closest)
+                            .send((id, current_closest))
                             .map_err(|_| NetworkError::InternalMsgChannelDropped)?;
                     }
                 }
@@ -129,7 +164,7 @@ impl SwarmDriver {
                     PrettyPrintRecordKey::from(&peer_record.record.key),
                     peer_record.peer
                 );
-                self.accumulate_get_record_found(id, peer_record, stats, step)?;
+                self.accumulate_get_record_found(id, peer_record, step)?;
             }
             kad::Event::OutboundQueryProgressed {
                 id,
@@ -315,6 +350,45 @@ impl SwarmDriver {
         Ok(())
     }
 
+    /// Updates the network discovery with the outcome of a query made for one of its candidates. Self and the peers
+    /// that are already part of our RT are not turned into candidates, as querying them brings no new information.
+    /// A candidate whose queries keep returning no peers is retired by `NetworkDiscovery::record_query_result`. The
+    /// buckets that get their first candidate from this result are queried right away.
+    pub(crate) fn handle_network_discovery_result(
+        &mut self,
+        candidate: &NetworkAddress,
+        closest_peers: Vec<PeerId>,
+        success: bool,
+    ) {
+        self.network_discovery
+            .record_query_result(candidate, success);
+
+        let known_peers = closest_peers
+            .iter()
+            .filter(|peer| {
+                self.swarm
+                    .behaviour_mut()
+                    .kademlia
+                    .kbucket(**peer)
+                    .is_some_and(|kbucket| {
+                        kbucket
+                            .iter()
+                            .any(|entry| entry.node.key.preimage() == *peer)
+                    })
+            })
+            .copied()
+            .collect::<HashSet<_>>();
+        let newly_covered = self.network_discovery.handle_get_closest_query(
+            candidate,
+            closest_peers.into_iter().collect(),
+            |peer| known_peers.contains(peer),
+        );
+        // Query the buckets that we just got the first candidate for, instead of waiting for the next round.
+        for ilog2 in newly_covered {
+            self.trigger_bucket_discovery(ilog2);
+        }
+    }
+
     // For `get_record` returning behaviour:
     //   1, targeting a non-existing entry
     //     there will only be one event of `kad::Event::OutboundQueryProgressed`
@@ -342,11 +416,13 @@ impl SwarmDriver {
     ///   check fails.
     /// - if multiple content hashes are found, we return a SplitRecord Error
     ///   And then we stop the kad query as we are done here.
-    fn accumulate_get_record_found(
+    ///
+    /// With `GetRecordResolution::Majority`, the quorum is instead reached once enough holders answered whatever copy
+    /// they hold, the caller then picking the majority copy out of the SplitRecord.
+    pub(crate) fn accumulate_get_record_found(
         &mut self,
         query_id: QueryId,
         peer_record: PeerRecord,
-        _stats: QueryStats,
         step: ProgressStep,
     ) -> Result<()> {
         let peer_id = if let Some(peer_id) = peer_record.peer {
@@ -382,6 +458,13 @@ impl SwarmDriver {
                 };
 
             let expected_answers = get_quorum_value(&cfg.get_quorum);
+            let responded_peers = match cfg.resolution {
+                GetRecordResolution::FirstQuorum => responded_peers,
+                GetRecordResolution::Majority => result_map
+                    .values()
+                    .map(|(_, peer_list)| peer_list.len())
+                    .sum(),
+            };
 
             debug!("Expecting {expected_answers:?} answers for record {pretty_key:?} task {query_id:?}, received {responded_peers} so far");
 
@@ -395,7 +478,7 @@ impl SwarmDriver {
                 let (_key, senders, result_map, _) = entry.remove();
 
                 if result_map.len() == 1 {
-                    Self::send_record_after_checking_target(senders, peer_record.record, &cfg)?;
+                    self.send_record_after_checking_target(senders, peer_record.record, &cfg)?;
                 } else {
                     debug!("For record {pretty_key:?} task {query_id:?}, fetch completed with split record");
                     let mut accumulated_spends = BTreeSet::new();
@@ -589,7 +672,7 @@ impl SwarmDriver {
                 // if we have enough responses here, we can return the record
                 if let Some((record, peers)) = result_map.values().next() {
                     if peers.len() >= required_response_count {
-                        Self::send_record_after_checking_target(senders, record.clone(), &cfg)?;
+                        self.send_record_after_checking_target(senders, record.clone(), &cfg)?;
                         return Ok(());
                     }
                 }
@@ -607,12 +690,18 @@ impl SwarmDriver {
         Ok(())
     }
 
+    /// The verified record is kept in the record cache, so that fetching it again is served locally.
     fn send_record_after_checking_target(
+        &mut self,
         senders: Vec<oneshot::Sender<std::result::Result<Record, GetRecordError>>>,
         record: Record,
         cfg: &GetRecordCfg,
     ) -> Result<()> {
         let res = if cfg.does_target_match(&record) {
+            let _ = self.record_cache.insert(&record);
+            self.publish_observed_event(ObservedNetworkEvent::RecordFetched {
+                key: PrettyPrintRecordKey::from(&record.key).into_owned(),
+            });
             Ok(record)
         } else {
             Err(GetRecordError::RecordDoesNotMatch(record))
@@ -10,7 +10,7 @@ mod kad;
 mod request_response;
 mod swarm;
 
-use crate::{driver::SwarmDriver, error::Result};
+use crate::{driver::SwarmDriver, error::Result, ObservedNetworkEvent};
 use core::fmt;
 use custom_debug::Debug as CustomDebug;
 #[cfg(feature = "local-discovery")]
@@ -44,6 +44,7 @@ pub(super) enum NodeEvent {
     Identify(Box<libp2p::identify::Event>),
     RelayClient(Box<libp2p::relay::client::Event>),
     RelayServer(Box<libp2p::relay::Event>),
+    Dcutr(Box<libp2p::dcutr::Event>),
     Void(void::Void),
 }
 
@@ -88,6 +89,11 @@ impl From<libp2p::relay::Event> for NodeEvent {
         NodeEvent::RelayServer(Box::new(event))
     }
 }
+impl From<libp2p::dcutr::Event> for NodeEvent {
+    fn from(event: libp2p::dcutr::Event) -> Self {
+        NodeEvent::Dcutr(Box::new(event))
+    }
+}
 
 impl From<void::Void> for NodeEvent {
     fn from(event: void::Void) -> Self {
@@ -225,6 +231,19 @@ impl SwarmDriver {
         );
         self.log_kbuckets(&added_peer);
         self.send_event(NetworkEvent::PeerAdded(added_peer, self.peers_in_rt));
+        self.publish_observed_event(ObservedNetworkEvent::PeerAdded {
+            peer_id: added_peer,
+            bucket: self.network_discovery.bucket_for_peer(&added_peer),
+        });
+
+        // Stop targeting the bucket of the added peer once it is full.
+        if let Some(kbucket) = self.swarm.behaviour_mut().kademlia.kbucket(added_peer) {
+            if let Some(ilog2) = kbucket.range().0.ilog2() {
+                if kbucket.num_entries() >= K_VALUE.get() {
+                    self.network_discovery.notify_bucket_full(ilog2);
+                }
+            }
+        }
 
         #[cfg(feature = "open-metrics")]
         if let Some(metrics_recorder) = &self.metrics_recorder {
@@ -243,6 +262,33 @@ impl SwarmDriver {
         );
         self.log_kbuckets(&removed_peer);
         self.send_event(NetworkEvent::PeerRemoved(removed_peer, self.peers_in_rt));
+        self.publish_observed_event(ObservedNetworkEvent::PeerRemoved {
+            peer_id: removed_peer,
+            bucket: self.network_discovery.bucket_for_peer(&removed_peer),
+        });
+
+        // The removed peer is not worth querying anymore.
+        if self.network_discovery.remove_peer(&removed_peer) {
+            debug!("Removed {removed_peer:?} from the network discovery candidates");
+        }
+
+        // Directly try to refill the bucket of the removed peer if it has become empty.
+        if let Some(ilog2) = NetworkAddress::from_peer(self.self_peer_id)
+            .distance(&NetworkAddress::from_peer(removed_peer))
+            .ilog2()
+        {
+            self.network_discovery.notify_bucket_has_room(ilog2);
+            // Only the non-empty buckets are iterated over.
+            let is_bucket_empty = !self
+                .swarm
+                .behaviour_mut()
+                .kademlia
+                .kbuckets()
+                .any(|kbucket| kbucket.range().0.ilog2() == Some(ilog2));
+            if is_bucket_empty && self.network_discovery.has_candidates_for_bucket(ilog2) {
+                self.trigger_bucket_discovery(ilog2);
+            }
+        }
 
         #[cfg(feature = "open-metrics")]
         if let Some(metrics_recorder) = &self.metrics_recorder {
@@ -287,6 +333,11 @@ impl SwarmDriver {
 
         let estimated_network_size =
             Self::estimate_network_size(peers_in_non_full_buckets, num_of_full_buckets);
+        // The estimate is too rough to bound the network discovery while our RT is nearly empty.
+        if total_peers >= K_VALUE.get() {
+            self.network_discovery
+                .set_network_size_estimate(estimated_network_size);
+        }
         #[cfg(feature = "open-metrics")]
         if let Some(metrics_recorder) = &self.metrics_recorder {
             let _ = metrics_recorder
@@ -10,6 +10,11 @@ use crate::{
     cmd::NetworkSwarmCmd, log_markers::Marker, sort_peers_by_address, MsgResponder, NetworkError,
     NetworkEvent, SwarmDriver, CLOSE_GROUP_SIZE,
 };
+#[cfg(feature = "open-metrics")]
+use crate::{
+    metrics::{Operation, Outcome},
+    target_arch::Instant,
+};
 use itertools::Itertools;
 use libp2p::request_response::{self, Message};
 use rand::{rngs::OsRng, thread_rng, Rng};
@@ -120,7 +125,10 @@ impl SwarmDriver {
                     response,
                 } => {
                     debug!("Got response {request_id:?} from peer {peer:?}, res: {response}.");
-                    if let Some(sender) = self.pending_requests.remove(&request_id) {
+                    self.record_exchange_success(peer);
+                    if let Some((sender, _sent_at)) = self.pending_requests.remove(&request_id) {
+                        #[cfg(feature = "open-metrics")]
+                        self.record_request_duration(Outcome::Ok, _sent_at);
                         // The sender will be provided if the caller (Requester) is awaiting for a response
                         // at the call site.
                         // Else the Request was just sent to the peer and the Response was
@@ -130,6 +138,9 @@ impl SwarmDriver {
                                 .send(Ok(response))
                                 .map_err(|_| NetworkError::InternalMsgChannelDropped)?,
                             None => {
+                                if let Response::Cmd(CmdResponse::Replicate(_)) = &response {
+                                    self.replication_peer_answered(&peer);
+                                }
                                 if let Response::Cmd(CmdResponse::Replicate(Ok(()))) = response {
                                     // Nothing to do, response was fine
                                     // This only exists to ensure we dont drop the handle and
@@ -154,7 +165,10 @@ impl SwarmDriver {
                 error,
                 peer,
             } => {
-                if let Some(sender) = self.pending_requests.remove(&request_id) {
+                self.record_exchange_failure(peer);
+                if let Some((sender, _sent_at)) = self.pending_requests.remove(&request_id) {
+                    #[cfg(feature = "open-metrics")]
+                    self.record_request_duration(Outcome::from(&error), _sent_at);
                     match sender {
                         Some(sender) => {
                             sender
@@ -163,6 +177,8 @@ impl SwarmDriver {
                         }
                         None => {
                             warn!("RequestResponse: OutboundFailure for request_id: {request_id:?} and peer: {peer:?}, with error: {error:?}");
+                            // The replication lists are the main requests not awaited, the peer won't answer it.
+                            self.replication_peer_answered(&peer);
                             return Err(NetworkError::ReceivedResponseDropped(request_id));
                         }
                     }
@@ -185,6 +201,17 @@ impl SwarmDriver {
         Ok(())
     }
 
+    #[cfg(feature = "open-metrics")]
+    fn record_request_duration(&self, outcome: Outcome, sent_at: Instant) {
+        if let Some(metrics_recorder) = &self.metrics_recorder {
+            metrics_recorder.operation_latency.observe(
+                Operation::RequestResponse,
+                outcome,
+                sent_at.elapsed(),
+            );
+        }
+    }
+
     fn add_keys_to_replication_fetcher(
         &mut self,
         sender: NetworkAddress,
@@ -7,8 +7,9 @@
 // permissions and limitations relating to use of the SAFE Network Software.
 
 use crate::{
-    cmd::LocalSwarmCmd, event::NodeEvent, multiaddr_is_global, multiaddr_strip_p2p,
-    relay_manager::is_a_relayed_peer, target_arch::Instant, NetworkEvent, Result, SwarmDriver,
+    cmd::LocalSwarmCmd, dial_backoff::DialBackedOff, event::NodeEvent, multiaddr_is_global,
+    multiaddr_strip_p2p, relay_manager::is_a_relayed_peer, target_arch::Instant, NetworkEvent,
+    ObservedNetworkEvent, Result, SwarmDriver,
 };
 #[cfg(feature = "local-discovery")]
 use libp2p::mdns;
@@ -106,6 +107,30 @@ impl SwarmDriver {
                     _ => {}
                 }
             }
+            SwarmEvent::Behaviour(NodeEvent::Dcutr(event)) => {
+                #[cfg(feature = "open-metrics")]
+                if let Some(metrics_recorder) = &self.metrics_recorder {
+                    metrics_recorder.record(&(*event));
+                }
+
+                event_string = "dcutr_event";
+
+                self.publish_observed_event(ObservedNetworkEvent::HolePunchAttempted {
+                    peer_id: event.remote_peer_id,
+                    succeeded: event.result.is_ok(),
+                });
+                // The relayed connection is kept on failure, the peer remaining reachable through the relay.
+                match event.result {
+                    Ok(connection_id) => info!(
+                        "Hole punched a direct connection {connection_id:?} to the relayed peer {:?}",
+                        event.remote_peer_id
+                    ),
+                    Err(err) => debug!(
+                        "Could not upgrade the relayed connection to {:?}: {err:?}",
+                        event.remote_peer_id
+                    ),
+                }
+            }
             SwarmEvent::Behaviour(NodeEvent::Identify(iden)) => {
                 // Record the Identify event for metrics if the feature is enabled.
                 #[cfg(feature = "open-metrics")]
@@ -363,7 +388,9 @@ impl SwarmDriver {
                     connection_id,
                     (peer_id, Instant::now() + Duration::from_secs(60)),
                 );
+                self.enforce_max_established_connections(connection_id);
                 self.record_connection_metrics();
+                self.record_dial_backoff_metric();
 
                 if endpoint.is_dialer() {
                     self.dialed_peers.push(peer_id);
@@ -381,6 +408,14 @@ impl SwarmDriver {
                 let _ = self.live_connected_peers.remove(&connection_id);
                 self.record_connection_metrics();
             }
+            SwarmEvent::OutgoingConnectionError {
+                peer_id: Some(failed_peer_id),
+                error: DialError::Denied { cause },
+                ..
+            } if cause.downcast_ref::<DialBackedOff>().is_some() => {
+                event_string = "OutgoingConnBackedOff";
+                trace!("Not dialing {failed_peer_id:?}, its dials are backed off");
+            }
             SwarmEvent::OutgoingConnectionError {
                 peer_id: Some(failed_peer_id),
                 error,
@@ -388,9 +423,15 @@ impl SwarmDriver {
             } => {
                 event_string = "OutgoingConnErr";
                 warn!("OutgoingConnectionError to {failed_peer_id:?} on {connection_id:?} - {error:?}");
+                self.publish_observed_event(ObservedNetworkEvent::DialFailed {
+                    peer_id: failed_peer_id,
+                    error: error.to_string(),
+                });
                 let _ = self.live_connected_peers.remove(&connection_id);
                 self.record_connection_metrics();
+                self.record_dial_backoff_metric();
 
+                let is_denied = matches!(error, DialError::Denied { .. });
                 // we need to decide if this was a critical error and the peer should be removed from the routing table
                 let should_clean_peer = match error {
                     DialError::Transport(errors) => {
@@ -485,6 +526,10 @@ impl SwarmDriver {
 
                 if should_clean_peer {
                     warn!("Tracking issue of {failed_peer_id:?}. Clearing it out for now");
+                    // Our own blocklist denies the dials to the blocked peers, that's not on them.
+                    if !is_denied {
+                        self.record_exchange_failure(failed_peer_id);
+                    }
 
                     if let Some(dead_peer) = self
                         .swarm
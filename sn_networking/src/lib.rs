@@ -9,21 +9,30 @@
 #[macro_use]
 extern crate tracing;
 
+mod blocklist;
 mod bootstrap;
 mod circular_vec;
 mod cmd;
+mod connection_eviction;
+mod dial_backoff;
 mod driver;
 mod error;
 mod event;
 mod external_address;
+#[cfg(feature = "local-discovery")]
+mod local_discovery;
 mod log_markers;
 #[cfg(feature = "open-metrics")]
 mod metrics;
 mod network_discovery;
+mod observed_events;
+mod peer_score;
+mod record_cache;
 mod record_store;
 mod record_store_api;
 mod relay_manager;
 mod replication_fetcher;
+mod replication_throttle;
 mod spends;
 pub mod target_arch;
 mod transfers;
@@ -34,12 +43,16 @@ use xor_name::XorName;
 
 // re-export arch dependent deps for use in the crate, or above
 pub use self::{
-    cmd::{NodeIssue, SwarmLocalState},
+    cmd::{KBucketInfo, NodeIssue, SwarmLocalState},
     driver::{
-        GetRecordCfg, NetworkBuilder, PutRecordCfg, SwarmDriver, VerificationKind, MAX_PACKET_SIZE,
+        ClosestPeersRetryPolicy, GetRecordCfg, GetRecordResolution, MajorityRecord, NetworkBuilder,
+        PutRecordCfg, SwarmDriver, VerificationKind, VerifyAfterPutCfg, MAX_PACKET_SIZE,
     },
     error::{GetRecordError, NetworkError},
     event::{MsgResponder, NetworkEvent},
+    network_discovery::{NetworkDiscoveryConfig, NetworkDiscoveryStats},
+    observed_events::ObservedNetworkEvent,
+    record_cache::RecordCacheConfig,
     record_store::{calculate_cost_for_records, NodeRecordStore},
     transfers::{get_raw_signed_spends_from_record, get_signed_spend_from_record},
 };
@@ -61,17 +74,21 @@ use rand::Rng;
 use sn_protocol::{
     error::Error as ProtocolError,
     messages::{ChunkProof, Cmd, Nonce, Query, QueryResponse, Request, Response},
-    storage::{RecordType, RetryStrategy},
+    storage::{
+        try_deserialize_record, try_serialize_record, Chunk, RecordHeader, RecordKind, RecordType,
+        RetryStrategy,
+    },
     NetworkAddress, PrettyPrintKBucketKey, PrettyPrintRecordKey, CLOSE_GROUP_SIZE,
 };
-use sn_transfers::{MainPubkey, NanoTokens, PaymentQuote, QuotingMetrics};
+use sn_transfers::{MainPubkey, NanoTokens, Payment, PaymentQuote, QuotingMetrics};
 use std::{
-    collections::{BTreeMap, BTreeSet, HashMap},
+    collections::{BTreeMap, BTreeSet, HashMap, HashSet},
     net::IpAddr,
     path::PathBuf,
     sync::Arc,
 };
 use tokio::sync::{
+    broadcast,
     mpsc::{self, Sender},
     oneshot,
 };
@@ -97,6 +114,21 @@ const MAX_WAIT_BEFORE_READING_A_PUT: Duration = Duration::from_millis(750);
 /// Min duration to wait for verification
 const MIN_WAIT_BEFORE_READING_A_PUT: Duration = Duration::from_millis(300);
 
+/// Returns the content the holders keep for the chunk put: the chunks are stored without the payment they are uploaded
+/// with. Returns `None` for the other records, which are merged or transformed by their holders.
+fn stored_chunk_content(record: &Record) -> Result<Option<Vec<u8>>> {
+    match RecordHeader::from_record(record) {
+        Ok(header) if header.kind == RecordKind::ChunkWithPayment => {
+            let (_payment, chunk) = try_deserialize_record::<(Payment, Chunk)>(record)?;
+            Ok(Some(
+                try_serialize_record(&chunk, RecordKind::Chunk)?.to_vec(),
+            ))
+        }
+        Ok(header) if header.kind == RecordKind::Chunk => Ok(Some(record.value.clone())),
+        _ => Ok(None),
+    }
+}
+
 /// Sort the provided peers by their distance to the given `NetworkAddress`.
 /// Return with the closest expected number of entries if has.
 pub fn sort_peers_by_address<'a>(
@@ -162,6 +194,8 @@ struct NetworkInner {
     peer_id: PeerId,
     root_dir_path: PathBuf,
     keypair: Keypair,
+    closest_peers_retry: ClosestPeersRetryPolicy,
+    observed_events: broadcast::Sender<ObservedNetworkEvent>,
 }
 
 impl Network {
@@ -171,6 +205,8 @@ impl Network {
         peer_id: PeerId,
         root_dir_path: PathBuf,
         keypair: Keypair,
+        closest_peers_retry: ClosestPeersRetryPolicy,
+        observed_events: broadcast::Sender<ObservedNetworkEvent>,
     ) -> Self {
         Self {
             inner: Arc::new(NetworkInner {
@@ -179,10 +215,19 @@ impl Network {
                 peer_id,
                 root_dir_path,
                 keypair,
+                closest_peers_retry,
+                observed_events,
             }),
         }
     }
 
+    /// Subscribes to the `ObservedNetworkEvent`s, e.g., the peers added to the routing table or the records stored.
+    /// Only the events published after the subscription are received. A subscriber lagging too far behind misses the
+    /// oldest events, and gets a `RecvError::Lagged` instead.
+    pub fn events_channel(&self) -> broadcast::Receiver<ObservedNetworkEvent> {
+        self.inner.observed_events.subscribe()
+    }
+
     /// Returns the `PeerId` of the instance.
     pub fn peer_id(&self) -> PeerId {
         self.inner.peer_id
@@ -254,6 +299,27 @@ impl Network {
             .map_err(|_e| NetworkError::InternalMsgChannelDropped)
     }
 
+    /// Returns the peers held by each KBucket of our local Routing Table, keyed by the ilog2 distance of the bucket,
+    /// along with their connection state and the capacity of the bucket. Does not include self.
+    pub async fn get_kbuckets_info(&self) -> Result<BTreeMap<u32, KBucketInfo>> {
+        let (sender, receiver) = oneshot::channel();
+        self.send_local_swarm_cmd(LocalSwarmCmd::GetKBucketsInfo { sender });
+        receiver
+            .await
+            .map_err(|_e| NetworkError::InternalMsgChannelDropped)
+    }
+
+    /// Never talk to the peer again: its connections are refused both ways and it is evicted from our RT. The blocked
+    /// peers of a node are persisted under its root dir, so that they stay blocked across restarts.
+    pub fn block_peer(&self, peer_id: PeerId) {
+        self.send_local_swarm_cmd(LocalSwarmCmd::BlockPeer { peer_id });
+    }
+
+    /// Lifts a block set by `block_peer`. A peer that we have found to be bad ourselves stays blocked.
+    pub fn unblock_peer(&self, peer_id: PeerId) {
+        self.send_local_swarm_cmd(LocalSwarmCmd::UnblockPeer { peer_id });
+    }
+
     /// Returns all the PeerId from all the KBuckets from our local Routing Table
     /// Also contains our own PeerId.
     pub async fn get_closest_k_value_local_peers(&self) -> Result<Vec<PeerId>> {
@@ -442,10 +508,11 @@ impl Network {
     /// which has the risk of returning with old versions.
     /// So, to improve the accuracy, query closest_peers first, then fetch registers
     /// And merge them if they are with different content.
+    /// The copies are returned along with their holders, so that the majority one can be picked.
     pub async fn get_register_record_from_network(
         &self,
         key: RecordKey,
-    ) -> Result<HashMap<XorName, Record>> {
+    ) -> Result<HashMap<XorName, (Record, HashSet<PeerId>)>> {
         let record_address = NetworkAddress::from_record_key(&key);
         // The requirement of having at least CLOSE_GROUP_SIZE
         // close nodes will be checked internally automatically.
@@ -461,8 +528,11 @@ impl Network {
             .await;
 
         // loop over responses, collecting all fetched register records
-        let mut all_register_copies = HashMap::new();
-        for response in responses.into_values().flatten() {
+        let mut all_register_copies: HashMap<XorName, (Record, HashSet<PeerId>)> = HashMap::new();
+        for (peer, response) in responses {
+            let Ok(response) = response else {
+                continue;
+            };
             match response {
                 Response::Query(QueryResponse::GetRegisterRecord(Ok((holder, content)))) => {
                     let register_record = Record::new(key.clone(), content.to_vec());
@@ -470,7 +540,11 @@ impl Network {
                     debug!(
                         "RegisterRecordReq of {record_address:?} received register of version {content_hash:?} from {holder:?}"
                     );
-                    let _ = all_register_copies.insert(content_hash, register_record);
+                    let _ = all_register_copies
+                        .entry(content_hash)
+                        .or_insert_with(|| (register_record, HashSet::new()))
+                        .1
+                        .insert(peer);
                 }
                 _ => {
                     error!(
@@ -566,8 +640,13 @@ impl Network {
                     }
                 };
 
+                // A majority is as good as it gets, retrying would only risk losing it.
+                let found_majority = cfg.resolution == GetRecordResolution::Majority
+                    && matches!(&result, Err(GetRecordError::SplitRecord { result_map })
+                        if MajorityRecord::from_copies(result_map).is_some());
+
                 // if we don't want to retry, throw permanent error
-                if cfg.retry_strategy.is_none() {
+                if cfg.retry_strategy.is_none() || found_majority {
                     if let Err(e) = result {
                         return Err(BackoffError::Permanent(NetworkError::from(e)));
                     }
@@ -584,6 +663,41 @@ impl Network {
         .await
     }
 
+    /// Get the Record held by the majority of the holders that answered, along with the holders that returned a
+    /// different copy. The quorum and retries of the provided cfg still apply, its resolution is overridden.
+    pub async fn get_majority_record_from_network(
+        &self,
+        key: RecordKey,
+        cfg: &GetRecordCfg,
+    ) -> Result<MajorityRecord> {
+        let cfg = GetRecordCfg {
+            resolution: GetRecordResolution::Majority,
+            ..cfg.clone()
+        };
+        match self.get_record_from_network(key, &cfg).await {
+            Ok(record) => Ok(MajorityRecord {
+                record,
+                dissenting_holders: Default::default(),
+            }),
+            Err(NetworkError::GetRecordError(GetRecordError::SplitRecord { result_map })) => {
+                match MajorityRecord::from_copies(&result_map) {
+                    Some(majority) => {
+                        warn!(
+                            "Picked the majority copy of {:?}, dissenting holders: {:?}",
+                            PrettyPrintRecordKey::from(&majority.record.key),
+                            majority.dissenting_holders
+                        );
+                        Ok(majority)
+                    }
+                    None => Err(NetworkError::GetRecordError(GetRecordError::SplitRecord {
+                        result_map,
+                    })),
+                }
+            }
+            Err(err) => Err(err),
+        }
+    }
+
     /// Get the cost of storing the next record from the network
     pub async fn get_local_storecost(
         &self,
@@ -711,8 +825,12 @@ impl Network {
                 )
                 .await?;
             } else {
+                let get_cfg = GetRecordCfg {
+                    bypass_cache: true,
+                    ..get_cfg.clone()
+                };
                 match self
-                    .get_record_from_network(record.key.clone(), get_cfg)
+                    .get_record_from_network(record.key.clone(), &get_cfg)
                     .await
                 {
                     Ok(_) => {
@@ -733,7 +851,114 @@ impl Network {
                 }
             }
         }
-        response
+        response?;
+
+        if let Some(verify_cfg) = &cfg.verify_after_put {
+            self.verify_and_repair_put(&record, cfg, verify_cfg).await?;
+        }
+        Ok(())
+    }
+
+    /// Fetches the record back after its PUT, putting it again to the intended holders missing it until its copy
+    /// matches, see `VerifyAfterPutCfg`.
+    async fn verify_and_repair_put(
+        &self,
+        record: &Record,
+        cfg: &PutRecordCfg,
+        verify_cfg: &VerifyAfterPutCfg,
+    ) -> Result<()> {
+        let pretty_key = PrettyPrintRecordKey::from(&record.key);
+        let address = NetworkAddress::from_record_key(&record.key);
+        let Some(stored_content) = stored_chunk_content(record)? else {
+            warn!("Only the chunks are verified after PUT, {pretty_key:?} is not a chunk");
+            return Ok(());
+        };
+        let expected_content_hash = XorName::from_content(&stored_content);
+        let get_cfg = GetRecordCfg {
+            get_quorum: Quorum::One,
+            resolution: Default::default(),
+            retry_strategy: None,
+            target_record: None,
+            expected_holders: Default::default(),
+            is_register: false,
+            // The record cache would only return the record we fetched ourselves.
+            bypass_cache: true,
+        };
+
+        let mut retry = 0;
+        loop {
+            sleep(verify_cfg.delay).await;
+            match self
+                .get_record_from_network(record.key.clone(), &get_cfg)
+                .await
+            {
+                Ok(fetched) if XorName::from_content(&fetched.value) == expected_content_hash => {
+                    debug!("Record {pretty_key:?} verified to be stored after {retry} repairs");
+                    return Ok(());
+                }
+                Ok(_) => {
+                    warn!("Record {pretty_key:?} fetched after PUT does not match the one put")
+                }
+                Err(err) => warn!("Record {pretty_key:?} could not be fetched after PUT: {err:?}"),
+            }
+            if retry >= verify_cfg.retries {
+                error!("Record {pretty_key:?} is still not stored after {retry} repairs");
+                return Err(NetworkError::RecordNotStoredByNodes(address));
+            }
+            retry += 1;
+
+            let intended_holders = match &cfg.use_put_record_to {
+                Some(peers) => peers.clone(),
+                None => self.client_get_closest_peers(&address).await?,
+            };
+            let non_holders = self
+                .non_holders_of(&address, expected_content_hash, &intended_holders)
+                .await;
+            if non_holders.is_empty() {
+                // The holders may have only stored the record in the meantime.
+                debug!("All the intended holders of {pretty_key:?} hold it, verifying again");
+                continue;
+            }
+            info!(
+                "Putting {pretty_key:?} again to {non_holders:?}, repair {retry}/{}",
+                verify_cfg.retries
+            );
+            let (sender, receiver) = oneshot::channel();
+            self.send_network_swarm_cmd(NetworkSwarmCmd::PutRecordTo {
+                peers: non_holders,
+                record: record.clone(),
+                sender,
+                quorum: cfg.put_quorum,
+            });
+            if let Err(err) = receiver.await? {
+                warn!("Failed to put {pretty_key:?} again: {err:?}");
+            }
+        }
+    }
+
+    /// Returns the peers that do not hold a copy of the record with the expected content.
+    async fn non_holders_of(
+        &self,
+        address: &NetworkAddress,
+        expected_content_hash: XorName,
+        peers: &[PeerId],
+    ) -> Vec<PeerId> {
+        let request = Request::Query(Query::GetReplicatedRecord {
+            requester: NetworkAddress::from_peer(self.peer_id()),
+            key: address.clone(),
+        });
+        self.send_and_get_responses(peers, &request, true)
+            .await
+            .into_iter()
+            .filter_map(|(peer_id, response)| match response {
+                Ok(Response::Query(QueryResponse::GetReplicatedRecord(Ok((_holder, content)))))
+                    if XorName::from_content(&content) == expected_content_hash =>
+                {
+                    None
+                }
+                _ => Some(peer_id),
+            })
+            .collect()
     }
 
     /// Notify ReplicationFetch a fetch attempt is completed.
@@ -863,6 +1088,37 @@ impl Network {
         self.send_local_swarm_cmd(LocalSwarmCmd::TriggerUnrelevantRecordCleanup)
     }
 
+    /// Trigger a round of network discovery right away, instead of waiting for the next one. The triggers made within
+    /// a few seconds of each other collapse into a single round.
+    pub fn trigger_network_discovery(&self) {
+        self.send_local_swarm_cmd(LocalSwarmCmd::TriggerNetworkDiscovery)
+    }
+
+    /// Return the whole candidate pool of the network discovery as JSON, e.g., to debug the coverage of the buckets.
+    pub async fn get_network_discovery_snapshot(&self) -> Result<String> {
+        let (sender, receiver) = oneshot::channel();
+        self.send_local_swarm_cmd(LocalSwarmCmd::GetNetworkDiscoverySnapshot { sender });
+        receiver.await?
+    }
+
+    /// Pause or resume the network discovery. While paused, no discovery query is issued, but the candidates are
+    /// retained. The current state is reported by `get_swarm_local_state`.
+    pub fn set_network_discovery_enabled(&self, enabled: bool) {
+        self.send_local_swarm_cmd(LocalSwarmCmd::SetNetworkDiscoveryEnabled { enabled })
+    }
+
+    /// Set the bytes per second of the records sent to the peers fetching them during replication, 0 lifting the
+    /// limit. The transfers queued beyond the previous limit are kept.
+    pub fn set_replication_bandwidth_limit(&self, bytes_per_sec: u64) {
+        self.send_local_swarm_cmd(LocalSwarmCmd::SetReplicationBandwidthLimit { bytes_per_sec })
+    }
+
+    /// Switch the mDNS local discovery on or off. The peers already discovered through it remain in our RT.
+    #[cfg(feature = "local-discovery")]
+    pub fn set_local_discovery(&self, enabled: bool) {
+        self.send_local_swarm_cmd(LocalSwarmCmd::SetLocalDiscovery { enabled })
+    }
+
     /// Helper to send NetworkSwarmCmd
     fn send_network_swarm_cmd(&self, cmd: NetworkSwarmCmd) {
         send_network_swarm_cmd(self.network_swarm_cmd_sender().clone(), cmd);
@@ -874,30 +1130,54 @@ impl Network {
 
     /// Returns the closest peers to the given `XorName`, sorted by their distance to the xor_name.
     /// If `client` is false, then include `self` among the `closest_peers`
+    ///
+    /// The query is retried as per the `ClosestPeersRetryPolicy` while fewer than `CLOSE_GROUP_SIZE` peers have been
+    /// found, the closest peers being picked among the peers found by every attempt.
     pub async fn get_closest_peers(
         &self,
         key: &NetworkAddress,
         client: bool,
     ) -> Result<Vec<PeerId>> {
-        debug!("Getting the closest peers to {key:?}");
-        let (sender, receiver) = oneshot::channel();
-        self.send_network_swarm_cmd(NetworkSwarmCmd::GetClosestPeersToAddressFromNetwork {
-            key: key.clone(),
-            sender,
-        });
-        let k_bucket_peers = receiver.await?;
-
-        // Count self in if among the CLOSE_GROUP_SIZE closest and sort the result
-        let result_len = k_bucket_peers.len();
-        let mut closest_peers = k_bucket_peers;
-        // ensure we're not including self here
-        if client {
-            // remove our peer id from the calculations here:
-            closest_peers.retain(|&x| x != self.peer_id());
-            if result_len != closest_peers.len() {
-                info!("Remove self client from the closest_peers");
+        let policy = self.inner.closest_peers_retry;
+        let attempts = policy.attempts.max(1);
+        // The peers found by all the attempts so far, the closest of which are returned.
+        let mut closest_peers = Vec::new();
+        let mut attempt = 0;
+        loop {
+            attempt += 1;
+            debug!("Getting the closest peers to {key:?}, attempt {attempt}/{attempts}");
+            let (sender, receiver) = oneshot::channel();
+            self.send_network_swarm_cmd(NetworkSwarmCmd::GetClosestPeersToAddressFromNetwork {
+                key: key.clone(),
+                sender,
+            });
+            let (query_id, mut k_bucket_peers) = receiver.await?;
+
+            // ensure we're not including self here
+            if client {
+                // remove our peer id from the calculations here:
+                let result_len = k_bucket_peers.len();
+                k_bucket_peers.retain(|&x| x != self.peer_id());
+                if result_len != k_bucket_peers.len() {
+                    info!("Remove self client from the closest_peers");
+                }
             }
+            let found = k_bucket_peers.len();
+            for peer_id in k_bucket_peers {
+                if !closest_peers.contains(&peer_id) {
+                    closest_peers.push(peer_id);
+                }
+            }
+            debug!("Query {query_id:?} for the closest peers to {key:?} returned {found} peers on attempt {attempt}/{attempts}, {} found so far", closest_peers.len());
+
+            if closest_peers.len() >= CLOSE_GROUP_SIZE || attempt >= attempts {
+                break;
+            }
+            let delay = policy.delay_before_retry(attempt as u32);
+            info!("Query {query_id:?} for the closest peers to {key:?} did not find the {CLOSE_GROUP_SIZE} peers required, retrying in {delay:?}");
+            sleep(delay).await;
         }
+
         if tracing::level_enabled!(tracing::Level::DEBUG) {
             let close_peers_pretty_print: Vec<_> = closest_peers
                 .iter()
@@ -1113,6 +1393,7 @@ mod tests {
     use eyre::bail;
 
     use super::*;
+    use crate::bootstrap::QUERIES_PER_DISCOVERY_TICK;
     use sn_transfers::PaymentQuote;
 
     #[test]
@@ -1174,6 +1455,790 @@ mod tests {
         Ok(())
     }
 
+    #[tokio::test]
+    async fn on_demand_network_discovery_issues_queries_right_away() -> eyre::Result<()> {
+        let (_network, _events, mut swarm_driver) =
+            NetworkBuilder::new(Keypair::generate_ed25519(), false, std::env::temp_dir())
+                .build_client()?;
+        swarm_driver.network_discovery.populate();
+        assert!(swarm_driver.pending_get_closest_peers.is_empty());
+
+        swarm_driver.handle_local_cmd(LocalSwarmCmd::TriggerNetworkDiscovery)?;
+        let issued = swarm_driver.pending_get_closest_peers.len();
+        assert!(issued > 0);
+
+        // A burst of triggers collapses into the round already made.
+        swarm_driver.handle_local_cmd(LocalSwarmCmd::TriggerNetworkDiscovery)?;
+        assert_eq!(swarm_driver.pending_get_closest_peers.len(), issued);
+        Ok(())
+    }
+
+    /// A `Network` whose swarm is mocked by a task answering each closest peers query with the next result.
+    fn network_with_mocked_closest_peers(
+        policy: ClosestPeersRetryPolicy,
+        results: Vec<Vec<PeerId>>,
+    ) -> (Network, tokio::task::JoinHandle<usize>) {
+        let (network_swarm_cmd_sender, mut network_swarm_cmd_receiver) = mpsc::channel(10);
+        let (local_swarm_cmd_sender, _local_swarm_cmd_receiver) = mpsc::channel(10);
+        let keypair = Keypair::generate_ed25519();
+        let network = Network::new(
+            network_swarm_cmd_sender,
+            local_swarm_cmd_sender,
+            keypair.public().to_peer_id(),
+            std::env::temp_dir(),
+            keypair,
+            policy,
+            broadcast::channel(1).0,
+        );
+        let driver = tokio::spawn(async move {
+            // Only used to mint the ids of the queries, as they cannot be built otherwise.
+            let peer_id = PeerId::random();
+            let mut kademlia = libp2p::kad::Behaviour::with_config(
+                peer_id,
+                libp2p::kad::store::MemoryStore::new(peer_id),
+                libp2p::kad::Config::new(libp2p::StreamProtocol::new("/mocked/kad")),
+            );
+            let mut queries = 0;
+            let mut results = results.into_iter();
+            while let Some(cmd) = network_swarm_cmd_receiver.recv().await {
+                if let NetworkSwarmCmd::GetClosestPeersToAddressFromNetwork { sender, .. } = cmd {
+                    queries += 1;
+                    let query_id = kademlia.get_closest_peers(PeerId::random());
+                    let _ = sender.send((query_id, results.next().unwrap_or_default()));
+                }
+            }
+            queries
+        });
+        (network, driver)
+    }
+
+    fn random_peers(count: usize) -> Vec<PeerId> {
+        (0..count).map(|_| PeerId::random()).collect()
+    }
+
+    #[tokio::test]
+    async fn closest_peers_query_is_retried_until_enough_peers_are_found() -> eyre::Result<()> {
+        let policy = ClosestPeersRetryPolicy {
+            attempts: 3,
+            base_delay: Duration::from_millis(10),
+            jitter: Duration::ZERO,
+        };
+        let first = random_peers(2);
+        let second = random_peers(CLOSE_GROUP_SIZE);
+        let (network, driver) =
+            network_with_mocked_closest_peers(policy, vec![first.clone(), second.clone()]);
+
+        let key = NetworkAddress::from_peer(PeerId::random());
+        let closest = network.client_get_closest_peers(&key).await?;
+        drop(network);
+        assert_eq!(driver.await?, 2);
+
+        // The closest are picked among the peers of both attempts.
+        let all = first.into_iter().chain(second).collect::<Vec<_>>();
+        let expected = sort_peers_by_address(&all, &key, CLOSE_GROUP_SIZE)?
+            .into_iter()
+            .cloned()
+            .collect::<Vec<_>>();
+        assert_eq!(closest, expected);
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn closest_peers_query_gives_up_after_the_policy_attempts() -> eyre::Result<()> {
+        let policy = ClosestPeersRetryPolicy {
+            attempts: 3,
+            base_delay: Duration::from_millis(10),
+            jitter: Duration::from_millis(5),
+        };
+        let (network, driver) = network_with_mocked_closest_peers(
+            policy,
+            vec![random_peers(1), random_peers(1), random_peers(1)],
+        );
+
+        let key = NetworkAddress::from_peer(PeerId::random());
+        let result = network.client_get_closest_peers(&key).await;
+        drop(network);
+        assert_eq!(driver.await?, 3);
+        assert!(matches!(
+            result,
+            Err(NetworkError::NotEnoughPeers { found: 3, .. })
+        ));
+
+        let (network, driver) =
+            network_with_mocked_closest_peers(ClosestPeersRetryPolicy::no_retry(), vec![]);
+        assert!(network.client_get_closest_peers(&key).await.is_err());
+        drop(network);
+        assert_eq!(driver.await?, 1);
+        Ok(())
+    }
+
+    /// A `Network` whose swarm is mocked by a task along with a single holder, which drops the record it is put the
+    /// first `drops` times. The task returns the peers each PUT has been sent to.
+    fn network_with_mocked_record_holder(
+        holder: PeerId,
+        drops: usize,
+    ) -> (Network, tokio::task::JoinHandle<Vec<Vec<PeerId>>>) {
+        let (network_swarm_cmd_sender, mut network_swarm_cmd_receiver) = mpsc::channel(10);
+        let (local_swarm_cmd_sender, _local_swarm_cmd_receiver) = mpsc::channel(10);
+        let keypair = Keypair::generate_ed25519();
+        let network = Network::new(
+            network_swarm_cmd_sender,
+            local_swarm_cmd_sender,
+            keypair.public().to_peer_id(),
+            std::env::temp_dir(),
+            keypair,
+            ClosestPeersRetryPolicy::no_retry(),
+            broadcast::channel(1).0,
+        );
+        let driver = tokio::spawn(async move {
+            let mut stored: Option<Record> = None;
+            let mut puts = Vec::new();
+            while let Some(cmd) = network_swarm_cmd_receiver.recv().await {
+                match cmd {
+                    NetworkSwarmCmd::PutRecordTo {
+                        peers,
+                        record,
+                        sender,
+                        ..
+                    } => {
+                        if puts.len() >= drops && peers.contains(&holder) {
+                            stored = Some(record);
+                        }
+                        puts.push(peers);
+                        let _ = sender.send(Ok(()));
+                    }
+                    NetworkSwarmCmd::GetNetworkRecord { sender, cfg, .. } => {
+                        assert!(cfg.bypass_cache, "the verification reaches the network");
+                        let _ = sender.send(stored.clone().ok_or(GetRecordError::RecordNotFound));
+                    }
+                    NetworkSwarmCmd::SendRequest {
+                        req: Request::Query(Query::GetReplicatedRecord { key, .. }),
+                        peer,
+                        sender: Some(sender),
+                    } => {
+                        let holder_address = NetworkAddress::from_peer(peer);
+                        let result = match &stored {
+                            Some(record) if peer == holder => {
+                                Ok((holder_address, record.value.clone().into()))
+                            }
+                            _ => Err(ProtocolError::ReplicatedRecordNotFound {
+                                holder: Box::new(holder_address),
+                                key: Box::new(key),
+                            }),
+                        };
+                        let _ = sender.send(Ok(Response::Query(
+                            QueryResponse::GetReplicatedRecord(result),
+                        )));
+                    }
+                    _ => {}
+                }
+            }
+            puts
+        });
+        (network, driver)
+    }
+
+    fn put_cfg_verified_after_put(holder: PeerId) -> PutRecordCfg {
+        PutRecordCfg {
+            put_quorum: Quorum::One,
+            retry_strategy: None,
+            use_put_record_to: Some(vec![holder]),
+            verification: None,
+            verify_after_put: Some(VerifyAfterPutCfg {
+                retries: 2,
+                delay: Duration::from_millis(10),
+            }),
+        }
+    }
+
+    fn chunk_record() -> eyre::Result<Record> {
+        let chunk = Chunk::new(bytes::Bytes::from_static(b"content"));
+        Ok(Record::new(
+            chunk.network_address().to_record_key(),
+            try_serialize_record(&chunk, RecordKind::Chunk)?.to_vec(),
+        ))
+    }
+
+    #[tokio::test]
+    async fn record_dropped_by_its_holder_is_put_again() -> eyre::Result<()> {
+        let holder = PeerId::random();
+        let (network, driver) = network_with_mocked_record_holder(holder, 2);
+        network
+            .put_record(chunk_record()?, &put_cfg_verified_after_put(holder))
+            .await?;
+        drop(network);
+        // The holder dropped the PUT and the first repair, the second repair stuck.
+        assert_eq!(driver.await?, vec![vec![holder]; 3]);
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn record_put_is_repaired_at_most_the_configured_retries() -> eyre::Result<()> {
+        let holder = PeerId::random();
+        let (network, driver) = network_with_mocked_record_holder(holder, usize::MAX);
+        let result = network
+            .put_record(chunk_record()?, &put_cfg_verified_after_put(holder))
+            .await;
+        drop(network);
+        assert!(matches!(
+            result,
+            Err(NetworkError::RecordNotStoredByNodes(_))
+        ));
+        assert_eq!(driver.await?.len(), 3);
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn only_chunks_are_verified_after_put() -> eyre::Result<()> {
+        let holder = PeerId::random();
+        let (network, driver) = network_with_mocked_record_holder(holder, usize::MAX);
+        let not_a_chunk = Record::new(RecordKey::new(&b"key"), b"content".to_vec());
+
+        network
+            .put_record(not_a_chunk, &put_cfg_verified_after_put(holder))
+            .await?;
+        drop(network);
+        assert_eq!(driver.await?, vec![vec![holder]]);
+        Ok(())
+    }
+
+    #[test]
+    fn retry_delay_doubles_and_stays_within_the_jitter() {
+        let policy = ClosestPeersRetryPolicy {
+            attempts: 4,
+            base_delay: Duration::from_secs(1),
+            jitter: Duration::from_millis(100),
+        };
+        for (retry, backoff) in [(1, 1), (2, 2), (3, 4)] {
+            let delay = policy.delay_before_retry(retry);
+            let backoff = Duration::from_secs(backoff);
+            assert!(delay >= backoff && delay <= backoff + policy.jitter);
+        }
+    }
+
+    #[tokio::test]
+    async fn kbuckets_info_lists_the_known_peers_under_their_bucket() -> eyre::Result<()> {
+        let (_network, _events, mut swarm_driver) =
+            NetworkBuilder::new(Keypair::generate_ed25519(), false, std::env::temp_dir())
+                .build_client()?;
+        let self_key = NetworkAddress::from_peer(swarm_driver.self_peer_id).as_kbucket_key();
+        let peers = random_peers(10);
+        let addr: Multiaddr = "/ip4/127.0.0.1/udp/12000/quic-v1".parse()?;
+        for peer_id in &peers {
+            let _ = swarm_driver
+                .swarm
+                .behaviour_mut()
+                .kademlia
+                .add_address(peer_id, addr.clone());
+        }
+
+        let (sender, mut receiver) = oneshot::channel();
+        swarm_driver.handle_local_cmd(LocalSwarmCmd::GetKBucketsInfo { sender })?;
+        let kbuckets = receiver.try_recv()?;
+        for peer_id in &peers {
+            let ilog2 = self_key
+                .distance(&NetworkAddress::from_peer(*peer_id).as_kbucket_key())
+                .ilog2()
+                .expect("a peer is not self");
+            let kbucket = &kbuckets[&ilog2];
+            // The peers have never been dialed.
+            assert!(kbucket.peers.contains(&(*peer_id, false, 0)));
+            assert_eq!(kbucket.capacity, libp2p::kad::K_VALUE.get());
+            assert!(!kbucket.is_full());
+        }
+        let held = kbuckets
+            .values()
+            .map(|kbucket| kbucket.peers.len())
+            .sum::<usize>();
+        assert_eq!(held, peers.len());
+
+        let full = KBucketInfo {
+            peers: random_peers(libp2p::kad::K_VALUE.get())
+                .into_iter()
+                .map(|peer_id| (peer_id, true, 0))
+                .collect(),
+            capacity: libp2p::kad::K_VALUE.get(),
+        };
+        assert!(full.is_full());
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn consistently_failing_peer_is_evicted_from_the_routing_table() -> eyre::Result<()> {
+        let (_network, _events, mut swarm_driver) =
+            NetworkBuilder::new(Keypair::generate_ed25519(), false, std::env::temp_dir())
+                .build_client()?;
+        let (good, failing) = (PeerId::random(), PeerId::random());
+        let addr: Multiaddr = "/ip4/127.0.0.1/udp/12000/quic-v1".parse()?;
+        for peer_id in [good, failing] {
+            let _ = swarm_driver
+                .swarm
+                .behaviour_mut()
+                .kademlia
+                .add_address(&peer_id, addr.clone());
+        }
+        let scores_in_kbuckets = |swarm_driver: &mut SwarmDriver| -> eyre::Result<_> {
+            let (sender, mut receiver) = oneshot::channel();
+            swarm_driver.handle_local_cmd(LocalSwarmCmd::GetKBucketsInfo { sender })?;
+            Ok(receiver
+                .try_recv()?
+                .into_values()
+                .flat_map(|kbucket| kbucket.peers)
+                .map(|(peer_id, _connected, score)| (peer_id, score))
+                .collect::<BTreeMap<_, _>>())
+        };
+
+        swarm_driver.record_exchange_success(good);
+        swarm_driver.record_exchange_failure(failing);
+        let scores = scores_in_kbuckets(&mut swarm_driver)?;
+        assert!(scores[&good] > 0);
+        assert!(scores[&failing] < 0);
+
+        while !swarm_driver.peer_scores.is_evicted(&failing) {
+            swarm_driver.record_exchange_success(good);
+            swarm_driver.record_exchange_failure(failing);
+        }
+        let scores = scores_in_kbuckets(&mut swarm_driver)?;
+        assert!(scores.contains_key(&good));
+        assert!(!scores.contains_key(&failing));
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn blocked_peer_connections_are_refused_both_ways() -> eyre::Result<()> {
+        use futures::StreamExt;
+        use libp2p::swarm::{dial_opts::DialOpts, DialError, ListenError, SwarmEvent};
+
+        let root_dir = std::env::temp_dir().join(format!("blocklist-{}", uuid::Uuid::new_v4()));
+        let mut builder = NetworkBuilder::new(Keypair::generate_ed25519(), true, root_dir.clone());
+        builder.listen_addr("127.0.0.1:0".parse()?);
+        let (_node_network, _node_events, mut node) = builder.build_node()?;
+        let (_client_network, _client_events, mut client) =
+            NetworkBuilder::new(Keypair::generate_ed25519(), true, std::env::temp_dir())
+                .build_client()?;
+        let client_peer_id = client.self_peer_id;
+
+        let listen_addr = loop {
+            if let SwarmEvent::NewListenAddr { address, .. } = node.swarm.select_next_some().await {
+                if address.iter().any(|protocol| protocol == Protocol::QuicV1) {
+                    break address;
+                }
+            }
+        };
+        node.handle_local_cmd(LocalSwarmCmd::BlockPeer {
+            peer_id: client_peer_id,
+        })?;
+
+        // We refuse to dial the blocked peer.
+        let dial = node.swarm.dial(
+            DialOpts::peer_id(client_peer_id)
+                .addresses(vec![listen_addr.clone()])
+                .build(),
+        );
+        assert!(matches!(dial, Err(DialError::Denied { .. })));
+
+        // And its inbound connections are refused.
+        client
+            .swarm
+            .dial(listen_addr.clone().with(Protocol::P2p(node.self_peer_id)))?;
+        tokio::time::timeout(Duration::from_secs(10), async {
+            loop {
+                tokio::select! {
+                    event = node.swarm.select_next_some() => {
+                        if let SwarmEvent::IncomingConnectionError {
+                            error: ListenError::Denied { .. },
+                            ..
+                        } = event
+                        {
+                            break;
+                        }
+                    }
+                    _ = client.swarm.select_next_some() => {}
+                }
+            }
+        })
+        .await?;
+        assert!(!node.swarm.is_connected(&client_peer_id));
+
+        // The block survives a restart, until lifted.
+        let restored =
+            blocklist::PeerBlocklist::load(root_dir.join(blocklist::BLOCKED_PEERS_FILENAME));
+        assert!(restored.contains(&client_peer_id));
+        node.handle_local_cmd(LocalSwarmCmd::UnblockPeer {
+            peer_id: client_peer_id,
+        })?;
+        let dial = node.swarm.dial(
+            DialOpts::peer_id(client_peer_id)
+                .addresses(vec![listen_addr])
+                .build(),
+        );
+        assert!(!matches!(dial, Err(DialError::Denied { .. })));
+
+        std::fs::remove_dir_all(root_dir)?;
+        Ok(())
+    }
+
+    /// Runs a local node, subscribed to its observed events.
+    fn run_local_node() -> eyre::Result<(
+        Network,
+        mpsc::Receiver<NetworkEvent>,
+        broadcast::Receiver<ObservedNetworkEvent>,
+    )> {
+        run_local_node_with(|_| {})
+    }
+
+    /// Runs a local node configured by `configure`, subscribed to its observed events.
+    fn run_local_node_with(
+        configure: impl FnOnce(&mut NetworkBuilder),
+    ) -> eyre::Result<(
+        Network,
+        mpsc::Receiver<NetworkEvent>,
+        broadcast::Receiver<ObservedNetworkEvent>,
+    )> {
+        let root_dir = std::env::temp_dir().join(format!("observed-{}", uuid::Uuid::new_v4()));
+        let mut builder = NetworkBuilder::new(Keypair::generate_ed25519(), true, root_dir);
+        builder.listen_addr("127.0.0.1:0".parse()?);
+        configure(&mut builder);
+        let (network, events, driver) = builder.build_node()?;
+        let observed = network.events_channel();
+        let _handle = tokio::spawn(driver.run());
+        Ok((network, events, observed))
+    }
+
+    /// Waits for the node to listen on a QUIC address.
+    async fn quic_listen_addr(
+        events: &mut mpsc::Receiver<NetworkEvent>,
+    ) -> eyre::Result<Multiaddr> {
+        Ok(tokio::time::timeout(Duration::from_secs(10), async {
+            loop {
+                if let Some(NetworkEvent::NewListenAddr(addr)) = events.recv().await {
+                    if addr.iter().any(|protocol| protocol == Protocol::QuicV1) {
+                        return addr;
+                    }
+                }
+            }
+        })
+        .await?)
+    }
+
+    /// Skips the observed events until one matches.
+    async fn next_observed(
+        observed: &mut broadcast::Receiver<ObservedNetworkEvent>,
+        matches: impl Fn(&ObservedNetworkEvent) -> bool,
+    ) -> eyre::Result<ObservedNetworkEvent> {
+        Ok(tokio::time::timeout(Duration::from_secs(30), async {
+            loop {
+                let event = observed.recv().await?;
+                if matches(&event) {
+                    return Ok::<_, broadcast::error::RecvError>(event);
+                }
+            }
+        })
+        .await??)
+    }
+
+    #[tokio::test]
+    async fn subscribers_observe_the_first_population_of_each_discovery_bucket() -> eyre::Result<()>
+    {
+        let (_node, _events, mut observed) = run_local_node()?;
+
+        // The initial candidates cover the farthest buckets, each of them being notified once.
+        let mut buckets = BTreeSet::new();
+        while !buckets.contains(&255) {
+            let ObservedNetworkEvent::DiscoveryBucketPopulated { bucket } =
+                next_observed(&mut observed, |event| {
+                    matches!(event, ObservedNetworkEvent::DiscoveryBucketPopulated { .. })
+                })
+                .await?
+            else {
+                eyre::bail!("only the populated buckets are matched");
+            };
+            assert!(buckets.insert(bucket), "bucket {bucket} notified twice");
+        }
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn subscribers_observe_a_two_node_exchange() -> eyre::Result<()> {
+        use sn_protocol::storage::{try_serialize_record, Chunk, RecordKind};
+
+        let (holder, mut holder_events, mut holder_observed) = run_local_node()?;
+        let (fetcher, _fetcher_events, mut fetcher_observed) = run_local_node()?;
+        let listen_addr = quic_listen_addr(&mut holder_events).await?;
+        fetcher
+            .dial(listen_addr.with(Protocol::P2p(holder.peer_id())))
+            .await?;
+
+        // Both nodes add the other one to their RT.
+        let bucket = NetworkAddress::from_peer(holder.peer_id())
+            .distance(&NetworkAddress::from_peer(fetcher.peer_id()))
+            .ilog2();
+        for (observed, peer_id) in [
+            (&mut holder_observed, fetcher.peer_id()),
+            (&mut fetcher_observed, holder.peer_id()),
+        ] {
+            let event = next_observed(observed, |event| {
+                matches!(event, ObservedNetworkEvent::PeerAdded { .. })
+            })
+            .await?;
+            assert_eq!(event, ObservedNetworkEvent::PeerAdded { peer_id, bucket });
+        }
+
+        let chunk = Chunk::new(bytes::Bytes::from_static(b"observed chunk"));
+        let record = Record::new(
+            chunk.network_address().to_record_key(),
+            try_serialize_record(&chunk, RecordKind::Chunk)?.to_vec(),
+        );
+        let key = PrettyPrintRecordKey::from(&record.key).into_owned();
+        holder.put_local_record(record.clone());
+        let event = next_observed(&mut holder_observed, |event| {
+            matches!(event, ObservedNetworkEvent::RecordStored { .. })
+        })
+        .await?;
+        assert_eq!(
+            event,
+            ObservedNetworkEvent::RecordStored { key: key.clone() }
+        );
+
+        let get_cfg = GetRecordCfg {
+            get_quorum: Quorum::One,
+            resolution: Default::default(),
+            retry_strategy: None,
+            target_record: None,
+            expected_holders: Default::default(),
+            is_register: false,
+            bypass_cache: false,
+        };
+        let fetched = fetcher
+            .get_record_from_network(record.key.clone(), &get_cfg)
+            .await?;
+        assert_eq!(fetched.value, record.value);
+        let event = next_observed(&mut fetcher_observed, |event| {
+            matches!(event, ObservedNetworkEvent::RecordFetched { .. })
+        })
+        .await?;
+        assert_eq!(event, ObservedNetworkEvent::RecordFetched { key });
+
+        // The holder replicates its record to the fetcher, which acknowledges the list.
+        holder.trigger_interval_replication();
+        let round = match next_observed(&mut holder_observed, |event| {
+            matches!(event, ObservedNetworkEvent::ReplicationStarted { .. })
+        })
+        .await?
+        {
+            ObservedNetworkEvent::ReplicationStarted {
+                round,
+                targets,
+                keys,
+            } => {
+                assert_eq!((targets, keys), (1, 1));
+                round
+            }
+            other => eyre::bail!("expected a replication round to start, got {other:?}"),
+        };
+        let event = next_observed(&mut holder_observed, |event| {
+            matches!(event, ObservedNetworkEvent::ReplicationCompleted { .. })
+        })
+        .await?;
+        assert_eq!(
+            event,
+            ObservedNetworkEvent::ReplicationCompleted { round, targets: 1 }
+        );
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn kbuckets_info_lists_the_connected_peers_of_a_local_swarm() -> eyre::Result<()> {
+        let (first, mut first_events, mut first_observed) = run_local_node()?;
+        let listen_addr = quic_listen_addr(&mut first_events)
+            .await?
+            .with(Protocol::P2p(first.peer_id()));
+        let mut others = Vec::new();
+        for _ in 0..3 {
+            // The receivers are kept, not to stall the drivers of the peers.
+            let (other, events, observed) = run_local_node()?;
+            other.dial(listen_addr.clone()).await?;
+            let _ = next_observed(&mut first_observed, |event| {
+                matches!(event, ObservedNetworkEvent::PeerAdded { peer_id, .. } if *peer_id == other.peer_id())
+            })
+            .await?;
+            others.push((other, events, observed));
+        }
+
+        let kbuckets = first.get_kbuckets_info().await?;
+        let self_addr = NetworkAddress::from_peer(first.peer_id());
+        for (other, _, _) in &others {
+            let ilog2 = self_addr
+                .distance(&NetworkAddress::from_peer(other.peer_id()))
+                .ilog2()
+                .expect("a peer is not self");
+            let kbucket = &kbuckets[&ilog2];
+            assert!(kbucket
+                .peers
+                .iter()
+                .any(|(peer_id, connected, _)| *peer_id == other.peer_id() && *connected));
+            assert!(!kbucket.is_full());
+        }
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn connections_beyond_the_limit_are_evicted_outside_the_close_group() -> eyre::Result<()>
+    {
+        let (limited, mut limited_events, mut limited_observed) = run_local_node_with(|builder| {
+            builder.max_established_connections(CLOSE_GROUP_SIZE as u32)
+        })?;
+        let listen_addr = quic_listen_addr(&mut limited_events)
+            .await?
+            .with(Protocol::P2p(limited.peer_id()));
+
+        let mut peers = Vec::new();
+        for _ in 0..CLOSE_GROUP_SIZE + 2 {
+            // The receivers are kept, not to stall the drivers of the peers.
+            let (peer, events, observed) = run_local_node()?;
+            peer.dial(listen_addr.clone()).await?;
+            peers.push((peer, events, observed));
+        }
+
+        // Whatever the order the connections got established in, the close group ends up connected.
+        let self_addr = NetworkAddress::from_peer(limited.peer_id());
+        let mut by_distance = peers
+            .iter()
+            .map(|(peer, _, _)| peer.peer_id())
+            .collect::<Vec<_>>();
+        by_distance.sort_by_key(|peer_id| self_addr.distance(&NetworkAddress::from_peer(*peer_id)));
+        let close_group = by_distance[..CLOSE_GROUP_SIZE]
+            .iter()
+            .copied()
+            .collect::<HashSet<_>>();
+        let event = next_observed(&mut limited_observed, |event| {
+            matches!(event, ObservedNetworkEvent::ConnectionEvicted { .. })
+        })
+        .await?;
+        assert!(
+            !matches!(event, ObservedNetworkEvent::ConnectionEvicted { peer_id } if close_group.contains(&peer_id))
+        );
+        tokio::time::timeout(Duration::from_secs(30), async {
+            loop {
+                let connected = limited
+                    .get_swarm_local_state()
+                    .await?
+                    .connected_peers
+                    .into_iter()
+                    .collect::<HashSet<_>>();
+                if connected == close_group {
+                    return Ok::<_, NetworkError>(());
+                }
+                tokio::time::sleep(Duration::from_millis(100)).await;
+            }
+        })
+        .await??;
+        Ok(())
+    }
+
+    #[cfg(feature = "local-discovery")]
+    #[tokio::test]
+    async fn peers_are_no_longer_discovered_once_local_discovery_is_off() -> eyre::Result<()> {
+        let is_added = |peer: PeerId| {
+            move |event: &ObservedNetworkEvent| match event {
+                ObservedNetworkEvent::PeerAdded { peer_id, .. } => *peer_id == peer,
+                _ => false,
+            }
+        };
+        // mDNS only advertises the addresses of the non-loopback interfaces.
+        let run_lan_node = || {
+            run_local_node_with(|builder| {
+                builder.listen_addr("0.0.0.0:0".parse().expect("a valid socket address"));
+            })
+        };
+        let (first, _first_events, mut first_observed) = run_lan_node()?;
+        let (second, _second_events, mut second_observed) = run_lan_node()?;
+        let _ = next_observed(&mut second_observed, is_added(first.peer_id())).await?;
+        let _ = next_observed(&mut first_observed, is_added(second.peer_id())).await?;
+
+        // The peers discovered so far are kept.
+        first.set_local_discovery(false);
+        second.set_local_discovery(false);
+        let in_rt = |network: &Network, peer_id: PeerId| {
+            let network = network.clone();
+            async move {
+                Ok::<_, NetworkError>(
+                    network
+                        .get_kbuckets()
+                        .await?
+                        .values()
+                        .flatten()
+                        .any(|peer| *peer == peer_id),
+                )
+            }
+        };
+        assert!(in_rt(&first, second.peer_id()).await?);
+        assert!(in_rt(&second, first.peer_id()).await?);
+
+        // A node started afterwards cannot discover the first one, whatever its own setting.
+        let (_third, _third_events, mut third_observed) = run_lan_node()?;
+        assert!(tokio::time::timeout(
+            Duration::from_secs(15),
+            next_observed(&mut third_observed, is_added(first.peer_id()))
+        )
+        .await
+        .is_err());
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn a_node_behind_a_nat_is_dialed_through_its_relay() -> eyre::Result<()> {
+        let (relay, _relay_events, _relay_observed) = run_local_node()?;
+        let (private, mut private_events, mut private_observed) =
+            run_local_node_with(|builder| builder.is_behind_home_network(true))?;
+        let (dialer, _dialer_events, mut dialer_observed) = run_local_node()?;
+
+        // Being dialed, rather than dialing it as a bootstrap peer, gets the relay picked as a relay candidate.
+        relay
+            .dial(quic_listen_addr(&mut private_events).await?)
+            .await?;
+
+        // The reservation is made on the next relay manager tick.
+        let relayed_addr = tokio::time::timeout(
+            crate::driver::RELAY_MANAGER_RESERVATION_INTERVAL + Duration::from_secs(30),
+            async {
+                loop {
+                    if let Some(NetworkEvent::NewListenAddr(addr)) = private_events.recv().await {
+                        if addr.iter().any(|protocol| protocol == Protocol::P2pCircuit) {
+                            return addr;
+                        }
+                    }
+                }
+            },
+        )
+        .await?;
+        assert!(relayed_addr
+            .iter()
+            .any(|protocol| protocol == Protocol::P2p(relay.peer_id())));
+
+        // The dialer only knows of the relayed address.
+        dialer.dial(relayed_addr).await?;
+        let _ = next_observed(&mut dialer_observed, |event| match event {
+            ObservedNetworkEvent::PeerAdded { peer_id, .. } => *peer_id == private.peer_id(),
+            _ => false,
+        })
+        .await?;
+
+        // The relayed connection is then upgraded into a direct one.
+        let event = next_observed(&mut private_observed, |event| {
+            matches!(event, ObservedNetworkEvent::HolePunchAttempted { peer_id, .. } if *peer_id == dialer.peer_id())
+        })
+        .await?;
+        assert_eq!(
+            event,
+            ObservedNetworkEvent::HolePunchAttempted {
+                peer_id: dialer.peer_id(),
+                succeeded: true
+            }
+        );
+        Ok(())
+    }
+
     #[test]
     fn test_network_sign_verify() -> eyre::Result<()> {
         let (network, _, _) =
@@ -1184,4 +2249,63 @@ mod tests {
         assert!(network.verify(msg, &sig));
         Ok(())
     }
+
+    #[tokio::test]
+    async fn network_discovery_round_schedules_the_queries_of_the_ticks() -> eyre::Result<()> {
+        let (_network, _events, mut swarm_driver) =
+            NetworkBuilder::new(Keypair::generate_ed25519(), false, std::env::temp_dir())
+                .build_client()?;
+        swarm_driver.network_discovery.populate();
+
+        swarm_driver.trigger_network_discovery();
+        assert!(swarm_driver.pending_get_closest_peers.is_empty());
+        let scheduled = swarm_driver.network_discovery_queue.len();
+        assert!(scheduled > QUERIES_PER_DISCOVERY_TICK);
+        assert!(scheduled <= swarm_driver.network_discovery.queries_per_round());
+        // The next round only tops the schedule up.
+        swarm_driver.trigger_network_discovery();
+        assert_eq!(swarm_driver.network_discovery_queue.len(), scheduled);
+
+        swarm_driver.peers_in_rt = 1;
+        swarm_driver.query_next_network_discovery_targets();
+        assert_eq!(
+            swarm_driver.pending_get_closest_peers.len(),
+            QUERIES_PER_DISCOVERY_TICK
+        );
+        assert_eq!(
+            swarm_driver.network_discovery_queue.len(),
+            scheduled - QUERIES_PER_DISCOVERY_TICK
+        );
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn discovery_target_is_retired_after_consecutive_empty_results() -> eyre::Result<()> {
+        let (_network, _events, mut swarm_driver) =
+            NetworkBuilder::new(Keypair::generate_ed25519(), false, std::env::temp_dir())
+                .build_client()?;
+        let peer_id = PeerId::random();
+        swarm_driver.network_discovery.seed(&[peer_id]);
+        let target = NetworkAddress::from_peer(peer_id);
+        let ilog2 = target
+            .as_kbucket_key()
+            .distance(&NetworkAddress::from_peer(swarm_driver.self_peer_id).as_kbucket_key())
+            .ilog2()
+            .ok_or_else(|| eyre::eyre!("a random peer has a bucket"))?;
+        let is_candidate = |swarm_driver: &SwarmDriver| {
+            swarm_driver
+                .network_discovery
+                .candidates_for_bucket(ilog2)
+                .any(|candidate| *candidate == target)
+        };
+
+        // The failed queries are tolerated up to the threshold.
+        for _ in 0..2 {
+            swarm_driver.handle_network_discovery_result(&target, vec![], false);
+            assert!(is_candidate(&swarm_driver));
+        }
+        swarm_driver.handle_network_discovery_result(&target, vec![], false);
+        assert!(!is_candidate(&swarm_driver));
+        Ok(())
+    }
 }
@@ -0,0 +1,182 @@
+// Copyright 2024 MaidSafe.net limited.
+//
+// This SAFE Network Software is licensed to you under The General Public License (GPL), version 3.
+// Unless required by applicable law or agreed to in writing, the SAFE Network Software distributed
+// under the GPL Licence is distributed on an "AS IS" BASIS, WITHOUT WARRANTIES OR CONDITIONS OF ANY
+// KIND, either express or implied. Please review the Licences for the specific language governing
+// permissions and limitations relating to use of the SAFE Network Software.
+
+use crate::driver::SwarmDriver;
+use libp2p::{
+    core::{transport::ListenerId, transport::PortUse, Endpoint},
+    mdns,
+    swarm::{
+        dummy, ConnectionDenied, ConnectionId, ExpiredListenAddr, FromSwarm, NetworkBehaviour,
+        NewListenAddr, THandler, THandlerInEvent, THandlerOutEvent, ToSwarm,
+    },
+    Multiaddr, PeerId,
+};
+use std::{
+    io,
+    task::{Context, Poll, Waker},
+};
+
+/// The mDNS behaviour, which can be switched on and off at runtime. While off, the mDNS sockets are closed, hence we
+/// neither advertise ourselves nor discover anyone on the local network. The peers already discovered are kept in
+/// our RT.
+pub(crate) struct LocalDiscovery {
+    mdns: Option<mdns::tokio::Behaviour>,
+    config: mdns::Config,
+    self_peer_id: PeerId,
+    // The addresses advertised by mDNS, replayed to it when it is switched back on.
+    listen_addrs: Vec<(ListenerId, Multiaddr)>,
+    waker: Option<Waker>,
+}
+
+impl LocalDiscovery {
+    pub(crate) fn new(
+        config: mdns::Config,
+        self_peer_id: PeerId,
+        enabled: bool,
+    ) -> io::Result<Self> {
+        let mut local_discovery = Self {
+            mdns: None,
+            config,
+            self_peer_id,
+            listen_addrs: Vec::new(),
+            waker: None,
+        };
+        local_discovery.set_enabled(enabled)?;
+        Ok(local_discovery)
+    }
+
+    pub(crate) fn is_enabled(&self) -> bool {
+        self.mdns.is_some()
+    }
+
+    /// Switches mDNS on or off. Switching it on again starts a new mDNS instance, advertising our current listen
+    /// addresses.
+    pub(crate) fn set_enabled(&mut self, enabled: bool) -> io::Result<()> {
+        if enabled == self.is_enabled() {
+            return Ok(());
+        }
+        if !enabled {
+            if let Some(mut mdns) = self.mdns.take() {
+                // The mDNS tasks outlive the behaviour until they discover a peer, they are left with nothing to
+                // advertise meanwhile.
+                for (listener_id, addr) in &self.listen_addrs {
+                    mdns.on_swarm_event(FromSwarm::ExpiredListenAddr(ExpiredListenAddr {
+                        listener_id: *listener_id,
+                        addr,
+                    }));
+                }
+            }
+            return Ok(());
+        }
+
+        let mut mdns = mdns::tokio::Behaviour::new(self.config.clone(), self.self_peer_id)?;
+        for (listener_id, addr) in &self.listen_addrs {
+            mdns.on_swarm_event(FromSwarm::NewListenAddr(NewListenAddr {
+                listener_id: *listener_id,
+                addr,
+            }));
+        }
+        self.mdns = Some(mdns);
+        if let Some(waker) = self.waker.take() {
+            waker.wake();
+        }
+        Ok(())
+    }
+}
+
+impl NetworkBehaviour for LocalDiscovery {
+    type ConnectionHandler = dummy::ConnectionHandler;
+    type ToSwarm = mdns::Event;
+
+    fn handle_established_inbound_connection(
+        &mut self,
+        _connection_id: ConnectionId,
+        _peer: PeerId,
+        _local_addr: &Multiaddr,
+        _remote_addr: &Multiaddr,
+    ) -> Result<THandler<Self>, ConnectionDenied> {
+        Ok(dummy::ConnectionHandler)
+    }
+
+    fn handle_pending_outbound_connection(
+        &mut self,
+        connection_id: ConnectionId,
+        maybe_peer: Option<PeerId>,
+        addresses: &[Multiaddr],
+        effective_role: Endpoint,
+    ) -> Result<Vec<Multiaddr>, ConnectionDenied> {
+        match self.mdns.as_mut() {
+            Some(mdns) => mdns.handle_pending_outbound_connection(
+                connection_id,
+                maybe_peer,
+                addresses,
+                effective_role,
+            ),
+            None => Ok(vec![]),
+        }
+    }
+
+    fn handle_established_outbound_connection(
+        &mut self,
+        _connection_id: ConnectionId,
+        _peer: PeerId,
+        _addr: &Multiaddr,
+        _role_override: Endpoint,
+        _port_use: PortUse,
+    ) -> Result<THandler<Self>, ConnectionDenied> {
+        Ok(dummy::ConnectionHandler)
+    }
+
+    fn on_swarm_event(&mut self, event: FromSwarm) {
+        match event {
+            FromSwarm::NewListenAddr(NewListenAddr { listener_id, addr }) => {
+                self.listen_addrs.push((listener_id, addr.clone()));
+            }
+            FromSwarm::ExpiredListenAddr(ExpiredListenAddr { listener_id, addr }) => {
+                self.listen_addrs
+                    .retain(|(id, listen_addr)| *id != listener_id || listen_addr != addr);
+            }
+            _ => {}
+        }
+        if let Some(mdns) = self.mdns.as_mut() {
+            mdns.on_swarm_event(event);
+        }
+    }
+
+    fn on_connection_handler_event(
+        &mut self,
+        _peer_id: PeerId,
+        _connection_id: ConnectionId,
+        event: THandlerOutEvent<Self>,
+    ) {
+        void::unreachable(event)
+    }
+
+    fn poll(
+        &mut self,
+        cx: &mut Context<'_>,
+    ) -> Poll<ToSwarm<Self::ToSwarm, THandlerInEvent<Self>>> {
+        match self.mdns.as_mut() {
+            Some(mdns) => mdns.poll(cx),
+            None => {
+                self.waker = Some(cx.waker().clone());
+                Poll::Pending
+            }
+        }
+    }
+}
+
+impl SwarmDriver {
+    /// Switches the mDNS local discovery on or off. The peers already discovered remain in our RT.
+    pub(crate) fn set_local_discovery(&mut self, enabled: bool) {
+        info!("Setting the mDNS local discovery enabled: {enabled}");
+        if let Err(err) = self.swarm.behaviour_mut().mdns.set_enabled(enabled) {
+            error!("Failed to start the mDNS local discovery: {err:?}");
+        }
+    }
+}
@@ -8,6 +8,8 @@
 
 // Implementation to record `libp2p::upnp::Event` metrics
 mod bad_node;
+mod network_discovery;
+mod operation_latency;
 pub mod service;
 #[cfg(feature = "upnp")]
 mod upnp;
@@ -17,6 +19,8 @@ use crate::MetricsRegistries;
 use crate::{log_markers::Marker, target_arch::sleep};
 use bad_node::{ShunnedCountAcrossTimeFrames, TimeFrame};
 use libp2p::metrics::{Metrics as Libp2pMetrics, Recorder};
+pub(crate) use network_discovery::NetworkDiscoveryMetrics;
+pub(crate) use operation_latency::{Operation, OperationLatencyMetrics, Outcome};
 use prometheus_client::{
     metrics::family::Family,
     metrics::{counter::Counter, gauge::Gauge},
@@ -41,7 +45,16 @@ pub(crate) struct NetworkMetricsRecorder {
     pub(crate) estimated_network_size: Gauge,
     pub(crate) open_connections: Gauge,
     pub(crate) peers_in_routing_table: Gauge,
+    pub(crate) evicted_peers: Gauge,
+    pub(crate) evicted_connections: Counter,
+    pub(crate) peers_in_dial_backoff: Gauge,
+    pub(crate) record_cache_hits: Counter,
+    pub(crate) record_cache_misses: Counter,
+    pub(crate) observed_events_dropped: Counter,
+    pub(crate) replication_queue_depth: Gauge,
     pub(crate) records_stored: Gauge,
+    pub(crate) network_discovery: NetworkDiscoveryMetrics,
+    pub(crate) operation_latency: OperationLatencyMetrics,
 
     // store cost
     store_cost: Gauge,
@@ -105,6 +118,51 @@ impl NetworkMetricsRecorder {
             "The total number of peers in our routing table",
             peers_in_routing_table.clone(),
         );
+        let evicted_peers = Gauge::default();
+        sub_registry.register(
+            "evicted_peers",
+            "The number of peers currently evicted from our routing table for scoring too low",
+            evicted_peers.clone(),
+        );
+        let evicted_connections = Counter::default();
+        sub_registry.register(
+            "evicted_connections",
+            "The number of connections closed to stay within the established connections limit",
+            evicted_connections.clone(),
+        );
+        let peers_in_dial_backoff = Gauge::default();
+        sub_registry.register(
+            "peers_in_dial_backoff",
+            "The number of peers not dialed for a while after their consecutive dial failures",
+            peers_in_dial_backoff.clone(),
+        );
+        let record_cache_hits = Counter::default();
+        sub_registry.register(
+            "record_cache_hits",
+            "The number of record fetches served from the record cache",
+            record_cache_hits.clone(),
+        );
+        let record_cache_misses = Counter::default();
+        sub_registry.register(
+            "record_cache_misses",
+            "The number of record fetches not found in the record cache",
+            record_cache_misses.clone(),
+        );
+        let observed_events_dropped = Counter::default();
+        let replication_queue_depth = Gauge::default();
+        sub_registry.register(
+            "replication_queue_depth",
+            "The number of replication transfers queued by the replication bandwidth limit",
+            replication_queue_depth.clone(),
+        );
+        sub_registry.register(
+            "observed_events_dropped",
+            "The number of observed network events dropped for a subscriber lagging behind",
+            observed_events_dropped.clone(),
+        );
+
+        let network_discovery = NetworkDiscoveryMetrics::register(sub_registry);
+        let operation_latency = OperationLatencyMetrics::register(sub_registry);
 
         let shunned_count = Counter::default();
         sub_registry.register(
@@ -196,10 +254,19 @@ impl NetworkMetricsRecorder {
             upnp_events,
 
             records_stored,
+            network_discovery,
+            operation_latency,
             estimated_network_size,
             connected_peers,
             open_connections,
             peers_in_routing_table,
+            evicted_peers,
+            evicted_connections,
+            peers_in_dial_backoff,
+            record_cache_hits,
+            record_cache_misses,
+            observed_events_dropped,
+            replication_queue_depth,
             store_cost,
             relevant_records,
             max_records,
@@ -296,6 +363,12 @@ impl Recorder<libp2p::relay::Event> for NetworkMetricsRecorder {
     }
 }
 
+impl Recorder<libp2p::dcutr::Event> for NetworkMetricsRecorder {
+    fn record(&self, event: &libp2p::dcutr::Event) {
+        self.libp2p_metrics.record(event)
+    }
+}
+
 impl Recorder<libp2p::identify::Event> for NetworkMetricsRecorder {
     fn record(&self, event: &libp2p::identify::Event) {
         self.libp2p_metrics.record(event)
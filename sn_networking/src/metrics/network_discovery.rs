@@ -0,0 +1,82 @@
+// Copyright 2024 MaidSafe.net limited.
+//
+// This SAFE Network Software is licensed to you under The General Public License (GPL), version 3.
+// Unless required by applicable law or agreed to in writing, the SAFE Network Software distributed
+// under the GPL Licence is distributed on an "AS IS" BASIS, WITHOUT WARRANTIES OR CONDITIONS OF ANY
+// KIND, either express or implied. Please review the Licences for the specific language governing
+// permissions and limitations relating to use of the SAFE Network Software.
+
+use prometheus_client::{
+    metrics::{
+        counter::Counter,
+        gauge::Gauge,
+        histogram::{exponential_buckets, Histogram},
+    },
+    registry::Registry,
+};
+
+/// The metrics updated by the `NetworkDiscovery`. The handle is cheap to clone, every clone updating the same series.
+#[derive(Debug, Clone)]
+pub(crate) struct NetworkDiscoveryMetrics {
+    pub(crate) candidates_generated: Counter,
+    pub(crate) duplicate_candidates: Counter,
+    pub(crate) buckets_covered: Gauge,
+    pub(crate) uncovered_buckets: Gauge,
+    pub(crate) refresh_duration: Histogram,
+    pub(crate) closest_query_duration: Histogram,
+}
+
+impl NetworkDiscoveryMetrics {
+    /// Registers the series into the provided registry, under the `network_discovery` prefix.
+    pub(crate) fn register(registry: &mut Registry) -> Self {
+        let sub_registry = registry.sub_registry_with_prefix("network_discovery");
+
+        let candidates_generated = Counter::default();
+        sub_registry.register(
+            "candidates_generated",
+            "The number of candidates generated for the buckets that had room for them",
+            candidates_generated.clone(),
+        );
+        let duplicate_candidates = Counter::default();
+        sub_registry.register(
+            "duplicate_candidates",
+            "The number of candidates discarded as they were already held by their bucket",
+            duplicate_candidates.clone(),
+        );
+        let buckets_covered = Gauge::default();
+        sub_registry.register(
+            "buckets_covered",
+            "The number of buckets for which we hold at least one candidate",
+            buckets_covered.clone(),
+        );
+        let uncovered_buckets = Gauge::default();
+        sub_registry.register(
+            "uncovered_buckets",
+            "The number of targeted buckets for which we hold no candidate",
+            uncovered_buckets.clone(),
+        );
+        // From 1ms to ~16s
+        let refresh_duration = Histogram::new(exponential_buckets(0.001, 2.0, 15));
+        sub_registry.register(
+            "refresh_duration_seconds",
+            "The time taken to refresh the candidates",
+            refresh_duration.clone(),
+        );
+        // From 10µs to ~160ms
+        let closest_query_duration = Histogram::new(exponential_buckets(0.000_01, 2.0, 15));
+        sub_registry.register(
+            "closest_query_duration_seconds",
+            "The time taken to handle the result of a kad::GetClosestPeers query",
+            closest_query_duration.clone(),
+        );
+
+        Self {
+            candidates_generated,
+            duplicate_candidates,
+            buckets_covered,
+            uncovered_buckets,
+            refresh_duration,
+            closest_query_duration,
+        }
+    }
+}
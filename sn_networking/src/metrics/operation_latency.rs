@@ -0,0 +1,197 @@
+// Copyright 2024 MaidSafe.net limited.
+//
+// This SAFE Network Software is licensed to you under The General Public License (GPL), version 3.
+// Unless required by applicable law or agreed to in writing, the SAFE Network Software distributed
+// under the GPL Licence is distributed on an "AS IS" BASIS, WITHOUT WARRANTIES OR CONDITIONS OF ANY
+// KIND, either express or implied. Please review the Licences for the specific language governing
+// permissions and limitations relating to use of the SAFE Network Software.
+
+use libp2p::{
+    kad::{GetClosestPeersError, GetRecordError, PutRecordError, QueryResult, QueryStats},
+    request_response::OutboundFailure,
+};
+use prometheus_client::{
+    encoding::{EncodeLabelSet, EncodeLabelValue, LabelValueEncoder},
+    metrics::{
+        family::Family,
+        histogram::{exponential_buckets, Histogram},
+    },
+    registry::Registry,
+};
+use std::{fmt::Write, time::Duration};
+
+#[derive(Debug, Clone, Copy, Hash, PartialEq, Eq, EncodeLabelSet)]
+pub(crate) struct OperationLatencyLabels {
+    operation: Operation,
+    outcome: Outcome,
+}
+
+#[derive(Debug, Clone, Copy, Hash, PartialEq, Eq)]
+pub(crate) enum Operation {
+    GetClosest,
+    GetRecord,
+    PutRecord,
+    RequestResponse,
+}
+
+impl EncodeLabelValue for Operation {
+    fn encode(&self, encoder: &mut LabelValueEncoder) -> Result<(), std::fmt::Error> {
+        encoder.write_str(match self {
+            Operation::GetClosest => "get_closest",
+            Operation::GetRecord => "get_record",
+            Operation::PutRecord => "put_record",
+            Operation::RequestResponse => "req_resp",
+        })
+    }
+}
+
+#[derive(Debug, Clone, Copy, Hash, PartialEq, Eq)]
+pub(crate) enum Outcome {
+    Ok,
+    Timeout,
+    Error,
+}
+
+impl EncodeLabelValue for Outcome {
+    fn encode(&self, encoder: &mut LabelValueEncoder) -> Result<(), std::fmt::Error> {
+        encoder.write_str(match self {
+            Outcome::Ok => "ok",
+            Outcome::Timeout => "timeout",
+            Outcome::Error => "error",
+        })
+    }
+}
+
+impl From<&OutboundFailure> for Outcome {
+    fn from(failure: &OutboundFailure) -> Self {
+        match failure {
+            OutboundFailure::Timeout => Outcome::Timeout,
+            _ => Outcome::Error,
+        }
+    }
+}
+
+/// Returns the operation and the outcome of a kad query, None for the queries other than the closest peers and the
+/// record ones.
+fn kad_query_labels(result: &QueryResult) -> Option<(Operation, Outcome)> {
+    let labels = match result {
+        QueryResult::GetClosestPeers(Ok(_)) => (Operation::GetClosest, Outcome::Ok),
+        QueryResult::GetClosestPeers(Err(GetClosestPeersError::Timeout { .. })) => {
+            (Operation::GetClosest, Outcome::Timeout)
+        }
+        QueryResult::GetRecord(Ok(_)) => (Operation::GetRecord, Outcome::Ok),
+        QueryResult::GetRecord(Err(GetRecordError::Timeout { .. })) => {
+            (Operation::GetRecord, Outcome::Timeout)
+        }
+        QueryResult::GetRecord(Err(_)) => (Operation::GetRecord, Outcome::Error),
+        QueryResult::PutRecord(Ok(_)) => (Operation::PutRecord, Outcome::Ok),
+        QueryResult::PutRecord(Err(PutRecordError::Timeout { .. })) => {
+            (Operation::PutRecord, Outcome::Timeout)
+        }
+        QueryResult::PutRecord(Err(_)) => (Operation::PutRecord, Outcome::Error),
+        _ => return None,
+    };
+    Some(labels)
+}
+
+/// The durations of the kad queries and of the request-response exchanges, by operation and outcome. The handle is
+/// cheap to clone, every clone updating the same series.
+#[derive(Debug, Clone)]
+pub(crate) struct OperationLatencyMetrics {
+    durations: Family<OperationLatencyLabels, Histogram, fn() -> Histogram>,
+}
+
+impl OperationLatencyMetrics {
+    /// Registers the series into the provided registry.
+    pub(crate) fn register(registry: &mut Registry) -> Self {
+        // From 10ms to ~164s
+        let durations: Family<_, _, fn() -> Histogram> =
+            Family::new_with_constructor(|| Histogram::new(exponential_buckets(0.01, 2.0, 15)));
+        registry.register(
+            "operation_duration_seconds",
+            "The time taken by the closest peers and record queries, and by the request-response exchanges",
+            durations.clone(),
+        );
+        Self { durations }
+    }
+
+    pub(crate) fn observe(&self, operation: Operation, outcome: Outcome, duration: Duration) {
+        self.durations
+            .get_or_create(&OperationLatencyLabels { operation, outcome })
+            .observe(duration.as_secs_f64());
+    }
+
+    /// Records the duration of a finished kad query, the queries other than the closest peers and the record ones
+    /// being ignored.
+    pub(crate) fn observe_kad_query(&self, result: &QueryResult, stats: &QueryStats) {
+        if let (Some((operation, outcome)), Some(duration)) =
+            (kad_query_labels(result), stats.duration())
+        {
+            self.observe(operation, outcome, duration);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use libp2p::kad::RecordKey;
+    use prometheus_client::encoding::text::encode;
+
+    #[test]
+    fn durations_are_observed_by_operation_and_outcome() -> eyre::Result<()> {
+        let mut registry = Registry::default();
+        let metrics = OperationLatencyMetrics::register(&mut registry);
+
+        metrics.observe(
+            Operation::RequestResponse,
+            Outcome::Ok,
+            Duration::from_millis(15),
+        );
+        metrics.observe(
+            Operation::RequestResponse,
+            Outcome::from(&OutboundFailure::Timeout),
+            Duration::from_secs(100),
+        );
+        let key = RecordKey::new(&[0]);
+        if let Some((operation, outcome)) =
+            kad_query_labels(&QueryResult::GetRecord(Err(GetRecordError::Timeout {
+                key: key.clone(),
+            })))
+        {
+            metrics.observe(operation, outcome, Duration::from_secs(1));
+        }
+        assert_eq!(
+            kad_query_labels(&QueryResult::PutRecord(Err(PutRecordError::QuorumFailed {
+                key,
+                success: vec![],
+                quorum: std::num::NonZeroUsize::MIN,
+            }))),
+            Some((Operation::PutRecord, Outcome::Error))
+        );
+
+        let mut encoded = String::new();
+        encode(&mut encoded, &registry)?;
+        let series = |labels: &str, value: &str| {
+            encoded.contains(&format!(
+                "operation_duration_seconds_count{{{labels}}} {value}"
+            ))
+        };
+        assert!(series(r#"operation="req_resp",outcome="ok""#, "1"));
+        assert!(series(r#"operation="req_resp",outcome="timeout""#, "1"));
+        assert!(series(r#"operation="get_record",outcome="timeout""#, "1"));
+        assert!(!encoded.contains(r#"outcome="error""#));
+
+        // The observations land in the buckets covering them, the last ones reaching past 120s.
+        assert!(encoded.contains(
+            r#"operation_duration_seconds_bucket{le="0.02",operation="req_resp",outcome="ok"} 1"#
+        ));
+        assert!(encoded.contains(
+            r#"operation_duration_seconds_bucket{le="81.92",operation="req_resp",outcome="timeout"} 0"#
+        ));
+        assert!(encoded.contains(
+            r#"operation_duration_seconds_bucket{le="163.84",operation="req_resp",outcome="timeout"} 1"#
+        ));
+        Ok(())
+    }
+}
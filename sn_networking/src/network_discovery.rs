@@ -6,168 +6,5059 @@
 // KIND, either express or implied. Please review the Licences for the specific language governing
 // permissions and limitations relating to use of the SAFE Network Software.
 
+#[cfg(feature = "open-metrics")]
+use crate::metrics::NetworkDiscoveryMetrics;
 use crate::target_arch::Instant;
-use libp2p::{kad::KBucketKey, PeerId};
-use rand::{thread_rng, Rng};
-use rayon::iter::{IntoParallelIterator, ParallelIterator};
+use libp2p::{
+    kad::{KBucketKey, K_VALUE},
+    PeerId,
+};
+use rand::{rngs::StdRng, Rng, RngCore, SeedableRng};
+use rayon::{
+    iter::{Either, IndexedParallelIterator, IntoParallelIterator, ParallelIterator},
+    ThreadPool,
+};
+use serde::{Deserialize, Serialize};
 use sn_protocol::NetworkAddress;
-use std::collections::{btree_map::Entry, BTreeMap};
+use std::{
+    collections::{BTreeMap, BTreeSet, HashMap, HashSet},
+    fs,
+    ops::RangeInclusive,
+    path::{Path, PathBuf},
+    sync::{
+        atomic::{AtomicU64, AtomicUsize, Ordering},
+        Arc,
+    },
+    time::Duration,
+};
+use tokio::sync::mpsc;
 
-// The number of PeerId to generate when starting an instance of NetworkDiscovery
-const INITIAL_GENERATION_ATTEMPTS: usize = 10_000;
-// The number of PeerId to generate during each invocation to refresh our candidates
-const GENERATION_ATTEMPTS: usize = 1_000;
-// The max number of PeerId to keep per bucket
-const MAX_PEERS_PER_BUCKET: usize = 5;
+/// The number of buckets that the candidates are expected to cover once the initial generation is done. These are
+/// the farther buckets, which are hit by a few thousand attempts.
+pub(crate) const INITIAL_COVERAGE_TARGET: usize = 8;
+
+/// The file under the node's root dir into which the candidates are persisted, the generated ones along with the
+/// snapshot of the observed ones, see `NetworkDiscoverySnapshot`.
+pub(crate) const NETWORK_DISCOVERY_CANDIDATES_FILENAME: &str = "network_discovery_candidates";
+
+// The default number of PeerId to generate when starting an instance of NetworkDiscovery
+const DEFAULT_INITIAL_GENERATION_ATTEMPTS: usize = 10_000;
+// The default number of PeerId to generate during each invocation to refresh our candidates
+const DEFAULT_GENERATION_ATTEMPTS: usize = 1_000;
+// The number of PeerId generated by each batch of an incremental generation, see `new_incremental`
+const INITIAL_BATCH_ATTEMPTS: usize = 1_000;
+// The max number of PeerId to generate by each call to `refresh_targeting`. The driver calls it on its own thread at
+// every round of network discovery, hence the buckets left uncovered are rather targeted again by the next rounds.
+const MAX_TARGETED_GENERATION_ATTEMPTS: usize = 5_000;
+/// A warning is logged by `check_coverage` if more of the targeted buckets than this hold no candidate.
+const UNCOVERED_BUCKETS_WARNING_THRESHOLD: usize = 3;
+/// The max number of PeerIds sampled by `candidate_for_emptied_bucket` when the bucket holds no candidate.
+const EMPTIED_BUCKET_GENERATION_ATTEMPTS: usize = 1_000;
+// The default max number of PeerId to keep per bucket
+const DEFAULT_MAX_PEERS_PER_BUCKET: usize = 5;
+// The default max number of candidates held across all the buckets.
+const DEFAULT_MAX_TOTAL_CANDIDATES: usize = 1_000;
+// The max factor by which the weight of a bucket is raised by the queries of its candidates that returned peers.
+const MAX_BUCKET_SUCCESS_BOOST: usize = 3;
+// The default number of candidates queried during a round of network discovery.
+const DEFAULT_QUERIES_PER_ROUND: usize = 10;
+// The buckets that are explicitly targeted if the random sampling has missed them. The closer buckets are not
+// targeted as the chance of generating a candidate for them is negligible.
+const TARGETED_BUCKETS: RangeInclusive<u32> = 245..=255;
+// A targeted bucket holding fewer candidates than this that have not been queried since the last refresh is depleted
+const DEPLETED_BUCKET_REMAINING_CANDIDATES: usize = 2;
+// A refresh is needed once more than this many targeted buckets are depleted
+const MAX_DEPLETED_BUCKETS: usize = 2;
+// A candidate is evicted once its queries have failed this many times in a row
+const MAX_CONSECUTIVE_QUERY_FAILURES: u8 = 3;
+// The number of PeerIds generated from each seed by a seeded NetworkDiscovery, i.e., the work of a rayon task.
+const SEEDED_GENERATION_CHUNK: usize = 256;
+// The number of buckets closer than the expected closest peer that are still deemed plausible, to absorb the error of
+// the network size estimate. See `set_network_size_estimate`.
+const NETWORK_SIZE_MARGIN_BUCKETS: u32 = 3;
+// A candidate handed out by `take_candidates` can be handed out again if no result has been received for it within
+// this duration, which leaves plenty of room for the kad query timeout.
+const IN_FLIGHT_CANDIDATE_TIMEOUT: Duration = Duration::from_secs(30);
+/// A bucket is not picked by `CandidateSelection::RoundRobin` if one of its candidates has been queried within this duration.
+const BUCKET_QUERY_COOLDOWN: Duration = Duration::from_secs(30);
+// The default max number of the peers returned by a `GetClosestPeers` query that are ingested. A well-behaved peer
+// returns at most K_VALUE peers.
+const DEFAULT_MAX_OBSERVED_PEERS_PER_QUERY: usize = K_VALUE.get();
+
+/// The settings used by NetworkDiscovery to generate and retain its candidates.
+///
+/// Lowering the attempts reduces the CPU spent on generation (useful on low-powered devices), at the cost of a
+/// worse bucket coverage. E.g., 2_000 initial attempts cut the startup generation fivefold, while still reaching most
+/// of the targeted buckets. The `NetworkBuilder` rejects the attempts of 0.
+#[derive(Debug, Clone)]
+pub struct NetworkDiscoveryConfig {
+    /// The number of PeerId to generate when starting an instance of NetworkDiscovery.
+    pub initial_attempts: usize,
+    /// The max number of PeerId to generate during each invocation to refresh our candidates. It is scaled down
+    /// according to the number of targeted buckets that still lack candidates.
+    pub refresh_attempts: usize,
+    /// The max number of candidates to keep per bucket. A value of 0 is clamped to 1.
+    pub max_peers_per_bucket: usize,
+    /// The max number of candidates held across all the buckets, to bound the memory used on constrained nodes. Once
+    /// reached, the candidates of the fullest buckets are evicted first, the farthest ones among equally full buckets.
+    pub max_total_candidates: usize,
+    /// The number of candidates selected for a `GetClosestPeers` query during each round of network discovery.
+    pub queries_per_round: usize,
+    /// Whether the ilog2 distance of every generated PeerId is recorded, retained or not, see
+    /// `generation_histogram`. This is meant for diagnosing the coverage of the close buckets.
+    pub record_generation_histogram: bool,
+    /// The max number of the peers returned by a `GetClosestPeers` query that are ingested as candidates, the ones
+    /// closest to self being kept. This bounds the work caused by a peer returning a flood of bogus peers.
+    pub max_observed_peers_per_query: usize,
+    /// Checks each peer returned by a `GetClosestPeers` query before it is ingested, the rejected ones being dropped.
+    /// All the peers are accepted if None.
+    pub observed_peer_validator: Option<fn(&PeerId) -> bool>,
+    /// The thread pool on which the candidates are generated. Defaults to the global rayon pool.
+    ///
+    /// The generation keeps every thread of the pool busy while it runs, so on the global pool it competes with any
+    /// other rayon work of the process. A dedicated pool with a few threads bounds the CPU used by the generation,
+    /// at the cost of it taking longer to complete.
+    pub thread_pool: Option<Arc<ThreadPool>>,
+}
+
+impl Default for NetworkDiscoveryConfig {
+    fn default() -> Self {
+        Self {
+            initial_attempts: DEFAULT_INITIAL_GENERATION_ATTEMPTS,
+            refresh_attempts: DEFAULT_GENERATION_ATTEMPTS,
+            max_peers_per_bucket: DEFAULT_MAX_PEERS_PER_BUCKET,
+            max_total_candidates: DEFAULT_MAX_TOTAL_CANDIDATES,
+            queries_per_round: DEFAULT_QUERIES_PER_ROUND,
+            record_generation_histogram: false,
+            max_observed_peers_per_query: DEFAULT_MAX_OBSERVED_PEERS_PER_QUERY,
+            observed_peer_validator: None,
+            thread_pool: None,
+        }
+    }
+}
+
+/// The outcome of a round of candidate generation. A low `accepted` count compared to the `attempts` indicates that
+/// the generation is becoming ineffective, e.g., the reachable buckets are already full.
+#[derive(Debug, Default, Clone, PartialEq, Eq)]
+pub(crate) struct CandidateGenerationStats {
+    /// The number of PeerIds generated.
+    pub(crate) attempts: usize,
+    /// The number of new candidates retained.
+    pub(crate) accepted: usize,
+    /// The number of buckets that retained at least one new candidate.
+    pub(crate) buckets_touched: usize,
+    /// The number of buckets whose front candidate, i.e., the next one to be queried from them, changed. This happens
+    /// even without any new candidate, e.g., once an expired in-flight candidate is moved to the back of its bucket.
+    pub(crate) fronts_changed: usize,
+}
+
+impl CandidateGenerationStats {
+    /// Returns true if any candidate was retained or any bucket's front changed, i.e., if the candidates to query may
+    /// differ from the ones before the refresh.
+    pub(crate) fn changed(&self) -> bool {
+        self.accepted > 0 || self.fronts_changed > 0
+    }
+}
+
+/// A snapshot of the state of the NetworkDiscovery, for metrics and diagnostics.
+#[derive(Debug, Clone, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub struct NetworkDiscoveryStats {
+    /// The number of buckets for which we hold at least one candidate.
+    pub buckets_covered: usize,
+    /// The total number of candidates held.
+    pub total_candidates: usize,
+    /// The closest bucket covered.
+    pub min_ilog2: Option<u32>,
+    /// The farthest bucket covered.
+    pub max_ilog2: Option<u32>,
+    /// The time elapsed since the candidates were last refreshed, if ever.
+    pub since_last_refresh: Option<Duration>,
+    /// The number of candidates that have been handed out for querying since start.
+    pub candidates_consumed: usize,
+    /// The number of PeerIds generated by the last refresh.
+    pub last_refresh_attempts: usize,
+    /// Whether the network discovery is paused, see `NetworkDiscovery::pause`.
+    pub paused: bool,
+    /// The number of PeerIds generated so far for each ilog2 distance, see `NetworkDiscovery::generation_histogram`.
+    pub generation_histogram: BTreeMap<u32, u64>,
+}
+
+/// The observed candidates of a NetworkDiscovery, so that the peers found through the kad::GetClosestPeers queries
+/// can be reused after a restart. The generated candidates are not part of it, as they are cheap to regenerate.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub(crate) struct NetworkDiscoverySnapshot {
+    /// The address of the node that the snapshot has been taken for.
+    self_address: NetworkAddress,
+    observed: Vec<NetworkAddress>,
+}
+
+impl NetworkDiscoverySnapshot {
+    /// Reads the snapshot from the provided candidates file, see `NetworkDiscovery::persist_task`. Returns `None` if the
+    /// file is missing or cannot be deserialized.
+    #[cfg(not(target_arch = "wasm32"))]
+    pub(crate) fn read_from_disk(path: &Path) -> Option<Self> {
+        PersistedCandidates::read_from_disk(path).map(|persisted| persisted.snapshot)
+    }
+}
+
+/// The content of the candidates file: the generated candidates of each bucket, along with the snapshot of the
+/// observed ones. Both are kept in a single file, written by the `NetworkDiscovery::persist_task`.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+struct PersistedCandidates {
+    generated: BTreeMap<u32, Vec<NetworkAddress>>,
+    snapshot: NetworkDiscoverySnapshot,
+}
+
+impl PersistedCandidates {
+    // The file is written in full before replacing any existing one, so that a crash cannot leave a partially written
+    // file behind.
+    #[cfg(not(target_arch = "wasm32"))]
+    fn write_to_disk(&self, path: &Path) -> std::io::Result<()> {
+        let bytes = rmp_serde::to_vec(self).map_err(std::io::Error::other)?;
+        let tmp_path = path.with_extension("tmp");
+        fs::write(&tmp_path, bytes)?;
+        fs::rename(tmp_path, path)
+    }
+
+    // Returns `None` if the file is missing or cannot be deserialized.
+    fn read_from_disk(path: &Path) -> Option<Self> {
+        let bytes = fs::read(path).ok()?;
+        match rmp_serde::from_slice(&bytes) {
+            Ok(persisted) => Some(persisted),
+            Err(err) => {
+                warn!("Ignoring the corrupt network discovery candidates file {path:?}: {err}");
+                None
+            }
+        }
+    }
+}
+
+/// The full candidate pool of a NetworkDiscovery, i.e., the generated and observed candidates of every bucket along
+/// with their query state, e.g., to dump the state of a running node. Such a dump can be loaded into a unit test
+/// through `DiscoverySnapshot::from_json` and `NetworkDiscovery::from_discovery_snapshot`, to reproduce its behaviour
+/// locally. Unlike `NetworkDiscoverySnapshot`, this is not meant to be persisted across restarts.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub(crate) struct DiscoverySnapshot {
+    /// The bytes of the PeerId that the pool has been built for.
+    self_peer_id: Vec<u8>,
+    /// The candidates of each ilog2 bucket, from the oldest to the newest.
+    buckets: BTreeMap<u32, Vec<CandidateEntrySnapshot>>,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+struct CandidateEntrySnapshot {
+    addr: NetworkAddress,
+    /// Whether the candidate is a real peer observed through a query, rather than a generated one.
+    observed: bool,
+    /// The time elapsed since the candidate has been inserted, as of the snapshot.
+    age: Duration,
+    /// The time elapsed since the candidate has last been queried, as of the snapshot.
+    last_queried_ago: Option<Duration>,
+    failed_attempts: u8,
+}
+
+impl DiscoverySnapshot {
+    /// Serializes the snapshot into JSON.
+    pub(crate) fn to_json(&self) -> serde_json::Result<String> {
+        serde_json::to_string(self)
+    }
+
+    /// Deserializes a snapshot from JSON, see `to_json`.
+    #[cfg(test)]
+    pub(crate) fn from_json(json: &str) -> serde_json::Result<Self> {
+        serde_json::from_str(json)
+    }
+}
+
+/// The number of candidates held in each bucket at some point, see `NetworkDiscovery::candidate_snapshot`. Two of
+/// them taken a while apart show how the discovery progressed in the meantime.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub(crate) struct CandidateSnapshot {
+    buckets: BTreeMap<u32, usize>,
+}
+
+/// The signed change of the number of candidates of each bucket between two `CandidateSnapshot`s. The buckets whose
+/// count did not change are left out.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub(crate) struct BucketDelta {
+    changes: BTreeMap<u32, i64>,
+}
+
+impl CandidateSnapshot {
+    pub(crate) fn new(buckets: BTreeMap<u32, usize>) -> Self {
+        Self { buckets }
+    }
+
+    /// Returns the changes from `self` to the later `other` snapshot, i.e., positive for the buckets that gained
+    /// candidates since `self`.
+    pub(crate) fn diff(&self, other: &Self) -> BucketDelta {
+        let count_of = |snapshot: &Self, ilog2: &u32| {
+            snapshot.buckets.get(ilog2).copied().unwrap_or_default() as i64
+        };
+        let changes = self
+            .buckets
+            .keys()
+            .chain(other.buckets.keys())
+            .filter_map(|ilog2| {
+                let change = count_of(other, ilog2) - count_of(self, ilog2);
+                (change != 0).then_some((*ilog2, change))
+            })
+            .collect();
+        BucketDelta { changes }
+    }
+}
+
+impl BucketDelta {
+    /// Returns the buckets that gained candidates, along with how many.
+    pub(crate) fn gained(&self) -> impl Iterator<Item = (u32, i64)> + '_ {
+        self.changes
+            .iter()
+            .filter(|(_, change)| **change > 0)
+            .map(|(ilog2, change)| (*ilog2, *change))
+    }
+
+    /// Returns the buckets that lost candidates, along with how many.
+    pub(crate) fn lost(&self) -> impl Iterator<Item = (u32, i64)> + '_ {
+        self.changes
+            .iter()
+            .filter(|(_, change)| **change < 0)
+            .map(|(ilog2, change)| (*ilog2, *change))
+    }
+
+    /// Returns true if no bucket changed.
+    pub(crate) fn is_empty(&self) -> bool {
+        self.changes.is_empty()
+    }
+}
+
+/// Where a candidate originates from.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub(crate) enum CandidateSource {
+    /// Generated from a random PeerId, these are used to query the gaps in our RT. These never go stale.
+    Generated,
+    /// A real peer observed through a kad::GetClosestPeers query, which might go offline.
+    Observed(PeerId),
+}
+
+/// How `NetworkDiscovery::take_candidates` picks the candidates to query. The buckets that are full in our RT, or
+/// too close to hold any peer given the estimated network size, are never picked.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub(crate) enum CandidateSelection {
+    /// The buckets are visited in the ascending order of their ilog2 distance, taking one candidate from each of them
+    /// per pass, so that the candidates are spread over as many close buckets as possible.
+    Closest,
+    /// Unlike the other selections, a bucket can provide several candidates in a row. Each pick is weighted by how
+    /// empty the RT bucket is and by how many unqueried candidates it still holds, so the emptier buckets with fresh
+    /// candidates receive most of the queries. The buckets whose queries returned peers are favoured, up to
+    /// `MAX_BUCKET_SUCCESS_BOOST` times.
+    Weighted,
+    /// One candidate per bucket, the buckets being visited in a round-robin, each hand-out resuming after the last
+    /// bucket of the previous one. A bucket is skipped if one of its candidates has been queried within the
+    /// `BUCKET_QUERY_COOLDOWN`, and the least recently queried candidate of each bucket is picked. Hence every bucket
+    /// is queried over successive hand-outs, even with a small `n`, at a steady pace.
+    RoundRobin,
+}
+
+#[derive(Debug, Clone)]
+struct Candidate {
+    addr: NetworkAddress,
+    /// The kbucket key of `addr`. Hashing the address is the costliest part of locating its bucket, hence it is done
+    /// once, when the candidate is created.
+    key: KBucketKey<Vec<u8>>,
+    inserted_at: Instant,
+    source: CandidateSource,
+    /// The number of consecutive failed queries for this candidate.
+    failed_attempts: u8,
+    last_queried: Option<Instant>,
+}
+
+impl Candidate {
+    fn new(addr: NetworkAddress, source: CandidateSource, inserted_at: Instant) -> Self {
+        Self {
+            key: addr.as_kbucket_key(),
+            addr,
+            inserted_at,
+            source,
+            failed_attempts: 0,
+            last_queried: None,
+        }
+    }
+
+    fn ilog2(&self, self_key: &KBucketKey<PeerId>) -> Option<u32> {
+        self.key.distance(self_key).ilog2()
+    }
+}
 
 /// Keep track of NetworkAddresses belonging to every bucket (if we can generate them with reasonable effort)
 /// which we can then query using Kad::GetClosestPeers to effectively fill our RT.
 #[derive(Debug, Clone)]
 pub(crate) struct NetworkDiscovery {
     self_key: KBucketKey<PeerId>,
-    candidates: BTreeMap<u32, Vec<NetworkAddress>>,
+    // Keyed by the ilog2 distance to self, hence iterated from the closest bucket to the farthest one. All the
+    // candidate lookups rely on this order, regardless of how the candidates were inserted.
+    candidates: BTreeMap<u32, Vec<Candidate>>,
+    initial_attempts: usize,
+    refresh_attempts: usize,
+    max_peers_per_bucket: usize,
+    max_total_candidates: usize,
+    queries_per_round: usize,
+    max_observed_peers_per_query: usize,
+    observed_peer_validator: Option<fn(&PeerId) -> bool>,
+    // The distances of all the PeerIds generated so far, if enabled.
+    generation_histogram: Option<Arc<GenerationHistogram>>,
+    // The file used to persist the candidates across restarts, if any.
+    candidates_file: Option<PathBuf>,
+    // The content of the candidates file as of the last `persist_task`, so that it is only written again once changed.
+    #[cfg(not(target_arch = "wasm32"))]
+    persisted: Option<PersistedCandidates>,
+    // The number of peers in each bucket of our RT, as of the last snapshot.
+    bucket_fill: HashMap<u32, usize>,
+    // The closer buckets are unlikely to hold any peer given the estimated size of the network, hence not worth any
+    // generation or query.
+    min_plausible_ilog2: u32,
+    // The number of queries of each bucket's candidates that returned peers.
+    bucket_successes: HashMap<u32, usize>,
+    // When a candidate of each bucket was last queried.
+    bucket_last_queried: HashMap<u32, Instant>,
+    // The candidates held as of the previous round of network discovery, see `candidates_delta_since_last_round`.
+    last_round_snapshot: CandidateSnapshot,
+    // The bucket last picked by `CandidateSelection::RoundRobin`, the next hand-out resuming from the following one.
+    query_cursor: Option<u32>,
+    // The candidates handed out by `take_candidates` whose query result is pending, along with when they were taken.
+    // They remain in their bucket meanwhile, but are not handed out again.
+    in_flight: HashMap<NetworkAddress, Instant>,
+    last_refresh: Option<Instant>,
+    last_refresh_attempts: usize,
+    candidates_consumed: usize,
+    consumed_since_refresh: usize,
+    // The share of the initial attempts that is yet to be generated by the refreshes, see `new_incremental`.
+    pending_initial_attempts: usize,
+    // The pool used for the generation, the global one if None.
+    thread_pool: Option<Arc<ThreadPool>>,
+    // The rng from which the candidates are derived, if reproducible candidates are wanted. Uses the OS entropy if
+    // not set.
+    rng: Option<StdRng>,
+    #[cfg(feature = "open-metrics")]
+    metrics: Option<NetworkDiscoveryMetrics>,
+    // Notified with the ilog2 of each bucket the first time it gets a candidate, along with the buckets notified so
+    // far. Nothing is tracked unless a sender has been set.
+    populated_sender: Option<(mpsc::Sender<u32>, BTreeSet<u32>)>,
+    // While paused, no candidate is handed out for querying and no candidate is generated.
+    paused: bool,
 }
 
 impl NetworkDiscovery {
-    /// Create a new instance of NetworkDiscovery and tries to populate each bucket with random peers.
-    pub(crate) fn new(self_peer_id: &PeerId) -> Self {
-        let start = Instant::now();
-        let self_key = KBucketKey::from(*self_peer_id);
-        let candidates = Self::generate_candidates(&self_key, INITIAL_GENERATION_ATTEMPTS);
+    /// Create a new instance of NetworkDiscovery whose candidates are all derived from the provided rng, instead of the
+    /// OS entropy. For a given `self_peer_id` and rng, the same candidates are generated every time, which makes the
+    /// bucket coverage, refreshes and selections reproducible in tests.
+    #[cfg(test)]
+    pub(crate) fn new_with_rng(self_peer_id: &PeerId, mut rng: impl RngCore + Send) -> Self {
+        let mut seed = <StdRng as SeedableRng>::Seed::default();
+        rng.fill_bytes(&mut seed);
+        let mut network_discovery = Self::new_lazy(self_peer_id, NetworkDiscoveryConfig::default());
+        network_discovery.rng = Some(StdRng::from_seed(seed));
+        network_discovery.populate();
+        network_discovery
+    }
+
+    /// Create a new instance of NetworkDiscovery using the provided config and tries to populate each bucket with
+    /// random peers.
+    #[cfg(test)]
+    pub(crate) fn with_config(self_peer_id: &PeerId, config: NetworkDiscoveryConfig) -> Self {
+        let mut network_discovery = Self::new_lazy(self_peer_id, config);
+        network_discovery.populate();
+        network_discovery
+    }
+
+    /// Create a new instance of NetworkDiscovery without any candidates. This returns immediately, the initial
+    /// candidates are generated by the task returned from `population_task`, which can be run on a blocking thread.
+    pub(crate) fn new_lazy(self_peer_id: &PeerId, config: NetworkDiscoveryConfig) -> Self {
+        let max_peers_per_bucket = if config.max_peers_per_bucket == 0 {
+            warn!("NetworkDiscovery cannot keep 0 peers per bucket, clamping it to 1");
+            1
+        } else {
+            config.max_peers_per_bucket
+        };
+
+        let network_discovery = Self {
+            self_key: KBucketKey::from(*self_peer_id),
+            candidates: Default::default(),
+            initial_attempts: config.initial_attempts,
+            refresh_attempts: config.refresh_attempts,
+            max_peers_per_bucket,
+            max_total_candidates: config.max_total_candidates,
+            queries_per_round: config.queries_per_round,
+            max_observed_peers_per_query: config.max_observed_peers_per_query,
+            observed_peer_validator: config.observed_peer_validator,
+            generation_histogram: config
+                .record_generation_histogram
+                .then(|| Arc::new(GenerationHistogram::default())),
+            candidates_file: None,
+            #[cfg(not(target_arch = "wasm32"))]
+            persisted: None,
+            bucket_fill: Default::default(),
+            min_plausible_ilog2: 0,
+            bucket_successes: Default::default(),
+            bucket_last_queried: Default::default(),
+            last_round_snapshot: Default::default(),
+            query_cursor: None,
+            in_flight: Default::default(),
+            last_refresh: None,
+            last_refresh_attempts: 0,
+            candidates_consumed: 0,
+            consumed_since_refresh: 0,
+            pending_initial_attempts: 0,
+            thread_pool: config.thread_pool,
+            rng: None,
+            #[cfg(feature = "open-metrics")]
+            metrics: None,
+            populated_sender: None,
+            paused: false,
+        };
+        let reachable_buckets =
+            network_discovery.theoretical_reachable_buckets(network_discovery.initial_attempts);
+        info!(
+            "NetworkDiscovery can realistically cover the buckets {reachable_buckets:?} with {} initial attempts",
+            network_discovery.initial_attempts
+        );
+        network_discovery
+    }
+
+    /// Estimates the ilog2 buckets that can realistically be filled by generating `attempts` random PeerIds.
+    ///
+    /// A random key falls into the bucket `b` with a probability of `2^(b - 256)`, i.e., half of them land in 255, a
+    /// quarter in 254 and so on. A bucket is deemed reachable if at least one hit is expected, i.e., if
+    /// `attempts * 2^(b - 256) >= 1`, which holds for `b >= 256 - log2(attempts)`. The returned range is empty if not
+    /// even the farthest bucket is expected to be hit.
+    pub(crate) fn theoretical_reachable_buckets(&self, attempts: usize) -> RangeInclusive<u32> {
+        let closest = match attempts.checked_ilog2() {
+            Some(log2) => 256_u32.saturating_sub(log2),
+            None => 256,
+        };
+        closest..=255
+    }
+
+    /// Create a new instance of NetworkDiscovery that only generates a first batch of `INITIAL_BATCH_ATTEMPTS` on the
+    /// current thread. The rest of the initial attempts are spread over the next calls to `try_refresh_candidates`,
+    /// one batch at a time, so that the construction stays cheap where the generation cannot be moved off-thread.
+    #[cfg(any(test, target_arch = "wasm32"))]
+    pub(crate) fn new_incremental(self_peer_id: &PeerId, config: NetworkDiscoveryConfig) -> Self {
+        let mut network_discovery = Self::new_lazy(self_peer_id, config);
+        let first_batch = network_discovery
+            .initial_attempts
+            .min(INITIAL_BATCH_ATTEMPTS);
+        network_discovery.pending_initial_attempts =
+            network_discovery.initial_attempts - first_batch;
+        network_discovery.initial_attempts = first_batch;
+        network_discovery.populate();
+        network_discovery
+    }
 
+    /// Create a new instance of NetworkDiscovery that generates candidates in batches of `INITIAL_BATCH_ATTEMPTS` until
+    /// they cover at least `target_buckets` buckets, or `max_attempts` PeerIds have been generated. Unlike the fixed
+    /// initial attempts, this stops as soon as the coverage is reached. Returns the instance along with the number of
+    /// attempts actually used.
+    ///
+    /// The `initial_attempts` of the config are not used, `population_task` only restores the candidates file if any.
+    pub(crate) fn new_until_coverage(
+        self_peer_id: &PeerId,
+        config: NetworkDiscoveryConfig,
+        target_buckets: usize,
+        max_attempts: usize,
+    ) -> (Self, usize) {
+        let mut network_discovery = Self::new_lazy(self_peer_id, config);
+        network_discovery.initial_attempts = 0;
+        let mut attempts = 0;
+        while !network_discovery.has_reached_coverage(target_buckets) && attempts < max_attempts {
+            let batch = INITIAL_BATCH_ATTEMPTS.min(max_attempts - attempts);
+            attempts += batch;
+            let peers = PeerIds::new(batch, network_discovery.rng.as_mut());
+            let generated = Self::install(network_discovery.thread_pool.as_deref(), || {
+                Self::generate_candidates(
+                    &network_discovery.self_key,
+                    peers,
+                    network_discovery.max_peers_per_bucket,
+                    network_discovery.generation_histogram.as_deref(),
+                )
+            });
+            let _ = network_discovery.insert_generated_candidates(batch, generated);
+        }
+        network_discovery.enforce_max_total_candidates();
         info!(
-            "Time to generate NetworkDiscoveryCandidates: {:?}",
-            start.elapsed()
+            "Generated the network discovery candidates with {attempts} attempts, covering {} buckets out of the {target_buckets} targeted",
+            network_discovery.candidates.len()
         );
-        let buckets_covered = candidates
+        (network_discovery, attempts)
+    }
+
+    /// Returns the key against which the ilog2 distances of the candidates are computed.
+    pub(crate) fn self_key(&self) -> &KBucketKey<PeerId> {
+        &self.self_key
+    }
+
+    /// Returns the ilog2 bucket the peer falls into relative to self, as computed by `handle_get_closest_query`,
+    /// without touching the candidates. Self has no bucket, the distance being zero.
+    pub(crate) fn bucket_for_peer(&self, peer: &PeerId) -> Option<u32> {
+        NetworkAddress::from_peer(*peer)
+            .as_kbucket_key()
+            .distance(&self.self_key)
+            .ilog2()
+    }
+
+    /// Set the metrics to report the generation and the coverage of the candidates to the metrics server.
+    #[cfg(feature = "open-metrics")]
+    pub(crate) fn set_metrics(&mut self, metrics: NetworkDiscoveryMetrics) {
+        self.metrics = Some(metrics);
+        self.record_coverage();
+    }
+
+    /// Set the channel notified with the ilog2 of each bucket the first time it gets a candidate, e.g., to chart the
+    /// growth of the coverage. Every bucket is notified at most once over the lifetime of the instance, the buckets
+    /// that already hold candidates are deemed notified. The notifications are dropped if the channel is full.
+    pub(crate) fn set_populated_bucket_sender(&mut self, sender: mpsc::Sender<u32>) {
+        let populated = self.candidates.keys().copied().collect();
+        self.populated_sender = Some((sender, populated));
+    }
+
+    /// Pauses the network discovery: no candidate is returned for querying and the refreshes generate nothing until
+    /// `resume` is called. The candidates held are retained, and the peers observed through the queries that are
+    /// still in flight keep being recorded.
+    pub(crate) fn pause(&mut self) {
+        if !self.paused {
+            info!("Pausing the network discovery");
+            self.paused = true;
+        }
+    }
+
+    /// Resumes the network discovery after a `pause`.
+    pub(crate) fn resume(&mut self) {
+        if self.paused {
+            info!("Resuming the network discovery");
+            self.paused = false;
+        }
+    }
+
+    /// Returns true if the network discovery is paused, see `pause`.
+    pub(crate) fn is_paused(&self) -> bool {
+        self.paused
+    }
+
+    // Notifies the populated bucket sender, if any, the first time the bucket gets a candidate.
+    fn notify_populated(&mut self, ilog2: u32) {
+        let Some((sender, populated)) = &mut self.populated_sender else {
+            return;
+        };
+        if populated.insert(ilog2) {
+            if let Err(err) = sender.try_send(ilog2) {
+                warn!("Failed to notify the first population of the bucket {ilog2}: {err}");
+            }
+        }
+    }
+
+    // Reports the number of buckets covered to the metrics server, if any.
+    fn record_coverage(&self) {
+        #[cfg(feature = "open-metrics")]
+        if let Some(metrics) = &self.metrics {
+            let _ = metrics.buckets_covered.set(self.candidates.len() as i64);
+        }
+    }
+
+    /// Returns true once the candidates cover at least `target_buckets` buckets.
+    pub(crate) fn has_reached_coverage(&self, target_buckets: usize) -> bool {
+        self.candidates.len() >= target_buckets
+    }
+
+    /// Create a new instance of NetworkDiscovery without any generated candidates, holding the observed candidates of
+    /// the snapshot. A snapshot taken for a previous PeerId of the node, e.g., once restarted with a fresh keypair, is
+    /// re-keyed to the current one, see `rekey`.
+    ///
+    /// The restored candidates are considered as freshly observed, so they are pruned once they reach the max age.
+    #[cfg(not(target_arch = "wasm32"))]
+    pub(crate) fn from_snapshot(
+        self_peer_id: &PeerId,
+        config: NetworkDiscoveryConfig,
+        snapshot: NetworkDiscoverySnapshot,
+    ) -> Self {
+        let previous_peer_id = match snapshot.self_address.as_peer_id() {
+            Some(peer_id) if peer_id == *self_peer_id => None,
+            Some(peer_id) => Some(peer_id),
+            None => {
+                warn!(
+                    "Discarding the network discovery snapshot taken for {:?}",
+                    snapshot.self_address
+                );
+                return Self::new_lazy(self_peer_id, config);
+            }
+        };
+        let mut network_discovery =
+            Self::new_lazy(previous_peer_id.as_ref().unwrap_or(self_peer_id), config);
+
+        let observed = snapshot
+            .observed
             .iter()
-            .map(|(ilog2, candidates)| (*ilog2, candidates.len()))
+            .filter_map(NetworkAddress::as_peer_id)
             .collect::<Vec<_>>();
+        info!(
+            "Restoring {} observed network discovery candidates",
+            observed.len()
+        );
+        let _ = network_discovery.insert_observed_peers(observed, |_| false);
+        if let Some(previous_peer_id) = previous_peer_id {
+            info!("Re-keying the network discovery snapshot taken for {previous_peer_id:?}");
+            let _ = network_discovery.rekey(self_peer_id);
+        }
+        network_discovery
+    }
+
+    /// Re-keys the instance for a node that got a new PeerId, e.g., after its keypair has been rotated. Every observed
+    /// candidate is moved into its ilog2 bucket relative to the new key, keeping the `max_peers_per_bucket` newest ones,
+    /// and the new PeerId itself is dropped if it was a candidate. The generated candidates were sampled for the buckets
+    /// of the previous key, hence are discarded. Only the buckets left empty by the move are generated for, with the
+    /// `refresh_attempts`.
+    ///
+    /// The state tied to the buckets of the previous key, e.g., the fill of our RT, is reset.
+    pub(crate) fn rekey(&mut self, new_peer_id: &PeerId) -> CandidateGenerationStats {
+        self.self_key = KBucketKey::from(*new_peer_id);
+        let mut candidates = std::mem::take(&mut self.candidates)
+            .into_values()
+            .flatten()
+            .filter(|candidate| matches!(candidate.source, CandidateSource::Observed(_)))
+            .filter_map(|candidate| Some((candidate.ilog2(&self.self_key)?, candidate)))
+            .fold(
+                BTreeMap::<u32, Vec<Candidate>>::new(),
+                |mut acc, (ilog2, candidate)| {
+                    acc.entry(ilog2).or_default().push(candidate);
+                    acc
+                },
+            );
+        for bucket in candidates.values_mut() {
+            // The newest candidates are kept at the back, as in `insert_candidates_from`.
+            bucket.sort_by_key(|candidate| candidate.inserted_at);
+            let excess = bucket.len().saturating_sub(self.max_peers_per_bucket);
+            let _ = bucket.drain(..excess);
+        }
+        self.candidates = candidates;
+        self.bucket_fill.clear();
+        self.bucket_successes.clear();
+        self.bucket_last_queried.clear();
+        self.query_cursor = None;
+        // The pending queries were handed out for the previous buckets, their results are still ingested against the new
+        // key, but they no longer hold back any candidate.
+        self.in_flight.clear();
+        if let Some((_sender, populated)) = &mut self.populated_sender {
+            *populated = self.candidates.keys().copied().collect();
+        }
+
+        let wanted = (self.min_plausible_ilog2..=255)
+            .filter(|ilog2| !self.candidates.contains_key(ilog2))
+            .map(|ilog2| (ilog2, self.max_peers_per_bucket))
+            .collect::<BTreeMap<_, _>>();
+        let attempts = self.refresh_attempts;
+        let peers = PeerIds::new(attempts, self.rng.as_mut());
+        let (generated, _) = Self::install(self.thread_pool.as_deref(), || {
+            Self::generate_wanted_candidates(
+                &self.self_key,
+                peers,
+                &wanted,
+                self.generation_histogram.as_deref(),
+            )
+        });
+        let stats = self.insert_generated_candidates(attempts, generated);
+        self.enforce_max_total_candidates();
+        info!(
+            "NetworkDiscovery re-keyed to {new_peer_id:?}, the candidates now cover the buckets {:?}",
+            self.bucket_occupancy()
+        );
+        stats
+    }
+
+    /// Inserts the provided peers, e.g., our bootstrap peers, as observed candidates. This makes the first rounds of
+    /// network discovery query real nodes rather than only the synthetic keys.
+    pub(crate) fn seed(&mut self, peers: &[PeerId]) {
+        let newly_covered = self.insert_observed_peers(peers.iter().copied(), |_| false);
+        debug!(
+            "Seeded the network discovery with {} peers, covering the new buckets {newly_covered:?}",
+            peers.len()
+        );
+    }
+
+    /// Takes a snapshot of the observed candidates.
+    pub(crate) fn to_snapshot(&self) -> NetworkDiscoverySnapshot {
+        NetworkDiscoverySnapshot {
+            self_address: NetworkAddress::from_peer(*self.self_key.preimage()),
+            observed: self
+                .candidates_by_source(|source| matches!(source, CandidateSource::Observed(_)))
+                .map(|(_ilog2, addr)| addr.clone())
+                .collect(),
+        }
+    }
+
+    /// Takes a snapshot of the whole candidate pool, see `DiscoverySnapshot`.
+    pub(crate) fn snapshot(&self) -> DiscoverySnapshot {
+        let now = Instant::now();
+        let buckets = self
+            .candidates
+            .iter()
+            .map(|(ilog2, candidates)| {
+                let candidates = candidates
+                    .iter()
+                    .map(|candidate| CandidateEntrySnapshot {
+                        addr: candidate.addr.clone(),
+                        observed: matches!(candidate.source, CandidateSource::Observed(_)),
+                        age: now.saturating_duration_since(candidate.inserted_at),
+                        last_queried_ago: candidate
+                            .last_queried
+                            .map(|queried_at| now.saturating_duration_since(queried_at)),
+                        failed_attempts: candidate.failed_attempts,
+                    })
+                    .collect();
+                (*ilog2, candidates)
+            })
+            .collect();
+        DiscoverySnapshot {
+            self_peer_id: self.self_key.preimage().to_bytes(),
+            buckets,
+        }
+    }
+
+    /// Restores the candidate pool of a `DiscoverySnapshot`, keyed by the PeerId it has been taken for. The candidates
+    /// keep their source, their query state and their age. Returns None if the PeerId of the snapshot is invalid.
+    #[cfg(test)]
+    pub(crate) fn from_discovery_snapshot(
+        config: NetworkDiscoveryConfig,
+        snapshot: DiscoverySnapshot,
+    ) -> Option<Self> {
+        let self_peer_id = match PeerId::from_bytes(&snapshot.self_peer_id) {
+            Ok(peer_id) => peer_id,
+            Err(err) => {
+                warn!("Ignoring the network discovery snapshot of an invalid PeerId: {err}");
+                return None;
+            }
+        };
+        let mut network_discovery = Self::new_lazy(&self_peer_id, config);
+        let now = Instant::now();
+        for snapshot_candidate in snapshot.buckets.into_values().flatten() {
+            let source = match snapshot_candidate.addr.as_peer_id() {
+                Some(peer) if snapshot_candidate.observed => CandidateSource::Observed(peer),
+                _ => CandidateSource::Generated,
+            };
+            let mut candidate = Candidate::new(
+                snapshot_candidate.addr,
+                source,
+                now.checked_sub(snapshot_candidate.age).unwrap_or(now),
+            );
+            candidate.failed_attempts = snapshot_candidate.failed_attempts;
+            candidate.last_queried = snapshot_candidate
+                .last_queried_ago
+                .map(|ago| now.checked_sub(ago).unwrap_or(now));
+            // The buckets are recomputed rather than trusted.
+            let Some(ilog2) = candidate.ilog2(&network_discovery.self_key) else {
+                continue;
+            };
+            network_discovery
+                .candidates
+                .entry(ilog2)
+                .or_default()
+                .push(candidate);
+        }
+        network_discovery.enforce_max_total_candidates();
+        Some(network_discovery)
+    }
+
+    /// Persist the candidates into the provided file. The candidates stored in it are reused by the `population_task`
+    /// instead of generating fresh ones.
+    #[cfg(not(target_arch = "wasm32"))]
+    pub(crate) fn set_candidates_file(&mut self, path: PathBuf) {
+        self.candidates_file = Some(path);
+    }
+
+    /// Generates the initial candidates on the current thread.
+    #[cfg(any(test, target_arch = "wasm32"))]
+    pub(crate) fn populate(&mut self) {
+        let candidates = self.population_task()();
+        self.populate_with(candidates);
+    }
+
+    /// Returns a task that generates the initial candidates. The result should be passed to `populate_with`.
+    pub(crate) fn population_task(
+        &mut self,
+    ) -> impl FnOnce() -> BTreeMap<u32, Vec<NetworkAddress>> + Send + 'static {
+        // The task draws from its own rng, so that it can run on another thread.
+        let mut rng = self.rng.as_mut().map(StdRng::from_rng).and_then(Result::ok);
+        let self_key = self.self_key;
+        let initial_attempts = self.initial_attempts;
+        let max_peers_per_bucket = self.max_peers_per_bucket;
+        let candidates_file = self.candidates_file.clone();
+        let thread_pool = self.thread_pool.clone();
+        let histogram = self.generation_histogram.clone();
+
+        move || {
+            let start = Instant::now();
+            if let Some(candidates) = candidates_file
+                .and_then(|path| Self::read_from_disk(&path, self_key.preimage()))
+                .filter(|candidates| !candidates.is_empty())
+            {
+                info!(
+                    "Time to restore NetworkDiscoveryCandidates from disk: {:?}",
+                    start.elapsed()
+                );
+                return candidates;
+            }
+
+            let candidates = Self::install(thread_pool.as_deref(), || {
+                // Half of the attempts are held back for the targeted buckets missed by the random sampling, so that
+                // no more than `initial_attempts` PeerIds are generated overall.
+                let held_back = initial_attempts / 2;
+                let peers = PeerIds::new(initial_attempts - held_back, rng.as_mut());
+                let mut candidates = Self::generate_candidates(
+                    &self_key,
+                    peers,
+                    max_peers_per_bucket,
+                    histogram.as_deref(),
+                );
+                Self::fill_missing_buckets(
+                    &self_key,
+                    &mut candidates,
+                    held_back,
+                    rng.as_mut(),
+                    histogram.as_deref(),
+                );
+                candidates
+            });
+            info!(
+                "Time to generate NetworkDiscoveryCandidates: {:?}",
+                start.elapsed()
+            );
+            candidates
+        }
+    }
+
+    /// Adds the candidates produced by the `population_task`. These only fill up the room left in each bucket, so that
+    /// the candidates inserted in the meantime, e.g., the seeded peers, are not evicted.
+    pub(crate) fn populate_with(&mut self, candidates: BTreeMap<u32, Vec<NetworkAddress>>) {
+        for (ilog2, mut candidates) in candidates {
+            let room = self
+                .max_peers_per_bucket
+                .saturating_sub(self.candidates.get(&ilog2).map_or(0, Vec::len));
+            candidates.truncate(room);
+            if !candidates.is_empty() {
+                let _ = self.insert_candidates(ilog2, candidates);
+            }
+        }
+        self.enforce_max_total_candidates();
+        let buckets_covered = self.bucket_occupancy();
         info!("The generated network discovery candidates currently cover these ilog2 buckets: {buckets_covered:?}");
+    }
 
-        Self {
-            self_key,
-            candidates,
+    /// Returns a task that writes the candidates to the candidates file, if one has been set: the generated candidates
+    /// of each bucket, along with the snapshot of the observed ones, see `to_snapshot`. None is returned if they are
+    /// unchanged since the last task, so that the file is only written on change. The task is meant to be run on a
+    /// blocking thread, as the driver does once per round of network discovery.
+    #[cfg(not(target_arch = "wasm32"))]
+    pub(crate) fn persist_task(&mut self) -> Option<impl FnOnce() + Send + 'static> {
+        let path = self.candidates_file.clone()?;
+        let generated = self
+            .candidates_by_source(|source| *source == CandidateSource::Generated)
+            .fold(
+                BTreeMap::<u32, Vec<NetworkAddress>>::new(),
+                |mut acc, (ilog2, addr)| {
+                    acc.entry(ilog2).or_default().push(addr.clone());
+                    acc
+                },
+            );
+        let persisted = PersistedCandidates {
+            generated,
+            snapshot: self.to_snapshot(),
+        };
+        if self.persisted.as_ref() == Some(&persisted) {
+            return None;
+        }
+        self.persisted = Some(persisted.clone());
+
+        Some(move || {
+            if let Err(err) = persisted.write_to_disk(&path) {
+                warn!("Failed to persist the network discovery candidates to {path:?}: {err}");
+            }
+        })
+    }
+
+    /// Reads the generated candidates from the provided candidates file, see `persist_task`. Entries that do not fall
+    /// into their claimed bucket for the provided PeerId are discarded. Returns `None` if the file is missing or cannot
+    /// be deserialized.
+    pub(crate) fn read_from_disk(
+        path: &Path,
+        self_peer_id: &PeerId,
+    ) -> Option<BTreeMap<u32, Vec<NetworkAddress>>> {
+        let stored = PersistedCandidates::read_from_disk(path)?.generated;
+
+        let self_key = KBucketKey::from(*self_peer_id);
+        let candidates = stored
+            .into_iter()
+            .filter_map(|(ilog2, candidates)| {
+                let candidates = candidates
+                    .into_iter()
+                    .filter(|candidate| {
+                        candidate.as_kbucket_key().distance(&self_key).ilog2() == Some(ilog2)
+                    })
+                    .collect::<Vec<_>>();
+                (!candidates.is_empty()).then_some((ilog2, candidates))
+            })
+            .collect();
+        Some(candidates)
+    }
+
+    /// Returns the number of candidates currently held across all the buckets.
+    pub(crate) fn total_candidates(&self) -> usize {
+        self.candidates.values().map(Vec::len).sum()
+    }
+
+    /// Returns the number of candidates currently held for each ilog2 bucket, sorted by the ilog2 distance.
+    pub(crate) fn bucket_occupancy(&self) -> BTreeMap<u32, usize> {
+        self.candidates
+            .iter()
+            .map(|(ilog2, candidates)| (*ilog2, candidates.len()))
+            .collect()
+    }
+
+    /// Returns the number of candidates held in each bucket, to be compared with a later one, see
+    /// `CandidateSnapshot::diff`. Unlike `snapshot`, this only counts the candidates.
+    pub(crate) fn candidate_snapshot(&self) -> CandidateSnapshot {
+        CandidateSnapshot::new(self.bucket_occupancy())
+    }
+
+    /// Returns how the number of candidates of each bucket changed since the previous call, i.e., since the previous
+    /// round of network discovery.
+    pub(crate) fn candidates_delta_since_last_round(&mut self) -> BucketDelta {
+        let snapshot = self.candidate_snapshot();
+        let delta = self.last_round_snapshot.diff(&snapshot);
+        self.last_round_snapshot = snapshot;
+        delta
+    }
+
+    /// Returns a snapshot of the current state.
+    pub(crate) fn stats(&self) -> NetworkDiscoveryStats {
+        NetworkDiscoveryStats {
+            buckets_covered: self.candidates.len(),
+            total_candidates: self.total_candidates(),
+            min_ilog2: self.candidates.keys().next().copied(),
+            max_ilog2: self.candidates.keys().next_back().copied(),
+            since_last_refresh: self.last_refresh.map(|instant| instant.elapsed()),
+            candidates_consumed: self.candidates_consumed,
+            last_refresh_attempts: self.last_refresh_attempts,
+            paused: self.paused,
+            generation_histogram: self.generation_histogram(),
+        }
+    }
+
+    /// Returns the number of PeerIds generated so far for each ilog2 distance, including the ones that were not
+    /// retained as candidates. Empty unless `record_generation_histogram` is enabled.
+    pub(crate) fn generation_histogram(&self) -> BTreeMap<u32, u64> {
+        self.generation_histogram
+            .as_deref()
+            .map(GenerationHistogram::snapshot)
+            .unwrap_or_default()
+    }
+
+    // Evicts the oldest candidate of the fullest bucket, the farthest one among equally full buckets, until we hold no
+    // more than `max_total_candidates`. This keeps the candidates of the closer buckets, which are the hardest to get.
+    fn enforce_max_total_candidates(&mut self) {
+        let mut total = self.total_candidates();
+        while total > self.max_total_candidates {
+            let Some((&ilog2, _)) = self
+                .candidates
+                .iter()
+                .max_by_key(|(ilog2, candidates)| (candidates.len(), **ilog2))
+            else {
+                break;
+            };
+            if let Some(candidates) = self.candidates.get_mut(&ilog2) {
+                let _ = candidates.remove(0);
+                if candidates.is_empty() {
+                    let _ = self.candidates.remove(&ilog2);
+                }
+            }
+            total -= 1;
+        }
+        self.record_coverage();
+    }
+
+    // Notes that a kad::GetClosestPeers query is made for the candidate, see `round_robin_targets`.
+    fn mark_queried(&mut self, addr: &NetworkAddress, now: Instant) {
+        let Some(ilog2) = addr.as_kbucket_key().distance(&self.self_key).ilog2() else {
+            return;
+        };
+        let _ = self.bucket_last_queried.insert(ilog2, now);
+        if let Some(candidate) = self.candidates.get_mut(&ilog2).and_then(|candidates| {
+            candidates
+                .iter_mut()
+                .find(|candidate| &candidate.addr == addr)
+        }) {
+            candidate.last_queried = Some(now);
+        }
+    }
+
+    /// The result from the kad::GetClosestPeers query made for the `target` candidate are again used to update our
+    /// kbucket. Self and the peers for which `is_known` returns true, e.g., the ones already in our RT, are skipped.
+    ///
+    /// The target is moved to the back of its bucket, so that the untouched candidates are queried before it. Being
+    /// queried, it is also evicted before them. If the query returned any peer, its bucket is credited with a success
+    /// that raises its weight in `CandidateSelection::Weighted`. A target whose queries keep failing is retired by
+    /// `record_query_result`.
+    ///
+    /// Only the `max_observed_peers_per_query` returned peers closest to self that pass the `observed_peer_validator`
+    /// are ingested, the rest being dropped.
+    ///
+    /// Returns the ilog2 buckets that did not hold any candidate before this result.
+    pub(crate) fn handle_get_closest_query(
+        &mut self,
+        target: &NetworkAddress,
+        closest_peers: HashSet<PeerId>,
+        is_known: impl Fn(&PeerId) -> bool,
+    ) -> Vec<u32> {
+        #[cfg(feature = "open-metrics")]
+        let now = Instant::now();
+        // A target handed out by `take_candidates` is back from its flight.
+        let _ = self.in_flight.remove(target);
+        self.move_to_back(target);
+        if let Some(ilog2) = target.as_kbucket_key().distance(&self.self_key).ilog2() {
+            if !closest_peers.is_empty() {
+                *self.bucket_successes.entry(ilog2).or_default() += 1;
+            }
+        }
+        let closest_peers = self.closest_valid_peers(closest_peers);
+        let newly_covered = self.insert_observed_peers(closest_peers, is_known);
+        #[cfg(feature = "open-metrics")]
+        if let Some(metrics) = &self.metrics {
+            metrics
+                .closest_query_duration
+                .observe(now.elapsed().as_secs_f64());
+        }
+        newly_covered
+    }
+
+    // Returns the `max_observed_peers_per_query` peers closest to self among the ones passing the validator.
+    fn closest_valid_peers(&self, peers: HashSet<PeerId>) -> Vec<PeerId> {
+        let returned = peers.len();
+        let mut peers = peers
+            .into_iter()
+            .filter(|peer| {
+                self.observed_peer_validator
+                    .is_none_or(|is_valid| is_valid(peer))
+            })
+            .map(|peer| (KBucketKey::from(peer).distance(&self.self_key), peer))
+            .collect::<Vec<_>>();
+        let valid = peers.len();
+        if valid > self.max_observed_peers_per_query {
+            let _ = peers
+                .select_nth_unstable_by_key(self.max_observed_peers_per_query, |(distance, _)| {
+                    *distance
+                });
+            peers.truncate(self.max_observed_peers_per_query);
         }
+        if peers.len() < returned {
+            debug!(
+                "Dropping {} of the {returned} peers returned by a query, {} of them failed the validation",
+                returned - peers.len(),
+                returned - valid
+            );
+        }
+        peers.into_iter().map(|(_, peer)| peer).collect()
     }
 
-    /// The result from the kad::GetClosestPeers are again used to update our kbucket.
-    pub(crate) fn handle_get_closest_query(&mut self, closest_peers: Vec<PeerId>) {
+    // Inserts the peers as observed candidates, skipping self and the peers for which `is_known` returns true. Returns
+    // the ilog2 buckets that did not hold any candidate before.
+    fn insert_observed_peers(
+        &mut self,
+        peers: impl IntoIterator<Item = PeerId>,
+        is_known: impl Fn(&PeerId) -> bool,
+    ) -> Vec<u32> {
         let now = Instant::now();
 
-        let candidates_map: BTreeMap<u32, Vec<NetworkAddress>> = closest_peers
+        let candidates_map: BTreeMap<u32, Vec<Candidate>> = peers
             .into_iter()
+            .filter(|peer| peer != self.self_key.preimage() && !is_known(peer))
             .filter_map(|peer| {
-                let peer = NetworkAddress::from_peer(peer);
-                let peer_key = peer.as_kbucket_key();
-                peer_key
-                    .distance(&self.self_key)
-                    .ilog2()
-                    .map(|ilog2| (ilog2, peer))
+                let candidate = Candidate::new(
+                    NetworkAddress::from_peer(peer),
+                    CandidateSource::Observed(peer),
+                    now,
+                );
+                candidate
+                    .ilog2(&self.self_key)
+                    .map(|ilog2| (ilog2, candidate))
             })
             // To collect the NetworkAddresses into a vector.
-            .fold(BTreeMap::new(), |mut acc, (ilog2, peer)| {
-                acc.entry(ilog2).or_default().push(peer);
+            .fold(BTreeMap::new(), |mut acc, (ilog2, candidate)| {
+                acc.entry(ilog2).or_default().push(candidate);
                 acc
             });
 
+        let mut newly_covered = Vec::new();
         for (ilog2, candidates) in candidates_map {
-            self.insert_candidates(ilog2, candidates);
+            if !self.has_candidates_for_bucket(ilog2) {
+                newly_covered.push(ilog2);
+            }
+            let _ = self.insert_candidates_from(ilog2, candidates);
         }
+        self.enforce_max_total_candidates();
 
         trace!(
             "It took {:?} to NetworkDiscovery::handle get closest query",
             now.elapsed()
         );
+        newly_covered
     }
 
-    /// Returns one random candidate per bucket. Also tries to refresh the candidate list.
-    /// Todo: Limit the candidates to return. Favor the closest buckets.
-    pub(crate) fn candidates(&mut self) -> Vec<&NetworkAddress> {
-        self.try_refresh_candidates();
-
-        let mut rng = thread_rng();
-        let mut op = Vec::with_capacity(self.candidates.len());
-
-        let candidates = self.candidates.values().filter_map(|candidates| {
-            // get a random index each time
-            let random_index = rng.gen::<usize>() % candidates.len();
-            candidates.get(random_index)
-        });
-        op.extend(candidates);
-        op
+    /// Updates the number of peers held by each bucket of our RT. The candidates of the buckets that are already full
+    /// are not returned for querying, and the emptiest buckets are favoured.
+    pub(crate) fn set_bucket_fill(&mut self, fill: HashMap<u32, usize>) {
+        self.bucket_fill = fill;
     }
 
-    /// Tries to refresh our current candidate list. We replace the old ones with new if we find any.
-    fn try_refresh_candidates(&mut self) {
-        let candidates_vec = Self::generate_candidates(&self.self_key, GENERATION_ATTEMPTS);
-        for (ilog2, candidates) in candidates_vec {
-            self.insert_candidates(ilog2, candidates);
-        }
+    /// The number of candidates to select during each round of network discovery.
+    pub(crate) fn queries_per_round(&self) -> usize {
+        self.queries_per_round
     }
 
-    // Insert the new candidates and remove the old ones to maintain MAX_PEERS_PER_BUCKET.
-    fn insert_candidates(&mut self, ilog2: u32, new_candidates: Vec<NetworkAddress>) {
-        match self.candidates.entry(ilog2) {
-            Entry::Occupied(mut entry) => {
-                let existing_candidates = entry.get_mut();
-                // insert only newly seen new_candidates
-                let new_candidates: Vec<_> = new_candidates
-                    .into_iter()
-                    .filter(|candidate| !existing_candidates.contains(candidate))
-                    .collect();
-                existing_candidates.extend(new_candidates);
-                // Keep only the last MAX_PEERS_PER_BUCKET elements i.e., the newest ones
-                let excess = existing_candidates
-                    .len()
-                    .saturating_sub(MAX_PEERS_PER_BUCKET);
-                if excess > 0 {
-                    existing_candidates.drain(..excess);
+    // Samples up to `n` candidates to query, each pick being weighted by how empty the RT bucket is and by how many
+    // unqueried candidates it still holds, see `CandidateSelection::Weighted`.
+    fn select_weighted(&self, n: usize, rng: &mut impl Rng) -> Vec<NetworkAddress> {
+        if self.paused {
+            return Vec::new();
+        }
+        // The unqueried candidates of each bucket come first, the generated ones being preferred among them.
+        let mut buckets = self
+            .candidates
+            .iter()
+            .filter_map(|(ilog2, candidates)| {
+                let room = K_VALUE.get().saturating_sub(self.bucket_fill_of(*ilog2));
+                if room == 0 || *ilog2 < self.min_plausible_ilog2 {
+                    return None;
                 }
+                let boost = 1 + self.bucket_successes.get(ilog2).map_or(0, |successes| {
+                    (*successes).min(MAX_BUCKET_SUCCESS_BOOST - 1)
+                });
+                let mut candidates = self.available(candidates).collect::<Vec<_>>();
+                candidates.sort_by_key(|candidate| candidate.last_queried.is_some());
+                let unqueried = candidates
+                    .iter()
+                    .filter(|candidate| candidate.last_queried.is_none())
+                    .count();
+                Some((room * boost, unqueried, candidates.into_iter()))
+            })
+            .collect::<Vec<_>>();
+
+        // The already queried candidates keep a small weight, so that a bucket is not starved once it runs out of
+        // fresh candidates.
+        let weight = |room: usize, unqueried: usize| room * (unqueried + 1);
+        let mut selected = Vec::new();
+        while selected.len() < n {
+            let total: usize = buckets
+                .iter()
+                .map(|(room, unqueried, _)| weight(*room, *unqueried))
+                .sum();
+            if total == 0 {
+                break;
             }
-            Entry::Vacant(entry) => {
-                entry.insert(new_candidates);
+            let mut pick = rng.gen_range(0..total);
+            let Some(index) = buckets.iter().position(|(room, unqueried, _)| {
+                let bucket_weight = weight(*room, *unqueried);
+                if pick < bucket_weight {
+                    true
+                } else {
+                    pick -= bucket_weight;
+                    false
+                }
+            }) else {
+                break;
+            };
+
+            let (room, unqueried, candidates) = &mut buckets[index];
+            match candidates.next() {
+                Some(candidate) => {
+                    *unqueried = unqueried.saturating_sub(1);
+                    selected.push(candidate.addr.clone());
+                }
+                // The bucket is exhausted, it can no longer be picked.
+                None => *room = 0,
             }
         }
+        selected
     }
 
-    /// Uses rayon to parallelize the generation
-    fn generate_candidates(
-        self_key: &KBucketKey<PeerId>,
-        num_to_generate: usize,
-    ) -> BTreeMap<u32, Vec<NetworkAddress>> {
-        (0..num_to_generate)
-            .into_par_iter()
-            .filter_map(|_| {
-                let candidate = NetworkAddress::from_peer(PeerId::random());
-                let candidate_key = candidate.as_kbucket_key();
-                let ilog2 = candidate_key.distance(&self_key).ilog2()?;
-                Some((ilog2, candidate))
-            })
-            // Since it is parallel iterator, the fold fn batches the items and will produce multiple outputs. So we
-            // should use reduce fn to combine multiple outputs.
-            .fold(
-                BTreeMap::new,
-                |mut acc: BTreeMap<u32, Vec<NetworkAddress>>, (ilog2, candidate)| {
-                    acc.entry(ilog2).or_default().push(candidate);
-                    acc
-                },
-            )
-            .reduce(
-                BTreeMap::new,
-                |mut acc: BTreeMap<u32, Vec<NetworkAddress>>, map| {
-                    for (ilog2, candidates) in map {
-                        let entry = acc.entry(ilog2).or_default();
-                        for candidate in candidates {
-                            if entry.len() < MAX_PEERS_PER_BUCKET {
-                                entry.push(candidate);
-                            } else {
-                                break;
-                            }
-                        }
-                    }
-                    acc
-                },
-            )
+    /// Returns the candidates along with their ilog2 bucket, whose source matches the provided filter.
+    pub(crate) fn candidates_by_source<'a>(
+        &'a self,
+        filter: impl Fn(&CandidateSource) -> bool + 'a,
+    ) -> impl Iterator<Item = (u32, &'a NetworkAddress)> + 'a {
+        self.candidates.iter().flat_map(move |(ilog2, candidates)| {
+            candidates
+                .iter()
+                .filter(|candidate| filter(&candidate.source))
+                .map(|candidate| (*ilog2, &candidate.addr))
+                .collect::<Vec<_>>()
+        })
+    }
+
+    // The buckets that are not full in our RT, in the ascending order of their ilog2 distance. None while paused.
+    fn buckets_to_query(&self) -> impl Iterator<Item = (&u32, &Vec<Candidate>)> {
+        self.candidates
+            .iter()
+            .filter(|(ilog2, _)| !self.paused && self.is_bucket_needed(**ilog2))
+    }
+
+    /// Notes that the bucket of our RT has become full, it is skipped until notified of having room again. The
+    /// candidates of the bucket are retained, in case it loses peers later on.
+    pub(crate) fn notify_bucket_full(&mut self, ilog2: u32) {
+        let _ = self.bucket_fill.insert(ilog2, K_VALUE.get());
+    }
+
+    /// Notes that the bucket of our RT has lost a peer, so that its candidates are queried again.
+    pub(crate) fn notify_bucket_has_room(&mut self, ilog2: u32) {
+        if let Some(fill) = self.bucket_fill.get_mut(&ilog2) {
+            *fill = (*fill).min(K_VALUE.get() - 1);
+        }
+    }
+
+    /// Updates the estimated number of nodes in the network. With `n` nodes spread uniformly over the key space, the
+    /// bucket `b` is expected to hold `n * 2^(b - 256)` of them, hence the buckets closer than `256 - log2(n)`, with a
+    /// margin of `NETWORK_SIZE_MARGIN_BUCKETS`, are most likely empty. These are neither generated for nor selected
+    /// for querying, until the estimate grows. An estimate of 0 lifts the bound.
+    pub(crate) fn set_network_size_estimate(&mut self, n: usize) {
+        self.min_plausible_ilog2 = match n.checked_ilog2() {
+            Some(log2) => 256_u32
+                .saturating_sub(log2 + NETWORK_SIZE_MARGIN_BUCKETS)
+                .min(255),
+            None => 0,
+        };
+    }
+
+    // Whether the bucket is worth generating for and querying, i.e., it is not full in our RT and not too close to hold
+    // any peer given the estimated network size.
+    fn is_bucket_needed(&self, ilog2: u32) -> bool {
+        self.bucket_fill_of(ilog2) < K_VALUE.get() && ilog2 >= self.min_plausible_ilog2
+    }
+
+    fn bucket_fill_of(&self, ilog2: u32) -> usize {
+        self.bucket_fill.get(&ilog2).copied().unwrap_or_default()
+    }
+
+    // Orders the candidates of a bucket with the generated ones first.
+    fn generated_first(candidates: &[Candidate]) -> impl Iterator<Item = &Candidate> {
+        let is_generated = |candidate: &&Candidate| candidate.source == CandidateSource::Generated;
+        candidates
+            .iter()
+            .filter(is_generated)
+            .chain(candidates.iter().filter(move |c| !is_generated(c)))
+    }
+
+    // Returns up to `budget` distinct candidates to query, favouring the closest buckets, see
+    // `CandidateSelection::Closest`.
+    fn query_plan(&self, budget: usize) -> Vec<NetworkAddress> {
+        let mut buckets = self
+            .buckets_to_query()
+            .map(|(_ilog2, candidates)| self.available(candidates))
+            .collect::<Vec<_>>();
+        let mut plan = Vec::new();
+        while plan.len() < budget && !buckets.is_empty() {
+            // A candidate belongs to a single bucket, hence the plan holds no duplicates.
+            buckets.retain_mut(|candidates| match candidates.next() {
+                Some(candidate) if plan.len() < budget => {
+                    plan.push(candidate.addr.clone());
+                    true
+                }
+                Some(_) => true,
+                None => false,
+            });
+        }
+        plan
+    }
+
+    // Returns up to `budget` candidates to query, one per bucket, round-robin across the buckets, see
+    // `CandidateSelection::RoundRobin`.
+    fn round_robin_targets(&mut self, budget: usize, now: Instant) -> Vec<NetworkAddress> {
+        let is_cooling_down = |ilog2: &u32| {
+            self.bucket_last_queried
+                .get(ilog2)
+                .is_some_and(|queried_at| {
+                    now.saturating_duration_since(*queried_at) < BUCKET_QUERY_COOLDOWN
+                })
+        };
+        let (after_cursor, up_to_cursor): (Vec<_>, Vec<_>) = self
+            .buckets_to_query()
+            .filter(|(ilog2, _)| !is_cooling_down(ilog2))
+            .partition(|(ilog2, _)| self.query_cursor.is_none_or(|cursor| **ilog2 > cursor));
+        let targets = after_cursor
+            .into_iter()
+            .chain(up_to_cursor)
+            .filter_map(|(ilog2, candidates)| {
+                // The generated candidates come first, hence are preferred among the equally queried ones.
+                let candidate = self
+                    .available(candidates)
+                    .min_by_key(|candidate| candidate.last_queried)?;
+                Some((*ilog2, candidate.addr.clone()))
+            })
+            .take(budget)
+            .collect::<Vec<_>>();
+
+        if let Some((ilog2, _)) = targets.last() {
+            self.query_cursor = Some(*ilog2);
+        }
+        targets.into_iter().map(|(_, addr)| addr).collect()
+    }
+
+    /// Hands out up to `n` candidates to query, picked according to the `selection`. The candidates are marked as
+    /// queried and in flight, so that they cannot be handed out again while their query is pending. They remain in
+    /// their bucket, which is thus still covered. They can be handed out again once their result is handled by
+    /// `handle_get_closest_query`, or once they have been in flight for longer than `IN_FLIGHT_CANDIDATE_TIMEOUT`,
+    /// which is checked on every hand-out.
+    pub(crate) fn take_candidates(
+        &mut self,
+        n: usize,
+        selection: CandidateSelection,
+    ) -> Vec<NetworkAddress> {
+        self.take_candidates_at(n, selection, Instant::now())
+    }
+
+    fn take_candidates_at(
+        &mut self,
+        n: usize,
+        selection: CandidateSelection,
+        now: Instant,
+    ) -> Vec<NetworkAddress> {
+        self.expire_in_flight_at(now);
+        let taken = match selection {
+            CandidateSelection::Closest => self.query_plan(n),
+            // The seeded rng is taken out for the selection, so that it can borrow the candidates.
+            CandidateSelection::Weighted => match self.rng.take() {
+                Some(mut rng) => {
+                    let selected = self.select_weighted(n, &mut rng);
+                    self.rng = Some(rng);
+                    selected
+                }
+                None => self.select_weighted(n, &mut rand::thread_rng()),
+            },
+            CandidateSelection::RoundRobin => self.round_robin_targets(n, now),
+        };
+        for addr in &taken {
+            self.take_candidate(addr, now);
+        }
+        taken
+    }
+
+    /// Returns true if the candidate has been handed out and its query result is still pending.
+    pub(crate) fn is_in_flight(&self, addr: &NetworkAddress) -> bool {
+        self.in_flight.contains_key(addr)
+    }
+
+    // Hands out the candidate: it is accounted as consumed, marked as queried and tracked as in flight.
+    fn take_candidate(&mut self, addr: &NetworkAddress, now: Instant) {
+        self.candidates_consumed += 1;
+        self.consumed_since_refresh += 1;
+        self.mark_queried(addr, now);
+        let _ = self.in_flight.insert(addr.clone(), now);
+    }
+
+    // Stops tracking the candidates that have been in flight for longer than `IN_FLIGHT_CANDIDATE_TIMEOUT`, which can
+    // then be handed out again. They are moved to the back of their bucket, as if their query had returned.
+    fn expire_in_flight_at(&mut self, now: Instant) {
+        let expired = self
+            .in_flight
+            .iter()
+            .filter(|(_, taken_at)| {
+                now.saturating_duration_since(**taken_at) > IN_FLIGHT_CANDIDATE_TIMEOUT
+            })
+            .map(|(addr, _)| addr.clone())
+            .collect::<Vec<_>>();
+        for addr in expired {
+            debug!("No result for the network discovery candidate {addr:?}, handing it out again");
+            let _ = self.in_flight.remove(&addr);
+            self.move_to_back(&addr);
+        }
+    }
+
+    // Moves the candidate to the back of its bucket, so that the other candidates are queried before it.
+    fn move_to_back(&mut self, addr: &NetworkAddress) {
+        let Some(ilog2) = addr.as_kbucket_key().distance(&self.self_key).ilog2() else {
+            return;
+        };
+        if let Some(candidates) = self.candidates.get_mut(&ilog2) {
+            if let Some(index) = candidates
+                .iter()
+                .position(|candidate| &candidate.addr == addr)
+            {
+                let candidate = candidates.remove(index);
+                candidates.push(candidate);
+            }
+        }
+    }
+
+    // The candidates of a bucket that can be handed out, i.e., the ones not in flight, the generated ones first.
+    fn available<'a>(&'a self, candidates: &'a [Candidate]) -> impl Iterator<Item = &'a Candidate> {
+        Self::generated_first(candidates)
+            .filter(|candidate| !self.in_flight.contains_key(&candidate.addr))
+    }
+
+    /// Returns the candidates held for the provided ilog2 bucket.
+    pub(crate) fn candidates_for_bucket(
+        &self,
+        ilog2: u32,
+    ) -> impl Iterator<Item = &NetworkAddress> {
+        self.candidates
+            .get(&ilog2)
+            .into_iter()
+            .flatten()
+            .map(|candidate| &candidate.addr)
+    }
+
+    /// Hands out a candidate of the provided ilog2 bucket, e.g., to refill the bucket of our RT that has become empty.
+    /// If we hold none, one is generated with `NetworkAddress::random_in_bucket`, which only succeeds for the farther
+    /// buckets within `EMPTIED_BUCKET_GENERATION_ATTEMPTS`. The candidate is in flight until its result is handled, as
+    /// with `take_candidates`. None is returned if all the candidates of the bucket are already in flight.
+    pub(crate) fn candidate_for_emptied_bucket(&mut self, ilog2: u32) -> Option<NetworkAddress> {
+        let addr = if self.has_candidates_for_bucket(ilog2) {
+            // The bucket is already being queried if all of its candidates are in flight.
+            self.candidates_for_bucket(ilog2)
+                .find(|addr| !self.is_in_flight(addr))?
+                .clone()
+        } else if ilog2 < self.min_plausible_ilog2 {
+            return None;
+        } else {
+            let addr = NetworkAddress::random_in_bucket(
+                &self.self_key,
+                ilog2,
+                EMPTIED_BUCKET_GENERATION_ATTEMPTS,
+            )?;
+            let _ = self.insert_candidates(ilog2, vec![addr.clone()]);
+            self.enforce_max_total_candidates();
+            addr
+        };
+        self.take_candidate(&addr, Instant::now());
+        Some(addr)
+    }
+
+    /// Returns true if we hold at least one candidate for the provided ilog2 bucket.
+    pub(crate) fn has_candidates_for_bucket(&self, ilog2: u32) -> bool {
+        self.candidates
+            .get(&ilog2)
+            .is_some_and(|candidates| !candidates.is_empty())
+    }
+
+    /// Returns the ilog2 buckets of the `range` for which we hold no candidate, in ascending order. This is meant for
+    /// alerting on a poor coverage of the network discovery.
+    pub(crate) fn empty_buckets(&self, range: RangeInclusive<u32>) -> Vec<u32> {
+        range
+            .filter(|ilog2| !self.has_candidates_for_bucket(*ilog2))
+            .collect()
+    }
+
+    /// Returns the ilog2 buckets of `min_ilog2..=255` for which we hold no candidate, in ascending order.
+    pub(crate) fn uncovered_buckets(&self, min_ilog2: u32) -> Vec<u32> {
+        self.empty_buckets(min_ilog2.min(255)..=255)
+    }
+
+    /// Reports the `TARGETED_BUCKETS` that are plausible given the network size, for which we hold no candidate. A
+    /// warning is logged if there are more than `UNCOVERED_BUCKETS_WARNING_THRESHOLD` of them. Returns those buckets.
+    pub(crate) fn check_coverage(&self) -> Vec<u32> {
+        let uncovered =
+            self.uncovered_buckets((*TARGETED_BUCKETS.start()).max(self.min_plausible_ilog2));
+        #[cfg(feature = "open-metrics")]
+        if let Some(metrics) = &self.metrics {
+            let _ = metrics.uncovered_buckets.set(uncovered.len() as i64);
+        }
+        if uncovered.len() > UNCOVERED_BUCKETS_WARNING_THRESHOLD {
+            warn!("NetworkDiscovery holds no candidate for the buckets {uncovered:?}");
+        }
+        uncovered
+    }
+
+    /// Tries to refresh our current candidate list. Only the buckets that have room for more candidates and are not
+    /// full in our RT are generated for, the generation stops once all of them are filled up.
+    ///
+    /// The generation effort is proportional to the number of `TARGETED_BUCKETS` still lacking candidates, see
+    /// `adaptive_refresh_attempts`. If the initial generation is still incomplete, the next batch of the initial
+    /// attempts is generated as well.
+    ///
+    /// Returns the outcome of the refresh, see `CandidateGenerationStats::changed` for whether it changed anything.
+    pub(crate) fn try_refresh_candidates(&mut self) -> CandidateGenerationStats {
+        let fronts = self.bucket_fronts();
+        let Some(refresh) = self.start_refresh() else {
+            return CandidateGenerationStats {
+                fronts_changed: self.fronts_changed_since(&fronts),
+                ..Default::default()
+            };
+        };
+        let peers = PeerIds::new(refresh.attempts, self.rng.as_mut());
+        let (random, generated) = Self::install(self.thread_pool.as_deref(), || {
+            Self::generate_wanted_candidates(
+                &self.self_key,
+                peers,
+                &refresh.wanted,
+                self.generation_histogram.as_deref(),
+            )
+        });
+        let mut stats = self.finish_refresh(refresh, random, generated);
+        stats.fronts_changed = self.fronts_changed_since(&fronts);
+        stats
+    }
+
+    /// Same as `try_refresh_candidates`, unless the last refresh happened less than `min_interval` ago, in which case
+    /// nothing is done. Returns the outcome of the refresh, None if it was skipped.
+    pub(crate) fn try_refresh_candidates_throttled(
+        &mut self,
+        min_interval: Duration,
+    ) -> Option<CandidateGenerationStats> {
+        self.try_refresh_candidates_throttled_at(min_interval, Instant::now())
+    }
+
+    fn try_refresh_candidates_throttled_at(
+        &mut self,
+        min_interval: Duration,
+        now: Instant,
+    ) -> Option<CandidateGenerationStats> {
+        if let Some(last_refresh) = self.last_refresh {
+            let elapsed = now.saturating_duration_since(last_refresh);
+            if elapsed < min_interval {
+                trace!("NetworkDiscovery was refreshed {elapsed:?} ago, skipping the refresh");
+                return None;
+            }
+        }
+        Some(self.try_refresh_candidates())
+    }
+
+    // The front candidate of each bucket, i.e., the next one to be queried from it.
+    fn bucket_fronts(&self) -> BTreeMap<u32, NetworkAddress> {
+        self.candidates
+            .iter()
+            .filter_map(|(ilog2, candidates)| Some((*ilog2, candidates.first()?.addr.clone())))
+            .collect()
+    }
+
+    // The number of buckets whose front candidate differs from the provided ones, the buckets emptied or newly filled
+    // since included.
+    fn fronts_changed_since(&self, fronts: &BTreeMap<u32, NetworkAddress>) -> usize {
+        let current = self.bucket_fronts();
+        let changed = current
+            .iter()
+            .filter(|(ilog2, addr)| fronts.get(ilog2) != Some(addr))
+            .count();
+        let emptied = fronts
+            .keys()
+            .filter(|ilog2| !current.contains_key(ilog2))
+            .count();
+        changed + emptied
+    }
+
+    // Accounts for a new refresh and determines what it has to generate. Returns None if there is nothing to generate.
+    fn start_refresh(&mut self) -> Option<PendingRefresh> {
+        let started_at = Instant::now();
+        self.expire_in_flight_at(started_at);
+        if self.paused {
+            trace!("NetworkDiscovery is paused, skipping the refresh");
+            return None;
+        }
+        let initial_batch = self.pending_initial_attempts.min(INITIAL_BATCH_ATTEMPTS);
+        self.pending_initial_attempts -= initial_batch;
+        let attempts = self.adaptive_refresh_attempts() + initial_batch;
+        self.last_refresh = Some(started_at);
+        self.last_refresh_attempts = attempts;
+        self.consumed_since_refresh = 0;
+        if attempts == 0 {
+            trace!("NetworkDiscovery candidates are saturated, skipping the refresh");
+            return None;
+        }
+
+        Some(PendingRefresh {
+            started_at,
+            attempts,
+            wanted: self.wanted_buckets(),
+        })
+    }
+
+    // Completes the refresh with the candidates generated from the random PeerIds.
+    fn finish_refresh(
+        &mut self,
+        refresh: PendingRefresh,
+        candidates_vec: BTreeMap<u32, Vec<NetworkAddress>>,
+        generated: usize,
+    ) -> CandidateGenerationStats {
+        let PendingRefresh {
+            started_at,
+            attempts,
+            ..
+        } = refresh;
+        let stats = self.insert_generated_candidates(attempts, candidates_vec);
+        self.enforce_max_total_candidates();
+        #[cfg(feature = "open-metrics")]
+        if let Some(metrics) = &self.metrics {
+            metrics
+                .refresh_duration
+                .observe(started_at.elapsed().as_secs_f64());
+        }
+        trace!("NetworkDiscovery candidates refreshed with {generated} of {attempts} attempts in {:?}: {stats:?}", started_at.elapsed());
+        stats
+    }
+
+    // The number of candidates that each bucket has room for, skipping the full buckets and the ones full in our RT.
+    fn wanted_buckets(&self) -> BTreeMap<u32, usize> {
+        (0..=255)
+            .filter(|ilog2| self.is_bucket_needed(*ilog2))
+            .filter_map(|ilog2| {
+                let held = self.candidates.get(&ilog2).map_or(0, Vec::len);
+                let room = self.max_peers_per_bucket.saturating_sub(held);
+                (room > 0).then_some((ilog2, room))
+            })
+            .collect()
+    }
+
+    /// Returns true if the candidates are worth refreshing, i.e., if more than `MAX_DEPLETED_BUCKETS` of the
+    /// targeted buckets that our RT still needs hold fewer than `DEPLETED_BUCKET_REMAINING_CANDIDATES` candidates that
+    /// have not been queried since the last refresh, or if more candidates than half of the held ones have been
+    /// handed out since the last refresh. An incomplete initial generation always needs a refresh.
+    pub(crate) fn should_refresh(&self) -> bool {
+        if self.pending_initial_attempts > 0 {
+            return true;
+        }
+        let depleted = self
+            .targeted_buckets_needed()
+            .filter(|ilog2| {
+                self.remaining_candidates(*ilog2) < DEPLETED_BUCKET_REMAINING_CANDIDATES
+            })
+            .count();
+        depleted > MAX_DEPLETED_BUCKETS || self.consumed_since_refresh * 2 > self.total_candidates()
+    }
+
+    /// Returns the share of the targeted buckets that our RT still needs, for which we hold at least one candidate.
+    /// Returns 1.0 if our RT does not need any of them.
+    pub(crate) fn coverage_ratio(&self) -> f64 {
+        let needed = self.targeted_buckets_needed().count();
+        if needed == 0 {
+            return 1.0;
+        }
+        let covered = self
+            .targeted_buckets_needed()
+            .filter(|ilog2| self.has_candidates_for_bucket(*ilog2))
+            .count();
+        covered as f64 / needed as f64
+    }
+
+    // The `TARGETED_BUCKETS` that are not full in our RT.
+    fn targeted_buckets_needed(&self) -> impl Iterator<Item = u32> + '_ {
+        TARGETED_BUCKETS.filter(|ilog2| self.is_bucket_needed(*ilog2))
+    }
+
+    // The number of candidates of the bucket that have not been queried since the last refresh.
+    fn remaining_candidates(&self, ilog2: u32) -> usize {
+        self.candidates
+            .get(&ilog2)
+            .into_iter()
+            .flatten()
+            .filter(
+                |candidate| match (candidate.last_queried, self.last_refresh) {
+                    (None, _) => true,
+                    (Some(last_queried), Some(last_refresh)) => last_queried < last_refresh,
+                    (Some(_), None) => false,
+                },
+            )
+            .count()
+    }
+
+    /// Returns the share of the `refresh_attempts` matching the share of the `TARGETED_BUCKETS` that hold less than
+    /// `max_peers_per_bucket` candidates. The buckets that are full in our RT do not need any candidate. Returns 0
+    /// once all of them are saturated.
+    fn adaptive_refresh_attempts(&self) -> usize {
+        let unsaturated = TARGETED_BUCKETS
+            .filter(|ilog2| {
+                let held = self.candidates.get(ilog2).map_or(0, Vec::len);
+                held < self.max_peers_per_bucket && self.is_bucket_needed(*ilog2)
+            })
+            .count();
+        (self.refresh_attempts * unsaturated).div_ceil(TARGETED_BUCKETS.count())
+    }
+
+    /// Keeps generating candidates until each of the `missing_buckets` holds at least one candidate, or until
+    /// `MAX_TARGETED_GENERATION_ATTEMPTS` PeerIds have been generated by this call. Only the requested buckets are
+    /// updated. Returns the requested buckets that remain uncovered, to be targeted again by a later call.
+    pub(crate) fn refresh_targeting(&mut self, missing_buckets: &[u32]) -> Vec<u32> {
+        let mut uncovered = missing_buckets
+            .iter()
+            .copied()
+            .filter(|ilog2| !self.has_candidates_for_bucket(*ilog2))
+            .collect::<BTreeSet<_>>();
+        if self.paused {
+            return uncovered.into_iter().collect();
+        }
+        let batch_size = self.refresh_attempts.max(1);
+        let mut attempts = 0;
+        while !uncovered.is_empty() && attempts < MAX_TARGETED_GENERATION_ATTEMPTS {
+            let batch = batch_size.min(MAX_TARGETED_GENERATION_ATTEMPTS - attempts);
+            attempts += batch;
+            let peers = PeerIds::new(batch, self.rng.as_mut());
+            let generated = Self::install(self.thread_pool.as_deref(), || {
+                Self::generate_candidates(
+                    &self.self_key,
+                    peers,
+                    self.max_peers_per_bucket,
+                    self.generation_histogram.as_deref(),
+                )
+            });
+            for (ilog2, candidates) in generated {
+                if uncovered.remove(&ilog2) {
+                    let _ = self.insert_candidates(ilog2, candidates);
+                }
+            }
+        }
+        self.enforce_max_total_candidates();
+        trace!("Targeted {missing_buckets:?} with {attempts} attempts, uncovered: {uncovered:?}");
+        uncovered.into_iter().collect()
+    }
+
+    /// Returns the `TARGETED_BUCKETS` for which we hold no candidate, while our RT still needs more peers.
+    pub(crate) fn missing_targeted_buckets(&self) -> Vec<u32> {
+        TARGETED_BUCKETS
+            .filter(|ilog2| {
+                !self.has_candidates_for_bucket(*ilog2) && self.is_bucket_needed(*ilog2)
+            })
+            .collect()
+    }
+
+    // Inserts the candidates generated from the provided number of attempts and accounts for how many got accepted.
+    fn insert_generated_candidates(
+        &mut self,
+        attempts: usize,
+        generated: BTreeMap<u32, Vec<NetworkAddress>>,
+    ) -> CandidateGenerationStats {
+        let mut stats = CandidateGenerationStats {
+            attempts,
+            ..Default::default()
+        };
+        for (ilog2, candidates) in generated {
+            let accepted = self.insert_candidates(ilog2, candidates);
+            if accepted > 0 {
+                stats.accepted += accepted;
+                stats.buckets_touched += 1;
+            }
+        }
+        stats
+    }
+
+    /// Records the outcome of a kad::GetClosestPeers query made for the candidate. The candidate is evicted after
+    /// `MAX_CONSECUTIVE_QUERY_FAILURES` consecutive failures, so that the bucket can be backfilled by the next refresh.
+    pub(crate) fn record_query_result(&mut self, addr: &NetworkAddress, success: bool) {
+        let Some(ilog2) = addr.as_kbucket_key().distance(&self.self_key).ilog2() else {
+            return;
+        };
+        let Some(candidates) = self.candidates.get_mut(&ilog2) else {
+            return;
+        };
+        let Some(index) = candidates
+            .iter()
+            .position(|candidate| &candidate.addr == addr)
+        else {
+            return;
+        };
+
+        let candidate = &mut candidates[index];
+        if success {
+            candidate.failed_attempts = 0;
+            return;
+        }
+        candidate.failed_attempts = candidate.failed_attempts.saturating_add(1);
+        if candidate.failed_attempts >= MAX_CONSECUTIVE_QUERY_FAILURES {
+            debug!("Evicting the network discovery candidate {addr:?} after {MAX_CONSECUTIVE_QUERY_FAILURES} failed queries");
+            let _ = candidates.remove(index);
+            if candidates.is_empty() {
+                let _ = self.candidates.remove(&ilog2);
+            }
+            let _ = self.in_flight.remove(addr);
+        }
+    }
+
+    /// Removes the candidate of the provided peer, e.g., once it is known to be unreachable. The bucket is removed if
+    /// it becomes empty. Returns true if the peer was a candidate.
+    pub(crate) fn remove_peer(&mut self, peer: &PeerId) -> bool {
+        let addr = NetworkAddress::from_peer(*peer);
+        let _ = self.in_flight.remove(&addr);
+        let Some(ilog2) = addr.as_kbucket_key().distance(&self.self_key).ilog2() else {
+            return false;
+        };
+        let Some(candidates) = self.candidates.get_mut(&ilog2) else {
+            return false;
+        };
+        let before = candidates.len();
+        candidates.retain(|candidate| candidate.addr != addr);
+        let removed = candidates.len() < before;
+        if candidates.is_empty() {
+            let _ = self.candidates.remove(&ilog2);
+        }
+        removed
+    }
+
+    /// Removes the observed candidates that have been inserted more than `max_age` ago, as those peers might have gone
+    /// offline since. The generated candidates are never pruned.
+    pub(crate) fn prune_older_than(&mut self, max_age: Duration) {
+        self.prune_older_than_at(Instant::now(), max_age);
+    }
+
+    fn prune_older_than_at(&mut self, now: Instant, max_age: Duration) {
+        let mut pruned = 0;
+        self.candidates.retain(|_ilog2, candidates| {
+            let before = candidates.len();
+            candidates.retain(|candidate| {
+                candidate.source == CandidateSource::Generated
+                    || now.saturating_duration_since(candidate.inserted_at) <= max_age
+            });
+            pruned += before - candidates.len();
+            !candidates.is_empty()
+        });
+        if pruned > 0 {
+            debug!("Pruned {pruned} stale network discovery candidates");
+            self.record_coverage();
+        }
+    }
+
+    /// Tries to generate a candidate for each of the `TARGETED_BUCKETS` that the random sampling has missed. The
+    /// `attempts` are shared equally between these missed buckets.
+    fn fill_missing_buckets(
+        self_key: &KBucketKey<PeerId>,
+        candidates: &mut BTreeMap<u32, Vec<NetworkAddress>>,
+        attempts: usize,
+        mut rng: Option<&mut StdRng>,
+        histogram: Option<&GenerationHistogram>,
+    ) {
+        let missing = TARGETED_BUCKETS
+            .filter(|ilog2| !candidates.contains_key(ilog2))
+            .collect::<Vec<_>>();
+        let Some(attempts_per_bucket) = attempts.checked_div(missing.len()) else {
+            return;
+        };
+        for ilog2 in missing {
+            let peers = PeerIds::new(attempts_per_bucket, rng.as_deref_mut());
+            if let Some(candidate) =
+                Self::generate_candidate_in_bucket(self_key, ilog2, peers, histogram)
+            {
+                let _ = candidates.insert(ilog2, vec![candidate]);
+            }
+        }
+    }
+
+    /// Tries to generate a candidate that falls within the provided ilog2 bucket, giving up once the `peers` are
+    /// exhausted.
+    ///
+    /// The kbucket key is the sha256 hash of the PeerId, hence we cannot derive a PeerId for a given bucket by
+    /// flipping the bits of our own key. Instead we sample random PeerIds until one lands in the bucket. A bucket `b`
+    /// is hit with a probability of `2^(b - 256)`, so this is only feasible for the farther buckets.
+    fn generate_candidate_in_bucket(
+        self_key: &KBucketKey<PeerId>,
+        ilog2: u32,
+        peers: PeerIds,
+        histogram: Option<&GenerationHistogram>,
+    ) -> Option<NetworkAddress> {
+        let is_seeded = matches!(peers, PeerIds::Seeded { .. });
+        let in_bucket =
+            |peer: &CandidatePeerId| peer.ilog2_recorded(self_key, histogram) == Some(ilog2);
+        let peers = peers.into_par_iter();
+        // The seeded generation returns the first hit so that it is reproducible, any hit will do otherwise, which
+        // spares the rayon tasks from waiting for the ones holding the earlier PeerIds.
+        let candidate = if is_seeded {
+            peers.find_first(in_bucket)
+        } else {
+            peers.find_any(in_bucket)
+        };
+        candidate.map(CandidatePeerId::into_address)
+    }
+
+    // Insert the newly generated candidates.
+    fn insert_candidates(&mut self, ilog2: u32, new_candidates: Vec<NetworkAddress>) -> usize {
+        #[cfg(feature = "open-metrics")]
+        if let Some(metrics) = &self.metrics {
+            let _ = metrics
+                .candidates_generated
+                .inc_by(new_candidates.len() as u64);
+        }
+        let now = Instant::now();
+        let new_candidates = new_candidates
+            .into_iter()
+            .map(|addr| Candidate::new(addr, CandidateSource::Generated, now))
+            .collect();
+        self.insert_candidates_from(ilog2, new_candidates)
+    }
+
+    // Insert the new candidates and remove the old ones to maintain max_peers_per_bucket.
+    // Candidates that are already present in the bucket are never inserted again.
+    // Returns the number of new candidates that have been retained.
+    fn insert_candidates_from(&mut self, ilog2: u32, new_candidates: Vec<Candidate>) -> usize {
+        let existing_candidates = self.candidates.entry(ilog2).or_default();
+        let is_empty = existing_candidates.is_empty();
+        let mut inserted = 0;
+        for new_candidate in new_candidates {
+            // insert only newly seen candidates
+            if !existing_candidates
+                .iter()
+                .any(|candidate| candidate.addr == new_candidate.addr)
+            {
+                existing_candidates.push(new_candidate);
+                inserted += 1;
+            } else {
+                #[cfg(feature = "open-metrics")]
+                if let Some(metrics) = &self.metrics {
+                    let _ = metrics.duplicate_candidates.inc();
+                }
+            }
+        }
+        // Keep only max_peers_per_bucket elements. The queried ones are evicted first, the least recently queried before
+        // the others, and then the oldest ones, so that a candidate is not evicted before it gets queried.
+        while existing_candidates.len() > self.max_peers_per_bucket {
+            let Some(index) = existing_candidates
+                .iter()
+                .enumerate()
+                .min_by_key(|(index, candidate)| {
+                    (
+                        candidate.last_queried.is_none(),
+                        candidate.last_queried,
+                        *index,
+                    )
+                })
+                .map(|(index, _)| index)
+            else {
+                break;
+            };
+            let _ = existing_candidates.remove(index);
+        }
+        if is_empty && inserted > 0 {
+            self.notify_populated(ilog2);
+        }
+        // The newest candidates are never queried and at the back, hence they are the last ones to be removed.
+        inserted.min(self.max_peers_per_bucket)
+    }
+
+    // Runs the provided generation on the thread pool if one has been set, else on the global pool.
+    fn install<R: Send>(thread_pool: Option<&ThreadPool>, op: impl FnOnce() -> R + Send) -> R {
+        match thread_pool {
+            Some(thread_pool) => thread_pool.install(op),
+            None => op(),
+        }
+    }
+
+    /// Uses rayon to parallelize the generation
+    fn generate_candidates(
+        self_key: &KBucketKey<PeerId>,
+        peers: PeerIds,
+        max_peers_per_bucket: usize,
+        histogram: Option<&GenerationHistogram>,
+    ) -> BTreeMap<u32, Vec<NetworkAddress>> {
+        Self::bucket_candidates(
+            self_key,
+            peers.into_par_iter(),
+            max_peers_per_bucket,
+            histogram,
+        )
+    }
+
+    /// Same as `generate_candidates`, but only the candidates of the `wanted` buckets are kept, up to the number wanted
+    /// for each of them. The generation stops early once all of them are satisfied. Returns the candidates along with
+    /// the number of PeerIds that have actually been generated.
+    ///
+    /// The seeded `peers` are generated sequentially, as the candidates retained and the point of the early stop would
+    /// otherwise depend on the scheduling of the threads.
+    fn generate_wanted_candidates(
+        self_key: &KBucketKey<PeerId>,
+        peers: PeerIds,
+        wanted: &BTreeMap<u32, usize>,
+        histogram: Option<&GenerationHistogram>,
+    ) -> (BTreeMap<u32, Vec<NetworkAddress>>, usize) {
+        let hits = wanted
+            .keys()
+            .map(|ilog2| (*ilog2, AtomicUsize::new(0)))
+            .collect::<BTreeMap<_, _>>();
+        let unsatisfied = AtomicUsize::new(wanted.len());
+        let generated = AtomicUsize::new(0);
+
+        let keep_wanted = |peer: CandidatePeerId| {
+            // Stops the generation of every thread once all the wanted buckets got their hits.
+            if unsatisfied.load(Ordering::Relaxed) == 0 {
+                return None;
+            }
+            let _ = generated.fetch_add(1, Ordering::Relaxed);
+            let Some(ilog2) = peer.ilog2_recorded(self_key, histogram) else {
+                return Some(None);
+            };
+            let (Some(count), Some(needed)) = (hits.get(&ilog2), wanted.get(&ilog2)) else {
+                return Some(None);
+            };
+            let hit = count.fetch_add(1, Ordering::Relaxed) + 1;
+            if hit == *needed {
+                let _ = unsatisfied.fetch_sub(1, Ordering::Relaxed);
+            }
+            Some((hit <= *needed).then(|| (ilog2, peer.into_address())))
+        };
+        let push = |mut acc: BTreeMap<u32, Vec<NetworkAddress>>, (ilog2, candidate)| {
+            acc.entry(ilog2).or_default().push(candidate);
+            acc
+        };
+
+        let candidates = match peers {
+            PeerIds::Random(_) => peers
+                .into_par_iter()
+                .map(keep_wanted)
+                .while_some()
+                .flatten()
+                .fold(BTreeMap::new, push)
+                .reduce(BTreeMap::new, |mut acc, map| {
+                    for (ilog2, mut candidates) in map {
+                        acc.entry(ilog2).or_default().append(&mut candidates);
+                    }
+                    acc
+                }),
+            PeerIds::Seeded { .. } => peers
+                .into_iter()
+                .map_while(keep_wanted)
+                .flatten()
+                .fold(BTreeMap::new(), push),
+        };
+        (candidates, generated.into_inner())
+    }
+
+    /// Same as `generate_candidates`, but the PeerIds are derived from a seeded RNG. For a given `self_key` and `seed`
+    /// the produced candidates are always the same.
+    #[cfg(test)]
+    fn generate_candidates_seeded(
+        self_key: &KBucketKey<PeerId>,
+        num_to_generate: usize,
+        seed: u64,
+        max_peers_per_bucket: usize,
+    ) -> BTreeMap<u32, Vec<NetworkAddress>> {
+        let peers = PeerIds::new(num_to_generate, Some(&mut StdRng::seed_from_u64(seed)));
+        Self::generate_candidates(self_key, peers, max_peers_per_bucket, None)
+    }
+
+    /// Places the provided peers into their ilog2 buckets, keeping at most `max_peers_per_bucket` per bucket.
+    fn bucket_candidates(
+        self_key: &KBucketKey<PeerId>,
+        peers: impl ParallelIterator<Item = CandidatePeerId>,
+        max_peers_per_bucket: usize,
+        histogram: Option<&GenerationHistogram>,
+    ) -> BTreeMap<u32, Vec<NetworkAddress>> {
+        let push = |mut acc: BTreeMap<u32, Vec<CandidatePeerId>>, (ilog2, peer)| {
+            let entry = acc.entry(ilog2).or_default();
+            if entry.len() < max_peers_per_bucket {
+                entry.push(peer);
+            }
+            acc
+        };
+        peers
+            .filter_map(|peer| Some((peer.ilog2_recorded(self_key, histogram)?, peer)))
+            // Since it is parallel iterator, the fold fn batches the items and will produce multiple outputs. So we
+            // should use reduce fn to combine multiple outputs.
+            .fold(BTreeMap::new, push)
+            .reduce(BTreeMap::new, |acc, map| {
+                map.into_iter()
+                    .flat_map(|(ilog2, peers)| peers.into_iter().map(move |peer| (ilog2, peer)))
+                    .fold(acc, push)
+            })
+            .into_iter()
+            .map(|(ilog2, peers)| {
+                let candidates = peers.into_iter().map(CandidatePeerId::into_address);
+                (ilog2, candidates.collect())
+            })
+            .collect()
+    }
+}
+
+/// A refresh of the candidates that is being generated, see `NetworkDiscovery::start_refresh`.
+struct PendingRefresh {
+    started_at: Instant,
+    attempts: usize,
+    wanted: BTreeMap<u32, usize>,
+}
+
+/// The PeerIds to be generated as candidates. They are random by default, or derived from the seeds drawn from the rng
+/// of a NetworkDiscovery created through `new_with_rng`. Each seed covers a chunk of `SEEDED_GENERATION_CHUNK`
+/// PeerIds, so that the chunks can be generated by parallel tasks while the sequence of PeerIds stays reproducible.
+#[derive(Debug, Clone)]
+enum PeerIds {
+    Random(usize),
+    Seeded { count: usize, seeds: Vec<u64> },
+}
+
+impl PeerIds {
+    fn new(count: usize, rng: Option<&mut StdRng>) -> Self {
+        match rng {
+            Some(rng) => Self::Seeded {
+                count,
+                seeds: (0..count.div_ceil(SEEDED_GENERATION_CHUNK))
+                    .map(|_| rng.next_u64())
+                    .collect(),
+            },
+            None => Self::Random(count),
+        }
+    }
+
+    // The PeerIds derived from the seed of the chunk at `index`.
+    fn chunk(count: usize, index: usize, seed: u64) -> impl Iterator<Item = CandidatePeerId> {
+        let len = SEEDED_GENERATION_CHUNK.min(count - index * SEEDED_GENERATION_CHUNK);
+        let mut rng = StdRng::seed_from_u64(seed);
+        (0..len).map(move |_| CandidatePeerId::from_rng(&mut rng))
+    }
+
+    fn into_par_iter(self) -> impl ParallelIterator<Item = CandidatePeerId> {
+        match self {
+            Self::Random(count) => Either::Left(
+                (0..count)
+                    .into_par_iter()
+                    .map_init(rand::thread_rng, |rng, _| CandidatePeerId::from_rng(rng)),
+            ),
+            Self::Seeded { count, seeds } => Either::Right(
+                seeds
+                    .into_par_iter()
+                    .enumerate()
+                    .flat_map_iter(move |(index, seed)| Self::chunk(count, index, seed)),
+            ),
+        }
+    }
+
+    fn into_iter(self) -> impl Iterator<Item = CandidatePeerId> {
+        match self {
+            Self::Random(count) => {
+                let mut rng = rand::thread_rng();
+                Either::Left((0..count).map(move |_| CandidatePeerId::from_rng(&mut rng)))
+            }
+            Self::Seeded { count, seeds } => Either::Right(
+                seeds
+                    .into_iter()
+                    .enumerate()
+                    .flat_map(move |(index, seed)| Self::chunk(count, index, seed)),
+            ),
+        }
+    }
+}
+
+/// The bytes of a generated PeerId, made in the same way as `PeerId::random`: the identity multihash of a random
+/// digest.
+///
+/// Most of the generated PeerIds are discarded, so their ilog2 bucket is computed straight from these bytes, and only
+/// the retained ones are turned into a `NetworkAddress`. The kbucket key is the sha256 hash of the same bytes either
+/// way, hence the distances match the ones of the `NetworkAddress`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
+struct CandidatePeerId([u8; 34]);
+
+impl CandidatePeerId {
+    fn from_rng(rng: &mut impl RngCore) -> Self {
+        // The identity multihash code (0x00) followed by the digest length.
+        let mut bytes = [0; 34];
+        bytes[1] = 32;
+        rng.fill_bytes(&mut bytes[2..]);
+        Self(bytes)
+    }
+
+    fn ilog2(&self, self_key: &KBucketKey<PeerId>) -> Option<u32> {
+        KBucketKey::new(self.0).distance(self_key).ilog2()
+    }
+
+    // Same as `ilog2`, recording the distance into the histogram if any.
+    fn ilog2_recorded(
+        &self,
+        self_key: &KBucketKey<PeerId>,
+        histogram: Option<&GenerationHistogram>,
+    ) -> Option<u32> {
+        let ilog2 = self.ilog2(self_key)?;
+        if let Some(histogram) = histogram {
+            histogram.record(ilog2);
+        }
+        Some(ilog2)
+    }
+
+    fn into_peer_id(self) -> PeerId {
+        PeerId::from_bytes(&self.0).expect("The identity multihash is always a valid PeerId")
+    }
+
+    fn into_address(self) -> NetworkAddress {
+        NetworkAddress::from_peer(self.into_peer_id())
+    }
+}
+
+/// The number of generated PeerIds per ilog2 distance. It is updated concurrently by the generation threads.
+#[derive(Debug)]
+struct GenerationHistogram(Vec<AtomicU64>);
+
+impl Default for GenerationHistogram {
+    fn default() -> Self {
+        Self((0..256).map(|_| AtomicU64::new(0)).collect())
+    }
+}
+
+impl GenerationHistogram {
+    fn record(&self, ilog2: u32) {
+        if let Some(count) = self.0.get(ilog2 as usize) {
+            let _ = count.fetch_add(1, Ordering::Relaxed);
+        }
+    }
+
+    // The non-zero counts, keyed by ilog2.
+    fn snapshot(&self) -> BTreeMap<u32, u64> {
+        self.0
+            .iter()
+            .enumerate()
+            .filter_map(|(ilog2, count)| {
+                let count = count.load(Ordering::Relaxed);
+                (count > 0).then_some((ilog2 as u32, count))
+            })
+            .collect()
+    }
+}
+
+/// Creates a PeerId from random bytes in the same way as `PeerId::random`, but using the provided RNG.
+#[cfg(test)]
+fn peer_id_from_rng(rng: &mut impl RngCore) -> PeerId {
+    CandidatePeerId::from_rng(rng).into_peer_id()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn buckets_fill_up_to_the_configured_cap() {
+        let self_peer_id = PeerId::random();
+        let config = NetworkDiscoveryConfig {
+            max_peers_per_bucket: 10,
+            ..Default::default()
+        };
+        let network_discovery = NetworkDiscovery::with_config(&self_peer_id, config);
+
+        assert!(network_discovery
+            .candidates
+            .values()
+            .all(|candidates| candidates.len() <= 10));
+        // The farthest buckets are hit by half or a quarter of the random PeerIds, so they must be full.
+        assert_eq!(
+            network_discovery.candidates.get(&255).map(Vec::len),
+            Some(10)
+        );
+        assert!(network_discovery
+            .candidates
+            .values()
+            .any(|candidates| candidates.len() > DEFAULT_MAX_PEERS_PER_BUCKET));
+    }
+
+    #[test]
+    fn zero_peers_per_bucket_is_clamped_to_one() {
+        let self_peer_id = PeerId::random();
+        let config = NetworkDiscoveryConfig {
+            max_peers_per_bucket: 0,
+            ..Default::default()
+        };
+        let network_discovery = NetworkDiscovery::with_config(&self_peer_id, config);
+
+        assert_eq!(network_discovery.max_peers_per_bucket, 1);
+        assert!(!network_discovery.candidates.is_empty());
+        assert!(network_discovery
+            .candidates
+            .values()
+            .all(|candidates| candidates.len() == 1));
+    }
+
+    #[test]
+    fn generation_attempts_are_respected() {
+        let self_peer_id = PeerId::random();
+        let config = NetworkDiscoveryConfig {
+            initial_attempts: 0,
+            refresh_attempts: 20,
+            ..Default::default()
+        };
+        let mut network_discovery = NetworkDiscovery::with_config(&self_peer_id, config);
+        assert_eq!(network_discovery.total_candidates(), 0);
+
+        network_discovery.try_refresh_candidates();
+        let total = network_discovery.total_candidates();
+        assert!(total > 0);
+        assert!(total <= 20);
+
+        let config = NetworkDiscoveryConfig {
+            initial_attempts: 3,
+            refresh_attempts: 0,
+            ..Default::default()
+        };
+        let mut network_discovery = NetworkDiscovery::with_config(&self_peer_id, config);
+        let total = network_discovery.total_candidates();
+        assert!(total <= 3);
+
+        network_discovery.try_refresh_candidates();
+        assert_eq!(network_discovery.total_candidates(), total);
+    }
+
+    #[test]
+    fn candidate_snapshots_diff_into_the_per_bucket_changes() {
+        let earlier = CandidateSnapshot::new(BTreeMap::from([(250, 2), (252, 5), (255, 5)]));
+        let later = CandidateSnapshot::new(BTreeMap::from([(248, 1), (252, 3), (255, 5)]));
+
+        let delta = earlier.diff(&later);
+        assert_eq!(delta.gained().collect::<Vec<_>>(), vec![(248, 1)]);
+        assert_eq!(delta.lost().collect::<Vec<_>>(), vec![(250, -2), (252, -2)]);
+
+        // The reverse diff undoes the changes.
+        let reverse = later.diff(&earlier);
+        assert_eq!(
+            reverse.gained().collect::<Vec<_>>(),
+            vec![(250, 2), (252, 2)]
+        );
+        assert_eq!(reverse.lost().collect::<Vec<_>>(), vec![(248, -1)]);
+        assert!(earlier.diff(&earlier).is_empty());
+    }
+
+    #[test]
+    fn candidates_delta_covers_the_changes_since_the_last_round() {
+        let config = NetworkDiscoveryConfig {
+            initial_attempts: 0,
+            ..Default::default()
+        };
+        let mut network_discovery = NetworkDiscovery::with_config(&PeerId::random(), config);
+        assert!(network_discovery
+            .candidates_delta_since_last_round()
+            .is_empty());
+
+        let peer = PeerId::random();
+        let ilog2 = network_discovery.bucket_for_peer(&peer).expect("not self");
+        network_discovery.insert_candidates(ilog2, vec![NetworkAddress::from_peer(peer)]);
+        let delta = network_discovery.candidates_delta_since_last_round();
+        assert_eq!(delta.gained().collect::<Vec<_>>(), vec![(ilog2, 1)]);
+        assert_eq!(delta.lost().count(), 0);
+
+        // Only the changes made since the previous round are reported.
+        assert!(network_discovery
+            .candidates_delta_since_last_round()
+            .is_empty());
+        assert!(network_discovery.remove_peer(&peer));
+        let delta = network_discovery.candidates_delta_since_last_round();
+        assert_eq!(delta.gained().count(), 0);
+        assert_eq!(delta.lost().collect::<Vec<_>>(), vec![(ilog2, -1)]);
+    }
+
+    #[test]
+    fn bucket_occupancy_matches_insertions() {
+        let self_peer_id = PeerId::random();
+        let config = NetworkDiscoveryConfig {
+            initial_attempts: 0,
+            max_peers_per_bucket: 3,
+            ..Default::default()
+        };
+        let mut network_discovery = NetworkDiscovery::with_config(&self_peer_id, config);
+        assert!(network_discovery.bucket_occupancy().is_empty());
+
+        let random_addrs = |count: usize| {
+            (0..count)
+                .map(|_| NetworkAddress::from_peer(PeerId::random()))
+                .collect::<Vec<_>>()
+        };
+        network_discovery.insert_candidates(200, random_addrs(1));
+        network_discovery.insert_candidates(254, random_addrs(2));
+        network_discovery.insert_candidates(255, random_addrs(5));
+
+        let occupancy = network_discovery.bucket_occupancy();
+        assert_eq!(
+            occupancy.into_iter().collect::<Vec<_>>(),
+            vec![(200, 1), (254, 2), (255, 3)]
+        );
+    }
+
+    #[test]
+    fn generated_candidate_falls_in_the_requested_bucket() {
+        let self_peer_id = PeerId::random();
+        let self_key = KBucketKey::from(self_peer_id);
+
+        for ilog2 in 250..=255 {
+            let candidate = NetworkDiscovery::generate_candidate_in_bucket(
+                &self_key,
+                ilog2,
+                PeerIds::Random(100_000),
+                None,
+            )
+            .expect("a candidate should have been generated");
+            assert_eq!(
+                candidate.as_kbucket_key().distance(&self_key).ilog2(),
+                Some(ilog2)
+            );
+        }
+
+        // The closest buckets are practically unreachable by sampling.
+        assert!(NetworkDiscovery::generate_candidate_in_bucket(
+            &self_key,
+            0,
+            PeerIds::Random(1_000),
+            None
+        )
+        .is_none());
+    }
+
+    // A query target that is not one of the candidates, e.g., of a query made outside of the network discovery.
+    fn untracked_target() -> NetworkAddress {
+        NetworkAddress::from_peer(PeerId::random())
+    }
+
+    fn observed_candidate() -> Candidate {
+        let peer = PeerId::random();
+        Candidate::new(
+            NetworkAddress::from_peer(peer),
+            CandidateSource::Observed(peer),
+            Instant::now(),
+        )
+    }
+
+    fn addresses(network_discovery: &NetworkDiscovery) -> BTreeMap<u32, Vec<NetworkAddress>> {
+        network_discovery
+            .candidates
+            .iter()
+            .map(|(ilog2, candidates)| {
+                let addrs = candidates.iter().map(|c| c.addr.clone()).collect();
+                (*ilog2, addrs)
+            })
+            .collect()
+    }
+
+    fn assert_buckets_are_unique(network_discovery: &NetworkDiscovery) {
+        for candidates in network_discovery.candidates.values() {
+            let unique = candidates
+                .iter()
+                .map(|candidate| &candidate.addr)
+                .collect::<std::collections::HashSet<_>>();
+            assert_eq!(unique.len(), candidates.len());
+        }
+    }
+
+    #[test]
+    fn get_closest_query_does_not_insert_duplicates() {
+        let self_peer_id = PeerId::random();
+        let config = NetworkDiscoveryConfig {
+            initial_attempts: 0,
+            ..Default::default()
+        };
+        let mut network_discovery = NetworkDiscovery::with_config(&self_peer_id, config);
+
+        let peers = (0..3).map(|_| PeerId::random()).collect::<Vec<_>>();
+        network_discovery.handle_get_closest_query(
+            &untracked_target(),
+            peers.clone().into_iter().collect(),
+            |_| false,
+        );
+        let occupancy = network_discovery.bucket_occupancy();
+        assert_buckets_are_unique(&network_discovery);
+
+        // the same result again, along with a duplicate within the result itself
+        let mut repeated_peers = peers.clone();
+        repeated_peers.push(peers[0]);
+        network_discovery.handle_get_closest_query(
+            &untracked_target(),
+            repeated_peers.into_iter().collect(),
+            |_| false,
+        );
+        assert_buckets_are_unique(&network_discovery);
+        assert_eq!(network_discovery.bucket_occupancy(), occupancy);
+    }
+
+    #[test]
+    fn full_bucket_keeps_its_candidates_on_duplicates() {
+        let self_peer_id = PeerId::random();
+        let config = NetworkDiscoveryConfig {
+            initial_attempts: 0,
+            max_peers_per_bucket: 2,
+            ..Default::default()
+        };
+        let mut network_discovery = NetworkDiscovery::with_config(&self_peer_id, config);
+
+        let addrs = (0..2)
+            .map(|_| NetworkAddress::from_peer(PeerId::random()))
+            .collect::<Vec<_>>();
+        network_discovery.insert_candidates(255, addrs.clone());
+        network_discovery.insert_candidates(255, vec![addrs[0].clone()]);
+
+        assert_eq!(addresses(&network_discovery)[&255], addrs);
+    }
+
+    #[test]
+    fn seeded_generation_is_reproducible() {
+        let self_peer_id = peer_id_from_rng(&mut StdRng::seed_from_u64(0));
+        let self_key = KBucketKey::from(self_peer_id);
+
+        let first = NetworkDiscovery::generate_candidates_seeded(&self_key, 1_000, 42, 5);
+        let second = NetworkDiscovery::generate_candidates_seeded(&self_key, 1_000, 42, 5);
+        assert!(!first.is_empty());
+        assert_eq!(
+            first.keys().collect::<Vec<_>>(),
+            second.keys().collect::<Vec<_>>()
+        );
+        assert_eq!(first, second);
+
+        let other_seed = NetworkDiscovery::generate_candidates_seeded(&self_key, 1_000, 7, 5);
+        assert_ne!(first, other_seed);
+    }
+
+    #[test]
+    fn lazy_constructor_returns_before_the_generation() {
+        let self_peer_id = PeerId::random();
+        let config = NetworkDiscoveryConfig {
+            initial_attempts: 200_000,
+            ..Default::default()
+        };
+
+        let start = Instant::now();
+        let mut network_discovery = NetworkDiscovery::new_lazy(&self_peer_id, config);
+        let construction_time = start.elapsed();
+        assert!(network_discovery.candidates.is_empty());
+
+        let start = Instant::now();
+        let candidates = network_discovery.population_task()();
+        let generation_time = start.elapsed();
+        network_discovery.populate_with(candidates);
+        assert!(!network_discovery.candidates.is_empty());
+
+        assert!(construction_time * 10 < generation_time);
+    }
+
+    #[test]
+    fn bucket_for_peer_has_no_side_effect() {
+        let self_peer_id = PeerId::random();
+        let config = NetworkDiscoveryConfig {
+            initial_attempts: 1_000,
+            ..Default::default()
+        };
+        let network_discovery = NetworkDiscovery::with_config(&self_peer_id, config);
+        let before = addresses(&network_discovery);
+
+        let peer = PeerId::random();
+        let expected = KBucketKey::from(peer)
+            .distance(&KBucketKey::from(self_peer_id))
+            .ilog2();
+        assert!(expected.is_some());
+        assert_eq!(network_discovery.bucket_for_peer(&peer), expected);
+        assert_eq!(network_discovery.bucket_for_peer(&self_peer_id), None);
+
+        assert_eq!(addresses(&network_discovery), before);
+    }
+
+    #[test]
+    fn stored_kbucket_keys_spare_the_hashing() {
+        let self_key = KBucketKey::from(PeerId::random());
+        let candidates = (0..10_000)
+            .map(|_| {
+                Candidate::new(
+                    NetworkAddress::from_peer(PeerId::random()),
+                    CandidateSource::Generated,
+                    Instant::now(),
+                )
+            })
+            .collect::<Vec<_>>();
+
+        let start = Instant::now();
+        let recomputed = candidates
+            .iter()
+            .map(|candidate| candidate.addr.as_kbucket_key().distance(&self_key).ilog2())
+            .collect::<Vec<_>>();
+        let recomputing_time = start.elapsed();
+
+        let start = Instant::now();
+        let stored = candidates
+            .iter()
+            .map(|candidate| candidate.ilog2(&self_key))
+            .collect::<Vec<_>>();
+        let stored_time = start.elapsed();
+
+        assert_eq!(stored, recomputed);
+        assert!(stored_time < recomputing_time);
+    }
+
+    #[test]
+    fn candidates_are_restored_from_disk() -> eyre::Result<()> {
+        let storage_dir = std::env::temp_dir().join(uuid::Uuid::new_v4().to_string());
+        fs::create_dir_all(&storage_dir)?;
+        let path = storage_dir.join(NETWORK_DISCOVERY_CANDIDATES_FILENAME);
+        let self_peer_id = PeerId::random();
+        let config = NetworkDiscoveryConfig {
+            initial_attempts: 1_000,
+            ..Default::default()
+        };
+
+        let mut network_discovery = NetworkDiscovery::with_config(&self_peer_id, config.clone());
+        // a stale entry that does not belong to its claimed bucket
+        let stale = NetworkAddress::from_peer(PeerId::random());
+        let stale_bucket = (0..=255)
+            .find(|ilog2| {
+                stale
+                    .as_kbucket_key()
+                    .distance(&network_discovery.self_key)
+                    .ilog2()
+                    != Some(*ilog2)
+            })
+            .expect("an entry can only fall into a single bucket");
+        let expected = addresses(&network_discovery);
+        network_discovery
+            .candidates
+            .entry(stale_bucket)
+            .or_default()
+            .push(Candidate::new(
+                stale,
+                CandidateSource::Generated,
+                Instant::now(),
+            ));
+        // observed candidates are only persisted through the snapshot
+        network_discovery
+            .candidates
+            .entry(255)
+            .or_default()
+            .push(Candidate::new(
+                NetworkAddress::from_peer(PeerId::random()),
+                CandidateSource::Observed(PeerId::random()),
+                Instant::now(),
+            ));
+        network_discovery.set_candidates_file(path.clone());
+        let persist_task = network_discovery
+            .persist_task()
+            .ok_or_else(|| eyre::eyre!("the candidates should be persisted"))?;
+        persist_task();
+
+        let restored = NetworkDiscovery::read_from_disk(&path, &self_peer_id);
+        assert_eq!(restored.as_ref(), Some(&expected));
+
+        // the population task reuses the persisted candidates
+        let mut restarted = NetworkDiscovery::new_lazy(&self_peer_id, config);
+        restarted.set_candidates_file(path.clone());
+        restarted.populate();
+        assert_eq!(addresses(&restarted), expected);
+
+        // the candidates are invalid for any other PeerId
+        let other = NetworkDiscovery::read_from_disk(&path, &PeerId::random())
+            .expect("the file should be readable");
+        assert!(
+            other.values().map(Vec::len).sum::<usize>() < expected.values().map(Vec::len).sum()
+        );
+
+        fs::remove_dir_all(storage_dir)?;
+        Ok(())
+    }
+
+    #[test]
+    fn candidates_file_is_only_written_on_change() -> eyre::Result<()> {
+        let config = NetworkDiscoveryConfig {
+            initial_attempts: 1_000,
+            ..Default::default()
+        };
+        let mut network_discovery = NetworkDiscovery::with_config(&PeerId::random(), config);
+        assert!(network_discovery.persist_task().is_none());
+
+        let storage_dir = std::env::temp_dir().join(uuid::Uuid::new_v4().to_string());
+        fs::create_dir_all(&storage_dir)?;
+        let path = storage_dir.join(NETWORK_DISCOVERY_CANDIDATES_FILENAME);
+        network_discovery.set_candidates_file(path.clone());
+        let persist_task = network_discovery
+            .persist_task()
+            .ok_or_else(|| eyre::eyre!("the candidates should be persisted"))?;
+        persist_task();
+        assert!(path.exists());
+        assert!(network_discovery.persist_task().is_none());
+
+        // Either an observed or a generated candidate is a change.
+        let _ = network_discovery.handle_get_closest_query(
+            &untracked_target(),
+            HashSet::from_iter([PeerId::random()]),
+            |_| false,
+        );
+        assert!(network_discovery.persist_task().is_some());
+        assert!(network_discovery.persist_task().is_none());
+        let generated = addresses(&network_discovery)[&255][0]
+            .as_peer_id()
+            .ok_or_else(|| eyre::eyre!("a peer address"))?;
+        assert!(network_discovery.remove_peer(&generated));
+        assert!(network_discovery.persist_task().is_some());
+
+        fs::remove_dir_all(storage_dir)?;
+        Ok(())
+    }
+
+    #[test]
+    fn corrupt_candidates_file_falls_back_to_generation() -> eyre::Result<()> {
+        let storage_dir = std::env::temp_dir().join(uuid::Uuid::new_v4().to_string());
+        fs::create_dir_all(&storage_dir)?;
+        let path = storage_dir.join(NETWORK_DISCOVERY_CANDIDATES_FILENAME);
+        let self_peer_id = PeerId::random();
+
+        assert!(NetworkDiscovery::read_from_disk(&path, &self_peer_id).is_none());
+
+        fs::write(&path, [0xc1, 0x00, 0xff])?;
+        assert!(NetworkDiscovery::read_from_disk(&path, &self_peer_id).is_none());
+
+        let config = NetworkDiscoveryConfig {
+            initial_attempts: 100,
+            ..Default::default()
+        };
+        let mut network_discovery = NetworkDiscovery::new_lazy(&self_peer_id, config);
+        network_discovery.set_candidates_file(path);
+        network_discovery.populate();
+        assert!(!network_discovery.candidates.is_empty());
+
+        fs::remove_dir_all(storage_dir)?;
+        Ok(())
+    }
+
+    #[test]
+    fn generation_stats_account_for_accepted_candidates() {
+        let self_peer_id = PeerId::random();
+        let config = NetworkDiscoveryConfig {
+            initial_attempts: 0,
+            max_peers_per_bucket: 2,
+            ..Default::default()
+        };
+        let mut network_discovery = NetworkDiscovery::with_config(&self_peer_id, config);
+
+        let random_addrs = |count: usize| {
+            (0..count)
+                .map(|_| NetworkAddress::from_peer(PeerId::random()))
+                .collect::<Vec<_>>()
+        };
+        let existing = random_addrs(1);
+        network_discovery.insert_candidates(254, existing.clone());
+
+        let generated = BTreeMap::from([
+            // only the duplicate, nothing accepted
+            (254, existing),
+            // 3 new candidates, but only 2 are retained
+            (255, random_addrs(3)),
+            (200, random_addrs(1)),
+        ]);
+        let stats = network_discovery.insert_generated_candidates(10, generated);
+        assert_eq!(
+            stats,
+            CandidateGenerationStats {
+                attempts: 10,
+                accepted: 3,
+                buckets_touched: 2,
+                fronts_changed: 0,
+            }
+        );
+
+        let attempts = network_discovery.adaptive_refresh_attempts();
+        let stats = network_discovery.try_refresh_candidates();
+        assert_eq!(stats.attempts, attempts);
+        assert!(stats.accepted <= stats.attempts);
+        assert!(stats.buckets_touched <= stats.accepted);
+    }
+
+    #[test]
+    fn only_stale_observed_candidates_are_pruned() {
+        let self_peer_id = PeerId::random();
+        let config = NetworkDiscoveryConfig {
+            initial_attempts: 0,
+            ..Default::default()
+        };
+        let mut network_discovery = NetworkDiscovery::with_config(&self_peer_id, config);
+
+        let generated = NetworkAddress::from_peer(PeerId::random());
+        let _ = network_discovery.insert_candidates(255, vec![generated.clone()]);
+        let _ = network_discovery.insert_candidates_from(255, vec![observed_candidate()]);
+        let _ = network_discovery.insert_candidates_from(254, vec![observed_candidate()]);
+
+        let inserted_at = network_discovery.candidates[&255][1].inserted_at;
+        let max_age = Duration::from_secs(60);
+
+        // right at the boundary, nothing is pruned
+        network_discovery.prune_older_than_at(inserted_at + max_age, max_age);
+        assert_eq!(
+            network_discovery.bucket_occupancy(),
+            BTreeMap::from([(254, 1), (255, 2)])
+        );
+
+        // past the boundary, only the generated candidate remains and the emptied bucket is removed
+        network_discovery
+            .prune_older_than_at(inserted_at + max_age + Duration::from_secs(1), max_age);
+        assert_eq!(
+            addresses(&network_discovery),
+            BTreeMap::from([(255, vec![generated])])
+        );
+    }
+
+    #[test]
+    fn uncovered_buckets_report_the_gaps_exactly() {
+        let config = NetworkDiscoveryConfig {
+            initial_attempts: 0,
+            ..Default::default()
+        };
+        let mut network_discovery = NetworkDiscovery::with_config(&PeerId::random(), config);
+        assert_eq!(
+            network_discovery.uncovered_buckets(245),
+            (245..=255).collect::<Vec<_>>()
+        );
+        assert_eq!(
+            network_discovery.check_coverage(),
+            TARGETED_BUCKETS.collect::<Vec<_>>()
+        );
+
+        for ilog2 in (246..=255).filter(|ilog2| ![248, 252].contains(ilog2)) {
+            let _ = network_discovery
+                .insert_candidates(ilog2, vec![NetworkAddress::from_peer(PeerId::random())]);
+        }
+        assert_eq!(
+            network_discovery.uncovered_buckets(245),
+            vec![245, 248, 252]
+        );
+        assert_eq!(network_discovery.uncovered_buckets(250), vec![252]);
+        assert_eq!(network_discovery.uncovered_buckets(300), Vec::<u32>::new());
+        assert_eq!(network_discovery.check_coverage(), vec![245, 248, 252]);
+        assert_eq!(network_discovery.uncovered_buckets(0).len(), 256 - 8);
+
+        // The buckets that are implausible given the network size are not reported.
+        network_discovery.set_network_size_estimate(1 << 5);
+        assert_eq!(network_discovery.check_coverage(), vec![248, 252]);
+    }
+
+    #[test]
+    fn total_candidates_tracks_insertions_and_removals() {
+        let config = NetworkDiscoveryConfig {
+            initial_attempts: 0,
+            ..Default::default()
+        };
+        let mut network_discovery = NetworkDiscovery::with_config(&PeerId::random(), config);
+        assert_eq!(network_discovery.total_candidates(), 0);
+
+        let generated = NetworkAddress::from_peer(PeerId::random());
+        let _ = network_discovery.insert_candidates(255, vec![generated.clone()]);
+        // A duplicate is not counted twice.
+        let _ = network_discovery.insert_candidates(255, vec![generated]);
+        let observed = [PeerId::random(), PeerId::random()];
+        let _ = network_discovery.insert_observed_peers(observed, |_| false);
+        assert_eq!(network_discovery.total_candidates(), 3);
+        assert_eq!(network_discovery.stats().total_candidates, 3);
+
+        assert!(network_discovery.remove_peer(&observed[0]));
+        assert_eq!(network_discovery.total_candidates(), 2);
+
+        // Only the generated candidate survives the pruning of the stale observed ones.
+        let max_age = Duration::from_secs(60);
+        network_discovery.prune_older_than_at(Instant::now() + max_age * 2, max_age);
+        assert_eq!(network_discovery.total_candidates(), 1);
+    }
+
+    #[test]
+    fn expired_observed_candidates_are_backfilled_by_generation() {
+        let self_peer_id = PeerId::random();
+        let config = NetworkDiscoveryConfig {
+            initial_attempts: 0,
+            refresh_attempts: 1_000,
+            ..Default::default()
+        };
+        let mut network_discovery = NetworkDiscovery::with_config(&self_peer_id, config);
+        let generated = NetworkAddress::from_peer(PeerId::random());
+        let _ = network_discovery.insert_candidates(200, vec![generated.clone()]);
+        let _ = network_discovery.insert_candidates_from(255, vec![observed_candidate()]);
+
+        let max_age = Duration::from_millis(10);
+        std::thread::sleep(max_age * 2);
+        network_discovery.prune_older_than(max_age);
+        assert_eq!(
+            addresses(&network_discovery),
+            BTreeMap::from([(200, vec![generated])])
+        );
+
+        // the farthest bucket is hit by half of the generated PeerIds
+        let _stats = network_discovery.try_refresh_candidates();
+        assert_eq!(
+            network_discovery.bucket_occupancy()[&255],
+            DEFAULT_MAX_PEERS_PER_BUCKET
+        );
+        assert!(network_discovery.candidates[&255]
+            .iter()
+            .all(|candidate| candidate.source == CandidateSource::Generated));
+    }
+
+    #[test]
+    fn repeatedly_failing_candidate_is_evicted_and_backfilled() {
+        let self_peer_id = PeerId::random();
+        let config = NetworkDiscoveryConfig {
+            initial_attempts: 1_000,
+            refresh_attempts: 1_000,
+            ..Default::default()
+        };
+        let mut network_discovery = NetworkDiscovery::with_config(&self_peer_id, config);
+        let failing = network_discovery.candidates[&255][0].addr.clone();
+        let succeeding = network_discovery.candidates[&255][1].addr.clone();
+
+        // a success in between resets the consecutive failures
+        network_discovery.record_query_result(&succeeding, false);
+        network_discovery.record_query_result(&succeeding, false);
+        network_discovery.record_query_result(&succeeding, true);
+        network_discovery.record_query_result(&succeeding, false);
+
+        network_discovery.record_query_result(&failing, false);
+        network_discovery.record_query_result(&failing, false);
+        assert_eq!(network_discovery.bucket_occupancy()[&255], 5);
+        network_discovery.record_query_result(&failing, false);
+
+        let bucket = addresses(&network_discovery)[&255].clone();
+        assert_eq!(bucket.len(), 4);
+        assert!(!bucket.contains(&failing));
+        assert!(bucket.contains(&succeeding));
+
+        // the next refresh backfills the bucket
+        let _ = network_discovery.try_refresh_candidates();
+        assert_eq!(network_discovery.bucket_occupancy()[&255], 5);
+    }
+
+    #[test]
+    fn bucket_scoped_lookups_only_return_that_bucket() {
+        let self_peer_id = PeerId::random();
+        let config = NetworkDiscoveryConfig {
+            initial_attempts: 1_000,
+            ..Default::default()
+        };
+        let network_discovery = NetworkDiscovery::with_config(&self_peer_id, config);
+
+        for ilog2 in 250..=255 {
+            assert!(network_discovery.has_candidates_for_bucket(ilog2));
+            let candidates = network_discovery
+                .candidates_for_bucket(ilog2)
+                .collect::<Vec<_>>();
+            assert_eq!(
+                candidates.len(),
+                network_discovery.bucket_occupancy()[&ilog2]
+            );
+            assert!(candidates.iter().all(|candidate| {
+                candidate
+                    .as_kbucket_key()
+                    .distance(&network_discovery.self_key)
+                    .ilog2()
+                    == Some(ilog2)
+            }));
+        }
+
+        assert!(!network_discovery.has_candidates_for_bucket(0));
+        assert_eq!(network_discovery.candidates_for_bucket(0).count(), 0);
+    }
+
+    #[test]
+    fn generated_and_observed_candidates_are_distinguished() {
+        let self_peer_id = PeerId::random();
+        let config = NetworkDiscoveryConfig {
+            initial_attempts: 0,
+            ..Default::default()
+        };
+        let mut network_discovery = NetworkDiscovery::with_config(&self_peer_id, config);
+
+        let observed = observed_candidate();
+        let observed_addr = observed.addr.clone();
+        let generated = NetworkAddress::from_peer(PeerId::random());
+        let _ = network_discovery.insert_candidates_from(255, vec![observed]);
+        let _ = network_discovery.insert_candidates(255, vec![generated.clone()]);
+
+        let observed_peers = (0..3).map(|_| PeerId::random()).collect::<Vec<_>>();
+        network_discovery.handle_get_closest_query(
+            &untracked_target(),
+            observed_peers.clone().into_iter().collect(),
+            |_| false,
+        );
+
+        let generated_only = network_discovery
+            .candidates_by_source(|source| *source == CandidateSource::Generated)
+            .collect::<Vec<_>>();
+        assert_eq!(generated_only, vec![(255, &generated)]);
+
+        let observed = network_discovery
+            .candidates_by_source(|source| matches!(source, CandidateSource::Observed(_)))
+            .map(|(_, addr)| addr.clone())
+            .collect::<Vec<_>>();
+        assert_eq!(observed.len(), 4);
+        assert!(observed.contains(&observed_addr));
+        assert!(observed_peers
+            .iter()
+            .all(|peer| observed.contains(&NetworkAddress::from_peer(*peer))));
+
+        // the generated candidate is queried first, even though it was inserted last
+        let plan = network_discovery.query_plan(usize::MAX);
+        let position = |addr: &NetworkAddress| plan.iter().position(|queried| queried == addr);
+        assert!(position(&generated) < position(&observed_addr));
+    }
+
+    #[test]
+    fn same_peer_twice_into_an_empty_bucket_is_kept_once() {
+        let self_peer_id = PeerId::random();
+        let config = NetworkDiscoveryConfig {
+            initial_attempts: 0,
+            ..Default::default()
+        };
+        let mut network_discovery = NetworkDiscovery::with_config(&self_peer_id, config);
+
+        let peer = PeerId::random();
+        network_discovery.handle_get_closest_query(
+            &untracked_target(),
+            HashSet::from_iter(vec![peer, peer]),
+            |_| false,
+        );
+        network_discovery.handle_get_closest_query(
+            &untracked_target(),
+            HashSet::from_iter(vec![peer]),
+            |_| false,
+        );
+
+        assert_eq!(
+            network_discovery
+                .bucket_occupancy()
+                .values()
+                .collect::<Vec<_>>(),
+            vec![&1]
+        );
+    }
+
+    #[test]
+    fn full_rt_buckets_produce_no_query_targets() {
+        let self_peer_id = PeerId::random();
+        let config = NetworkDiscoveryConfig {
+            initial_attempts: 0,
+            ..Default::default()
+        };
+        let mut network_discovery = NetworkDiscovery::with_config(&self_peer_id, config);
+
+        let mut addrs = BTreeMap::new();
+        for ilog2 in [100, 200, 250, 255] {
+            let addr = NetworkAddress::from_peer(PeerId::random());
+            let _ = network_discovery.insert_candidates(ilog2, vec![addr.clone()]);
+            let _ = addrs.insert(ilog2, addr);
+        }
+
+        network_discovery.set_bucket_fill(HashMap::from([
+            (100, 3),
+            (200, K_VALUE.get()),
+            (255, K_VALUE.get() + 1),
+        ]));
+
+        // the full buckets are skipped
+        assert_eq!(
+            network_discovery.query_plan(usize::MAX),
+            vec![addrs[&100].clone(), addrs[&250].clone()]
+        );
+    }
+
+    #[test]
+    fn stats_reflect_refreshes_and_queries() {
+        let self_peer_id = PeerId::random();
+        let config = NetworkDiscoveryConfig {
+            initial_attempts: 0,
+            ..Default::default()
+        };
+        let mut network_discovery = NetworkDiscovery::with_config(&self_peer_id, config);
+        assert_eq!(network_discovery.stats(), NetworkDiscoveryStats::default());
+
+        let _ = network_discovery.try_refresh_candidates();
+        // Only the farther buckets are kept, so that a closer peer is quickly found below.
+        network_discovery
+            .candidates
+            .retain(|ilog2, _| *ilog2 >= 250);
+        let refreshed = network_discovery.stats();
+        assert!(refreshed.since_last_refresh.is_some());
+        assert!(refreshed.total_candidates > 0);
+        assert_eq!(
+            refreshed.buckets_covered,
+            network_discovery.candidates.len()
+        );
+        assert_eq!(refreshed.max_ilog2, Some(255));
+        assert!(refreshed.min_ilog2 <= refreshed.max_ilog2);
+
+        // a closer peer than any of the generated ones
+        let closest_bucket = refreshed.min_ilog2.expect("a bucket is covered");
+        let closer_peer = (0..100_000)
+            .map(|_| PeerId::random())
+            .find(|peer| {
+                NetworkAddress::from_peer(*peer)
+                    .as_kbucket_key()
+                    .distance(&network_discovery.self_key)
+                    .ilog2()
+                    < Some(closest_bucket)
+            })
+            .expect("a closer peer should be found");
+        network_discovery.handle_get_closest_query(
+            &untracked_target(),
+            HashSet::from_iter(vec![closer_peer]),
+            |_| false,
+        );
+        let queried = network_discovery.stats();
+        assert_eq!(queried.total_candidates, refreshed.total_candidates + 1);
+        assert!(queried.min_ilog2 < refreshed.min_ilog2);
+
+        let _ = network_discovery.take_candidates(1, CandidateSelection::Closest);
+        assert_eq!(network_discovery.stats().candidates_consumed, 1);
+    }
+
+    #[test]
+    fn generation_runs_on_the_provided_thread_pool() -> eyre::Result<()> {
+        let thread_pool = Arc::new(rayon::ThreadPoolBuilder::new().num_threads(1).build()?);
+        assert_eq!(
+            NetworkDiscovery::install(Some(&thread_pool), rayon::current_num_threads),
+            1
+        );
+
+        let self_peer_id = PeerId::random();
+        let config = NetworkDiscoveryConfig {
+            thread_pool: Some(thread_pool),
+            ..Default::default()
+        };
+        let mut network_discovery = NetworkDiscovery::with_config(&self_peer_id, config);
+        assert!(network_discovery.has_candidates_for_bucket(255));
+
+        let attempts = network_discovery.adaptive_refresh_attempts();
+        let stats = network_discovery.try_refresh_candidates();
+        assert_eq!(stats.attempts, attempts);
+        Ok(())
+    }
+
+    #[test]
+    fn generation_until_coverage_stops_once_the_target_is_met() {
+        let self_peer_id = PeerId::random();
+
+        // The 4 farthest buckets are hit by the first batch, with overwhelming odds.
+        let (network_discovery, attempts) =
+            NetworkDiscovery::new_until_coverage(&self_peer_id, Default::default(), 4, 100_000);
+        assert_eq!(attempts, INITIAL_BATCH_ATTEMPTS);
+        assert!(network_discovery.has_reached_coverage(4));
+
+        // No amount of attempts covers all the buckets, the ceiling applies, even mid batch.
+        let max_attempts = INITIAL_BATCH_ATTEMPTS * 2 + INITIAL_BATCH_ATTEMPTS / 2;
+        let (network_discovery, attempts) = NetworkDiscovery::new_until_coverage(
+            &self_peer_id,
+            Default::default(),
+            256,
+            max_attempts,
+        );
+        assert_eq!(attempts, max_attempts);
+        assert!(!network_discovery.has_reached_coverage(256));
+        assert!(network_discovery.has_reached_coverage(4));
+
+        // A coverage that is already met generates nothing.
+        let (network_discovery, attempts) =
+            NetworkDiscovery::new_until_coverage(&self_peer_id, Default::default(), 0, 100_000);
+        assert_eq!(attempts, 0);
+        assert_eq!(network_discovery.total_candidates(), 0);
+    }
+
+    #[test]
+    fn incremental_construction_reaches_parity_with_eager_construction() {
+        let self_peer_id = PeerId::random();
+        let eager = NetworkDiscovery::with_config(&self_peer_id, Default::default());
+        let mut incremental = NetworkDiscovery::new_incremental(&self_peer_id, Default::default());
+
+        let first_batch = incremental.bucket_occupancy().values().sum::<usize>();
+        assert!(first_batch > 0);
+        assert!(first_batch <= eager.bucket_occupancy().values().sum::<usize>());
+
+        // The remaining initial attempts are spent one batch per refresh.
+        let refreshes = (DEFAULT_INITIAL_GENERATION_ATTEMPTS - INITIAL_BATCH_ATTEMPTS)
+            .div_ceil(INITIAL_BATCH_ATTEMPTS);
+        for _ in 0..refreshes {
+            let attempts = incremental.adaptive_refresh_attempts();
+            let stats = incremental.try_refresh_candidates();
+            assert_eq!(stats.attempts, attempts + INITIAL_BATCH_ATTEMPTS);
+        }
+        assert_eq!(incremental.pending_initial_attempts, 0);
+        let attempts = incremental.adaptive_refresh_attempts();
+        assert_eq!(incremental.try_refresh_candidates().attempts, attempts);
+
+        // The farther buckets are easily filled up by both, the closer ones are left to chance.
+        let eager_occupancy = eager.bucket_occupancy();
+        let incremental_occupancy = incremental.bucket_occupancy();
+        for ilog2 in 248..=255 {
+            assert_eq!(eager_occupancy[&ilog2], DEFAULT_MAX_PEERS_PER_BUCKET);
+            assert_eq!(incremental_occupancy[&ilog2], DEFAULT_MAX_PEERS_PER_BUCKET);
+        }
+        assert!(incremental.has_reached_coverage(INITIAL_COVERAGE_TARGET));
+        assert!(!incremental.has_reached_coverage(257));
+    }
+
+    #[test]
+    fn iteration_order_does_not_depend_on_the_insertion_order() {
+        let self_peer_id = PeerId::random();
+        let self_key = KBucketKey::from(self_peer_id);
+        let config = NetworkDiscoveryConfig {
+            initial_attempts: 0,
+            ..Default::default()
+        };
+
+        // a real peer for a few of the farther buckets, as if observed through a kad::GetClosestPeers query
+        let mut observed = BTreeMap::new();
+        while observed.len() < 4 {
+            let peer = PeerId::random();
+            if let Some(ilog2) = KBucketKey::from(peer).distance(&self_key).ilog2() {
+                let _ = observed.entry(ilog2).or_insert(peer);
+            }
+        }
+        let observed = observed.into_values().collect::<Vec<_>>();
+        let generated = [
+            (255, NetworkAddress::from_peer(PeerId::random())),
+            (10, NetworkAddress::from_peer(PeerId::random())),
+            (128, NetworkAddress::from_peer(PeerId::random())),
+        ];
+
+        let mut forward = NetworkDiscovery::with_config(&self_peer_id, config.clone());
+        for (ilog2, addr) in generated.iter() {
+            forward.insert_candidates(*ilog2, vec![addr.clone()]);
+        }
+        forward.handle_get_closest_query(
+            &untracked_target(),
+            observed.clone().into_iter().collect(),
+            |_| false,
+        );
+
+        let mut backward = NetworkDiscovery::with_config(&self_peer_id, config);
+        backward.handle_get_closest_query(
+            &untracked_target(),
+            observed.into_iter().rev().collect(),
+            |_| false,
+        );
+        for (ilog2, addr) in generated.iter().rev() {
+            backward.insert_candidates(*ilog2, vec![addr.clone()]);
+        }
+
+        let occupancy = forward.bucket_occupancy().into_keys().collect::<Vec<_>>();
+        assert!(occupancy.windows(2).all(|pair| pair[0] < pair[1]));
+        assert_eq!(
+            occupancy,
+            backward.bucket_occupancy().into_keys().collect::<Vec<_>>()
+        );
+        assert_eq!(
+            forward.query_plan(usize::MAX),
+            backward.query_plan(usize::MAX)
+        );
+        assert_eq!(forward.query_plan(3), backward.query_plan(3));
+        assert_eq!(forward.query_plan(1), vec![generated[1].1.clone()]);
+    }
+
+    #[test]
+    fn refresh_targeting_covers_the_requested_buckets() {
+        let self_peer_id = PeerId::random();
+        let config = NetworkDiscoveryConfig {
+            initial_attempts: 0,
+            ..Default::default()
+        };
+        let mut network_discovery = NetworkDiscovery::with_config(&self_peer_id, config);
+        assert_eq!(
+            network_discovery.missing_targeted_buckets(),
+            TARGETED_BUCKETS.collect::<Vec<_>>()
+        );
+
+        // 248 is hit once every 256 attempts on average, while 0 is unreachable
+        let uncovered = network_discovery.refresh_targeting(&[248, 255, 0]);
+        assert_eq!(uncovered, vec![0]);
+        assert_eq!(
+            network_discovery
+                .bucket_occupancy()
+                .into_keys()
+                .collect::<Vec<_>>(),
+            vec![248, 255]
+        );
+        assert!(!network_discovery.missing_targeted_buckets().contains(&248));
+
+        // the buckets that are full in our RT are not reported as missing
+        network_discovery.set_bucket_fill(HashMap::from([(245, K_VALUE.get())]));
+        assert!(!network_discovery.missing_targeted_buckets().contains(&245));
+
+        // the covered buckets are left untouched
+        let before = addresses(&network_discovery);
+        assert!(network_discovery.refresh_targeting(&[248, 255]).is_empty());
+        assert_eq!(addresses(&network_discovery), before);
+    }
+
+    #[test]
+    fn refresh_targeting_is_capped_per_call() {
+        let config = NetworkDiscoveryConfig {
+            initial_attempts: 0,
+            record_generation_histogram: true,
+            ..Default::default()
+        };
+        let mut network_discovery = NetworkDiscovery::with_config(&PeerId::random(), config);
+        let generated = |network_discovery: &NetworkDiscovery| {
+            network_discovery
+                .generation_histogram()
+                .values()
+                .sum::<u64>() as usize
+        };
+
+        // The unreachable bucket is given up on once the cap is reached, and retried by the next call.
+        assert_eq!(network_discovery.refresh_targeting(&[0]), vec![0]);
+        assert_eq!(
+            generated(&network_discovery),
+            MAX_TARGETED_GENERATION_ATTEMPTS
+        );
+        assert_eq!(network_discovery.refresh_targeting(&[0]), vec![0]);
+        assert_eq!(
+            generated(&network_discovery),
+            2 * MAX_TARGETED_GENERATION_ATTEMPTS
+        );
+    }
+
+    #[test]
+    fn snapshot_round_trips_the_observed_candidates_only() -> eyre::Result<()> {
+        let self_peer_id = PeerId::random();
+        let config = NetworkDiscoveryConfig {
+            initial_attempts: 1_000,
+            ..Default::default()
+        };
+        let mut network_discovery = NetworkDiscovery::with_config(&self_peer_id, config.clone());
+        let observed = (0..20).map(|_| PeerId::random()).collect::<Vec<_>>();
+        network_discovery.handle_get_closest_query(
+            &untracked_target(),
+            observed.into_iter().collect(),
+            |_| false,
+        );
+        let expected_observed = network_discovery
+            .candidates_by_source(|source| matches!(source, CandidateSource::Observed(_)))
+            .collect::<Vec<_>>();
+        assert!(!expected_observed.is_empty());
+
+        let snapshot = network_discovery.to_snapshot();
+        assert_eq!(snapshot.observed.len(), expected_observed.len());
+        let bytes = rmp_serde::to_vec(&snapshot)?;
+        let deserialized: NetworkDiscoverySnapshot = rmp_serde::from_slice(&bytes)?;
+        assert_eq!(deserialized, snapshot);
+
+        let restored = NetworkDiscovery::from_snapshot(&self_peer_id, config, deserialized);
+        assert_eq!(
+            restored
+                .candidates_by_source(|source| matches!(source, CandidateSource::Observed(_)))
+                .collect::<Vec<_>>(),
+            expected_observed
+        );
+        assert_eq!(
+            restored
+                .candidates_by_source(|source| *source == CandidateSource::Generated)
+                .count(),
+            0
+        );
+        Ok(())
+    }
+
+    #[test]
+    fn discovery_snapshot_restores_an_equivalent_pool() -> eyre::Result<()> {
+        let self_peer_id = PeerId::random();
+        let config = NetworkDiscoveryConfig {
+            initial_attempts: 0,
+            refresh_attempts: 1_000,
+            ..Default::default()
+        };
+        let mut network_discovery = NetworkDiscovery::with_config(&self_peer_id, config.clone());
+        let _ = network_discovery.try_refresh_candidates();
+        let _ =
+            network_discovery.insert_observed_peers((0..20).map(|_| PeerId::random()), |_| false);
+        let queried = network_discovery.query_plan(3);
+        for addr in &queried {
+            network_discovery.mark_queried(addr, Instant::now());
+        }
+        network_discovery.record_query_result(&queried[0], false);
+
+        let snapshot = network_discovery.snapshot();
+        let json = snapshot.to_json()?;
+        assert_eq!(DiscoverySnapshot::from_json(&json)?, snapshot);
+        let restored =
+            NetworkDiscovery::from_discovery_snapshot(config, DiscoverySnapshot::from_json(&json)?)
+                .expect("valid PeerId");
+
+        assert_eq!(restored.self_key, network_discovery.self_key);
+        assert_eq!(addresses(&restored), addresses(&network_discovery));
+        for (ilog2, candidates) in &network_discovery.candidates {
+            for (original, restored) in candidates.iter().zip(&restored.candidates[ilog2]) {
+                assert_eq!(restored.source, original.source);
+                assert_eq!(restored.failed_attempts, original.failed_attempts);
+                assert_eq!(
+                    restored.last_queried.is_some(),
+                    original.last_queried.is_some()
+                );
+                // The ages are carried over, give or take the time taken by the round-trip.
+                assert!(restored.inserted_at <= original.inserted_at + Duration::from_secs(1));
+                assert!(restored.inserted_at + Duration::from_secs(1) >= original.inserted_at);
+            }
+        }
+
+        let mut invalid = snapshot;
+        invalid.self_peer_id = vec![1, 2, 3];
+        assert!(NetworkDiscovery::from_discovery_snapshot(Default::default(), invalid).is_none());
+        Ok(())
+    }
+
+    #[test]
+    fn snapshot_of_a_previous_peer_id_is_rekeyed() -> eyre::Result<()> {
+        let config = NetworkDiscoveryConfig {
+            initial_attempts: 0,
+            refresh_attempts: 0,
+            max_peers_per_bucket: 1_000,
+            ..Default::default()
+        };
+        let mut network_discovery =
+            NetworkDiscovery::with_config(&PeerId::random(), config.clone());
+        network_discovery.handle_get_closest_query(
+            &untracked_target(),
+            (0..20).map(|_| PeerId::random()).collect(),
+            |_| false,
+        );
+
+        let dir = std::env::temp_dir().join(uuid::Uuid::new_v4().to_string());
+        fs::create_dir_all(&dir)?;
+        let path = dir.join(NETWORK_DISCOVERY_CANDIDATES_FILENAME);
+        network_discovery.set_candidates_file(path.clone());
+        let persist_task = network_discovery
+            .persist_task()
+            .ok_or_else(|| eyre::eyre!("the candidates should be persisted"))?;
+        persist_task();
+        let snapshot = NetworkDiscoverySnapshot::read_from_disk(&path)
+            .expect("the snapshot should have been persisted");
+        assert_eq!(snapshot, network_discovery.to_snapshot());
+
+        let new_peer_id = PeerId::random();
+        let new_key = KBucketKey::from(new_peer_id);
+        let restored = NetworkDiscovery::from_snapshot(&new_peer_id, config, snapshot.clone());
+        let restored = addresses(&restored);
+        for (ilog2, addrs) in &restored {
+            for addr in addrs {
+                assert_eq!(
+                    addr.as_kbucket_key().distance(&new_key).ilog2(),
+                    Some(*ilog2)
+                );
+            }
+        }
+        let restored = restored.into_values().flatten().collect::<HashSet<_>>();
+        assert_eq!(
+            restored,
+            snapshot.observed.into_iter().collect::<HashSet<_>>()
+        );
+
+        fs::remove_dir_all(dir)?;
+        Ok(())
+    }
+
+    #[test]
+    fn self_and_known_peers_are_not_turned_into_candidates() {
+        let self_peer_id = PeerId::random();
+        let config = NetworkDiscoveryConfig {
+            initial_attempts: 1_000,
+            ..Default::default()
+        };
+        let mut network_discovery = NetworkDiscovery::with_config(&self_peer_id, config);
+        let before = addresses(&network_discovery);
+
+        let known_peers = (0..10).map(|_| PeerId::random()).collect::<Vec<_>>();
+        let mut closest_peers = known_peers.clone();
+        closest_peers.push(self_peer_id);
+        network_discovery.handle_get_closest_query(
+            &untracked_target(),
+            closest_peers.clone().into_iter().collect(),
+            |peer| known_peers.contains(peer),
+        );
+        assert_eq!(addresses(&network_discovery), before);
+
+        // self is skipped even if it is not reported as known
+        network_discovery.handle_get_closest_query(
+            &untracked_target(),
+            HashSet::from_iter(vec![self_peer_id]),
+            |_| false,
+        );
+        assert_eq!(addresses(&network_discovery), before);
+
+        let unknown_peer = PeerId::random();
+        closest_peers.push(unknown_peer);
+        network_discovery.handle_get_closest_query(
+            &untracked_target(),
+            closest_peers.into_iter().collect(),
+            |peer| known_peers.contains(peer),
+        );
+        assert_eq!(
+            network_discovery
+                .candidates_by_source(|source| matches!(source, CandidateSource::Observed(_)))
+                .map(|(_ilog2, addr)| addr.clone())
+                .collect::<Vec<_>>(),
+            vec![NetworkAddress::from_peer(unknown_peer)]
+        );
+    }
+
+    #[test]
+    fn reachable_buckets_widen_as_attempts_increase() {
+        let self_peer_id = PeerId::random();
+        let config = NetworkDiscoveryConfig {
+            initial_attempts: 0,
+            ..Default::default()
+        };
+        let network_discovery = NetworkDiscovery::with_config(&self_peer_id, config);
+
+        assert!(network_discovery
+            .theoretical_reachable_buckets(0)
+            .is_empty());
+        assert!(network_discovery
+            .theoretical_reachable_buckets(1)
+            .is_empty());
+        assert_eq!(
+            network_discovery.theoretical_reachable_buckets(2),
+            255..=255
+        );
+        assert_eq!(
+            network_discovery.theoretical_reachable_buckets(DEFAULT_INITIAL_GENERATION_ATTEMPTS),
+            243..=255
+        );
+
+        let mut previous = network_discovery.theoretical_reachable_buckets(1);
+        for attempts in [10, 100, 1_000, 10_000, 100_000, usize::MAX] {
+            let reachable = network_discovery.theoretical_reachable_buckets(attempts);
+            assert!(reachable.start() < previous.start());
+            assert_eq!(*reachable.end(), 255);
+            previous = reachable;
+        }
+        // The closest buckets stay out of reach, whatever the budget.
+        assert!(*previous.start() > 128);
+    }
+
+    #[test]
+    fn newly_covered_buckets_are_reported() {
+        let self_peer_id = PeerId::random();
+        let self_key = KBucketKey::from(self_peer_id);
+        let config = NetworkDiscoveryConfig {
+            initial_attempts: 0,
+            ..Default::default()
+        };
+        let mut network_discovery = NetworkDiscovery::with_config(&self_peer_id, config);
+        let ilog2_of = |peer: &PeerId| KBucketKey::from(*peer).distance(&self_key).ilog2();
+
+        let peer = PeerId::random();
+        let newly_covered = network_discovery.handle_get_closest_query(
+            &untracked_target(),
+            HashSet::from_iter(vec![peer]),
+            |_| false,
+        );
+        assert_eq!(
+            newly_covered,
+            ilog2_of(&peer).into_iter().collect::<Vec<_>>()
+        );
+
+        // the same result again, and another peer from an already covered bucket
+        assert!(network_discovery
+            .handle_get_closest_query(&untracked_target(), HashSet::from_iter(vec![peer]), |_| {
+                false
+            })
+            .is_empty());
+        let same_bucket_peer = (0..1_000)
+            .map(|_| PeerId::random())
+            .find(|other| ilog2_of(other) == ilog2_of(&peer))
+            .expect("a peer of the same bucket should be found");
+        assert!(network_discovery
+            .handle_get_closest_query(
+                &untracked_target(),
+                HashSet::from_iter(vec![same_bucket_peer]),
+                |_| false
+            )
+            .is_empty());
+
+        // a result spanning a new bucket
+        let other_bucket_peer = (0..1_000)
+            .map(|_| PeerId::random())
+            .find(|other| ilog2_of(other) != ilog2_of(&peer))
+            .expect("a peer of another bucket should be found");
+        assert_eq!(
+            network_discovery.handle_get_closest_query(
+                &untracked_target(),
+                HashSet::from_iter(vec![peer, other_bucket_peer]),
+                |_| false
+            ),
+            ilog2_of(&other_bucket_peer).into_iter().collect::<Vec<_>>()
+        );
+    }
+
+    #[test]
+    fn refresh_effort_adapts_to_the_unsaturated_buckets() {
+        let self_peer_id = PeerId::random();
+        let config = NetworkDiscoveryConfig {
+            initial_attempts: 0,
+            refresh_attempts: 1_100,
+            ..Default::default()
+        };
+        let mut network_discovery = NetworkDiscovery::with_config(&self_peer_id, config);
+        let random_addrs = |count: usize| {
+            (0..count)
+                .map(|_| NetworkAddress::from_peer(PeerId::random()))
+                .collect::<Vec<_>>()
+        };
+
+        // every targeted bucket is empty
+        assert_eq!(network_discovery.adaptive_refresh_attempts(), 1_100);
+
+        for ilog2 in TARGETED_BUCKETS.skip(5) {
+            let _ = network_discovery
+                .insert_candidates(ilog2, random_addrs(DEFAULT_MAX_PEERS_PER_BUCKET));
+        }
+        assert_eq!(network_discovery.adaptive_refresh_attempts(), 500);
+
+        // the remaining ones are full in our RT
+        network_discovery.set_bucket_fill(
+            TARGETED_BUCKETS
+                .take(5)
+                .map(|ilog2| (ilog2, K_VALUE.get()))
+                .collect(),
+        );
+        assert_eq!(network_discovery.adaptive_refresh_attempts(), 0);
+        let before = addresses(&network_discovery);
+        assert_eq!(
+            network_discovery.try_refresh_candidates(),
+            CandidateGenerationStats::default()
+        );
+        assert_eq!(addresses(&network_discovery), before);
+        assert_eq!(network_discovery.stats().last_refresh_attempts, 0);
+        assert!(network_discovery.stats().since_last_refresh.is_some());
+
+        network_discovery.set_bucket_fill(Default::default());
+        let stats = network_discovery.try_refresh_candidates();
+        assert_eq!(stats.attempts, 500);
+        assert_eq!(network_discovery.stats().last_refresh_attempts, 500);
+    }
+
+    #[test]
+    fn removed_peer_is_no_longer_a_candidate() {
+        let self_peer_id = PeerId::random();
+        let config = NetworkDiscoveryConfig {
+            initial_attempts: 0,
+            ..Default::default()
+        };
+        let mut network_discovery = NetworkDiscovery::with_config(&self_peer_id, config);
+        let peer = PeerId::random();
+        let ilog2 = network_discovery.handle_get_closest_query(
+            &untracked_target(),
+            HashSet::from_iter(vec![peer]),
+            |_| false,
+        )[0];
+        assert!(network_discovery.has_candidates_for_bucket(ilog2));
+
+        assert!(!network_discovery.remove_peer(&PeerId::random()));
+        assert!(!network_discovery.remove_peer(&self_peer_id));
+        assert!(network_discovery.remove_peer(&peer));
+        assert!(!network_discovery.has_candidates_for_bucket(ilog2));
+        assert!(network_discovery.candidates.is_empty());
+        assert!(!network_discovery.remove_peer(&peer));
+    }
+
+    #[test]
+    fn seeded_peers_are_candidates_and_survive_the_population() {
+        let self_peer_id = PeerId::random();
+        let self_key = KBucketKey::from(self_peer_id);
+        let config = NetworkDiscoveryConfig {
+            initial_attempts: 0,
+            ..Default::default()
+        };
+        let mut network_discovery = NetworkDiscovery::with_config(&self_peer_id, config.clone());
+        let seed_peers = (0..3).map(|_| PeerId::random()).collect::<Vec<_>>();
+        network_discovery.seed(&seed_peers);
+
+        for peer in &seed_peers {
+            let ilog2 = KBucketKey::from(*peer)
+                .distance(&self_key)
+                .ilog2()
+                .expect("not self");
+            assert!(network_discovery
+                .candidates_for_bucket(ilog2)
+                .any(|addr| addr == &NetworkAddress::from_peer(*peer)));
+        }
+        let candidates = network_discovery.query_plan(usize::MAX);
+        assert_eq!(candidates.len(), seed_peers.len());
+        assert!(seed_peers
+            .iter()
+            .all(|peer| candidates.contains(&NetworkAddress::from_peer(*peer))));
+
+        // the population running in the background does not evict them from the farther buckets
+        let population = NetworkDiscovery::generate_candidates(
+            &self_key,
+            PeerIds::Random(1_000),
+            DEFAULT_MAX_PEERS_PER_BUCKET,
+            None,
+        );
+        network_discovery.populate_with(population);
+        assert!(seed_peers.iter().all(|peer| network_discovery
+            .candidates_by_source(|source| *source == CandidateSource::Observed(*peer))
+            .count()
+            == 1));
+        assert!(network_discovery
+            .bucket_occupancy()
+            .values()
+            .all(|held| *held <= DEFAULT_MAX_PEERS_PER_BUCKET));
+    }
+
+    #[test]
+    fn should_refresh_flips_at_the_thresholds() {
+        let self_peer_id = PeerId::random();
+        let config = NetworkDiscoveryConfig {
+            initial_attempts: 0,
+            refresh_attempts: 0,
+            ..Default::default()
+        };
+        let mut network_discovery = NetworkDiscovery::with_config(&self_peer_id, config);
+        assert!(network_discovery.should_refresh());
+        assert_eq!(network_discovery.coverage_ratio(), 0.0);
+
+        for ilog2 in TARGETED_BUCKETS {
+            let addrs = (0..DEFAULT_MAX_PEERS_PER_BUCKET)
+                .map(|_| NetworkAddress::from_peer(PeerId::random()))
+                .collect();
+            let _ = network_discovery.insert_candidates(ilog2, addrs);
+        }
+        let _ = network_discovery.try_refresh_candidates();
+        assert!(!network_discovery.should_refresh());
+        assert_eq!(network_discovery.coverage_ratio(), 1.0);
+
+        // depleting a bucket leaves a single candidate that has not been queried yet. The random addresses do not
+        // belong to the buckets they are inserted into, hence they are marked as queried directly.
+        let deplete = |network_discovery: &mut NetworkDiscovery, ilog2: u32| {
+            let now = Instant::now();
+            for candidate in network_discovery
+                .candidates
+                .get_mut(&ilog2)
+                .into_iter()
+                .flatten()
+                .skip(1)
+            {
+                candidate.last_queried = Some(now);
+            }
+        };
+        for ilog2 in TARGETED_BUCKETS.take(MAX_DEPLETED_BUCKETS) {
+            deplete(&mut network_discovery, ilog2);
+        }
+        assert!(!network_discovery.should_refresh());
+        deplete(&mut network_discovery, *TARGETED_BUCKETS.end());
+        assert!(network_discovery.should_refresh());
+
+        // the depleted buckets that are full in our RT do not matter
+        network_discovery
+            .set_bucket_fill(HashMap::from([(*TARGETED_BUCKETS.end(), K_VALUE.get())]));
+        assert!(!network_discovery.should_refresh());
+
+        // half of the 55 candidates have been consumed, but not more. The candidates are consumed round-robin, so
+        // that each bucket keeps at least 2 remaining candidates.
+        let _ = network_discovery.try_refresh_candidates();
+        let buckets = addresses(&network_discovery);
+        let addrs = (0..3)
+            .flat_map(|index| buckets.values().map(move |addrs| addrs[index].clone()))
+            .collect::<Vec<_>>();
+        for addr in addrs.iter().take(27) {
+            network_discovery.take_candidate(addr, Instant::now());
+        }
+        assert!(!network_discovery.should_refresh());
+        network_discovery.take_candidate(&addrs[27], Instant::now());
+        assert!(network_discovery.should_refresh());
+
+        // a refresh resets the consumption
+        let _ = network_discovery.try_refresh_candidates();
+        assert!(!network_discovery.should_refresh());
+    }
+
+    #[test]
+    fn generation_stops_once_the_wanted_buckets_are_satisfied() {
+        let self_peer_id = PeerId::random();
+        let self_key = KBucketKey::from(self_peer_id);
+
+        // every bucket is wanted, as without any saturation
+        let all = (0..=255)
+            .map(|ilog2| (ilog2, DEFAULT_MAX_PEERS_PER_BUCKET))
+            .collect::<BTreeMap<_, _>>();
+        let (candidates, generated) = NetworkDiscovery::generate_wanted_candidates(
+            &self_key,
+            PeerIds::Random(10_000),
+            &all,
+            None,
+        );
+        assert_eq!(generated, 10_000);
+        for ilog2 in 250..=255 {
+            assert_eq!(candidates[&ilog2].len(), DEFAULT_MAX_PEERS_PER_BUCKET);
+        }
+        assert!(candidates.iter().all(|(ilog2, addrs)| addrs.len()
+            <= DEFAULT_MAX_PEERS_PER_BUCKET
+            && addrs
+                .iter()
+                .all(|addr| addr.as_kbucket_key().distance(&self_key).ilog2() == Some(*ilog2))));
+
+        // only the farthest buckets are wanted, they are hit by 3 out of 4 PeerIds
+        let wanted = BTreeMap::from([(254, 2), (255, 1)]);
+        let (candidates, generated) = NetworkDiscovery::generate_wanted_candidates(
+            &self_key,
+            PeerIds::Random(10_000),
+            &wanted,
+            None,
+        );
+        assert_eq!(
+            candidates
+                .iter()
+                .map(|(ilog2, addrs)| (*ilog2, addrs.len()))
+                .collect::<BTreeMap<_, _>>(),
+            wanted
+        );
+        assert!(generated < 1_000, "generated {generated} PeerIds");
+
+        // nothing is generated once every bucket is full
+        let config = NetworkDiscoveryConfig {
+            initial_attempts: 0,
+            ..Default::default()
+        };
+        let mut network_discovery = NetworkDiscovery::with_config(&self_peer_id, config);
+        network_discovery.set_bucket_fill((0..=255).map(|ilog2| (ilog2, K_VALUE.get())).collect());
+        assert!(network_discovery.wanted_buckets().is_empty());
+        assert_eq!(
+            NetworkDiscovery::generate_wanted_candidates(
+                &self_key,
+                PeerIds::Random(10_000),
+                &BTreeMap::new(),
+                None
+            ),
+            (BTreeMap::new(), 0)
+        );
+    }
+
+    #[test]
+    fn full_bucket_is_skipped_until_it_has_room() {
+        let self_peer_id = PeerId::random();
+        let config = NetworkDiscoveryConfig {
+            initial_attempts: 0,
+            refresh_attempts: 1_000,
+            ..Default::default()
+        };
+        let mut network_discovery = NetworkDiscovery::with_config(&self_peer_id, config);
+        let _ = network_discovery.try_refresh_candidates();
+        let bucket = network_discovery
+            .candidates_for_bucket(255)
+            .cloned()
+            .collect::<Vec<_>>();
+        let in_candidates = |network_discovery: &NetworkDiscovery| {
+            network_discovery
+                .query_plan(usize::MAX)
+                .iter()
+                .any(|addr| bucket.contains(addr))
+        };
+        assert!(in_candidates(&network_discovery));
+
+        network_discovery.notify_bucket_full(255);
+        assert!(!in_candidates(&network_discovery));
+        assert!(!network_discovery.wanted_buckets().contains_key(&255));
+        // the candidates are retained
+        assert_eq!(
+            network_discovery
+                .candidates_for_bucket(255)
+                .cloned()
+                .collect::<Vec<_>>(),
+            bucket
+        );
+
+        network_discovery.notify_bucket_has_room(255);
+        assert!(in_candidates(&network_discovery));
+        assert_eq!(network_discovery.bucket_fill_of(255), K_VALUE.get() - 1);
+
+        // a bucket which was not full is left untouched
+        network_discovery.notify_bucket_has_room(254);
+        assert_eq!(network_discovery.bucket_fill_of(254), 0);
+    }
+
+    #[test]
+    fn selection_is_weighted_by_the_emptiness_of_the_rt_buckets() {
+        let self_peer_id = PeerId::random();
+        let config = NetworkDiscoveryConfig {
+            initial_attempts: 0,
+            refresh_attempts: 1_000,
+            ..Default::default()
+        };
+        let mut network_discovery = NetworkDiscovery::with_config(&self_peer_id, config);
+        let _ = network_discovery.try_refresh_candidates();
+        let addresses = addresses(&network_discovery);
+        assert!(addresses[&255].len() > 1 && addresses[&254].len() > 1);
+
+        // only the buckets 254 and 255 have room, the former being nearly full
+        let mut fill = (0..=255)
+            .map(|ilog2| (ilog2, K_VALUE.get()))
+            .collect::<HashMap<_, _>>();
+        let _ = fill.insert(255, 0);
+        let _ = fill.insert(254, K_VALUE.get() - 1);
+        network_discovery.set_bucket_fill(fill);
+
+        let mut rng = StdRng::seed_from_u64(7);
+        let mut picks = BTreeMap::<u32, usize>::new();
+        for _ in 0..200 {
+            let selected = network_discovery.select_weighted(1, &mut rng);
+            assert_eq!(selected.len(), 1);
+            let ilog2 = selected[0]
+                .as_kbucket_key()
+                .distance(&network_discovery.self_key)
+                .ilog2()
+                .expect("not self");
+            *picks.entry(ilog2).or_default() += 1;
+        }
+        assert_eq!(picks.keys().copied().collect::<Vec<_>>(), vec![254, 255]);
+        assert!(picks[&255] > picks[&254] * 4, "{picks:?}");
+
+        // a bucket provides several targets, but never a full RT bucket, nor the same candidate twice
+        let selected = network_discovery.select_weighted(usize::MAX, &mut rng);
+        assert_eq!(
+            selected.len(),
+            addresses[&255].len() + addresses[&254].len()
+        );
+        assert_eq!(
+            selected.iter().collect::<BTreeSet<_>>().len(),
+            selected.len()
+        );
+        assert!(selected
+            .iter()
+            .all(|addr| addresses[&255].contains(addr) || addresses[&254].contains(addr)));
+    }
+
+    #[test]
+    fn selection_is_reproducible_and_prefers_unqueried_candidates() {
+        let self_peer_id = PeerId::random();
+        let config = NetworkDiscoveryConfig {
+            initial_attempts: 0,
+            refresh_attempts: 1_000,
+            ..Default::default()
+        };
+        let mut network_discovery = NetworkDiscovery::with_config(&self_peer_id, config);
+        let _ = network_discovery.try_refresh_candidates();
+
+        let select = |network_discovery: &NetworkDiscovery, seed| {
+            network_discovery.select_weighted(8, &mut StdRng::seed_from_u64(seed))
+        };
+        let selected = select(&network_discovery, 42);
+        assert_eq!(selected.len(), 8);
+        assert_eq!(select(&network_discovery, 42), selected);
+
+        // the queried candidates are only picked again once the fresh ones of their bucket are exhausted
+        let queried = addresses(&network_discovery)[&255][0].clone();
+        network_discovery.mark_queried(&queried, Instant::now());
+        for seed in 0..20 {
+            let selected = select(&network_discovery, seed);
+            let from_255 = selected
+                .iter()
+                .take_while(|addr| **addr != queried)
+                .filter(|addr| {
+                    network_discovery
+                        .candidates_for_bucket(255)
+                        .any(|c| c == *addr)
+                })
+                .count();
+            if selected.contains(&queried) {
+                assert_eq!(
+                    from_255,
+                    network_discovery.candidates_for_bucket(255).count() - 1
+                );
+            }
+        }
+    }
+
+    #[test]
+    fn seeded_instances_have_the_exact_same_coverage() {
+        let self_peer_id = peer_id_from_rng(&mut StdRng::seed_from_u64(0));
+        let seeded =
+            |seed| NetworkDiscovery::new_with_rng(&self_peer_id, StdRng::seed_from_u64(seed));
+
+        let mut first = seeded(42);
+        let mut second = seeded(42);
+        let expected = [
+            (243, 1),
+            (245, 1),
+            (246, 4),
+            (247, 5),
+            (248, 5),
+            (249, 5),
+            (250, 5),
+            (251, 5),
+            (252, 5),
+            (253, 5),
+            (254, 5),
+            (255, 5),
+        ]
+        .into_iter()
+        .collect::<BTreeMap<_, _>>();
+        assert_eq!(first.bucket_occupancy(), expected);
+        assert_eq!(addresses(&first), addresses(&second));
+        assert_ne!(addresses(&seeded(7)), addresses(&first));
+
+        // the refreshes keep drawing from the same rng
+        for network_discovery in [&mut first, &mut second] {
+            let peer = addresses(network_discovery)[&255][0]
+                .as_peer_id()
+                .expect("a peer address");
+            assert!(network_discovery.remove_peer(&peer));
+        }
+        assert_eq!(
+            first.try_refresh_candidates(),
+            second.try_refresh_candidates()
+        );
+        assert_eq!(addresses(&first), addresses(&second));
+        assert_eq!(
+            first.refresh_targeting(&[243]),
+            second.refresh_targeting(&[243])
+        );
+        assert_eq!(addresses(&first), addresses(&second));
+    }
+
+    #[test]
+    fn seeded_peers_do_not_depend_on_the_parallelism() {
+        let peers = PeerIds::new(1_000, Some(&mut StdRng::seed_from_u64(42)));
+        let sequential = peers.clone().into_iter().collect::<Vec<_>>();
+        assert_eq!(sequential.len(), 1_000);
+        assert_eq!(sequential.iter().collect::<BTreeSet<_>>().len(), 1_000);
+        assert_eq!(peers.into_par_iter().collect::<Vec<_>>(), sequential);
+
+        let thread_pool = rayon::ThreadPoolBuilder::new()
+            .num_threads(1)
+            .build()
+            .expect("Failed to build the thread pool");
+        let peers = PeerIds::new(1_000, Some(&mut StdRng::seed_from_u64(42)));
+        assert_eq!(
+            thread_pool.install(|| peers.into_par_iter().collect::<Vec<_>>()),
+            sequential
+        );
+    }
+
+    #[test]
+    fn candidate_bytes_have_the_distance_of_their_network_address() {
+        let self_peer_id = PeerId::random();
+        let self_key = KBucketKey::from(self_peer_id);
+        let self_address = NetworkAddress::from_peer(self_peer_id);
+        let mut rng = StdRng::seed_from_u64(42);
+        for _ in 0..1_000 {
+            let peer = CandidatePeerId::from_rng(&mut rng);
+            let addr = peer.into_address();
+            assert_eq!(addr.as_peer_id(), Some(peer.into_peer_id()));
+            assert_eq!(peer.ilog2(&self_key), addr.distance(&self_address).ilog2());
+            assert_eq!(
+                peer.ilog2(&self_key),
+                addr.as_kbucket_key().distance(&self_key).ilog2()
+            );
+        }
+    }
+
+    #[test]
+    fn query_plan_respects_the_budget_and_favours_the_closest_buckets() {
+        let self_peer_id = PeerId::random();
+        let config = NetworkDiscoveryConfig {
+            initial_attempts: 0,
+            refresh_attempts: 1_000,
+            ..Default::default()
+        };
+        let mut network_discovery = NetworkDiscovery::with_config(&self_peer_id, config);
+        let _ = network_discovery.try_refresh_candidates();
+        let addresses = addresses(&network_discovery);
+        let total = addresses.values().map(Vec::len).sum::<usize>();
+        let closest = *addresses.keys().next().expect("some buckets are covered");
+        network_discovery.notify_bucket_full(255);
+
+        for budget in [0, 1, addresses.len(), total, total + 10] {
+            let plan = network_discovery.query_plan(budget);
+            let expected_len = budget.min(total - addresses[&255].len());
+            assert_eq!(plan.len(), expected_len, "budget {budget}");
+            assert_eq!(plan.iter().collect::<BTreeSet<_>>().len(), plan.len());
+            assert!(plan.iter().all(|addr| !addresses[&255].contains(addr)));
+            if budget > 0 {
+                assert!(addresses[&closest].contains(&plan[0]));
+            }
+        }
+
+        // each bucket gets a candidate before any bucket gets a second one
+        let plan = network_discovery.query_plan(addresses.len() - 1);
+        let planned_buckets = plan
+            .iter()
+            .filter_map(|addr| {
+                addresses
+                    .iter()
+                    .find(|(_, candidates)| candidates.contains(addr))
+                    .map(|(ilog2, _)| *ilog2)
+            })
+            .collect::<Vec<_>>();
+        let expected_buckets = addresses.keys().copied().filter(|ilog2| *ilog2 != 255);
+        assert_eq!(planned_buckets, expected_buckets.collect::<Vec<_>>());
+    }
+
+    #[test]
+    fn total_candidates_cap_evicts_from_the_fullest_and_farthest_buckets() {
+        let self_peer_id = PeerId::random();
+        let config = NetworkDiscoveryConfig {
+            initial_attempts: 0,
+            max_total_candidates: 5,
+            ..Default::default()
+        };
+        let mut network_discovery = NetworkDiscovery::with_config(&self_peer_id, config);
+        let bucket_addresses = |n| {
+            (0..n)
+                .map(|_| NetworkAddress::from_peer(PeerId::random()))
+                .collect::<Vec<_>>()
+        };
+        for ilog2 in [250, 251, 252] {
+            let _ = network_discovery.insert_candidates(ilog2, bucket_addresses(3));
+        }
+        let before = addresses(&network_discovery);
+
+        // the cap is enforced once the results of a query are handled
+        let _ = network_discovery.handle_get_closest_query(
+            &untracked_target(),
+            HashSet::from_iter(vec![]),
+            |_| false,
+        );
+        assert_eq!(network_discovery.stats().total_candidates, 5);
+        assert_eq!(
+            network_discovery.bucket_occupancy(),
+            BTreeMap::from([(250, 2), (251, 2), (252, 1)])
+        );
+        // the oldest candidates are the ones evicted
+        for (ilog2, candidates) in addresses(&network_discovery) {
+            assert!(before[&ilog2].ends_with(&candidates));
+        }
+
+        // as well as after a refresh
+        network_discovery.refresh_attempts = 1_000;
+        let _ = network_discovery.try_refresh_candidates();
+        assert_eq!(network_discovery.stats().total_candidates, 5);
+        assert!(network_discovery
+            .bucket_occupancy()
+            .values()
+            .all(|n| *n <= 2));
+    }
+
+    #[test]
+    fn empty_buckets_include_the_missing_and_the_emptied_ones() {
+        let self_peer_id = PeerId::random();
+        let config = NetworkDiscoveryConfig {
+            initial_attempts: 0,
+            ..Default::default()
+        };
+        let mut network_discovery = NetworkDiscovery::with_config(&self_peer_id, config);
+        assert_eq!(
+            network_discovery.empty_buckets(250..=255),
+            vec![250, 251, 252, 253, 254, 255]
+        );
+
+        for ilog2 in [251, 253, 255] {
+            let peer = NetworkAddress::from_peer(PeerId::random());
+            let _ = network_discovery.insert_candidates(ilog2, vec![peer]);
+        }
+        // a bucket that is still in the map, but holds no candidate anymore
+        let _ = network_discovery.candidates.insert(252, Vec::new());
+
+        assert_eq!(
+            network_discovery.empty_buckets(250..=255),
+            vec![250, 252, 254]
+        );
+        assert!(network_discovery.empty_buckets(253..=253).is_empty());
+        assert_eq!(network_discovery.empty_buckets(0..=1), vec![0, 1]);
+    }
+
+    #[test]
+    fn queried_candidate_is_rotated_whatever_its_result() {
+        let self_peer_id = PeerId::random();
+        let config = NetworkDiscoveryConfig {
+            initial_attempts: 0,
+            refresh_attempts: 1_000,
+            ..Default::default()
+        };
+        let mut network_discovery = NetworkDiscovery::with_config(&self_peer_id, config);
+        let _ = network_discovery.try_refresh_candidates();
+        let mut others_before = addresses(&network_discovery);
+        let before = others_before.remove(&255).unwrap_or_default();
+        assert!(before.len() >= 3);
+
+        // a useful result moves the target to the back, the others keep their order
+        let target = before[1].clone();
+        let returned = HashSet::from([PeerId::random()]);
+        let _ = network_discovery.handle_get_closest_query(&target, returned, |_| true);
+        let mut expected = before.clone();
+        let target = expected.remove(1);
+        expected.push(target.clone());
+        assert_eq!(addresses(&network_discovery)[&255], expected);
+        assert_eq!(network_discovery.bucket_successes.get(&255), Some(&1));
+
+        // an empty result rotates the target as well, without crediting its bucket
+        let target = expected.remove(0);
+        expected.push(target.clone());
+        let _ = network_discovery.handle_get_closest_query(&target, HashSet::new(), |_| true);
+        assert_eq!(addresses(&network_discovery)[&255], expected);
+        assert_eq!(network_discovery.bucket_successes.get(&255), Some(&1));
+
+        // the other buckets are untouched
+        let mut after = addresses(&network_discovery);
+        let _ = after.remove(&255);
+        assert_eq!(after, others_before);
+    }
+
+    #[test]
+    fn successful_buckets_are_favoured_by_the_selection() {
+        let self_peer_id = PeerId::random();
+        let config = NetworkDiscoveryConfig {
+            initial_attempts: 0,
+            refresh_attempts: 1_000,
+            ..Default::default()
+        };
+        let mut network_discovery = NetworkDiscovery::with_config(&self_peer_id, config);
+        let _ = network_discovery.try_refresh_candidates();
+        let addresses = addresses(&network_discovery);
+        let mut fill = (0..=255)
+            .map(|ilog2| (ilog2, K_VALUE.get()))
+            .collect::<HashMap<_, _>>();
+        let _ = fill.insert(255, 0);
+        let _ = fill.insert(254, 0);
+        network_discovery.set_bucket_fill(fill);
+
+        let picks_of_254 = |network_discovery: &NetworkDiscovery| {
+            let mut rng = StdRng::seed_from_u64(7);
+            (0..400)
+                .filter(|_| {
+                    let selected = network_discovery.select_weighted(1, &mut rng);
+                    addresses[&254].contains(&selected[0])
+                })
+                .count()
+        };
+        let unboosted = picks_of_254(&network_discovery);
+
+        // the boost is capped
+        for _ in 0..10 {
+            let target = addresses[&254][0].clone();
+            let returned = HashSet::from([PeerId::random()]);
+            let _ = network_discovery.handle_get_closest_query(&target, returned, |_| true);
+        }
+        let boosted = picks_of_254(&network_discovery);
+        assert!(boosted > unboosted, "{boosted} <= {unboosted}");
+        assert!(boosted < 400);
+    }
+
+    #[cfg(feature = "open-metrics")]
+    #[test]
+    fn metrics_follow_the_generation_and_the_queries() -> eyre::Result<()> {
+        use prometheus_client::{encoding::text::encode, registry::Registry};
+
+        let mut registry = Registry::default();
+        let metrics = NetworkDiscoveryMetrics::register(&mut registry);
+        let config = NetworkDiscoveryConfig {
+            initial_attempts: 0,
+            refresh_attempts: 1_000,
+            ..Default::default()
+        };
+        let mut network_discovery = NetworkDiscovery::with_config(&PeerId::random(), config);
+        network_discovery.set_metrics(metrics.clone());
+        assert_eq!(metrics.buckets_covered.get(), 0);
+
+        let stats = network_discovery.try_refresh_candidates();
+        assert!(stats.accepted > 0);
+        assert_eq!(metrics.candidates_generated.get(), stats.accepted as u64);
+        assert_eq!(
+            metrics.buckets_covered.get(),
+            network_discovery.stats().buckets_covered as i64
+        );
+
+        // Generating the same candidates again only produces duplicates.
+        let generated = addresses(&network_discovery);
+        let (ilog2, candidates) = generated.iter().next().expect("not empty");
+        let _ = network_discovery.insert_candidates(*ilog2, candidates.clone());
+        assert_eq!(metrics.duplicate_candidates.get(), candidates.len() as u64);
+
+        let _ = network_discovery.handle_get_closest_query(
+            &candidates[0],
+            HashSet::from([PeerId::random()]),
+            |_| false,
+        );
+
+        let mut encoded = String::new();
+        encode(&mut encoded, &registry)?;
+        assert!(encoded.contains("network_discovery_refresh_duration_seconds_count 1"));
+        assert!(encoded.contains("network_discovery_closest_query_duration_seconds_count 1"));
+        assert!(encoded.contains("network_discovery_buckets_covered"));
+
+        let uncovered = network_discovery.check_coverage();
+        assert_eq!(metrics.uncovered_buckets.get(), uncovered.len() as i64);
+        Ok(())
+    }
+
+    #[test]
+    fn first_population_of_each_bucket_is_notified_exactly_once() {
+        let (sender, mut receiver) = mpsc::channel(256);
+        let self_peer_id = PeerId::random();
+        let config = NetworkDiscoveryConfig {
+            initial_attempts: 0,
+            refresh_attempts: 1_000,
+            ..Default::default()
+        };
+        let mut network_discovery = NetworkDiscovery::with_config(&self_peer_id, config);
+        network_discovery.set_populated_bucket_sender(sender);
+        let drain = |receiver: &mut mpsc::Receiver<u32>| {
+            std::iter::from_fn(|| receiver.try_recv().ok()).collect::<Vec<_>>()
+        };
+
+        let _ = network_discovery.try_refresh_candidates();
+        let mut notified = drain(&mut receiver);
+        let covered = addresses(&network_discovery)
+            .into_keys()
+            .collect::<Vec<_>>();
+        notified.sort();
+        assert_eq!(notified, covered);
+
+        // Refilling the same buckets does not notify them again.
+        let _ = network_discovery.try_refresh_candidates();
+        let notified_again = drain(&mut receiver);
+        assert!(notified_again.iter().all(|ilog2| !covered.contains(ilog2)));
+
+        // Nor does a bucket that is emptied and then populated again.
+        let ilog2 = *covered.last().expect("not empty");
+        for addr in addresses(&network_discovery)
+            .remove(&ilog2)
+            .unwrap_or_default()
+        {
+            for _ in 0..MAX_CONSECUTIVE_QUERY_FAILURES {
+                network_discovery.record_query_result(&addr, false);
+            }
+        }
+        assert!(!network_discovery.has_candidates_for_bucket(ilog2));
+        let peer = std::iter::repeat_with(PeerId::random)
+            .find(|peer| {
+                KBucketKey::from(*peer)
+                    .distance(&network_discovery.self_key)
+                    .ilog2()
+                    == Some(ilog2)
+            })
+            .expect("infinite iterator");
+        let _ = network_discovery.insert_observed_peers([peer], |_| false);
+        assert!(network_discovery.has_candidates_for_bucket(ilog2));
+        assert!(drain(&mut receiver).is_empty());
+    }
+
+    #[test]
+    fn rekeyed_candidates_are_rebucketed_against_the_new_key() {
+        let config = NetworkDiscoveryConfig {
+            initial_attempts: 2_000,
+            refresh_attempts: 500,
+            ..Default::default()
+        };
+        let mut network_discovery = NetworkDiscovery::with_config(&PeerId::random(), config);
+        let observed = (0..30).map(|_| PeerId::random()).collect::<Vec<_>>();
+        let _ = network_discovery.insert_observed_peers(observed.iter().copied(), |_| false);
+        let taken = network_discovery.take_candidates(3, CandidateSelection::Closest);
+        assert!(taken
+            .iter()
+            .all(|addr| network_discovery.is_in_flight(addr)));
+        let before = addresses(&network_discovery)
+            .into_values()
+            .flatten()
+            .collect::<HashSet<_>>();
+
+        // The new PeerId is one of our candidates, it must not be kept as a candidate of itself.
+        let new_peer_id = *observed
+            .iter()
+            .find(|peer| before.contains(&NetworkAddress::from_peer(**peer)))
+            .expect("some observed peers are retained");
+        let new_key = KBucketKey::from(new_peer_id);
+        let stats = network_discovery.rekey(&new_peer_id);
+        assert_eq!(stats.attempts, 500);
+        assert!(taken
+            .iter()
+            .all(|addr| !network_discovery.is_in_flight(addr)));
+
+        let after = addresses(&network_discovery);
+        for (ilog2, addrs) in &after {
+            assert!(addrs.len() <= DEFAULT_MAX_PEERS_PER_BUCKET);
+            for addr in addrs {
+                assert_eq!(
+                    addr.as_kbucket_key().distance(&new_key).ilog2(),
+                    Some(*ilog2)
+                );
+            }
+        }
+        let after = after.into_values().flatten().collect::<HashSet<_>>();
+        assert!(!after.contains(&NetworkAddress::from_peer(new_peer_id)));
+        // Only the observed candidates were re-binned, the generated ones were generated again.
+        let retained = before.intersection(&after).cloned().collect::<HashSet<_>>();
+        assert!(!retained.is_empty());
+        assert!(retained.iter().all(|addr| observed
+            .iter()
+            .any(|peer| *addr == NetworkAddress::from_peer(*peer))));
+        assert_eq!(after.len() - retained.len(), stats.accepted);
+        assert_eq!(
+            network_discovery
+                .candidates_by_source(|source| *source == CandidateSource::Generated)
+                .count(),
+            stats.accepted
+        );
+        // The buckets holding observed candidates after the move are not generated for.
+        let observed_buckets = network_discovery
+            .candidates_by_source(|source| matches!(source, CandidateSource::Observed(_)))
+            .map(|(ilog2, _)| ilog2)
+            .collect::<HashSet<_>>();
+        assert!(network_discovery
+            .candidates_by_source(|source| *source == CandidateSource::Generated)
+            .all(|(ilog2, _)| !observed_buckets.contains(&ilog2)));
+    }
+
+    #[test]
+    fn rekeyed_observed_peers_land_in_their_recomputed_buckets() {
+        let config = NetworkDiscoveryConfig {
+            initial_attempts: 0,
+            refresh_attempts: 0,
+            ..Default::default()
+        };
+        let mut network_discovery = NetworkDiscovery::with_config(&PeerId::random(), config);
+        // No more than max_peers_per_bucket of them, so that none can be evicted by the move.
+        let observed = (0..DEFAULT_MAX_PEERS_PER_BUCKET)
+            .map(|_| PeerId::random())
+            .collect::<Vec<_>>();
+        let _ = network_discovery.insert_observed_peers(observed.iter().copied(), |_| false);
+        let _ = network_discovery
+            .insert_candidates(200, vec![NetworkAddress::from_peer(PeerId::random())]);
+
+        let new_peer_id = PeerId::random();
+        let new_key = KBucketKey::from(new_peer_id);
+        let _ = network_discovery.rekey(&new_peer_id);
+
+        let mut expected = BTreeMap::<u32, Vec<NetworkAddress>>::new();
+        for peer in &observed {
+            let addr = NetworkAddress::from_peer(*peer);
+            let ilog2 = addr
+                .as_kbucket_key()
+                .distance(&new_key)
+                .ilog2()
+                .expect("not self");
+            expected.entry(ilog2).or_default().push(addr);
+        }
+        let mut after = addresses(&network_discovery);
+        for addrs in after.values_mut() {
+            addrs.sort_by_key(|addr| addr.as_bytes());
+        }
+        for addrs in expected.values_mut() {
+            addrs.sort_by_key(|addr| addr.as_bytes());
+        }
+        assert_eq!(after, expected);
+    }
+
+    #[test]
+    fn only_the_closest_valid_peers_of_a_query_are_ingested() {
+        let self_peer_id = PeerId::random();
+        let config = NetworkDiscoveryConfig {
+            initial_attempts: 0,
+            max_peers_per_bucket: 1_000,
+            max_observed_peers_per_query: 20,
+            ..Default::default()
+        };
+        let mut network_discovery = NetworkDiscovery::with_config(&self_peer_id, config.clone());
+        let returned = (0..1_000).map(|_| PeerId::random()).collect::<HashSet<_>>();
+        let mut by_distance = returned.iter().copied().collect::<Vec<_>>();
+        by_distance
+            .sort_by_key(|peer| KBucketKey::from(*peer).distance(network_discovery.self_key()));
+        let closest = by_distance[..20]
+            .iter()
+            .map(|peer| NetworkAddress::from_peer(*peer))
+            .collect::<HashSet<_>>();
+
+        let target = NetworkAddress::from_peer(PeerId::random());
+        let _ = network_discovery.handle_get_closest_query(&target, returned.clone(), |_| false);
+        let ingested = network_discovery
+            .candidates
+            .values()
+            .flatten()
+            .map(|candidate| candidate.addr.clone())
+            .collect::<HashSet<_>>();
+        assert_eq!(ingested, closest);
+
+        // The peers rejected by the validator never make it into the candidates.
+        let mut network_discovery = NetworkDiscovery::with_config(
+            &self_peer_id,
+            NetworkDiscoveryConfig {
+                observed_peer_validator: Some(|_| false),
+                ..config
+            },
+        );
+        let _ = network_discovery.handle_get_closest_query(&target, returned, |_| false);
+        assert!(network_discovery.candidates.is_empty());
+    }
+
+    #[test]
+    fn flood_of_observed_peers_keeps_the_pool_bounded_and_the_near_buckets() {
+        let self_peer_id = PeerId::random();
+        let config = NetworkDiscoveryConfig {
+            initial_attempts: 0,
+            max_total_candidates: 20,
+            ..Default::default()
+        };
+        let mut network_discovery = NetworkDiscovery::with_config(&self_peer_id, config);
+        let near_buckets = 240..=244;
+        for ilog2 in near_buckets.clone() {
+            let addrs = (0..3)
+                .map(|_| NetworkAddress::from_peer(PeerId::random()))
+                .collect();
+            let _ = network_discovery.insert_candidates(ilog2, addrs);
+        }
+
+        for _ in 0..100 {
+            let flood = (0..100).map(|_| PeerId::random()).collect::<HashSet<_>>();
+            let target = NetworkAddress::from_peer(PeerId::random());
+            let _ = network_discovery.handle_get_closest_query(&target, flood, |_| false);
+            assert!(network_discovery.stats().total_candidates <= 20);
+        }
+        let occupancy = network_discovery.bucket_occupancy();
+        assert_eq!(occupancy.values().sum::<usize>(), 20);
+        for ilog2 in near_buckets {
+            assert!(
+                occupancy.get(&ilog2).is_some_and(|n| *n >= 1),
+                "{occupancy:?}"
+            );
+        }
+    }
+
+    #[test]
+    fn network_size_estimate_bounds_the_close_buckets() {
+        let self_peer_id = PeerId::random();
+        let config = NetworkDiscoveryConfig {
+            initial_attempts: 0,
+            refresh_attempts: 5_000,
+            ..Default::default()
+        };
+        let mut network_discovery = NetworkDiscovery::with_config(&self_peer_id, config);
+        let close_candidate = NetworkAddress::from_peer(PeerId::random());
+        let _ = network_discovery.insert_candidates(240, vec![close_candidate.clone()]);
+
+        // 25 nodes are not expected to be any closer than the bucket 249.
+        network_discovery.set_network_size_estimate(25);
+        let _ = network_discovery.try_refresh_candidates();
+        let occupancy = network_discovery.bucket_occupancy();
+        assert!(occupancy.keys().all(|ilog2| *ilog2 >= 249 || *ilog2 == 240));
+        assert!(occupancy.keys().any(|ilog2| *ilog2 >= 249));
+        assert!(!network_discovery
+            .select_weighted(usize::MAX, &mut rand::thread_rng())
+            .contains(&close_candidate));
+        assert!(!network_discovery
+            .query_plan(usize::MAX)
+            .contains(&close_candidate));
+        assert!(network_discovery
+            .missing_targeted_buckets()
+            .iter()
+            .all(|ilog2| *ilog2 >= 249));
+
+        // A million nodes make the bucket 240 plausible again.
+        network_discovery.set_network_size_estimate(1_000_000);
+        assert!(network_discovery
+            .select_weighted(usize::MAX, &mut rand::thread_rng())
+            .contains(&close_candidate));
+        assert!(network_discovery
+            .query_plan(usize::MAX)
+            .contains(&close_candidate));
+        assert_eq!(
+            network_discovery.missing_targeted_buckets(),
+            TARGETED_BUCKETS
+                .filter(|ilog2| !network_discovery.has_candidates_for_bucket(*ilog2))
+                .collect::<Vec<_>>()
+        );
+    }
+
+    #[test]
+    fn query_targets_round_robin_across_the_buckets_with_a_cooldown() {
+        let self_peer_id = PeerId::random();
+        let config = NetworkDiscoveryConfig {
+            initial_attempts: 0,
+            refresh_attempts: 1_000,
+            ..Default::default()
+        };
+        let mut network_discovery = NetworkDiscovery::with_config(&self_peer_id, config);
+        let _ = network_discovery.try_refresh_candidates();
+        let buckets = network_discovery
+            .candidates
+            .keys()
+            .copied()
+            .collect::<Vec<_>>();
+        assert!(buckets.len() > 3);
+        let self_key = KBucketKey::from(self_peer_id);
+        let bucket_of = |addr: &NetworkAddress| {
+            addr.as_kbucket_key()
+                .distance(&self_key)
+                .ilog2()
+                .expect("not self")
+        };
+
+        // Successive hand-outs visit the buckets in turn, from the closest one, one target per bucket.
+        let before = addresses(&network_discovery);
+        let now = Instant::now();
+        let mut queried = Vec::new();
+        loop {
+            let targets =
+                network_discovery.take_candidates_at(3, CandidateSelection::RoundRobin, now);
+            if targets.is_empty() {
+                break;
+            }
+            assert!(targets.len() <= 3);
+            queried.extend(targets);
+        }
+        assert_eq!(queried.iter().map(bucket_of).collect::<Vec<_>>(), buckets);
+
+        // Once cooled down, the round-robin wraps around, and the least recently queried candidates come next.
+        let later = now + BUCKET_QUERY_COOLDOWN + Duration::from_secs(1);
+        let targets =
+            network_discovery.take_candidates_at(2, CandidateSelection::RoundRobin, later);
+        assert_eq!(
+            targets.iter().map(bucket_of).collect::<Vec<_>>(),
+            buckets[..2]
+        );
+        for addr in &targets {
+            if before[&bucket_of(addr)].len() > 1 {
+                assert!(!queried.contains(addr));
+            }
+        }
+        // The buckets that were just returned are cooling down, the next hand-out resumes from the following bucket.
+        let targets =
+            network_discovery.take_candidates_at(usize::MAX, CandidateSelection::RoundRobin, later);
+        assert_eq!(
+            targets.iter().map(bucket_of).collect::<Vec<_>>(),
+            buckets[2..]
+        );
+        assert!(network_discovery
+            .take_candidates_at(usize::MAX, CandidateSelection::RoundRobin, later)
+            .is_empty());
+    }
+
+    #[test]
+    fn throttled_refresh_is_skipped_within_the_interval() {
+        let config = NetworkDiscoveryConfig {
+            initial_attempts: 0,
+            refresh_attempts: 1_000,
+            ..Default::default()
+        };
+        let mut network_discovery = NetworkDiscovery::with_config(&PeerId::random(), config);
+        let min_interval = Duration::from_secs(60);
+
+        assert!(network_discovery
+            .try_refresh_candidates_throttled(min_interval)
+            .is_some());
+        let refreshed_at = network_discovery.last_refresh.expect("refreshed");
+        assert!(network_discovery
+            .try_refresh_candidates_throttled(min_interval)
+            .is_none());
+        assert_eq!(network_discovery.last_refresh, Some(refreshed_at));
+
+        assert!(network_discovery
+            .try_refresh_candidates_throttled_at(min_interval, refreshed_at + min_interval)
+            .is_some());
+        assert!(network_discovery.last_refresh > Some(refreshed_at));
+    }
+
+    #[test]
+    fn self_key_is_the_key_of_our_peer_id() {
+        let self_peer_id = PeerId::random();
+        let network_discovery = NetworkDiscovery::with_config(
+            &self_peer_id,
+            NetworkDiscoveryConfig {
+                initial_attempts: 0,
+                ..Default::default()
+            },
+        );
+        assert_eq!(
+            network_discovery.self_key(),
+            &KBucketKey::from(self_peer_id)
+        );
+    }
+
+    #[test]
+    fn emptied_bucket_gets_a_generated_candidate_when_none_is_held() {
+        let self_peer_id = PeerId::random();
+        let mut network_discovery = NetworkDiscovery::with_config(
+            &self_peer_id,
+            NetworkDiscoveryConfig {
+                initial_attempts: 0,
+                ..Default::default()
+            },
+        );
+        assert!(!network_discovery.has_candidates_for_bucket(254));
+        let generated = network_discovery
+            .candidate_for_emptied_bucket(254)
+            .expect("the bucket 254 is hit every 4 attempts");
+        assert_eq!(
+            generated
+                .as_kbucket_key()
+                .distance(&KBucketKey::from(self_peer_id))
+                .ilog2(),
+            Some(254)
+        );
+        // It is retained, in flight until its result is handled, and then handed out again. Meanwhile, the bucket is
+        // still covered and no other query is made for it.
+        assert!(network_discovery.in_flight.contains_key(&generated));
+        assert!(network_discovery.has_candidates_for_bucket(254));
+        assert_eq!(network_discovery.candidate_for_emptied_bucket(254), None);
+        let _ = network_discovery.handle_get_closest_query(&generated, HashSet::new(), |_| true);
+        assert_eq!(
+            network_discovery
+                .candidates_for_bucket(254)
+                .collect::<Vec<_>>(),
+            vec![&generated]
+        );
+        assert_eq!(
+            network_discovery.candidate_for_emptied_bucket(254),
+            Some(generated)
+        );
+        // The closest buckets cannot be generated for.
+        assert_eq!(network_discovery.candidate_for_emptied_bucket(0), None);
+    }
+
+    #[test]
+    fn generation_honours_the_configured_attempts() {
+        let config = NetworkDiscoveryConfig {
+            initial_attempts: 2_000,
+            refresh_attempts: 110,
+            record_generation_histogram: true,
+            ..Default::default()
+        };
+        let mut network_discovery = NetworkDiscovery::with_config(&PeerId::random(), config);
+        let generated = |network_discovery: &NetworkDiscovery| {
+            network_discovery
+                .generation_histogram()
+                .values()
+                .sum::<u64>() as usize
+        };
+        // The targeted buckets missed by the random sampling are tried for out of the same budget.
+        let initial = generated(&network_discovery);
+        assert!((1_000..=2_000).contains(&initial), "{initial}");
+
+        // The refresh attempts are scaled down by the share of the targeted buckets still lacking candidates.
+        let attempts = network_discovery.adaptive_refresh_attempts();
+        assert!(attempts > 0 && attempts <= 110);
+        let stats = network_discovery.try_refresh_candidates();
+        assert_eq!(stats.attempts, attempts);
+        assert!(generated(&network_discovery) <= initial + attempts);
+    }
+
+    #[test]
+    fn refresh_reports_whether_the_candidates_changed() -> eyre::Result<()> {
+        let config = NetworkDiscoveryConfig {
+            initial_attempts: 0,
+            refresh_attempts: 1_000,
+            ..Default::default()
+        };
+        let mut network_discovery = NetworkDiscovery::with_config(&PeerId::random(), config);
+        let stats = network_discovery.try_refresh_candidates();
+        assert!(stats.accepted > 0);
+        assert_eq!(stats.fronts_changed, stats.buckets_touched);
+        assert!(stats.changed());
+
+        // The front candidate of a bucket holding several ones is taken, and its query never returns.
+        let (ilog2, taken) = addresses(&network_discovery)
+            .into_iter()
+            .find(|(_, addrs)| addrs.len() > 1)
+            .map(|(ilog2, addrs)| (ilog2, addrs[0].clone()))
+            .ok_or_else(|| eyre::eyre!("the farther buckets hold several candidates"))?;
+        let taken_at = Instant::now()
+            .checked_sub(IN_FLIGHT_CANDIDATE_TIMEOUT + Duration::from_secs(1))
+            .ok_or_else(|| eyre::eyre!("the clock is too early"))?;
+        network_discovery.take_candidate(&taken, taken_at);
+
+        // Every bucket is full, nothing can be generated, yet the expired candidate is moved to the back of its bucket.
+        network_discovery.set_bucket_fill(
+            TARGETED_BUCKETS
+                .map(|ilog2| (ilog2, K_VALUE.get()))
+                .collect(),
+        );
+        assert_eq!(network_discovery.adaptive_refresh_attempts(), 0);
+        let stats = network_discovery.try_refresh_candidates();
+        assert_eq!(stats.accepted, 0);
+        assert_eq!(stats.fronts_changed, 1);
+        assert!(stats.changed());
+        assert_eq!(addresses(&network_discovery)[&ilog2].last(), Some(&taken));
+
+        // A refresh of the saturated pool with nothing in flight is a no-op.
+        let stats = network_discovery.try_refresh_candidates();
+        assert_eq!(stats, CandidateGenerationStats::default());
+        assert!(!stats.changed());
+        Ok(())
+    }
+
+    #[test]
+    fn taken_candidates_are_handed_out_again_once_their_flight_times_out() {
+        let self_peer_id = PeerId::random();
+        let config = NetworkDiscoveryConfig {
+            initial_attempts: 0,
+            refresh_attempts: 1_000,
+            ..Default::default()
+        };
+        let mut network_discovery = NetworkDiscovery::with_config(&self_peer_id, config);
+        let _ = network_discovery.try_refresh_candidates();
+        let before = network_discovery.bucket_occupancy();
+        let missing = network_discovery.missing_targeted_buckets();
+
+        let taken = network_discovery.take_candidates(5, CandidateSelection::Closest);
+        assert_eq!(taken.len(), 5);
+        // In flight, they remain in their bucket, which is still covered, but they are not handed out again.
+        assert_eq!(network_discovery.bucket_occupancy(), before);
+        assert_eq!(network_discovery.missing_targeted_buckets(), missing);
+        let held = addresses(&network_discovery)
+            .into_values()
+            .flatten()
+            .collect::<HashSet<_>>();
+        assert!(taken.iter().all(|addr| held.contains(addr)));
+        assert!(network_discovery
+            .query_plan(usize::MAX)
+            .iter()
+            .all(|addr| !taken.contains(addr)));
+
+        // Without any result, they are moved to the back of their bucket by the first hand-out after their timeout.
+        let taken_at = Instant::now();
+        let _ = network_discovery.take_candidates_at(0, CandidateSelection::Closest, taken_at);
+        assert_eq!(network_discovery.in_flight.len(), 5);
+        let _ = network_discovery.take_candidates_at(
+            0,
+            CandidateSelection::Closest,
+            taken_at + IN_FLIGHT_CANDIDATE_TIMEOUT + Duration::from_secs(1),
+        );
+        assert!(network_discovery.in_flight.is_empty());
+        assert_eq!(network_discovery.bucket_occupancy(), before);
+        let self_key = KBucketKey::from(self_peer_id);
+        for (ilog2, candidates) in addresses(&network_discovery) {
+            let expired = taken
+                .iter()
+                .filter(|addr| addr.as_kbucket_key().distance(&self_key).ilog2() == Some(ilog2))
+                .collect::<HashSet<_>>();
+            let back = candidates
+                .iter()
+                .skip(candidates.len() - expired.len())
+                .collect::<HashSet<_>>();
+            assert_eq!(back, expired);
+        }
+    }
+
+    #[test]
+    fn queried_candidates_are_evicted_before_the_unqueried_ones() -> eyre::Result<()> {
+        let self_peer_id = PeerId::random();
+        let self_key = KBucketKey::from(self_peer_id);
+        let config = NetworkDiscoveryConfig {
+            initial_attempts: 0,
+            max_peers_per_bucket: 2,
+            ..Default::default()
+        };
+        let mut network_discovery = NetworkDiscovery::with_config(&self_peer_id, config);
+        let in_255 = || {
+            NetworkAddress::random_in_bucket(&self_key, 255, 1_000)
+                .ok_or_else(|| eyre::eyre!("the bucket 255 is hit every other attempt"))
+        };
+        let (oldest, queried, newer, newest) = (in_255()?, in_255()?, in_255()?, in_255()?);
+        let _ = network_discovery.insert_candidates(255, vec![oldest.clone(), queried.clone()]);
+        network_discovery.take_candidate(&queried, Instant::now());
+
+        // The queried candidate goes first, even though it is newer.
+        let _ = network_discovery.insert_candidates(255, vec![newer.clone()]);
+        assert_eq!(
+            addresses(&network_discovery)[&255],
+            vec![oldest.clone(), newer.clone()]
+        );
+        // Then the oldest one.
+        let _ = network_discovery.insert_candidates(255, vec![newest.clone()]);
+        assert_eq!(addresses(&network_discovery)[&255], vec![newer, newest]);
+        Ok(())
+    }
+
+    #[test]
+    fn taken_candidates_return_with_their_query_result() {
+        let self_peer_id = PeerId::random();
+        let config = NetworkDiscoveryConfig {
+            initial_attempts: 0,
+            refresh_attempts: 1_000,
+            ..Default::default()
+        };
+        let mut network_discovery = NetworkDiscovery::with_config(&self_peer_id, config);
+        let _ = network_discovery.try_refresh_candidates();
+        let taken = network_discovery.take_candidates(2, CandidateSelection::Closest);
+        assert_eq!(taken.len(), 2);
+
+        // Either result ends the flight, the candidate remaining in its bucket, as its retirement is left to
+        // `record_query_result`.
+        let returned = HashSet::from([PeerId::random()]);
+        let _ = network_discovery.handle_get_closest_query(&taken[0], returned, |_| true);
+        let _ = network_discovery.handle_get_closest_query(&taken[1], HashSet::new(), |_| true);
+        assert!(network_discovery.in_flight.is_empty());
+        let held = addresses(&network_discovery)
+            .into_values()
+            .flatten()
+            .collect::<HashSet<_>>();
+        assert!(held.contains(&taken[0]));
+        assert!(held.contains(&taken[1]));
+        let plan = network_discovery.query_plan(usize::MAX);
+        assert!(taken.iter().all(|addr| plan.contains(addr)));
+    }
+
+    #[test]
+    fn paused_network_discovery_hands_out_no_target_until_resumed() {
+        let config = NetworkDiscoveryConfig {
+            initial_attempts: 0,
+            refresh_attempts: 1_000,
+            ..Default::default()
+        };
+        let mut network_discovery = NetworkDiscovery::with_config(&PeerId::random(), config);
+        let _ = network_discovery.try_refresh_candidates();
+        let before = addresses(&network_discovery);
+        assert!(!before.is_empty());
+
+        network_discovery.pause();
+        assert!(network_discovery.is_paused());
+        assert!(network_discovery.stats().paused);
+        assert!(network_discovery.query_plan(usize::MAX).is_empty());
+        assert!(network_discovery
+            .select_weighted(usize::MAX, &mut rand::thread_rng())
+            .is_empty());
+        for selection in [
+            CandidateSelection::Closest,
+            CandidateSelection::Weighted,
+            CandidateSelection::RoundRobin,
+        ] {
+            assert!(network_discovery
+                .take_candidates(usize::MAX, selection)
+                .is_empty());
+        }
+        // Nothing is generated, but the pool is retained and the observed peers are still recorded.
+        assert_eq!(
+            network_discovery.try_refresh_candidates(),
+            CandidateGenerationStats::default()
+        );
+        let missing = network_discovery.missing_targeted_buckets();
+        assert_eq!(network_discovery.refresh_targeting(&missing), missing);
+        assert_eq!(addresses(&network_discovery), before);
+        let observed = PeerId::random();
+        let _ = network_discovery.insert_observed_peers([observed], |_| false);
+        assert!(network_discovery
+            .candidates_by_source(|source| *source == CandidateSource::Observed(observed))
+            .next()
+            .is_some());
+
+        network_discovery.resume();
+        assert!(!network_discovery.stats().paused);
+        assert!(!network_discovery.query_plan(usize::MAX).is_empty());
+        assert!(!network_discovery
+            .take_candidates(5, CandidateSelection::Closest)
+            .is_empty());
+    }
+
+    #[test]
+    fn generation_histogram_accounts_for_every_generated_peer() {
+        let self_key = KBucketKey::from(PeerId::random());
+        let histogram = GenerationHistogram::default();
+        let with_histogram = NetworkDiscovery::generate_candidates(
+            &self_key,
+            PeerIds::new(2_000, Some(&mut StdRng::seed_from_u64(1))),
+            DEFAULT_MAX_PEERS_PER_BUCKET,
+            Some(&histogram),
+        );
+        // Every random PeerId has a distance to self, hence they are all accounted for, retained or not.
+        let counts = histogram.snapshot();
+        assert_eq!(counts.values().sum::<u64>(), 2_000);
+        assert!(counts[&255] > DEFAULT_MAX_PEERS_PER_BUCKET as u64);
+        // The recording does not affect the candidates retained.
+        let without_histogram = NetworkDiscovery::generate_candidates(
+            &self_key,
+            PeerIds::new(2_000, Some(&mut StdRng::seed_from_u64(1))),
+            DEFAULT_MAX_PEERS_PER_BUCKET,
+            None,
+        );
+        assert_eq!(with_histogram, without_histogram);
+
+        // The instance records all of its generations once enabled, and nothing otherwise.
+        let config = NetworkDiscoveryConfig {
+            initial_attempts: 0,
+            refresh_attempts: 1_000,
+            record_generation_histogram: true,
+            ..Default::default()
+        };
+        let mut network_discovery = NetworkDiscovery::with_config(&PeerId::random(), config);
+        assert!(network_discovery.generation_histogram().is_empty());
+        let stats = network_discovery.try_refresh_candidates();
+        let generated = network_discovery.generation_histogram();
+        assert_eq!(network_discovery.stats().generation_histogram, generated);
+        assert!(generated.values().sum::<u64>() >= stats.accepted as u64);
+        assert!(generated.values().sum::<u64>() <= network_discovery.last_refresh_attempts as u64);
+        assert!(
+            NetworkDiscovery::with_config(&PeerId::random(), Default::default())
+                .generation_histogram()
+                .is_empty()
+        );
     }
 }
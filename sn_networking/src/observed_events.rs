@@ -0,0 +1,147 @@
+// Copyright 2024 MaidSafe.net limited.
+//
+// This SAFE Network Software is licensed to you under The General Public License (GPL), version 3.
+// Unless required by applicable law or agreed to in writing, the SAFE Network Software distributed
+// under the GPL Licence is distributed on an "AS IS" BASIS, WITHOUT WARRANTIES OR CONDITIONS OF ANY
+// KIND, either express or implied. Please review the Licences for the specific language governing
+// permissions and limitations relating to use of the SAFE Network Software.
+
+use crate::driver::SwarmDriver;
+use libp2p::PeerId;
+use sn_protocol::PrettyPrintRecordKey;
+use std::collections::{BTreeMap, HashSet};
+
+/// The number of events buffered for each subscriber of `Network::events_channel`. A subscriber lagging further
+/// behind misses the oldest events.
+pub(crate) const OBSERVED_EVENTS_CAPACITY: usize = 256;
+
+/// The events that can be observed through `Network::events_channel`, e.g., to monitor a node or to raise alerts,
+/// without scraping the logs. Unlike the `NetworkEvent`s, these carry no channel and are cheap to clone.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum ObservedNetworkEvent {
+    /// A peer has been added to the routing table, under the provided ilog2 bucket.
+    PeerAdded {
+        peer_id: PeerId,
+        bucket: Option<u32>,
+    },
+    /// A peer has been removed from the routing table, from the provided ilog2 bucket.
+    PeerRemoved {
+        peer_id: PeerId,
+        bucket: Option<u32>,
+    },
+    /// A record has been written to the local record store.
+    RecordStored { key: PrettyPrintRecordKey<'static> },
+    /// A record has been fetched from the network.
+    RecordFetched { key: PrettyPrintRecordKey<'static> },
+    /// The list of the records held has been sent to the closest peers.
+    ReplicationStarted {
+        round: u64,
+        targets: usize,
+        keys: usize,
+    },
+    /// All the peers of the replication round answered, or failed to.
+    ReplicationCompleted { round: u64, targets: usize },
+    /// Dialing a peer failed.
+    DialFailed { peer_id: PeerId, error: String },
+    /// A connection to the peer was closed to stay within the established connections limit.
+    ConnectionEvicted { peer_id: PeerId },
+    /// Upgrading the relayed connection to the peer into a direct one, i.e., hole punching it, succeeded or failed.
+    HolePunchAttempted { peer_id: PeerId, succeeded: bool },
+    /// The network discovery got its first candidate for the provided ilog2 bucket.
+    DiscoveryBucketPopulated { bucket: u32 },
+}
+
+/// The replication rounds awaiting the answers of their targets.
+#[derive(Debug, Default)]
+pub(crate) struct ReplicationRounds {
+    next_round: u64,
+    // The targets of each round, along with the ones that have not answered yet.
+    pending: BTreeMap<u64, (usize, HashSet<PeerId>)>,
+}
+
+impl ReplicationRounds {
+    /// Returns the number of the new round.
+    pub(crate) fn start(&mut self, targets: &[PeerId]) -> u64 {
+        let round = self.next_round;
+        self.next_round += 1;
+        let _ = self
+            .pending
+            .insert(round, (targets.len(), targets.iter().copied().collect()));
+        round
+    }
+
+    /// Records the answer of the peer to the oldest round that awaits it. Returns the round along with its number of
+    /// targets if it got completed.
+    pub(crate) fn peer_answered(&mut self, peer_id: &PeerId) -> Option<(u64, usize)> {
+        let (round, (targets, awaiting)) = self
+            .pending
+            .iter_mut()
+            .find(|(_, (_, awaiting))| awaiting.contains(peer_id))?;
+        let _ = awaiting.remove(peer_id);
+        if !awaiting.is_empty() {
+            return None;
+        }
+        let completed = (*round, *targets);
+        let _ = self.pending.remove(&completed.0);
+        Some(completed)
+    }
+}
+
+impl SwarmDriver {
+    /// Publishes the event to the subscribers of `Network::events_channel`, if any. The oldest event is dropped for
+    /// the subscribers that lag behind, which is accounted for in the metrics.
+    pub(crate) fn publish_observed_event(&self, event: ObservedNetworkEvent) {
+        if self.observed_events.receiver_count() == 0 {
+            return;
+        }
+        if self.observed_events.len() >= OBSERVED_EVENTS_CAPACITY {
+            trace!("A subscriber lags behind, dropping its oldest observed event");
+            #[cfg(feature = "open-metrics")]
+            if let Some(metrics_recorder) = &self.metrics_recorder {
+                let _ = metrics_recorder.observed_events_dropped.inc();
+            }
+        }
+        let _ = self.observed_events.send(event);
+    }
+
+    /// Starts a replication round towards the provided targets.
+    pub(crate) fn start_replication_round(&mut self, targets: &[PeerId], keys: usize) {
+        let round = self.replication_rounds.start(targets);
+        self.publish_observed_event(ObservedNetworkEvent::ReplicationStarted {
+            round,
+            targets: targets.len(),
+            keys,
+        });
+    }
+
+    /// Records that the peer answered the replication list we sent, or failed to.
+    pub(crate) fn replication_peer_answered(&mut self, peer_id: &PeerId) {
+        if let Some((round, targets)) = self.replication_rounds.peer_answered(peer_id) {
+            debug!("Replication round {round} towards {targets} peers completed");
+            self.publish_observed_event(ObservedNetworkEvent::ReplicationCompleted {
+                round,
+                targets,
+            });
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn replication_round_completes_once_all_its_targets_answered() {
+        let mut rounds = ReplicationRounds::default();
+        let (first, second) = (PeerId::random(), PeerId::random());
+        assert_eq!(rounds.start(&[first, second]), 0);
+        assert_eq!(rounds.start(&[first]), 1);
+
+        // The answers are matched with the oldest round awaiting the peer.
+        assert_eq!(rounds.peer_answered(&first), None);
+        assert_eq!(rounds.peer_answered(&PeerId::random()), None);
+        assert_eq!(rounds.peer_answered(&second), Some((0, 2)));
+        assert_eq!(rounds.peer_answered(&first), Some((1, 1)));
+        assert!(rounds.pending.is_empty());
+    }
+}
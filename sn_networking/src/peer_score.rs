@@ -0,0 +1,248 @@
+// Copyright 2024 MaidSafe.net limited.
+//
+// This SAFE Network Software is licensed to you under The General Public License (GPL), version 3.
+// Unless required by applicable law or agreed to in writing, the SAFE Network Software distributed
+// under the GPL Licence is distributed on an "AS IS" BASIS, WITHOUT WARRANTIES OR CONDITIONS OF ANY
+// KIND, either express or implied. Please review the Licences for the specific language governing
+// permissions and limitations relating to use of the SAFE Network Software.
+
+use crate::{driver::SwarmDriver, target_arch::Instant};
+use libp2p::PeerId;
+use std::{collections::HashMap, time::Duration};
+
+/// The points gained by a peer for each successful exchange.
+const SUCCESS_REWARD: i32 = 1;
+/// The points lost by a peer for each failed exchange.
+const FAILURE_PENALTY: i32 = 4;
+/// A long history of successes shall not shield a peer that started failing.
+const MAX_SCORE: i32 = 20;
+/// Under this score a peer is evicted from the RT and temporarily blocked.
+const EVICTION_THRESHOLD: i32 = -20;
+/// The scores move back towards zero by one point per interval.
+const DECAY_INTERVAL: Duration = Duration::from_secs(30);
+/// A failure is only held against a peer when another exchange succeeded that recently. Otherwise it is likely our
+/// own connectivity that is at fault.
+const CONCURRENT_SUCCESS_WINDOW: Duration = Duration::from_secs(30);
+/// How long an evicted peer stays blocked.
+const EVICTION_DURATION: Duration = Duration::from_secs(15 * 60);
+/// The interval at which the expired evictions are lifted.
+pub(crate) const EVICTION_CHECK_INTERVAL: Duration = Duration::from_secs(30);
+
+#[derive(Debug, Clone, Copy)]
+struct PeerScore {
+    score: i32,
+    updated: Instant,
+}
+
+impl PeerScore {
+    fn decayed(&self, now: Instant) -> i32 {
+        let steps =
+            now.saturating_duration_since(self.updated).as_secs() / DECAY_INTERVAL.as_secs();
+        let steps = i32::try_from(steps).unwrap_or(i32::MAX);
+        if self.score > 0 {
+            self.score.saturating_sub(steps).max(0)
+        } else {
+            self.score.saturating_add(steps).min(0)
+        }
+    }
+}
+
+/// Scores the peers we exchange with, so that the ones failing consistently can be evicted from the RT.
+#[derive(Debug, Default)]
+pub(crate) struct PeerScores {
+    scores: HashMap<PeerId, PeerScore>,
+    // The last time any exchange succeeded.
+    last_success: Option<Instant>,
+    // The evicted peers, along with the time their eviction expires.
+    evicted: HashMap<PeerId, Instant>,
+}
+
+impl PeerScores {
+    pub(crate) fn score(&self, peer_id: &PeerId, now: Instant) -> i32 {
+        self.scores
+            .get(peer_id)
+            .map_or(0, |score| score.decayed(now))
+    }
+
+    pub(crate) fn is_evicted(&self, peer_id: &PeerId) -> bool {
+        self.evicted.contains_key(peer_id)
+    }
+
+    pub(crate) fn evicted_count(&self) -> usize {
+        self.evicted.len()
+    }
+
+    pub(crate) fn record_success(&mut self, peer_id: PeerId, now: Instant) {
+        self.last_success = Some(now);
+        let score = (self.score(&peer_id, now) + SUCCESS_REWARD).min(MAX_SCORE);
+        let _ = self.scores.insert(
+            peer_id,
+            PeerScore {
+                score,
+                updated: now,
+            },
+        );
+    }
+
+    /// Returns true if the failure got the peer evicted.
+    pub(crate) fn record_failure(&mut self, peer_id: PeerId, now: Instant) -> bool {
+        if self.is_evicted(&peer_id) {
+            return false;
+        }
+        let others_succeeding = self
+            .last_success
+            .is_some_and(|last| now.saturating_duration_since(last) <= CONCURRENT_SUCCESS_WINDOW);
+        if !others_succeeding {
+            debug!("Not holding the failure against {peer_id:?}, as no other exchange succeeded lately");
+            return false;
+        }
+
+        let score = self.score(&peer_id, now).saturating_sub(FAILURE_PENALTY);
+        if score < EVICTION_THRESHOLD {
+            let _ = self.scores.remove(&peer_id);
+            let _ = self.evicted.insert(peer_id, now + EVICTION_DURATION);
+            return true;
+        }
+        let _ = self.scores.insert(
+            peer_id,
+            PeerScore {
+                score,
+                updated: now,
+            },
+        );
+        false
+    }
+
+    /// Returns the peers whose eviction expired. The scores that decayed back to zero are dropped as well.
+    pub(crate) fn take_expired_evictions(&mut self, now: Instant) -> Vec<PeerId> {
+        self.scores
+            .retain(|_peer_id, score| score.decayed(now) != 0);
+        let expired = self
+            .evicted
+            .iter()
+            .filter(|(_peer_id, until)| **until <= now)
+            .map(|(peer_id, _until)| *peer_id)
+            .collect::<Vec<_>>();
+        for peer_id in &expired {
+            let _ = self.evicted.remove(peer_id);
+        }
+        expired
+    }
+}
+
+impl SwarmDriver {
+    pub(crate) fn record_exchange_success(&mut self, peer_id: PeerId) {
+        self.peer_scores.record_success(peer_id, Instant::now());
+    }
+
+    /// Evicts the peer from the RT and blocks it for a while if it has been failing consistently.
+    pub(crate) fn record_exchange_failure(&mut self, peer_id: PeerId) {
+        if !self.peer_scores.record_failure(peer_id, Instant::now()) {
+            return;
+        }
+        warn!(
+            "Peer {peer_id:?} kept failing, evicting it for {EVICTION_DURATION:?}. {} peers are now evicted",
+            self.peer_scores.evicted_count()
+        );
+        self.swarm.behaviour_mut().blocklist.block_peer(peer_id);
+        if let Some(dead_peer) = self.swarm.behaviour_mut().kademlia.remove_peer(&peer_id) {
+            self.update_on_peer_removal(*dead_peer.node.key.preimage());
+        }
+        let _ = self.network_discovery.remove_peer(&peer_id);
+        self.record_evicted_peers_metric();
+    }
+
+    /// Unblocks the peers whose eviction expired, unless they got blocked for another reason in the meantime.
+    pub(crate) fn lift_expired_evictions(&mut self) {
+        for peer_id in self.peer_scores.take_expired_evictions(Instant::now()) {
+            let is_bad = self
+                .bad_nodes
+                .get(&peer_id)
+                .is_some_and(|(_issues, is_bad)| *is_bad);
+            if !is_bad && !self.blocked_peers.contains(&peer_id) {
+                info!("Eviction of {peer_id:?} expired, unblocking it");
+                self.swarm.behaviour_mut().blocklist.unblock_peer(peer_id);
+            }
+        }
+        self.record_evicted_peers_metric();
+    }
+
+    fn record_evicted_peers_metric(&self) {
+        #[cfg(feature = "open-metrics")]
+        if let Some(metrics_recorder) = self.metrics_recorder.as_ref() {
+            let _ = metrics_recorder
+                .evicted_peers
+                .set(self.peer_scores.evicted_count() as i64);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn failures_only_count_while_other_exchanges_succeed() {
+        let mut scores = PeerScores::default();
+        let (good, failing) = (PeerId::random(), PeerId::random());
+        let start = Instant::now();
+
+        // Nothing succeeded yet, we may well be the one offline.
+        assert!(!scores.record_failure(failing, start));
+        assert_eq!(scores.score(&failing, start), 0);
+
+        scores.record_success(good, start);
+        assert_eq!(scores.score(&good, start), SUCCESS_REWARD);
+        assert!(!scores.record_failure(failing, start));
+        assert_eq!(scores.score(&failing, start), -FAILURE_PENALTY);
+
+        // Once the successes are too old, the failures are ignored again.
+        let later = start + CONCURRENT_SUCCESS_WINDOW + Duration::from_secs(1);
+        assert!(!scores.record_failure(failing, later));
+        assert_eq!(scores.score(&failing, later), -FAILURE_PENALTY + 1);
+
+        // The scores decay back to zero, and are then dropped.
+        let much_later = start + DECAY_INTERVAL * 10;
+        assert_eq!(scores.score(&good, much_later), 0);
+        assert_eq!(scores.score(&failing, much_later), 0);
+        assert!(scores.take_expired_evictions(much_later).is_empty());
+        assert!(scores.scores.is_empty());
+    }
+
+    #[test]
+    fn consistently_failing_peer_is_evicted_for_a_while() {
+        let mut scores = PeerScores::default();
+        let (good, failing) = (PeerId::random(), PeerId::random());
+        let now = Instant::now();
+
+        let mut failures = 0;
+        loop {
+            scores.record_success(good, now);
+            failures += 1;
+            if scores.record_failure(failing, now) {
+                break;
+            }
+            assert!(failures < 100, "the peer was never evicted");
+        }
+        assert_eq!(failures, EVICTION_THRESHOLD.abs() / FAILURE_PENALTY + 1);
+        assert!(scores.is_evicted(&failing));
+        assert_eq!(scores.evicted_count(), 1);
+        assert_eq!(scores.score(&good, now), failures * SUCCESS_REWARD);
+        for _ in 0..MAX_SCORE {
+            scores.record_success(good, now);
+        }
+        assert_eq!(scores.score(&good, now), MAX_SCORE);
+        // An evicted peer isn't evicted again.
+        assert!(!scores.record_failure(failing, now));
+
+        assert!(scores
+            .take_expired_evictions(now + EVICTION_DURATION / 2)
+            .is_empty());
+        assert_eq!(
+            scores.take_expired_evictions(now + EVICTION_DURATION),
+            vec![failing]
+        );
+        assert!(!scores.is_evicted(&failing));
+        assert_eq!(scores.evicted_count(), 0);
+    }
+}
@@ -0,0 +1,243 @@
+// Copyright 2024 MaidSafe.net limited.
+//
+// This SAFE Network Software is licensed to you under The General Public License (GPL), version 3.
+// Unless required by applicable law or agreed to in writing, the SAFE Network Software distributed
+// under the GPL Licence is distributed on an "AS IS" BASIS, WITHOUT WARRANTIES OR CONDITIONS OF ANY
+// KIND, either express or implied. Please review the Licences for the specific language governing
+// permissions and limitations relating to use of the SAFE Network Software.
+
+use crate::{driver::SwarmDriver, target_arch::Instant, GetRecordCfg};
+use libp2p::kad::{Record, RecordKey};
+use lru::LruCache;
+use sn_protocol::storage::{try_deserialize_record, Chunk, RecordHeader, RecordKind};
+use std::time::Duration;
+
+/// The default total size of the payloads held by the record cache of a client.
+const DEFAULT_RECORD_CACHE_CAPACITY: usize = 64 * 1024 * 1024;
+/// The default time a record is served from the cache of a client.
+const DEFAULT_RECORD_CACHE_TTL: Duration = Duration::from_secs(10 * 60);
+
+/// The settings of the in-memory cache of the records fetched from the network.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct RecordCacheConfig {
+    /// The total size of the payloads held, the least recently used records being evicted beyond it. A zero capacity
+    /// disables the cache.
+    pub capacity: usize,
+    /// How long a record is served from the cache after being fetched.
+    pub ttl: Duration,
+}
+
+impl RecordCacheConfig {
+    pub fn disabled() -> Self {
+        Self {
+            capacity: 0,
+            ttl: Duration::ZERO,
+        }
+    }
+}
+
+impl Default for RecordCacheConfig {
+    fn default() -> Self {
+        Self {
+            capacity: DEFAULT_RECORD_CACHE_CAPACITY,
+            ttl: DEFAULT_RECORD_CACHE_TTL,
+        }
+    }
+}
+
+/// Keeps the recently fetched records, so that fetching them again is served locally. Only the chunks are held: their
+/// content is checked against their address, and they never change. The mutable records, e.g., registers and spends,
+/// are always fetched from the network.
+#[derive(Debug)]
+pub(crate) struct RecordCache {
+    entries: LruCache<RecordKey, (Record, Instant)>,
+    config: RecordCacheConfig,
+    size: usize,
+    pub(crate) hits: u64,
+    pub(crate) misses: u64,
+}
+
+impl RecordCache {
+    pub(crate) fn new(config: RecordCacheConfig) -> Self {
+        Self {
+            entries: LruCache::unbounded(),
+            config,
+            size: 0,
+            hits: 0,
+            misses: 0,
+        }
+    }
+
+    pub(crate) fn is_enabled(&self) -> bool {
+        self.config.capacity > 0 && !self.config.ttl.is_zero()
+    }
+
+    /// Returns the record if it has been fetched within the TTL.
+    pub(crate) fn get(&mut self, key: &RecordKey) -> Option<Record> {
+        if !self.is_enabled() {
+            return None;
+        }
+        let expired = match self.entries.get(key) {
+            Some((record, fetched_at)) if fetched_at.elapsed() < self.config.ttl => {
+                self.hits += 1;
+                return Some(record.clone());
+            }
+            Some(_) => true,
+            None => false,
+        };
+        if expired {
+            self.remove(key);
+        }
+        self.misses += 1;
+        None
+    }
+
+    /// Holds the record if it is a chunk matching its key. Returns false if the record is not cacheable.
+    pub(crate) fn insert(&mut self, record: &Record) -> bool {
+        if !self.is_enabled()
+            || record.value.len() > self.config.capacity
+            || !is_verified_chunk(record)
+        {
+            return false;
+        }
+        self.remove(&record.key);
+        self.size += record.value.len();
+        let _ = self
+            .entries
+            .put(record.key.clone(), (record.clone(), Instant::now()));
+        while self.size > self.config.capacity {
+            match self.entries.pop_lru() {
+                Some((_key, (evicted, _))) => self.size -= evicted.value.len(),
+                None => break,
+            }
+        }
+        true
+    }
+
+    fn remove(&mut self, key: &RecordKey) {
+        if let Some((record, _)) = self.entries.pop(key) {
+            self.size -= record.value.len();
+        }
+    }
+}
+
+impl SwarmDriver {
+    /// Returns the record if it has been fetched lately and matches the target of the query, unless the query must
+    /// reach the network, see `GetRecordCfg::may_use_cache`.
+    pub(crate) fn get_cached_record(
+        &mut self,
+        key: &RecordKey,
+        cfg: &GetRecordCfg,
+    ) -> Option<Record> {
+        if !self.record_cache.is_enabled() || !cfg.may_use_cache() {
+            return None;
+        }
+        let record = self
+            .record_cache
+            .get(key)
+            .filter(|record| cfg.does_target_match(record));
+
+        #[cfg(feature = "open-metrics")]
+        if let Some(metrics_recorder) = self.metrics_recorder.as_ref() {
+            if record.is_some() {
+                let _ = metrics_recorder.record_cache_hits.inc();
+            } else {
+                let _ = metrics_recorder.record_cache_misses.inc();
+            }
+        }
+        record
+    }
+}
+
+// The chunks are content addressed, hence cannot go stale once their content matches their address.
+fn is_verified_chunk(record: &Record) -> bool {
+    match RecordHeader::from_record(record) {
+        Ok(header) if header.kind == RecordKind::Chunk => {}
+        _ => return false,
+    }
+    match try_deserialize_record::<Chunk>(record) {
+        Ok(chunk) => chunk.network_address().to_record_key() == record.key,
+        Err(_) => false,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use bytes::Bytes;
+    use sn_protocol::storage::try_serialize_record;
+
+    fn chunk_record(size: usize) -> eyre::Result<Record> {
+        let content = (0..size).map(|_| rand::random::<u8>()).collect::<Vec<_>>();
+        let chunk = Chunk::new(Bytes::from(content));
+        Ok(Record::new(
+            chunk.network_address().to_record_key(),
+            try_serialize_record(&chunk, RecordKind::Chunk)?.to_vec(),
+        ))
+    }
+
+    #[test]
+    fn only_verified_chunks_are_cached() -> eyre::Result<()> {
+        let mut cache = RecordCache::new(RecordCacheConfig::default());
+        let chunk = chunk_record(1024)?;
+        assert!(cache.insert(&chunk));
+        assert_eq!(cache.get(&chunk.key), Some(chunk.clone()));
+
+        // A chunk under another key has not been verified.
+        let mut misplaced = chunk_record(1024)?;
+        misplaced.key = RecordKey::new(&b"another key");
+        assert!(!cache.insert(&misplaced));
+        assert_eq!(cache.get(&misplaced.key), None);
+
+        // The mutable records are never cached.
+        let mut register = chunk.clone();
+        register.key = RecordKey::new(&b"a register");
+        register.value[0] = 0;
+        assert!(!cache.insert(&register));
+
+        let mut disabled = RecordCache::new(RecordCacheConfig::disabled());
+        assert!(!disabled.insert(&chunk));
+        assert_eq!(disabled.get(&chunk.key), None);
+        Ok(())
+    }
+
+    #[test]
+    fn least_recently_used_records_are_evicted_beyond_the_capacity() -> eyre::Result<()> {
+        let records = (0..3)
+            .map(|_| chunk_record(1000))
+            .collect::<eyre::Result<Vec<_>>>()?;
+        let record_size = records[0].value.len();
+        let mut cache = RecordCache::new(RecordCacheConfig {
+            capacity: record_size * 2,
+            ..Default::default()
+        });
+        assert!(cache.insert(&records[0]));
+        assert!(cache.insert(&records[1]));
+        // Using the first record makes the second one the least recently used.
+        assert!(cache.get(&records[0].key).is_some());
+        assert!(cache.insert(&records[2]));
+
+        assert!(cache.get(&records[0].key).is_some());
+        assert!(cache.get(&records[1].key).is_none());
+        assert!(cache.get(&records[2].key).is_some());
+        assert_eq!(cache.size, record_size * 2);
+        assert_eq!((cache.hits, cache.misses), (3, 1));
+        Ok(())
+    }
+
+    #[test]
+    fn records_expire_after_the_ttl() -> eyre::Result<()> {
+        let mut cache = RecordCache::new(RecordCacheConfig {
+            ttl: Duration::from_millis(50),
+            ..Default::default()
+        });
+        let chunk = chunk_record(1024)?;
+        assert!(cache.insert(&chunk));
+        assert!(cache.get(&chunk.key).is_some());
+
+        std::thread::sleep(Duration::from_millis(60));
+        assert!(cache.get(&chunk.key).is_none());
+        assert_eq!(cache.size, 0);
+        Ok(())
+    }
+}
@@ -0,0 +1,229 @@
+// Copyright 2024 MaidSafe.net limited.
+//
+// This SAFE Network Software is licensed to you under The General Public License (GPL), version 3.
+// Unless required by applicable law or agreed to in writing, the SAFE Network Software distributed
+// under the GPL Licence is distributed on an "AS IS" BASIS, WITHOUT WARRANTIES OR CONDITIONS OF ANY
+// KIND, either express or implied. Please review the Licences for the specific language governing
+// permissions and limitations relating to use of the SAFE Network Software.
+
+use crate::{driver::SwarmDriver, error::NetworkError, target_arch::Instant};
+use libp2p::request_response::ResponseChannel as PeerResponseChannel;
+use sn_protocol::messages::{QueryResponse, Response};
+use std::{collections::VecDeque, time::Duration};
+
+/// The interval at which the queued replication transfers are checked for being sent.
+pub(crate) const REPLICATION_THROTTLE_CHECK_INTERVAL: Duration = Duration::from_millis(100);
+
+/// A token bucket pacing the outbound replication transfers to `rate` bytes per second. Up to a second worth of
+/// transfers can be sent at once, the excess ones are queued rather than dropped. A transfer larger than a second worth
+/// of bytes is sent once the bucket is full.
+#[derive(Debug)]
+pub(crate) struct ReplicationThrottle<T> {
+    // The bytes per second, 0 for unlimited.
+    rate: u64,
+    // The bytes that can be sent right away. This goes negative after a transfer larger than the available tokens, which
+    // then delays the next ones.
+    tokens: f64,
+    last_refill: Instant,
+    queue: VecDeque<(T, usize)>,
+}
+
+impl<T> ReplicationThrottle<T> {
+    pub(crate) fn new(rate: u64, now: Instant) -> Self {
+        Self {
+            rate,
+            tokens: rate as f64,
+            last_refill: now,
+            queue: Default::default(),
+        }
+    }
+
+    pub(crate) fn is_limited(&self) -> bool {
+        self.rate > 0
+    }
+
+    pub(crate) fn queue_len(&self) -> usize {
+        self.queue.len()
+    }
+
+    /// Sets the bytes per second, 0 lifting the limit. The queued transfers are kept.
+    pub(crate) fn set_rate(&mut self, rate: u64, now: Instant) {
+        if self.is_limited() {
+            self.refill(now);
+        } else {
+            self.tokens = rate as f64;
+            self.last_refill = now;
+        }
+        self.rate = rate;
+        self.tokens = self.tokens.min(rate as f64);
+    }
+
+    /// Queues a transfer of `size` bytes. Returns the transfers that can be sent now, in the order they were queued.
+    pub(crate) fn push(&mut self, item: T, size: usize, now: Instant) -> Vec<T> {
+        self.queue.push_back((item, size));
+        self.pop_ready(now)
+    }
+
+    /// Returns the queued transfers that can be sent now, in the order they were queued.
+    pub(crate) fn pop_ready(&mut self, now: Instant) -> Vec<T> {
+        if !self.is_limited() {
+            return self.queue.drain(..).map(|(item, _size)| item).collect();
+        }
+        self.refill(now);
+        let mut ready = Vec::new();
+        while let Some((_item, size)) = self.queue.front() {
+            let size = *size as f64;
+            if self.tokens < size.min(self.rate as f64) {
+                break;
+            }
+            self.tokens -= size;
+            if let Some((item, _size)) = self.queue.pop_front() {
+                ready.push(item);
+            }
+        }
+        ready
+    }
+
+    fn refill(&mut self, now: Instant) {
+        let elapsed = now.saturating_duration_since(self.last_refill);
+        self.tokens =
+            (self.tokens + elapsed.as_secs_f64() * self.rate as f64).min(self.rate as f64);
+        self.last_refill = now;
+    }
+}
+
+/// Returns the size of the record carried by the response, if it is a replication transfer. The clients fetch the
+/// records through kad, hence their responses never get here.
+fn replication_transfer_size(resp: &Response) -> Option<usize> {
+    match resp {
+        Response::Query(QueryResponse::GetReplicatedRecord(Ok((_holder, record)))) => {
+            Some(record.len())
+        }
+        _ => None,
+    }
+}
+
+impl SwarmDriver {
+    /// Sends the response to the peer, the replication transfers being paced by the bandwidth limit.
+    pub(crate) fn send_peer_response(
+        &mut self,
+        channel: PeerResponseChannel<Response>,
+        resp: Response,
+    ) -> Result<(), NetworkError> {
+        let size = match replication_transfer_size(&resp) {
+            Some(size) if self.replication_throttle.is_limited() => size,
+            _ => {
+                return self
+                    .swarm
+                    .behaviour_mut()
+                    .request_response
+                    .send_response(channel, resp)
+                    .map_err(NetworkError::OutgoingResponseDropped);
+            }
+        };
+        let ready = self
+            .replication_throttle
+            .push((channel, resp), size, Instant::now());
+        self.send_replication_transfers(ready);
+        Ok(())
+    }
+
+    /// Sends the queued replication transfers allowed by the bandwidth limit.
+    pub(crate) fn send_ready_replication_transfers(&mut self) {
+        if self.replication_throttle.queue_len() == 0 {
+            return;
+        }
+        let ready = self.replication_throttle.pop_ready(Instant::now());
+        self.send_replication_transfers(ready);
+    }
+
+    /// Sets the bytes per second of the replication transfers, 0 lifting the limit.
+    pub(crate) fn set_replication_bandwidth_limit(&mut self, bytes_per_sec: u64) {
+        info!("Setting the replication bandwidth limit to {bytes_per_sec} bytes/s");
+        self.replication_throttle
+            .set_rate(bytes_per_sec, Instant::now());
+        self.send_ready_replication_transfers();
+    }
+
+    fn send_replication_transfers(
+        &mut self,
+        ready: Vec<(PeerResponseChannel<Response>, Response)>,
+    ) {
+        for (channel, resp) in ready {
+            if let Err(resp) = self
+                .swarm
+                .behaviour_mut()
+                .request_response
+                .send_response(channel, resp)
+            {
+                warn!("Failed to send the replication transfer {resp:?}, the peer is no longer waiting for it");
+            }
+        }
+        let queued = self.replication_throttle.queue_len();
+        if queued > 0 {
+            trace!("{queued} replication transfers are queued by the bandwidth limit");
+        }
+        #[cfg(feature = "open-metrics")]
+        if let Some(metrics_recorder) = &self.metrics_recorder {
+            let _ = metrics_recorder.replication_queue_depth.set(queued as i64);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn transfers_are_paced_by_the_rate() {
+        let start = Instant::now();
+        let at = |millis| start + Duration::from_millis(millis);
+        let mut throttle = ReplicationThrottle::new(1_000, start);
+
+        // A second worth of transfers goes right away, the rest is queued.
+        let mut sent = Vec::new();
+        for transfer in 0..5 {
+            sent.extend(throttle.push(transfer, 500, start));
+        }
+        assert_eq!(sent, vec![0, 1]);
+        assert_eq!(throttle.queue_len(), 3);
+
+        assert!(throttle.pop_ready(at(400)).is_empty());
+        assert_eq!(throttle.pop_ready(at(500)), vec![2]);
+        assert!(throttle.pop_ready(at(900)).is_empty());
+        assert_eq!(throttle.pop_ready(at(1_000)), vec![3]);
+        assert_eq!(throttle.pop_ready(at(1_500)), vec![4]);
+        assert_eq!(throttle.queue_len(), 0);
+
+        // A transfer larger than the burst is sent once the bucket is full, the next ones then wait for the debt to be
+        // paid back.
+        assert!(throttle.push(5, 2_500, at(2_000)).is_empty());
+        assert_eq!(throttle.pop_ready(at(2_500)), vec![5]);
+        assert!(throttle.push(6, 100, at(3_000)).is_empty());
+        assert!(throttle.pop_ready(at(4_000)).is_empty());
+        assert_eq!(throttle.pop_ready(at(4_100)), vec![6]);
+    }
+
+    #[test]
+    fn lifting_the_limit_sends_the_queued_transfers() {
+        let start = Instant::now();
+        let mut throttle = ReplicationThrottle::new(100, start);
+        assert_eq!(throttle.push(0, 100, start), vec![0]);
+        assert!(throttle.push(1, 100, start).is_empty());
+        assert!(throttle.push(2, 100, start).is_empty());
+
+        // Lowering the rate keeps the transfers queued.
+        throttle.set_rate(10, start);
+        assert!(throttle
+            .pop_ready(start + Duration::from_millis(500))
+            .is_empty());
+
+        throttle.set_rate(0, start + Duration::from_secs(1));
+        assert!(!throttle.is_limited());
+        assert_eq!(
+            throttle.pop_ready(start + Duration::from_secs(1)),
+            vec![1, 2]
+        );
+        assert_eq!(throttle.push(3, 1_000_000, start), vec![3]);
+    }
+}
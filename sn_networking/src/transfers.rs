@@ -29,6 +29,7 @@ impl Network {
         let key = NetworkAddress::from_spend_address(address).to_record_key();
         let get_cfg = GetRecordCfg {
             get_quorum: Quorum::Majority,
+            resolution: Default::default(),
             retry_strategy: None,
             // This should not be set here. This function is used as a quick check to find the spends around the key during
             // validation. The returned records might possibly be double spend attempt and the record will not match
@@ -36,6 +37,7 @@ impl Network {
             target_record: None,
             expected_holders: Default::default(),
             is_register: false,
+            bypass_cache: false,
         };
         let record = self.get_record_from_network(key.clone(), &get_cfg).await?;
         debug!(
@@ -53,10 +55,12 @@ impl Network {
         let key = NetworkAddress::from_spend_address(address).to_record_key();
         let mut get_cfg = GetRecordCfg {
             get_quorum: Quorum::All,
+            resolution: Default::default(),
             retry_strategy: Some(RetryStrategy::Quick),
             target_record: None,
             expected_holders: Default::default(),
             is_register: false,
+            bypass_cache: false,
         };
         let record = match self.get_record_from_network(key.clone(), &get_cfg).await {
             Ok(record) => record,
@@ -72,8 +72,11 @@ struct Opt {
     /// Specify whether the node is operating from a home network and situated behind a NAT without port forwarding
     /// capabilities. Setting this to true, activates hole-punching to facilitate direct connections from other nodes.
     ///
+    /// The node then reserves a slot on a relay picked from its connected peers, and advertises the relayed address.
+    /// The connections coming through the relay are upgraded to direct ones where the NAT allows it.
+    ///
     /// If this not enabled and you're behind a NAT, the node is terminated.
-    #[clap(long, default_value_t = false)]
+    #[clap(long, default_value_t = false, visible_alias = "relay")]
     home_network: bool,
 
     /// Try to use UPnP to open a port in the home router and allow incoming connections.
@@ -81,6 +84,11 @@ struct Opt {
     #[clap(long, default_value_t = false)]
     upnp: bool,
 
+    /// Disable the mDNS discovery of the peers on the local network, e.g. on shared LANs.
+    #[cfg(feature = "local-discovery")]
+    #[clap(long, default_value_t = false)]
+    no_local_discovery: bool,
+
     /// Specify the logging output destination.
     ///
     /// Valid values are "stdout", "data-dir", or a custom path.
@@ -267,6 +275,8 @@ fn main() -> Result<()> {
             opt.upnp,
         );
         node_builder.is_behind_home_network = opt.home_network;
+        #[cfg(feature = "local-discovery")]
+        node_builder.local_discovery(!opt.no_local_discovery);
         #[cfg(feature = "open-metrics")]
         let mut node_builder = node_builder;
         // if enable flag is provided or only if the port is specified then enable the server by setting Some()
@@ -16,7 +16,8 @@ use sn_protocol::safenode_proto::{
     KBucketsRequest, KBucketsResponse, NetworkInfoRequest, NetworkInfoResponse, NodeEvent,
     NodeEventsRequest, NodeInfoRequest, NodeInfoResponse, RecordAddressesRequest,
     RecordAddressesResponse, RestartRequest, RestartResponse, StopRequest, StopResponse,
-    UpdateLogLevelRequest, UpdateLogLevelResponse, UpdateRequest, UpdateResponse,
+    TriggerNetworkDiscoveryRequest, TriggerNetworkDiscoveryResponse, UpdateLogLevelRequest,
+    UpdateLogLevelResponse, UpdateRequest, UpdateResponse,
 };
 use std::{
     collections::HashMap,
@@ -97,6 +98,7 @@ impl SafeNode for SafeNodeRpcService {
         let resp = Response::new(NetworkInfoResponse {
             connected_peers,
             listeners,
+            network_discovery_paused: state.network_discovery.paused,
         });
 
         Ok(resp)
@@ -176,13 +178,22 @@ impl SafeNode for SafeNodeRpcService {
 
         let kbuckets: HashMap<u32, k_buckets_response::Peers> = self
             .running_node
-            .get_kbuckets()
+            .get_kbuckets_info()
             .await
             .expect("failed to get k-buckets")
             .into_iter()
-            .map(|(ilog2_distance, peers)| {
-                let peers = peers.into_iter().map(|peer| peer.to_bytes()).collect();
-                let peers = k_buckets_response::Peers { peers };
+            .map(|(ilog2_distance, kbucket)| {
+                let is_full = kbucket.is_full();
+                let mut peers = k_buckets_response::Peers {
+                    capacity: kbucket.capacity as u32,
+                    is_full,
+                    ..Default::default()
+                };
+                for (peer, connected, score) in kbucket.peers {
+                    peers.peers.push(peer.to_bytes());
+                    peers.connected.push(connected);
+                    peers.scores.push(score);
+                }
                 (ilog2_distance, peers)
             })
             .collect();
@@ -283,6 +294,20 @@ impl SafeNode for SafeNodeRpcService {
             )),
         }
     }
+
+    async fn trigger_network_discovery(
+        &self,
+        request: Request<TriggerNetworkDiscoveryRequest>,
+    ) -> Result<Response<TriggerNetworkDiscoveryResponse>, Status> {
+        debug!(
+            "RPC request received at {}: {:?}",
+            self.addr,
+            request.get_ref()
+        );
+
+        self.running_node.trigger_network_discovery();
+        Ok(Response::new(TriggerNetworkDiscoveryResponse {}))
+    }
 }
 
 pub(crate) fn start_rpc_service(
@@ -46,7 +46,7 @@ pub use self::{
 use crate::error::{Error, Result};
 
 use libp2p::PeerId;
-use sn_networking::{Network, SwarmLocalState};
+use sn_networking::{KBucketInfo, Network, SwarmLocalState};
 use sn_protocol::{get_port_from_multiaddr, NetworkAddress};
 use sn_transfers::{HotWallet, NanoTokens};
 use std::{
@@ -92,6 +92,12 @@ impl RunningNode {
         Ok(state)
     }
 
+    /// Triggers a round of network discovery right away, e.g., once the routing table has shrunk drastically. The
+    /// triggers made within a few seconds of each other collapse into a single round.
+    pub fn trigger_network_discovery(&self) {
+        self.network.trigger_network_discovery();
+    }
+
     /// Return the node's listening port
     pub async fn get_node_listening_port(&self) -> Result<u16> {
         let listen_addrs = self.network.get_swarm_local_state().await?.listeners;
@@ -126,4 +132,11 @@ impl RunningNode {
         let kbuckets = self.network.get_kbuckets().await?;
         Ok(kbuckets)
     }
+
+    /// Returns the peers held by each Kbucket, keyed by the ilog2 distance of that Kbucket, along with their
+    /// connection state and the capacity of the Kbucket.
+    pub async fn get_kbuckets_info(&self) -> Result<BTreeMap<u32, KBucketInfo>> {
+        let kbuckets = self.network.get_kbuckets_info().await?;
+        Ok(kbuckets)
+    }
 }
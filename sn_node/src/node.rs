@@ -97,6 +97,8 @@ pub struct NodeBuilder {
     owner: Option<String>,
     #[cfg(feature = "upnp")]
     upnp: bool,
+    #[cfg(feature = "local-discovery")]
+    local_discovery: bool,
 }
 
 impl NodeBuilder {
@@ -122,6 +124,8 @@ impl NodeBuilder {
             owner,
             #[cfg(feature = "upnp")]
             upnp,
+            #[cfg(feature = "local-discovery")]
+            local_discovery: true,
         }
     }
 
@@ -131,6 +135,12 @@ impl NodeBuilder {
         self.metrics_server_port = port;
     }
 
+    #[cfg(feature = "local-discovery")]
+    /// Enable or disable the mDNS local discovery. Enabled by default
+    pub fn local_discovery(&mut self, enable: bool) {
+        self.local_discovery = enable;
+    }
+
     /// Asynchronously runs a new node instance, setting up the swarm driver,
     /// creating a data storage, and handling network events. Returns the
     /// created `RunningNode` which contains a `NodeEventsChannel` for listening
@@ -181,6 +191,8 @@ impl NodeBuilder {
 
         #[cfg(feature = "upnp")]
         network_builder.upnp(self.upnp);
+        #[cfg(feature = "local-discovery")]
+        network_builder.local_discovery(self.local_discovery);
 
         let (network, network_event_receiver, swarm_driver) = network_builder.build_node()?;
         let node_events_channel = NodeEventsChannel::default();
@@ -881,6 +893,7 @@ impl Node {
             retry_strategy: None,
             use_put_record_to: None,
             verification: None,
+            verify_after_put: None,
         };
 
         info!(
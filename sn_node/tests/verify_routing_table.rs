@@ -58,6 +58,15 @@ async fn verify_routing_table() -> Result<()> {
             .await?;
 
         let k_buckets = response.get_ref().kbuckets.clone();
+        for (ilog2, bucket) in k_buckets.iter() {
+            assert_eq!(
+                bucket.connected.len(),
+                bucket.peers.len(),
+                "the connection state of each peer of the bucket {ilog2} is provided"
+            );
+            assert_eq!(bucket.capacity as usize, K_VALUE.get());
+            assert_eq!(bucket.is_full, bucket.peers.len() >= K_VALUE.get());
+        }
         let k_buckets = k_buckets
             .into_iter()
             .map(|(ilog2, peers)| {
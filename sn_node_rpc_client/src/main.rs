@@ -9,11 +9,14 @@
 
 use clap::Parser;
 use color_eyre::eyre::Result;
+use libp2p::PeerId;
 use sn_logging::{Level, LogBuilder};
 use sn_node::NodeEvent;
-use sn_protocol::safenode_proto::{safe_node_client::SafeNodeClient, NodeEventsRequest};
+use sn_protocol::safenode_proto::{
+    safe_node_client::SafeNodeClient, KBucketsRequest, NodeEventsRequest,
+};
 use sn_service_management::rpc::{RpcActions, RpcClient};
-use std::{net::SocketAddr, time::Duration};
+use std::{collections::BTreeMap, net::SocketAddr, time::Duration};
 use tokio_stream::StreamExt;
 use tonic::Request;
 
@@ -48,6 +51,9 @@ enum Cmd {
     /// Retrieve information about the node's connections to the network
     #[clap(name = "netinfo")]
     Netinfo,
+    /// Retrieve the peers held by each bucket of the node's routing table
+    #[clap(name = "kbuckets")]
+    Kbuckets,
     /// Start listening for node events.
     /// Note this blocks the app and it will print events as they are broadcasted by the node
     #[clap(name = "events")]
@@ -127,6 +133,7 @@ async fn main() -> Result<()> {
     match opt.cmd {
         Cmd::Info => node_info(addr).await,
         Cmd::Netinfo => network_info(addr).await,
+        Cmd::Kbuckets => node_kbuckets(addr).await,
         Cmd::Events => node_events(addr).await,
         Cmd::Restart {
             delay_millis,
@@ -176,6 +183,42 @@ pub async fn network_info(addr: SocketAddr) -> Result<()> {
     Ok(())
 }
 
+pub async fn node_kbuckets(addr: SocketAddr) -> Result<()> {
+    let endpoint = format!("https://{addr}");
+    let mut client = SafeNodeClient::connect(endpoint).await?;
+    let response = client.k_buckets(Request::new(KBucketsRequest {})).await?;
+
+    let kbuckets = response
+        .into_inner()
+        .kbuckets
+        .into_iter()
+        .collect::<BTreeMap<_, _>>();
+    println!("Node's routing table:");
+    for (ilog2_distance, kbucket) in kbuckets {
+        println!();
+        println!(
+            "Bucket {ilog2_distance}: {}/{} peers{}",
+            kbucket.peers.len(),
+            kbucket.capacity,
+            if kbucket.is_full { " (full)" } else { "" }
+        );
+        for (index, peer_bytes) in kbucket.peers.iter().enumerate() {
+            let peer_id = PeerId::from_bytes(peer_bytes)?;
+            let state = match kbucket.connected.get(index) {
+                Some(true) => "connected",
+                Some(false) => "disconnected",
+                None => "unknown",
+            };
+            match kbucket.scores.get(index) {
+                Some(score) => println!("Peer: {peer_id} ({state}, score {score})"),
+                None => println!("Peer: {peer_id} ({state})"),
+            }
+        }
+    }
+
+    Ok(())
+}
+
 pub async fn node_events(addr: SocketAddr) -> Result<()> {
     let endpoint = format!("https://{addr}");
     let mut client = SafeNodeClient::connect(endpoint).await?;
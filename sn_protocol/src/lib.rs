@@ -193,6 +193,23 @@ impl NetworkAddress {
         self.as_kbucket_key().distance(&other.as_kbucket_key())
     }
 
+    /// Returns the address of a random PeerId that falls within the `ilog2` bucket of `reference`, i.e., whose
+    /// distance to `reference` has an ilog2 of `ilog2`. Gives up and returns None after `max_attempts` PeerIds.
+    ///
+    /// The kbucket key of an address is the sha256 hash of its bytes, so the address cannot be derived by flipping
+    /// the bits of `reference`. Random PeerIds are sampled instead, until one lands in the bucket. A bucket is hit
+    /// with a probability of `2^(ilog2 - 256)`, hence this is only practical for the farthest buckets, e.g., ~64
+    /// attempts are expected for the bucket 250.
+    pub fn random_in_bucket(
+        reference: &Key<PeerId>,
+        ilog2: u32,
+        max_attempts: usize,
+    ) -> Option<NetworkAddress> {
+        (0..max_attempts)
+            .map(|_| NetworkAddress::from_peer(PeerId::random()))
+            .find(|addr| addr.as_kbucket_key().distance(reference).ilog2() == Some(ilog2))
+    }
+
     // NB: Leaving this here as to demonstrate what we can do with this.
     // /// Return the uniquely determined key with the given distance to `self`.
     // ///
@@ -396,8 +413,26 @@ impl<'a> std::fmt::Debug for PrettyPrintRecordKey<'a> {
 mod tests {
     use crate::NetworkAddress;
     use bls::rand::thread_rng;
+    use libp2p::{kad::KBucketKey, PeerId};
     use sn_transfers::SpendAddress;
 
+    #[test]
+    fn random_in_bucket_only_returns_addresses_of_the_requested_bucket() {
+        let reference = KBucketKey::from(PeerId::random());
+        let ilog2_of = |addr: &NetworkAddress| addr.as_kbucket_key().distance(&reference).ilog2();
+        for ilog2 in 0..=255 {
+            if let Some(addr) = NetworkAddress::random_in_bucket(&reference, ilog2, 64) {
+                assert_eq!(ilog2_of(&addr), Some(ilog2));
+            }
+        }
+        // The farthest buckets are always reached with enough attempts.
+        for ilog2 in 250..=255 {
+            let addr = NetworkAddress::random_in_bucket(&reference, ilog2, 10_000);
+            assert_eq!(addr.as_ref().and_then(ilog2_of), Some(ilog2));
+        }
+        assert!(NetworkAddress::random_in_bucket(&reference, 255, 0).is_none());
+    }
+
     #[test]
     fn verify_spend_addr_is_actionable() {
         let xorname = xor_name::XorName::random(&mut thread_rng());